@@ -0,0 +1,449 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use polars::io::avro::AvroCompression;
+use polars::prelude::{
+    col, lit, DataType, Expr, FillNullStrategy, IpcCompression, ParquetCompression, StrptimeOptions,
+};
+
+// Define the command line: one subcommand per operation, each reading a file of its own
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+// Define the operations, each demonstrating a part of Polars on the file it is given
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Print the first rows
+    Head {
+        #[command(flatten)]
+        input: Input,
+        /// Number of rows to print
+        #[arg(short = 'n', long, default_value_t = 5)]
+        rows: usize,
+    },
+    /// Print the name and data type of every column
+    Schema {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Print summary statistics of every column
+    Describe {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Print the number of null values in every column
+    Nulls {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Replace null values, by a strategy or with a value
+    Fill {
+        #[command(flatten)]
+        input: Input,
+        /// Column to fill, repeated for several; every column the strategy applies to if omitted
+        #[arg(short, long = "column", value_name = "NAME")]
+        columns: Vec<String>,
+        /// How to compute the replacement of each null value
+        #[arg(short, long, value_enum, required_unless_present = "value")]
+        strategy: Option<Strategy>,
+        /// Value to replace null values with, read as the data type of each column
+        #[arg(long, conflicts_with = "strategy")]
+        value: Option<String>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Drop the rows holding a null value
+    DropNulls {
+        #[command(flatten)]
+        input: Input,
+        /// Column whose null values drop their row, repeated for several; every column if omitted
+        #[arg(short, long = "column", value_name = "NAME")]
+        columns: Vec<String>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Write the file in another format or layout, such as CSV as Parquet
+    Convert {
+        #[command(flatten)]
+        input: Input,
+        /// File to write, in the format its extension names unless `--output-format` says
+        #[arg(id = "output", value_name = "OUTPUT")]
+        output: PathBuf,
+        #[command(flatten)]
+        encoding: Encoding,
+    },
+}
+
+// Define the file an operation reads and how it is laid out
+#[derive(Debug, Clone, Args)]
+pub struct Input {
+    /// File to load, such as missing.csv
+    #[arg(id = "input", value_name = "INPUT", value_parser = existing_file)]
+    pub path: PathBuf,
+    /// Format of the input; that its extension names if omitted, or else CSV
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<Format>,
+    /// Read the first line of CSV, or the header row of Excel, as data rather than column names
+    #[arg(long)]
+    pub no_header: bool,
+    /// Character separating the fields of CSV, such as `,`, `;` or `\t`
+    #[arg(short, long, default_value = ",", value_parser = delimiter)]
+    pub delimiter: u8,
+    /// Rows of CSV or JSON read to infer the data type of each column, or `all`
+    #[arg(long, value_name = "ROWS", default_value = "100", value_parser = schema_length)]
+    pub infer_schema_length: SchemaLength,
+    /// Read CSV columns of dates such as `2023-02-12`, or of timestamps, as dates and timestamps
+    /// rather than text
+    #[arg(long)]
+    pub parse_dates: bool,
+    /// Sheet of an Excel workbook to read, by name or by position from 1; the first if omitted
+    #[arg(long, value_name = "SHEET")]
+    pub sheet: Option<String>,
+    /// Row of an Excel sheet holding the column names, counted from 1 with the rows above it left
+    /// out; the first row that is not empty if omitted
+    #[arg(long, value_name = "ROW", value_parser = clap::value_parser!(u32).range(1..))]
+    pub header_row: Option<u32>,
+    /// Column to keep, repeated for several, in that order; every column if omitted
+    #[arg(long = "select", value_name = "NAME")]
+    pub select: Vec<String>,
+    /// Comparison rows must pass to be kept, such as `Age>=18` or `Name==Alice`, with `==`, `!=`,
+    /// `<`, `<=`, `>` or `>=`; repeated for several that must all hold
+    #[arg(long = "filter", value_name = "COMPARISON", value_parser = filter)]
+    pub filters: Vec<Filter>,
+    /// Scan CSV lazily, so `--select` and `--filter` apply while it is read rather than after
+    #[arg(long)]
+    pub lazy: bool,
+    /// Print the optimized plan of reading the input rather than run the operation
+    #[arg(long)]
+    pub explain: bool,
+}
+
+// Define how many rows the data types of the columns are inferred from, every row if `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaLength(pub Option<usize>);
+
+impl Command {
+    pub fn input(&self) -> &Input {
+        match self {
+            Command::Head { input, .. }
+            | Command::Schema { input }
+            | Command::Describe { input }
+            | Command::Nulls { input }
+            | Command::Fill { input, .. }
+            | Command::DropNulls { input, .. }
+            | Command::Convert { input, .. } => input,
+        }
+    }
+}
+
+impl Input {
+    pub fn format(&self) -> Format {
+        self.input_format
+            .or_else(|| Format::of(&self.path))
+            .unwrap_or(Format::Csv)
+    }
+
+    pub fn has_header(&self) -> bool {
+        !self.no_header
+    }
+}
+
+// Define a comparison of a column with a value, the value read as the column's data type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub column: String,
+    comparison: Comparison,
+    value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl Comparison {
+    // Operators, those of two characters first so `<=` is not read as `<`
+    const OPERATORS: [(&'static str, Comparison); 7] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::NotEq),
+        ("<=", Comparison::LtEq),
+        (">=", Comparison::GtEq),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+        ("=", Comparison::Eq),
+    ];
+}
+
+impl Filter {
+    // Return the comparison as an expression on a column of the data type
+    pub fn expr(&self, dtype: &DataType) -> Expr {
+        let column = col(&self.column);
+        // Polars only casts text to numbers, so dates and timestamps are parsed
+        let value = match dtype {
+            DataType::Date | DataType::Datetime(_, _) => lit(self.value.clone())
+                .str()
+                .strptime(dtype.clone(), StrptimeOptions::default()),
+            _ => lit(self.value.clone()).strict_cast(dtype.clone()),
+        };
+        match self.comparison {
+            Comparison::Eq => column.eq(value),
+            Comparison::NotEq => column.neq(value),
+            Comparison::Lt => column.lt(value),
+            Comparison::LtEq => column.lt_eq(value),
+            Comparison::Gt => column.gt(value),
+            Comparison::GtEq => column.gt_eq(value),
+        }
+    }
+}
+
+// Define where an operation changing the data writes it; it is printed if nowhere
+#[derive(Debug, Clone, Args)]
+pub struct Output {
+    /// File to write the result to, in the format its extension names unless `--output-format`
+    /// says
+    #[arg(id = "output", short, long = "output", value_name = "FILE")]
+    pub path: Option<PathBuf>,
+    #[command(flatten)]
+    pub encoding: Encoding,
+}
+
+// Define how an output is encoded; the CSV layout is that of the input unless told otherwise
+#[derive(Debug, Clone, Args)]
+pub struct Encoding {
+    /// Format of the output; that its extension names if omitted, or else that of the input
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub output_format: Option<Format>,
+    /// Leave the line of column names out of CSV output
+    #[arg(long)]
+    pub output_no_header: bool,
+    /// Character separating the fields of CSV output; that of the input if omitted
+    #[arg(long, value_name = "DELIMITER", value_parser = delimiter)]
+    pub output_delimiter: Option<u8>,
+    /// Compression of Parquet, Arrow IPC or Avro output; snappy for Parquet and none for the
+    /// others if omitted
+    #[arg(long, value_enum)]
+    pub compression: Option<Compression>,
+    /// Indent JSON output rather than write it on one line; NDJSON is always a line per row
+    #[arg(long)]
+    pub pretty: bool,
+    /// Most rows of each row group of Parquet output; Polars' default if omitted
+    #[arg(long, value_name = "ROWS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub row_group_size: Option<u64>,
+}
+
+impl Encoding {
+    // Return the format to write `path` in
+    pub fn format(&self, path: &Path, input: &Input) -> Format {
+        self.output_format
+            .or_else(|| Format::of(path))
+            .unwrap_or_else(|| input.format())
+    }
+}
+
+// Define the file formats read and written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    /// An array of an object per row
+    Json,
+    /// A line of an object per row, as logs are written
+    Ndjson,
+    Parquet,
+    /// Arrow IPC, as Feather v2 files are
+    Ipc,
+    /// Avro object container files, as Kafka pipelines exchange; decimals are converted to doubles
+    Avro,
+    /// A sheet of an Excel or OpenDocument workbook, which is only read
+    Excel,
+}
+
+impl Format {
+    // Return the format a file extension names, in any case
+    fn of(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "ndjson" | "jsonl" => Some(Format::Ndjson),
+            "parquet" | "pq" => Some(Format::Parquet),
+            "arrow" | "feather" | "ipc" => Some(Format::Ipc),
+            "avro" => Some(Format::Avro),
+            "xlsx" | "xlsm" | "xlsb" | "xls" | "ods" => Some(Format::Excel),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Csv => write!(f, "CSV"),
+            Format::Json => write!(f, "JSON"),
+            Format::Ndjson => write!(f, "NDJSON"),
+            Format::Parquet => write!(f, "Parquet"),
+            Format::Ipc => write!(f, "Arrow IPC"),
+            Format::Avro => write!(f, "Avro"),
+            Format::Excel => write!(f, "Excel"),
+        }
+    }
+}
+
+// Define the compressions output can have, each format supporting some
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Deflate,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parquet(self) -> Option<ParquetCompression> {
+        match self {
+            Compression::Uncompressed => Some(ParquetCompression::Uncompressed),
+            Compression::Snappy => Some(ParquetCompression::Snappy),
+            Compression::Deflate => None,
+            Compression::Lz4 => Some(ParquetCompression::Lz4Raw),
+            Compression::Zstd => Some(ParquetCompression::Zstd(None)),
+        }
+    }
+
+    pub fn ipc(self) -> Option<Option<IpcCompression>> {
+        match self {
+            Compression::Uncompressed => Some(None),
+            Compression::Lz4 => Some(Some(IpcCompression::LZ4)),
+            Compression::Zstd => Some(Some(IpcCompression::ZSTD)),
+            Compression::Snappy | Compression::Deflate => None,
+        }
+    }
+
+    pub fn avro(self) -> Option<Option<AvroCompression>> {
+        match self {
+            Compression::Uncompressed => Some(None),
+            Compression::Snappy => Some(Some(AvroCompression::Snappy)),
+            Compression::Deflate => Some(Some(AvroCompression::Deflate)),
+            Compression::Lz4 | Compression::Zstd => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Uncompressed => write!(f, "uncompressed"),
+            Compression::Snappy => write!(f, "snappy"),
+            Compression::Deflate => write!(f, "deflate"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+// Define the strategies of `fill`, as Polars names them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// The previous value in the column
+    Forward,
+    /// The next value in the column
+    Backward,
+    /// The mean of the column
+    Mean,
+    /// The smallest value of the column
+    Min,
+    /// The largest value of the column
+    Max,
+    Zero,
+    One,
+}
+
+impl Strategy {
+    pub fn polars(self) -> FillNullStrategy {
+        match self {
+            Strategy::Forward => FillNullStrategy::Forward(None),
+            Strategy::Backward => FillNullStrategy::Backward(None),
+            Strategy::Mean => FillNullStrategy::Mean,
+            Strategy::Min => FillNullStrategy::Min,
+            Strategy::Max => FillNullStrategy::Max,
+            Strategy::Zero => FillNullStrategy::Zero,
+            Strategy::One => FillNullStrategy::One,
+        }
+    }
+
+    // Return whether the strategy computes a number, so only applies to numeric columns
+    pub fn is_numeric(self) -> bool {
+        !matches!(self, Strategy::Forward | Strategy::Backward)
+    }
+}
+
+// Accept an input only if it is a file, so a wrong path is reported as such rather than as the
+// I/O error Polars would fail with
+fn existing_file(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    match path.is_file() {
+        true => Ok(path),
+        false if path.exists() => Err(format!("`{}` is not a file", path.display())),
+        false => Err(format!("no file at `{}`", path.display())),
+    }
+}
+
+// Accept a number of rows to infer data types from, or `all`
+fn schema_length(value: &str) -> Result<SchemaLength, String> {
+    match value {
+        "all" => Ok(SchemaLength(None)),
+        _ => match value.parse::<usize>() {
+            Ok(rows) if rows > 0 => Ok(SchemaLength(Some(rows))),
+            _ => Err(format!("`{}` is neither a number of rows nor `all`", value)),
+        },
+    }
+}
+
+// Accept a delimiter of a single ASCII character, `\t` and `tab` naming the tab
+fn delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ => match value.as_bytes() {
+            [byte] if byte.is_ascii() => Ok(*byte),
+            _ => Err(format!(
+                "`{}` is not a single ASCII character, such as `,` or `;`",
+                value
+            )),
+        },
+    }
+}
+
+// Accept a comparison of a column with a value, such as `Age>=18`, spaces around the operator
+// allowed
+fn filter(value: &str) -> Result<Filter, String> {
+    let invalid = || {
+        format!(
+            "`{}` is not a comparison of a column with a value, such as `Age>=18`",
+            value
+        )
+    };
+    let at = value.find(['=', '!', '<', '>']).ok_or_else(invalid)?;
+    let (column, rest) = value.split_at(at);
+    let (operator, comparison) = Comparison::OPERATORS
+        .iter()
+        .find(|(operator, _)| rest.starts_with(operator))
+        .ok_or_else(invalid)?;
+    let column = column.trim();
+    if column.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Filter {
+        column: column.to_string(),
+        comparison: *comparison,
+        value: rest[operator.len()..].trim().to_string(),
+    })
+}
@@ -0,0 +1,138 @@
+use polars::prelude::*;
+
+use crate::cli::{Command, Input, Output, Strategy};
+use crate::error::Error;
+use crate::files;
+
+// Run the operation of the command line
+pub fn run(command: &Command) -> Result<(), Error> {
+    let input = command.input();
+    if input.explain {
+        let rows = match command {
+            Command::Head { rows, .. } => Some(*rows),
+            _ => None,
+        };
+        println!("{}", files::explain(input, rows)?);
+        return Ok(());
+    }
+    match command {
+        Command::Head { input, rows } => {
+            println!("{}", files::read(input, Some(*rows))?);
+            Ok(())
+        }
+        Command::Schema { input } => {
+            println!("{}", schema(&files::read(input, None)?)?);
+            Ok(())
+        }
+        Command::Describe { input } => {
+            println!("{}", files::read(input, None)?.describe(None)?);
+            Ok(())
+        }
+        Command::Nulls { input } => {
+            println!("{}", files::read(input, None)?.null_count());
+            Ok(())
+        }
+        Command::Fill {
+            input,
+            columns,
+            strategy,
+            value,
+            output,
+        } => {
+            let df = files::read(input, None)?;
+            let filled = match (strategy, value) {
+                (_, Some(value)) => fill_with(df, columns, value)?,
+                (Some(strategy), None) => fill(df, columns, *strategy)?,
+                // Refused by the command line
+                (None, None) => unreachable!("`fill` needs a strategy or a value"),
+            };
+            emit(input, output, filled)
+        }
+        Command::DropNulls {
+            input,
+            columns,
+            output,
+        } => {
+            let df = files::read(input, None)?;
+            check_columns(&df, columns)?;
+            let subset = (!columns.is_empty()).then_some(columns.as_slice());
+            emit(input, output, df.drop_nulls(subset)?)
+        }
+        Command::Convert {
+            input,
+            output,
+            encoding,
+        } => {
+            let mut df = files::read(input, None)?;
+            files::write(output, input, encoding, &mut df)
+        }
+    }
+}
+
+// Return the name and data type of every column, as a frame so it prints like the data does
+fn schema(df: &DataFrame) -> Result<DataFrame, Error> {
+    let names: Vec<&str> = df.get_column_names();
+    let dtypes: Vec<String> = df.dtypes().iter().map(ToString::to_string).collect();
+    Ok(df!("column" => names, "dtype" => dtypes)?)
+}
+
+// Refuse columns the data does not have, naming those it does
+fn check_columns(df: &DataFrame, columns: &[String]) -> Result<(), Error> {
+    match columns.iter().find(|name| df.column(name).is_err()) {
+        Some(name) => Err(Error::Column {
+            name: name.clone(),
+            available: df
+                .get_column_names()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Fill the null values of the columns by the strategy. Without columns named, numeric strategies
+// leave the columns they cannot apply to, such as those of text, alone.
+fn fill(mut df: DataFrame, columns: &[String], strategy: Strategy) -> Result<DataFrame, Error> {
+    check_columns(&df, columns)?;
+    let names: Vec<String> = match columns.is_empty() {
+        true => df
+            .get_columns()
+            .iter()
+            .filter(|series| !strategy.is_numeric() || series.dtype().is_numeric())
+            .map(|series| series.name().to_string())
+            .collect(),
+        false => columns.to_vec(),
+    };
+    for name in names {
+        let filled = df.column(&name)?.fill_null(strategy.polars())?;
+        df.with_column(filled)?;
+    }
+    Ok(df)
+}
+
+// Fill the null values of the columns, every one if none are named, with the value read as the
+// data type of each, refusing it where it is not one
+fn fill_with(df: DataFrame, columns: &[String], value: &str) -> Result<DataFrame, Error> {
+    check_columns(&df, columns)?;
+    let filled: Vec<Expr> = df
+        .get_columns()
+        .iter()
+        .filter(|series| columns.is_empty() || columns.iter().any(|name| name == series.name()))
+        .map(|series| {
+            col(series.name()).fill_null(lit(value.to_string()).strict_cast(series.dtype().clone()))
+        })
+        .collect();
+    Ok(df.lazy().with_columns(filled).collect()?)
+}
+
+// Write the result where the command line says, or else print it
+fn emit(input: &Input, output: &Output, mut df: DataFrame) -> Result<(), Error> {
+    match &output.path {
+        Some(path) => files::write(path, input, &output.encoding, &mut df),
+        None => {
+            println!("{}", df);
+            Ok(())
+        }
+    }
+}
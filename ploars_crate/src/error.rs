@@ -0,0 +1,161 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use polars::prelude::PolarsError;
+
+use crate::cli::{Compression, Format};
+
+// Define what can go wrong, each naming the file or column at fault so Polars' own message has
+// context
+#[derive(Debug)]
+pub enum Error {
+    Read {
+        path: PathBuf,
+        format: Format,
+        source: PolarsError,
+    },
+    Create {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Write {
+        path: PathBuf,
+        format: Format,
+        source: PolarsError,
+    },
+    Column {
+        name: String,
+        available: Vec<String>,
+    },
+    Compression {
+        format: Format,
+        compression: Compression,
+        supported: Vec<Compression>,
+    },
+    Workbook {
+        path: PathBuf,
+        source: calamine::Error,
+    },
+    Sheet {
+        path: PathBuf,
+        sheet: Option<String>,
+        available: Vec<String>,
+    },
+    Unwritable {
+        path: PathBuf,
+        format: Format,
+    },
+    Lazy {
+        path: PathBuf,
+        format: Format,
+    },
+    Polars(PolarsError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read {
+                path,
+                format,
+                source,
+            } => write!(
+                f,
+                "failed to read `{}` as {}: {}",
+                path.display(),
+                format,
+                source
+            ),
+            Error::Create { path, source } => {
+                write!(f, "failed to create `{}`: {}", path.display(), source)
+            }
+            Error::Write {
+                path,
+                format,
+                source,
+            } => write!(
+                f,
+                "failed to write `{}` as {}: {}",
+                path.display(),
+                format,
+                source
+            ),
+            Error::Column { name, available } => write!(
+                f,
+                "no column `{}`, the columns are {}",
+                name,
+                available.join(", ")
+            ),
+            Error::Compression {
+                format,
+                compression,
+                supported,
+            } => write!(
+                f,
+                "{} output cannot be compressed with {}, only with {}",
+                format,
+                compression,
+                supported
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            Error::Workbook { path, source } => write!(
+                f,
+                "failed to read `{}` as an Excel workbook: {}",
+                path.display(),
+                source
+            ),
+            Error::Sheet {
+                path,
+                sheet: Some(sheet),
+                available,
+            } => write!(
+                f,
+                "no sheet `{}` in `{}`, the sheets are {}",
+                sheet,
+                path.display(),
+                available.join(", ")
+            ),
+            Error::Sheet { path, .. } => write!(f, "`{}` has no sheets", path.display()),
+            Error::Unwritable { path, format } => write!(
+                f,
+                "cannot write `{}`, as {} is only read; write it as CSV, JSON, NDJSON, Parquet, \
+                 Arrow IPC or Avro",
+                path.display(),
+                format
+            ),
+            Error::Lazy { path, format } => write!(
+                f,
+                "cannot scan `{}` lazily, as only CSV is and it is {}; read it without `--lazy`",
+                path.display(),
+                format
+            ),
+            Error::Polars(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read { source, .. } | Error::Write { source, .. } => Some(source),
+            Error::Create { source, .. } => Some(source),
+            Error::Workbook { source, .. } => Some(source),
+            Error::Column { .. }
+            | Error::Compression { .. }
+            | Error::Sheet { .. }
+            | Error::Unwritable { .. }
+            | Error::Lazy { .. } => None,
+            Error::Polars(source) => Some(source),
+        }
+    }
+}
+
+impl From<PolarsError> for Error {
+    fn from(err: PolarsError) -> Self {
+        Error::Polars(err)
+    }
+}
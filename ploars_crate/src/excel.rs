@@ -0,0 +1,130 @@
+use std::fs;
+use std::io::Cursor;
+
+use calamine::{open_workbook_auto_from_rs, Data, HeaderRow, Range, Reader};
+use polars::prelude::*;
+
+use crate::cli::Input;
+use crate::error::Error;
+
+// Load the chosen sheet of an Excel workbook, only its first `rows` rows of data if given
+pub fn read(input: &Input, rows: Option<usize>) -> Result<DataFrame, Error> {
+    let range = sheet(input)?;
+    let mut lines = range.rows();
+    let names: Vec<String> = match input.has_header() {
+        true => lines
+            .next()
+            .map(|header| header.iter().map(ToString::to_string).collect())
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+    let lines: Vec<&[Data]> = lines.take(rows.unwrap_or(usize::MAX)).collect();
+    let columns = (0..range.width())
+        .map(|at| {
+            let name = match names.get(at) {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => format!("column_{}", at + 1),
+            };
+            let cells: Vec<&Data> = lines.iter().map(|line| &line[at]).collect();
+            column(&name, &cells)
+        })
+        .collect::<PolarsResult<Vec<Series>>>()?;
+    Ok(DataFrame::new(columns)?)
+}
+
+// Open the workbook whatever its extension, as its content tells which kind it is, and read the
+// sheet the command line names from its header row on
+fn sheet(input: &Input) -> Result<Range<Data>, Error> {
+    let workbook_error = |source| Error::Workbook {
+        path: input.path.clone(),
+        source,
+    };
+    let bytes = fs::read(&input.path).map_err(|source| workbook_error(source.into()))?;
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(bytes)).map_err(workbook_error)?;
+    if let Some(row) = input.header_row {
+        workbook.with_header_row(HeaderRow::Row(row - 1));
+    }
+    let names = workbook.sheet_names();
+    let name = match &input.sheet {
+        None => names.first(),
+        Some(wanted) => names.iter().find(|name| *name == wanted).or_else(|| {
+            let position = wanted.parse::<usize>().ok()?;
+            names.get(position.checked_sub(1)?)
+        }),
+    };
+    let Some(name) = name.cloned() else {
+        return Err(Error::Sheet {
+            path: input.path.clone(),
+            sheet: input.sheet.clone(),
+            available: names,
+        });
+    };
+    workbook.worksheet_range(&name).map_err(workbook_error)
+}
+
+// Build a column of the cells, typed as all of them are: whole numbers, numbers, booleans or dates,
+// or else text. Empty cells and those holding a formula error are null.
+fn column(name: &str, cells: &[&Data]) -> PolarsResult<Series> {
+    let values = || {
+        cells
+            .iter()
+            .filter(|cell| !matches!(cell, Data::Empty | Data::Error(_)))
+    };
+    let whole = |cell: &Data| match cell {
+        Data::Int(value) => Some(*value),
+        Data::Float(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+            Some(*value as i64)
+        }
+        _ => None,
+    };
+    let number = |cell: &Data| match cell {
+        Data::Int(value) => Some(*value as f64),
+        Data::Float(value) => Some(*value),
+        _ => None,
+    };
+    let boolean = |cell: &Data| match cell {
+        Data::Bool(value) => Some(*value),
+        _ => None,
+    };
+    let millis = |cell: &Data| match cell {
+        Data::DateTime(value) if value.is_datetime() => value
+            .as_datetime()
+            .map(|datetime| datetime.timestamp_millis()),
+        _ => None,
+    };
+    let text = |cell: &Data| match cell {
+        Data::Empty | Data::Error(_) => None,
+        _ => Some(cell.to_string()),
+    };
+
+    let series = if values().count() == 0 {
+        Series::new(name, cells.iter().map(|_| None::<&str>).collect::<Vec<_>>())
+    } else if values().all(|cell| whole(cell).is_some()) {
+        Series::new(
+            name,
+            cells.iter().map(|cell| whole(cell)).collect::<Vec<_>>(),
+        )
+    } else if values().all(|cell| number(cell).is_some()) {
+        Series::new(
+            name,
+            cells.iter().map(|cell| number(cell)).collect::<Vec<_>>(),
+        )
+    } else if values().all(|cell| boolean(cell).is_some()) {
+        Series::new(
+            name,
+            cells.iter().map(|cell| boolean(cell)).collect::<Vec<_>>(),
+        )
+    } else if values().all(|cell| millis(cell).is_some()) {
+        Series::new(
+            name,
+            cells.iter().map(|cell| millis(cell)).collect::<Vec<_>>(),
+        )
+        .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+    } else {
+        Series::new(
+            name,
+            cells.iter().map(|cell| text(cell)).collect::<Vec<_>>(),
+        )
+    };
+    Ok(series)
+}
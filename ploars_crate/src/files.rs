@@ -0,0 +1,300 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use clap::ValueEnum;
+use polars::io::avro::{AvroReader, AvroWriter};
+use polars::prelude::*;
+
+use crate::cli::{Compression, Encoding, Format, Input};
+use crate::error::Error;
+use crate::excel;
+
+// Load the file as the command line describes it, only its first `rows` rows if given
+pub fn read(input: &Input, rows: Option<usize>) -> Result<DataFrame, Error> {
+    frame(input, rows)?.collect().map_err(|source| Error::Read {
+        path: input.path.clone(),
+        format: input.format(),
+        source,
+    })
+}
+
+// Return the plan of loading the file as Polars optimizes it, showing what `--lazy` pushes down
+// into the scan
+pub fn explain(input: &Input, rows: Option<usize>) -> Result<String, Error> {
+    frame(input, rows)?
+        .describe_optimized_plan()
+        .map_err(Error::from)
+}
+
+// Return the file as a lazy frame keeping the rows and columns the command line asks for. CSV
+// scanned with `--lazy` is only read as far as they need; other files are loaded whole first.
+fn frame(input: &Input, rows: Option<usize>) -> Result<LazyFrame, Error> {
+    let format = input.format();
+    let failed = |source| Error::Read {
+        path: input.path.clone(),
+        format,
+        source,
+    };
+    let frame = match (input.lazy, format) {
+        (true, Format::Csv) => LazyCsvReader::new(&input.path)
+            .has_header(input.has_header())
+            .with_delimiter(input.delimiter)
+            .with_infer_schema_length(input.infer_schema_length.0)
+            .with_try_parse_dates(input.parse_dates)
+            .finish()
+            .map_err(failed)?,
+        (true, _) => {
+            return Err(Error::Lazy {
+                path: input.path.clone(),
+                format,
+            })
+        }
+        // Only the first rows are loaded if no filter may drop some of them
+        (false, _) => load(input, rows.filter(|_| input.filters.is_empty()))?.lazy(),
+    };
+    let schema = frame.schema().map_err(failed)?;
+    let dtype = |name: &String| {
+        schema.get(name).ok_or_else(|| Error::Column {
+            name: name.clone(),
+            available: schema.iter_names().map(ToString::to_string).collect(),
+        })
+    };
+    let mut frame = frame;
+    for filter in &input.filters {
+        frame = frame.filter(filter.expr(dtype(&filter.column)?));
+    }
+    if !input.select.is_empty() {
+        let columns = input
+            .select
+            .iter()
+            .map(|name| dtype(name).map(|_| col(name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        frame = frame.select(columns);
+    }
+    Ok(match rows {
+        Some(rows) => frame.limit(rows as IdxSize),
+        None => frame,
+    })
+}
+
+// Load the whole file, or only its first `rows` rows if given
+fn load(input: &Input, rows: Option<usize>) -> Result<DataFrame, Error> {
+    let format = input.format();
+    if format == Format::Excel {
+        return excel::read(input, rows);
+    }
+    let schema_length = input.infer_schema_length.0;
+    let read = || match format {
+        Format::Csv => CsvReader::from_path(&input.path)?
+            .infer_schema(schema_length)
+            .has_header(input.has_header())
+            .with_delimiter(input.delimiter)
+            .with_try_parse_dates(input.parse_dates)
+            .with_n_rows(rows)
+            .finish(),
+        Format::Json => read_json(&input.path, schema_length, rows),
+        Format::Ndjson => JsonLineReader::new(File::open(&input.path)?)
+            .infer_schema_len(schema_length)
+            .with_n_rows(rows)
+            .finish(),
+        Format::Avro => AvroReader::new(File::open(&input.path)?)
+            .with_n_rows(rows)
+            .finish()
+            .and_then(|df| from_avro(&df)),
+        Format::Parquet => ParquetReader::new(File::open(&input.path)?)
+            .with_n_rows(rows)
+            .finish(),
+        Format::Ipc => IpcReader::new(File::open(&input.path)?)
+            .with_n_rows(rows)
+            .finish(),
+        Format::Excel => unreachable!("workbooks are read by `excel::read`"),
+    };
+    read().map_err(|source| Error::Read {
+        path: input.path.clone(),
+        format,
+        source,
+    })
+}
+
+// Read a JSON array, which is parsed whole, so only the rows wanted are kept of it. Polars
+// panics on an empty array rather than read no rows, so that one is read here.
+fn read_json(
+    path: &Path,
+    schema_length: Option<usize>,
+    rows: Option<usize>,
+) -> PolarsResult<DataFrame> {
+    let json = fs::read(path)?;
+    let content = json.iter().filter(|byte| !byte.is_ascii_whitespace());
+    if content.eq(b"[]") {
+        return Ok(DataFrame::default());
+    }
+    let df = JsonReader::new(Cursor::new(json))
+        .with_json_format(JsonFormat::Json)
+        .infer_schema_len(schema_length)
+        .finish()?;
+    Ok(df.head(rows))
+}
+
+// Write the data to `path` encoded as the command line says
+pub fn write(
+    path: &Path,
+    input: &Input,
+    encoding: &Encoding,
+    df: &mut DataFrame,
+) -> Result<(), Error> {
+    let format = encoding.format(path, input);
+    if format == Format::Excel {
+        return Err(Error::Unwritable {
+            path: path.to_path_buf(),
+            format,
+        });
+    }
+    let create = || {
+        File::create(path).map_err(|source| Error::Create {
+            path: path.to_path_buf(),
+            source,
+        })
+    };
+    let written = match format {
+        Format::Csv => CsvWriter::new(&mut create()?)
+            .has_header(input.has_header() && !encoding.output_no_header)
+            .with_delimiter(encoding.output_delimiter.unwrap_or(input.delimiter))
+            .finish(df),
+        Format::Json if encoding.pretty => write_pretty(&mut create()?, df),
+        Format::Json => JsonWriter::new(&mut create()?)
+            .with_json_format(JsonFormat::Json)
+            .finish(df),
+        Format::Ndjson => JsonWriter::new(&mut create()?)
+            .with_json_format(JsonFormat::JsonLines)
+            .finish(df),
+        Format::Parquet => {
+            let compression = compression(format, encoding, Compression::parquet)?;
+            ParquetWriter::new(&mut create()?)
+                .with_compression(compression.unwrap_or(ParquetCompression::Snappy))
+                .with_row_group_size(encoding.row_group_size.map(|rows| rows as usize))
+                .finish(df)
+                .map(|_| ())
+        }
+        Format::Ipc => {
+            let compression = compression(format, encoding, Compression::ipc)?;
+            IpcWriter::new(&mut create()?)
+                .with_compression(compression.flatten())
+                .finish(df)
+        }
+        Format::Avro => {
+            let compression = compression(format, encoding, Compression::avro)?;
+            let mut df = avro_compatible(df).map_err(|source| Error::Write {
+                path: path.to_path_buf(),
+                format,
+                source,
+            })?;
+            AvroWriter::new(&mut create()?)
+                .with_compression(compression.flatten())
+                .finish(&mut df)
+        }
+        Format::Excel => unreachable!("workbooks are refused above"),
+    };
+    written.map_err(|source| Error::Write {
+        path: path.to_path_buf(),
+        format,
+        source,
+    })
+}
+
+// Return the compression the command line asks of the format, if any, refusing one the format
+// does not support
+fn compression<T>(
+    format: Format,
+    encoding: &Encoding,
+    of: fn(Compression) -> Option<T>,
+) -> Result<Option<T>, Error> {
+    let Some(compression) = encoding.compression else {
+        return Ok(None);
+    };
+    match of(compression) {
+        Some(compression) => Ok(Some(compression)),
+        None => Err(Error::Compression {
+            format,
+            compression,
+            supported: Compression::value_variants()
+                .iter()
+                .copied()
+                .filter(|other| *other != Compression::Uncompressed && of(*other).is_some())
+                .collect(),
+        }),
+    }
+}
+
+// Return the data with the types Avro has no logical type for converted to ones it has:
+// nanosecond timestamps become microsecond ones, and timestamps in a time zone are written as
+// local timestamps in UTC. Dates are written as they are, and decimals as doubles with a warning,
+// as Polars 0.29 writes a zero decimal as no bytes at all, which Avro readers refuse.
+fn avro_compatible(df: &DataFrame) -> PolarsResult<DataFrame> {
+    warn_decimals(df, "written to Avro as doubles");
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(|series| match series.dtype() {
+            DataType::Datetime(unit, zone) if *unit == TimeUnit::Nanoseconds || zone.is_some() => {
+                let unit = match unit {
+                    TimeUnit::Nanoseconds => TimeUnit::Microseconds,
+                    unit => *unit,
+                };
+                series.cast(&DataType::Datetime(unit, None))
+            }
+            DataType::Decimal(_, _) => series.cast(&DataType::Float64),
+            _ => Ok(series.clone()),
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    let mut df = DataFrame::new(columns)?;
+    // Polars writes the file header again before each chunk, so only one may be written
+    df.rechunk();
+    Ok(df)
+}
+
+// Write the data as an indented JSON array, which Polars only writes on one line, so reindent
+// what it writes; the columns keep their order, as serde_json preserves that of the keys
+fn write_pretty(file: &mut File, df: &mut DataFrame) -> PolarsResult<()> {
+    let mut compact = Vec::new();
+    JsonWriter::new(&mut compact)
+        .with_json_format(JsonFormat::Json)
+        .finish(df)?;
+    let rows: serde_json::Value = serde_json::from_slice(&compact).map_err(io::Error::from)?;
+    serde_json::to_writer_pretty(file, &rows).map_err(io::Error::from)?;
+    Ok(())
+}
+
+// Return the data read from Avro with its timestamps, which Avro keeps in UTC and Polars reads in
+// the `00:00` time zone, made local ones, as time zones are not built into this tool. Decimals
+// become doubles with a warning, which every format writes.
+fn from_avro(df: &DataFrame) -> PolarsResult<DataFrame> {
+    warn_decimals(df, "read from Avro as doubles");
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(|series| match series.dtype() {
+            DataType::Datetime(unit, Some(_)) => series.cast(&DataType::Datetime(*unit, None)),
+            DataType::Decimal(_, _) => series.cast(&DataType::Float64),
+            _ => Ok(series.clone()),
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    DataFrame::new(columns)
+}
+
+// Warn on stderr that the decimal columns of the data, if any, lose their exact values
+fn warn_decimals(df: &DataFrame, how: &str) {
+    let decimals: Vec<_> = df
+        .get_columns()
+        .iter()
+        .filter(|series| matches!(series.dtype(), DataType::Decimal(_, _)))
+        .map(|series| series.name())
+        .collect();
+    if !decimals.is_empty() {
+        eprintln!(
+            "warning: decimal columns {} {}, which may round their values",
+            decimals.join(", "),
+            how
+        );
+    }
+}
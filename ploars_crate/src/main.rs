@@ -1,14 +1,22 @@
-use polars::prelude::*;
-
-fn main() -> Result<(), PolarsError> {
-    let df = DataFrame::new(vec![
-        Series::new("column1", &[1, 2, 3]),
-        Series::new("column2", &["4", "5", "6"]),
-    ])?;
-    let converted_df = df
-        .lazy()
-        .select(&[col("column2").cast(DataType::Int32)])
-        .collect()?;
-    println!("{}", converted_df);
-    Ok(())
+mod cli;
+mod commands;
+mod error;
+mod excel;
+mod files;
+
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use crate::cli::Cli;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match commands::run(&cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
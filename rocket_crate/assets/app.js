@@ -0,0 +1,9 @@
+// Ask the API whether its dependencies are ready and show the answer
+fetch("/readyz")
+    .then((response) => response.json())
+    .then((body) => {
+        document.getElementById("status").textContent = body.status;
+    })
+    .catch(() => {
+        document.getElementById("status").textContent = "unreachable";
+    });
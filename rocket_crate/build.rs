@@ -0,0 +1,10 @@
+// Generate the gRPC server of proto/todos.proto with the protoc shipped as a crate, so building
+// needs no protoc installed, and rebuild when a migration embedded by `sqlx::migrate!` changes
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=migrations");
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/todos.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,9 @@
+-- One-time codes that stand in for an authenticator app that was lost, stored as SHA-256
+-- digests. A code is used once `used_at` is set; issuing a new set deletes the old one.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    username TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    used_at BIGINT NOT NULL DEFAULT 0,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (username, code_hash)
+);
@@ -0,0 +1,4 @@
+-- Webhook subscriptions and their deliveries belong to the tenant whose administrator registered
+-- them, and only receive that tenant's events; those registered before tenancy keep `default`
+ALTER TABLE webhook_subscriptions ADD COLUMN tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE webhook_deliveries ADD COLUMN tenant_id TEXT NOT NULL DEFAULT 'default';
@@ -0,0 +1,43 @@
+use std::time::Instant;
+
+use rocket::fairing::AdHoc;
+use rocket::request::Request;
+use tracing::info;
+
+use crate::telemetry;
+
+// Remember when a request arrived
+struct Started(Instant);
+
+// Return when the current request arrived
+pub fn started(req: &Request<'_>) -> Instant {
+    req.local_cache(|| Started(Instant::now())).0
+}
+
+// Log one event per request with its status, response size and latency, inside the request
+// span that carries its id, method, path, route and user
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Access Log", |rocket| async {
+        rocket
+            .attach(AdHoc::on_request("Access Log Start", |req, _| {
+                Box::pin(async move {
+                    started(req);
+                })
+            }))
+            .attach(AdHoc::on_response("Access Log", |req, res| {
+                Box::pin(async move {
+                    // Streamed bodies have no size until they are written out
+                    let bytes = res.body().preset_size();
+                    let elapsed_ms = started(req).elapsed().as_secs_f64() * 1000.0;
+                    telemetry::span(req).in_scope(|| {
+                        info!(
+                            status = res.status().code,
+                            bytes,
+                            elapsed_ms = format_args!("{:.3}", elapsed_ms),
+                            "request completed"
+                        )
+                    });
+                })
+            }))
+    })
+}
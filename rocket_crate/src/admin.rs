@@ -0,0 +1,256 @@
+use async_graphql::SimpleObject;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use validator::Validate;
+
+use crate::audit::{Action, Audit};
+use crate::auth::refresh::RefreshStore;
+use crate::auth::session::Sessions;
+use crate::auth::users::{self, User};
+use crate::auth::AdminUser;
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+use crate::metrics::Metrics;
+use crate::tenant::Tenant;
+use crate::validation::Validated;
+
+// Define a user as listed to administrators; the password hash is never returned
+#[derive(Debug, Serialize, ToSchema, SimpleObject)]
+#[graphql(name = "User")]
+pub struct UserSummary {
+    username: String,
+    roles: Vec<String>,
+    active: bool,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        UserSummary {
+            username: user.username,
+            roles: user.roles,
+            active: user.active,
+        }
+    }
+}
+
+// Define the JSON body replacing the roles of a user; an empty list takes every role away
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct RolesRequest {
+    #[validate(length(max = 32, message = "must list at most 32 roles"))]
+    #[schema(max_items = 32)]
+    roles: Vec<String>,
+}
+
+// Look up a user of the tenant, or answer 404
+async fn find_user(
+    db: &mut Connection<Db>,
+    tenant: &Tenant,
+    username: &str,
+) -> Result<User, ApiError> {
+    users::find(db, &tenant.id, username)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::not_found(format!("user `{}` does not exist", username)))
+}
+
+// Define a route handler that lists the users of the tenant
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The users of the tenant", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/users")]
+async fn list_users(
+    _admin: AdminUser,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+) -> Result<Json<Value>, ApiError> {
+    let users: Vec<UserSummary> = users::list(&mut db, &tenant.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(UserSummary::from)
+        .collect();
+    Ok(Json(json!({ "users": users })))
+}
+
+// Define a route handler that replaces the roles of a user of the tenant. The change applies
+// to tokens issued from then on, including those issued by refreshing.
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer" = [])),
+    request_body = RolesRequest,
+    responses(
+        (status = 200, description = "The user with its new roles", body = Value),
+        (status = 400, description = "Invalid role name", body = ApiError),
+        (status = 404, description = "No such user", body = ApiError),
+        (status = 422, description = "Too many roles", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[put("/admin/users/<username>/roles", format = "json", data = "<request>")]
+async fn set_roles(
+    admin: AdminUser,
+    username: &str,
+    request: Validated<Json<RolesRequest>>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+) -> Result<Json<Value>, ApiError> {
+    let RolesRequest { mut roles } = request.into_inner();
+    // Roles are stored comma-separated
+    if roles
+        .iter()
+        .any(|role| role.is_empty() || role.contains(|c: char| c == ',' || c.is_whitespace()))
+    {
+        return Err(ApiError::bad_request(
+            "role names must not be empty or hold commas or spaces",
+        ));
+    }
+    roles.sort();
+    roles.dedup();
+
+    let user = find_user(&mut db, &tenant, username).await?;
+    users::set_roles(&mut db, &tenant.id, username, &roles)
+        .await
+        .map_err(internal_error)?;
+    let detail = format!(
+        "roles of `{}` changed from [{}] to [{}]",
+        username,
+        user.roles.join(", "),
+        roles.join(", ")
+    );
+    audit
+        .record(Action::RolesChanged, Some(&admin.identity.subject), detail)
+        .await;
+    Ok(Json(json!({ "username": username, "roles": roles })))
+}
+
+// Define a route handler that deactivates a user of the tenant: it can no longer sign in, its
+// refresh tokens and sessions end at once, and access tokens already issued expire on their own
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user is deactivated, with how many refresh tokens and sessions ended", body = Value),
+        (status = 404, description = "No such user", body = ApiError),
+        (status = 409, description = "Administrators cannot deactivate themselves", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/admin/users/<username>/deactivate")]
+async fn deactivate(
+    admin: AdminUser,
+    username: &str,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+    refresh: &State<RefreshStore>,
+    sessions: Sessions<'_>,
+) -> Result<Json<Value>, ApiError> {
+    // Administrators cannot lock themselves out
+    if username == admin.identity.subject {
+        return Err(ApiError::new(
+            Status::Conflict,
+            "administrators cannot deactivate their own account",
+        ));
+    }
+    find_user(&mut db, &tenant, username).await?;
+    users::set_active(&mut db, &tenant.id, username, false)
+        .await
+        .map_err(internal_error)?;
+    let refresh_tokens = refresh.revoke_subject(username);
+    let ended = sessions.end_all(username).await;
+    let detail = format!(
+        "deactivated `{}`, revoking {} refresh tokens and {} sessions",
+        username, refresh_tokens, ended
+    );
+    audit
+        .record(
+            Action::UserDeactivated,
+            Some(&admin.identity.subject),
+            detail,
+        )
+        .await;
+    Ok(Json(json!({
+        "username": username,
+        "active": false,
+        "refresh_tokens_revoked": refresh_tokens,
+        "sessions_ended": ended,
+    })))
+}
+
+// Define a route handler that lets a deactivated user of the tenant sign in again
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user can sign in again", body = Value),
+        (status = 404, description = "No such user", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/admin/users/<username>/activate")]
+async fn activate(
+    admin: AdminUser,
+    username: &str,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+) -> Result<Json<Value>, ApiError> {
+    find_user(&mut db, &tenant, username).await?;
+    users::set_active(&mut db, &tenant.id, username, true)
+        .await
+        .map_err(internal_error)?;
+    let detail = format!("activated `{}`", username);
+    audit
+        .record(Action::UserActivated, Some(&admin.identity.subject), detail)
+        .await;
+    Ok(Json(json!({ "username": username, "active": true })))
+}
+
+// Define a route handler that returns the current metrics as JSON, for dashboards that do not
+// scrape `/metrics`
+#[utoipa::path(
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every metric by name, with its samples", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/metrics")]
+fn metrics(_admin: AdminUser, metrics: &State<Metrics>) -> Json<Value> {
+    Json(json!({ "metrics": metrics.snapshot() }))
+}
+
+// Define the part of the OpenAPI document describing the admin API
+#[derive(OpenApi)]
+#[openapi(paths(list_users, set_roles, deactivate, activate, metrics))]
+pub struct ApiDoc;
+
+// Mount the admin API. Every route requires the admin role, signed in with a second factor,
+// from the `admin_network`; the audit log, feature flags, maintenance mode, scheduler,
+// configuration reload and shutdown mount their own admin routes next to these.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Admin API", |rocket| async {
+        rocket.mount(
+            "/",
+            routes![list_users, set_roles, deactivate, activate, metrics],
+        )
+    })
+}
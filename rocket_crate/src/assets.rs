@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::fs::NamedFile;
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use serde::Deserialize;
+use tracing::warn;
+
+// Define the `assets` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetsConfig {
+    // Directory served under `/assets`, such as a built frontend
+    pub dir: PathBuf,
+    // Seconds browsers may reuse an asset before checking whether it changed
+    #[serde(default)]
+    pub max_age: u64,
+}
+
+// Define a guard reading the `If-Modified-Since` header of conditional requests
+pub struct IfModifiedSince(Option<SystemTime>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfModifiedSince {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let since = req
+            .headers()
+            .get_one("If-Modified-Since")
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+        request::Outcome::Success(IfModifiedSince(since))
+    }
+}
+
+// Define the response for an asset: the file itself, or 304 when the client's copy is current
+pub enum Asset {
+    File(NamedFile, Option<SystemTime>, u64),
+    NotModified(SystemTime, u64),
+}
+
+// Return the headers that let clients cache an asset and revalidate it later
+fn cache_headers(modified: Option<SystemTime>, max_age: u64) -> Vec<Header<'static>> {
+    let mut headers = vec![Header::new(
+        "Cache-Control",
+        format!("public, max-age={}", max_age),
+    )];
+    if let Some(modified) = modified {
+        headers.push(Header::new(
+            "Last-Modified",
+            httpdate::fmt_http_date(modified),
+        ));
+    }
+    headers
+}
+
+impl<'r> Responder<'r, 'static> for Asset {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (mut response, modified, max_age) = match self {
+            Asset::File(file, modified, max_age) => (
+                Response::build_from(file.respond_to(req)?),
+                modified,
+                max_age,
+            ),
+            Asset::NotModified(modified, max_age) => {
+                let mut response = Response::build();
+                response.status(Status::NotModified);
+                (response, Some(modified), max_age)
+            }
+        };
+        for header in cache_headers(modified, max_age) {
+            response.header(header);
+        }
+        response.ok()
+    }
+}
+
+// Drop the sub-second part, which HTTP dates cannot express, so timestamps compare equal
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+// Define a route handler that serves files from the assets directory, and the `index.html` of
+// directories. Rocket refuses paths with `..` or hidden segments, so requests cannot escape it.
+#[get("/assets/<path..>")]
+async fn asset(
+    path: PathBuf,
+    since: IfModifiedSince,
+    config: &State<AssetsConfig>,
+) -> Option<Asset> {
+    let mut path = config.dir.join(path);
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    let file = NamedFile::open(path).await.ok()?;
+    let modified = file
+        .file()
+        .metadata()
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(whole_seconds);
+
+    match (modified, since.0) {
+        (Some(modified), Some(since)) if modified <= since => {
+            Some(Asset::NotModified(modified, config.max_age))
+        }
+        _ => Some(Asset::File(file, modified, config.max_age)),
+    }
+}
+
+// Load the assets settings and mount the route serving them
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Assets", |rocket| async {
+        match rocket.figment().extract_inner::<AssetsConfig>("assets") {
+            Ok(config) => rocket.manage(config).mount("/", routes![asset]),
+            Err(err) if err.missing() => rocket,
+            Err(err) => {
+                warn!("assets not served, invalid `assets` configuration: {}", err);
+                rocket
+            }
+        }
+    })
+}
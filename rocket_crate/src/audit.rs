@@ -0,0 +1,295 @@
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{json, Json, Value};
+use rocket_db_pools::sqlx::{self, Row};
+use rocket_db_pools::{Connection, Database};
+use serde::Serialize;
+use tracing::error;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::auth::{self, AdminUser};
+use crate::db::{internal_error, Db, DbRow};
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+
+// Entries returned by `GET /admin/audit` unless the caller asks for fewer
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+// Define the security-relevant actions the audit log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Login,
+    LoginFailed,
+    // A request was refused because its credentials were missing, invalid or insufficient
+    AuthFailed,
+    TokenRefreshed,
+    PasswordReset,
+    RolesChanged,
+    UserDeactivated,
+    UserActivated,
+    // A new set of recovery codes replaced the old one, or was issued along with two-factor
+    // sign-in
+    RecoveryCodesIssued,
+    // A recovery code stood in for the authenticator app at sign-in
+    RecoveryCodeUsed,
+    // An administrator changed something through an admin route
+    AdminChange,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Login => "login",
+            Action::LoginFailed => "login_failed",
+            Action::AuthFailed => "auth_failed",
+            Action::TokenRefreshed => "token_refreshed",
+            Action::PasswordReset => "password_reset",
+            Action::RolesChanged => "roles_changed",
+            Action::UserDeactivated => "user_deactivated",
+            Action::UserActivated => "user_activated",
+            Action::RecoveryCodesIssued => "recovery_codes_issued",
+            Action::RecoveryCodeUsed => "recovery_code_used",
+            Action::AdminChange => "admin_action",
+        }
+    }
+}
+
+// Define an entry of the `audit_log` table. Entries are only ever inserted, never changed.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub action: String,
+    // Who acted, when known: the username, API key client or subject of the token
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub request_id: String,
+    pub tenant: String,
+    // What happened, such as the method and path of an admin action
+    pub detail: String,
+    pub created_at: i64,
+}
+
+impl AuditEntry {
+    fn from_row(row: &DbRow) -> Result<AuditEntry, sqlx::Error> {
+        // Unknown actors and addresses are stored as '', which the Any driver can decode
+        let actor: String = row.try_get("actor")?;
+        let ip: String = row.try_get("ip")?;
+        Ok(AuditEntry {
+            id: row.try_get("id")?,
+            action: row.try_get("action")?,
+            actor: Some(actor).filter(|actor| !actor.is_empty()),
+            ip: Some(ip).filter(|ip| !ip.is_empty()),
+            request_id: row.try_get("request_id")?,
+            tenant: row.try_get("tenant_id")?,
+            detail: row.try_get("detail")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Define a guard collecting what the audit log records about the current request: the client
+// address, request id and tenant
+pub struct Audit<'r> {
+    db: Option<&'r Db>,
+    pub ip: Option<IpAddr>,
+    request_id: &'r str,
+    tenant: String,
+}
+
+impl<'r> Audit<'r> {
+    pub async fn of(req: &'r Request<'_>) -> Audit<'r> {
+        let tenant = match req.guard::<Tenant>().await {
+            request::Outcome::Success(tenant) => tenant.id,
+            _ => String::new(),
+        };
+        Audit {
+            db: Db::fetch(req.rocket()),
+            ip: auth::client_address(req),
+            request_id: RequestId::of(req),
+            tenant,
+        }
+    }
+
+    // Append an entry; a failure to write it is logged rather than failing the request
+    pub async fn record(&self, action: Action, actor: Option<&str>, detail: impl Into<String>) {
+        let Some(db) = self.db else {
+            return;
+        };
+        let detail = detail.into();
+        let inserted = sqlx::query(
+            "INSERT INTO audit_log (action, actor, ip, request_id, tenant_id, detail, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(action.as_str())
+        .bind(actor.unwrap_or_default())
+        .bind(self.ip.map(|ip| ip.to_string()).unwrap_or_default())
+        .bind(self.request_id)
+        .bind(&self.tenant)
+        .bind(&detail)
+        .bind(now())
+        .execute(&**db)
+        .await;
+        if let Err(err) = inserted {
+            error!(
+                action = action.as_str(),
+                "failed to write audit entry: {}", err
+            );
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Audit<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Audit::of(req).await)
+    }
+}
+
+// Remember the administrator acting in the current request, so the changes they make are
+// recorded once the response is ready
+struct AdminActor(Option<String>);
+
+pub fn admin_acted(req: &Request<'_>, subject: &str) {
+    req.local_cache(|| AdminActor(Some(subject.to_string())));
+}
+
+// Define the filters of `GET /admin/audit`; `before` pages back from the oldest entry returned
+#[derive(Debug, FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditFilter {
+    action: Option<String>,
+    actor: Option<String>,
+    ip: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+// Define a route handler that lists audit entries, newest first, matching every given filter
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "list_audit_entries",
+    security(("bearer" = [])),
+    params(AuditFilter),
+    responses(
+        (status = 200, description = "Matching entries, newest first, and the id to pass as `before` for the next page", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/audit?<filter..>")]
+async fn list(
+    _admin: AdminUser,
+    mut db: Connection<Db>,
+    filter: AuditFilter,
+) -> Result<Json<Value>, ApiError> {
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    // Only the filters given become conditions, so no parameter is ever bound as NULL
+    let mut conditions = Vec::new();
+    let mut texts = Vec::new();
+    let mut numbers = Vec::new();
+    for (column, value) in [
+        ("action", filter.action),
+        ("actor", filter.actor),
+        ("ip", filter.ip),
+    ] {
+        if let Some(value) = value {
+            texts.push(value);
+            conditions.push(format!("{} = ${}", column, texts.len()));
+        }
+    }
+    for (condition, value) in [
+        ("created_at >=", filter.since),
+        ("created_at <=", filter.until),
+        ("id <", filter.before),
+    ] {
+        if let Some(value) = value {
+            numbers.push(value);
+            conditions.push(format!("{} ${}", condition, texts.len() + numbers.len()));
+        }
+    }
+    let clause = match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    };
+    let sql = format!(
+        "SELECT id, action, actor, ip, request_id, tenant_id, detail, created_at FROM audit_log
+         {clause} ORDER BY id DESC LIMIT {limit}"
+    );
+
+    let mut query = sqlx::query(&sql);
+    for text in &texts {
+        query = query.bind(text);
+    }
+    for number in numbers {
+        query = query.bind(number);
+    }
+    let rows = query.fetch_all(&mut **db).await.map_err(internal_error)?;
+    let entries = rows
+        .iter()
+        .map(AuditEntry::from_row)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(internal_error)?;
+    let before = match entries.len() as i64 == limit {
+        true => entries.last().map(|entry| entry.id),
+        false => None,
+    };
+    Ok(Json(json!({ "entries": entries, "before": before })))
+}
+
+// Define the part of the OpenAPI document describing the audit log
+#[derive(OpenApi)]
+#[openapi(paths(list))]
+pub struct ApiDoc;
+
+// Mount the audit log route and record refused credentials and the changes administrators
+// make once each response is ready
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Audit Log", |rocket| async {
+        rocket
+            .mount("/", routes![list])
+            .attach(AdHoc::on_response("Audit Log", |req, res| {
+                Box::pin(async move {
+                    let status = res.status();
+                    let target = format!("{} {}", req.method(), req.uri());
+                    if status == Status::Unauthorized || status == Status::Forbidden {
+                        if let Some(reason) = auth::failure_reason(req, &Locale::server(req)) {
+                            let detail = format!("{} -> {}: {}", target, status.code, reason);
+                            Audit::of(req)
+                                .await
+                                .record(Action::AuthFailed, None, detail)
+                                .await;
+                        }
+                    }
+                    let read_only =
+                        matches!(req.method(), Method::Get | Method::Head | Method::Options);
+                    let admin = &req.local_cache(|| AdminActor(None)).0;
+                    if let (false, Some(admin)) = (read_only, admin) {
+                        let detail = format!("{} -> {}", target, status.code);
+                        Audit::of(req)
+                            .await
+                            .record(Action::AdminChange, Some(admin), detail)
+                            .await;
+                    }
+                })
+            }))
+    })
+}
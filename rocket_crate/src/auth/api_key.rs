@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::identity::{admit, fail, Identity};
+use super::AuthError;
+
+// Define an API key as listed in the `api_keys` array of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyEntry {
+    pub name: String,
+    // Hex-encoded SHA-256 digest of the key, so the raw key never sits in config
+    pub key_sha256: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Tenant the key acts in; every tenant if omitted
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+// Define the store of accepted API keys, keyed by digest
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: HashMap<String, ApiKeyEntry>,
+}
+
+impl ApiKeyStore {
+    pub fn new(entries: Vec<ApiKeyEntry>) -> Self {
+        let keys = entries
+            .into_iter()
+            .map(|entry| (entry.key_sha256.to_ascii_lowercase(), entry))
+            .collect();
+        ApiKeyStore { keys }
+    }
+
+    pub fn find(&self, key: &str) -> Option<&ApiKeyEntry> {
+        let digest = format!("{:x}", Sha256::digest(key.as_bytes()));
+        self.keys.get(&digest)
+    }
+}
+
+// Define a guard for machine clients authenticating with the "X-Api-Key" header
+#[derive(Debug)]
+pub struct ApiKey {
+    pub identity: Identity,
+}
+
+impl ApiKey {
+    pub const HEADER: &'static str = "X-Api-Key";
+
+    // Resolve the key to an identity, or `None` when the header is absent
+    pub fn resolve(req: &Request<'_>) -> Option<Result<Identity, AuthError>> {
+        let key = req.headers().get_one(Self::HEADER)?;
+        let store = req.rocket().state::<ApiKeyStore>().expect("api key store");
+        Some(
+            store
+                .find(key.trim())
+                .map(|entry| Identity {
+                    subject: format!("apikey:{}", entry.name),
+                    roles: entry.roles.clone(),
+                    expires_at: None,
+                    // A key is a single secret, so it never stands in for the second factor
+                    // that admin routes and `Verified` ask of a person
+                    second_factor: false,
+                    tenant: entry.tenant.clone(),
+                })
+                .ok_or(AuthError::InvalidApiKey),
+        )
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match ApiKey::resolve(req) {
+            Some(Ok(identity)) => match admit(req, &identity).await {
+                Ok(()) => request::Outcome::Success(ApiKey { identity }),
+                Err((status, err)) => fail(req, status, err),
+            },
+            Some(Err(err)) => fail(req, Status::Unauthorized, err),
+            None => fail(req, Status::Unauthorized, AuthError::MissingApiKey),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use rocket::http::Status;
+use rocket::mtls::Certificate;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+
+use super::identity::{admit, fail, Identity};
+use super::AuthError;
+
+// Define a client certificate as listed in the `client_certs` array of Rocket.toml. Rocket has
+// already checked the certificate against the CAs of `tls.mutual`, so entries only have to say
+// which subjects are known and what they may do.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientCertEntry {
+    // Common name (CN) of the certificate subject, such as "deploy-bot.internal"
+    pub common_name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Tenant the service acts in; every tenant if omitted
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+// Define the store of known certificate subjects, keyed by common name
+#[derive(Debug, Default)]
+pub struct ClientCertStore {
+    subjects: HashMap<String, ClientCertEntry>,
+}
+
+impl ClientCertStore {
+    pub fn new(entries: Vec<ClientCertEntry>) -> Self {
+        let subjects = entries
+            .into_iter()
+            .map(|entry| (entry.common_name.clone(), entry))
+            .collect();
+        ClientCertStore { subjects }
+    }
+}
+
+// Define a guard for internal clients authenticating with a TLS client certificate
+#[derive(Debug)]
+pub struct ClientCert {
+    pub identity: Identity,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientCert {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let cert = match req.guard::<Certificate<'r>>().await {
+            request::Outcome::Success(cert) => cert,
+            _ => return fail(req, Status::Unauthorized, AuthError::MissingClientCert),
+        };
+        let store = req
+            .rocket()
+            .state::<ClientCertStore>()
+            .expect("client cert store");
+        let Some(entry) = cert
+            .subject()
+            .common_name()
+            .and_then(|name| store.subjects.get(name))
+        else {
+            return fail(req, Status::Forbidden, AuthError::UnknownClientCert);
+        };
+
+        let identity = Identity {
+            subject: format!("cert:{}", entry.common_name),
+            roles: entry.roles.clone(),
+            expires_at: u64::try_from(cert.validity().not_after.timestamp()).ok(),
+            // Holding the private key is itself a factor beyond anything typed in
+            second_factor: true,
+            tenant: entry.tenant.clone(),
+        };
+        match admit(req, &identity).await {
+            Ok(()) => request::Outcome::Success(ClientCert { identity }),
+            Err((status, err)) => fail(req, status, err),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use subtle::ConstantTimeEq;
+
+use crate::errors::ApiError;
+
+// Cookie holding the synchronizer token, which forms echo back in their `csrf_token` field
+pub const COOKIE: &str = "csrf";
+
+// Return this browser's CSRF token for embedding in forms, issuing one on first use. The token
+// sits in a private cookie, so another site can make the browser send it but never read it.
+pub fn token(cookies: &CookieJar<'_>) -> String {
+    if let Some(cookie) = cookies.get_private(COOKIE) {
+        return cookie.value().to_string();
+    }
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let cookie = Cookie::build((COOKIE, token.clone()))
+        .path("/")
+        .same_site(SameSite::Lax);
+    cookies.add_private(cookie);
+    token
+}
+
+// Check the token a state-changing form submitted against the browser's cookie, failing with
+// 403 Forbidden when it is missing or differs
+pub fn verify(cookies: &CookieJar<'_>, submitted: &str) -> Result<(), ApiError> {
+    match cookies.get_private(COOKIE) {
+        // Compared in constant time, so the token cannot be guessed byte by byte
+        Some(cookie)
+            if !submitted.is_empty()
+                && bool::from(cookie.value().as_bytes().ct_eq(submitted.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(ApiError::new(
+            Status::Forbidden,
+            "missing or invalid CSRF token; reload the page and try again",
+        )),
+    }
+}
@@ -0,0 +1,224 @@
+use std::marker::PhantomData;
+
+use async_graphql::SimpleObject;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+
+use super::api_key::ApiKey;
+use super::introspection::Introspection;
+use super::jwks::Jwks;
+use super::jwt::JwtConfig;
+use super::network::AdminNetwork;
+use super::{AuthError, AuthFailure};
+use crate::tenant::Tenant;
+use crate::{audit, quota, telemetry};
+
+// Define the authenticated caller of a request, as recovered from its bearer token or API key
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Identity {
+    pub subject: String,
+    pub roles: Vec<String>,
+    // API keys do not expire, so only bearer tokens carry an expiry
+    pub expires_at: Option<u64>,
+    // Whether the caller proved a second factor, such as a TOTP code, when signing in
+    pub second_factor: bool,
+    // Tenant the credentials were issued in, the only one they act in; credentials configured
+    // without one, such as an operator's API key, act in any
+    pub tenant: Option<String>,
+}
+
+impl Identity {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|granted| granted == role)
+    }
+}
+
+// Return whether an `amr` claim (RFC 8176) lists a second authentication factor
+pub(super) fn second_factor<'a>(amr: impl IntoIterator<Item = &'a str>) -> bool {
+    amr.into_iter()
+        .any(|method| matches!(method, "otp" | "mfa" | "hwk" | "sms"))
+}
+
+// Implement the FromRequest trait to extract and validate the JWT, falling back to an API key,
+// then charge the request against the caller's quota
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Identity {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let result = match req.headers().get_one("Authorization") {
+            None => ApiKey::resolve(req).unwrap_or(Err(AuthError::MissingHeader)),
+            Some(header) => match header.strip_prefix("Bearer ") {
+                None => Err(AuthError::NotBearer),
+                Some(token) => bearer(req, token.trim()).await,
+            },
+        };
+
+        let identity = match result {
+            Ok(identity) => identity,
+            Err(err @ AuthError::ProviderUnavailable) => {
+                return fail(req, Status::ServiceUnavailable, err)
+            }
+            Err(err) => return fail(req, Status::Unauthorized, err),
+        };
+
+        match admit(req, &identity).await {
+            Ok(()) => request::Outcome::Success(identity),
+            Err((status, err)) => fail(req, status, err),
+        }
+    }
+}
+
+// Verify a bearer token: opaque tokens with the introspection endpoint when one is configured,
+// JWTs with the keys of the external issuer they name or else with this server's own secret
+async fn bearer(req: &Request<'_>, token: &str) -> Result<Identity, AuthError> {
+    if let Some(introspection) = req.rocket().state::<Introspection>() {
+        if jsonwebtoken::decode_header(token).is_err() {
+            return introspection.verify(token).await;
+        }
+    }
+    let jwks = req.rocket().state::<Jwks>().expect("jwks cache");
+    if let Some(issuer) = jwks.issuer_of(token) {
+        return jwks.verify(issuer, token).await;
+    }
+    let config = req.rocket().state::<JwtConfig>().expect("jwt config");
+    config.verify(token).map(|claims| Identity {
+        subject: claims.sub,
+        roles: claims.roles,
+        expires_at: Some(claims.exp),
+        second_factor: second_factor(claims.amr.iter().map(String::as_str)),
+        tenant: Some(claims.tenant),
+    })
+}
+
+// Define a role that a route can demand from its caller
+pub trait RoleName {
+    const NAME: &'static str;
+    // Whether callers must also have signed in with a second factor to act in this role
+    const SECOND_FACTOR: bool = false;
+    // Whether callers must connect from the `admin_network` to act in this role
+    const ADMIN_NETWORK: bool = false;
+}
+
+// Define the roles known to this application
+pub struct Reader;
+pub struct Admin;
+
+impl RoleName for Reader {
+    const NAME: &'static str = "reader";
+}
+
+impl RoleName for Admin {
+    const NAME: &'static str = "admin";
+    const SECOND_FACTOR: bool = true;
+    const ADMIN_NETWORK: bool = true;
+}
+
+// Define a guard that only succeeds for callers holding the role `R`
+#[derive(Debug)]
+pub struct Requires<R: RoleName> {
+    pub identity: Identity,
+    role: PhantomData<R>,
+}
+
+// Define a guard for routes reserved to administrators
+pub type AdminUser = Requires<Admin>;
+
+#[rocket::async_trait]
+impl<'r, R: RoleName> FromRequest<'r> for Requires<R> {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        // Checked first, so credentials sent from elsewhere are not even looked at
+        if R::ADMIN_NETWORK {
+            if let request::Outcome::Error(failure) = req.guard::<AdminNetwork>().await {
+                return request::Outcome::Error(failure);
+            }
+        }
+        let identity = match Identity::from_request(req).await {
+            request::Outcome::Success(identity) => identity,
+            request::Outcome::Error(failure) => return request::Outcome::Error(failure),
+            request::Outcome::Forward(status) => return request::Outcome::Forward(status),
+        };
+
+        if !identity.has_role(R::NAME) {
+            fail(req, Status::Forbidden, AuthError::MissingRole(R::NAME))
+        } else if R::SECOND_FACTOR && !identity.second_factor {
+            fail(
+                req,
+                Status::Forbidden,
+                AuthError::SecondFactorRequired(R::NAME),
+            )
+        } else {
+            // Whatever an administrator changes is audited once the response is ready
+            if R::ADMIN_NETWORK {
+                audit::admin_acted(req, &identity.subject);
+            }
+            request::Outcome::Success(Requires {
+                identity,
+                role: PhantomData,
+            })
+        }
+    }
+}
+
+// Define a guard for callers who signed in with a second factor, a one-time or a recovery code,
+// whatever roles they hold
+#[derive(Debug)]
+pub struct Verified {
+    pub identity: Identity,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Verified {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match Identity::from_request(req).await {
+            request::Outcome::Success(identity) if identity.second_factor => {
+                request::Outcome::Success(Verified { identity })
+            }
+            request::Outcome::Success(_) => {
+                fail(req, Status::Forbidden, AuthError::SecondFactorMissing)
+            }
+            request::Outcome::Error(failure) => request::Outcome::Error(failure),
+            request::Outcome::Forward(status) => request::Outcome::Forward(status),
+        }
+    }
+}
+
+// Refuse credentials issued in another tenant than the one the request acts in, then record the
+// caller on the request span and count the request against its quota, failing once the quota is
+// used up
+pub(super) async fn admit(
+    req: &Request<'_>,
+    identity: &Identity,
+) -> Result<(), (Status, AuthError)> {
+    if let Some(issued) = &identity.tenant {
+        match req.guard::<Tenant>().await {
+            request::Outcome::Success(tenant) if tenant.id == *issued => {}
+            request::Outcome::Success(_) => {
+                return Err((Status::Forbidden, AuthError::WrongTenant))
+            }
+            // The 400 catcher says why the tenant was refused
+            _ => return Err((Status::BadRequest, AuthError::WrongTenant)),
+        }
+    }
+    telemetry::record_user(req, &identity.subject);
+    match quota::charge(req, &identity.subject).await {
+        Some(quota) if quota.exceeded => {
+            Err((Status::TooManyRequests, AuthError::RateLimited(quota.reset)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Record the failure for the catchers and fail the guard with the given status
+pub(super) fn fail<T>(
+    req: &Request<'_>,
+    status: Status,
+    err: AuthError,
+) -> request::Outcome<T, AuthError> {
+    req.local_cache(|| AuthFailure(Some(err.clone())));
+    request::Outcome::Error((status, err))
+}
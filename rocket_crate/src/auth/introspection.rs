@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rocket::serde::json::Value;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::breaker::CircuitBreakers;
+
+use super::identity::{second_factor, Identity};
+use super::jwks::amr;
+use super::AuthError;
+
+// Cached answers beyond this count make the next insert drop the expired ones first
+const MAX_CACHED: usize = 10_000;
+
+// Define the `introspection` table of Rocket.toml, for opaque tokens checked with the identity
+// provider as RFC 7662 describes
+#[derive(Debug, Clone, Deserialize)]
+pub struct IntrospectionConfig {
+    pub url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    // Claim listing the caller's roles, either an array or a space-separated string like `scope`
+    #[serde(default = "roles_claim")]
+    pub roles_claim: String,
+    // Seconds an answer is reused, never past the token's own expiry
+    #[serde(default = "cache_ttl")]
+    pub cache_ttl: u64,
+    // Tenant the provider's tokens act in; every tenant if omitted
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+fn roles_claim() -> String {
+    "scope".into()
+}
+
+fn cache_ttl() -> u64 {
+    60
+}
+
+// Define the introspection client with its cache of answers, keyed by token digest so raw
+// tokens are not kept in memory, managed as state. The provider's circuit breaker makes
+// requests fail fast while it is down.
+pub struct Introspection {
+    config: IntrospectionConfig,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, (Option<Identity>, Instant)>>,
+    breakers: CircuitBreakers,
+    host: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl Introspection {
+    pub fn new(config: IntrospectionConfig, breakers: CircuitBreakers) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("HTTP client");
+        Introspection {
+            host: CircuitBreakers::host_of(&config.url),
+            config,
+            client,
+            cache: Mutex::new(HashMap::new()),
+            breakers,
+        }
+    }
+
+    // Resolve an opaque token to the identity it was issued to
+    pub async fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+        let digest = format!("{:x}", Sha256::digest(token.as_bytes()));
+        if let Some(answer) = self.cached(&digest) {
+            return answer.ok_or(AuthError::InvalidToken("token is not active"));
+        }
+
+        if self.breakers.admit(&self.host).is_err() {
+            return Err(AuthError::ProviderUnavailable);
+        }
+        let response = match self.introspect(token).await {
+            Ok(response) => {
+                self.breakers.succeeded(&self.host);
+                response
+            }
+            Err(err) => {
+                warn!("token introspection failed: {}", err);
+                self.breakers.failed(&self.host);
+                return Err(AuthError::ProviderUnavailable);
+            }
+        };
+
+        let identity = self.identity(&response);
+        let mut ttl = self.config.cache_ttl;
+        if let Some(identity) = &identity {
+            ttl = ttl.min(
+                identity
+                    .expires_at
+                    .unwrap_or(u64::MAX)
+                    .saturating_sub(now()),
+            );
+        }
+        self.remember(digest, identity.clone(), Duration::from_secs(ttl));
+        identity.ok_or(AuthError::InvalidToken("token is not active"))
+    }
+
+    async fn introspect(&self, token: &str) -> Result<Value, reqwest::Error> {
+        self.client
+            .post(&self.config.url)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[("token", token), ("token_type_hint", "access_token")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    // Turn an introspection response into an identity, or `None` for inactive tokens
+    fn identity(&self, response: &Value) -> Option<Identity> {
+        if response["active"].as_bool() != Some(true) {
+            return None;
+        }
+        let expires_at = response["exp"].as_u64();
+        if expires_at.is_some_and(|exp| exp <= now()) {
+            return None;
+        }
+        let subject = response["sub"]
+            .as_str()
+            .or_else(|| response["username"].as_str())?
+            .to_string();
+        let roles = match &response[self.config.roles_claim.as_str()] {
+            Value::String(roles) => roles.split_whitespace().map(str::to_string).collect(),
+            Value::Array(roles) => roles
+                .iter()
+                .filter_map(|role| role.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(Identity {
+            subject,
+            roles,
+            expires_at,
+            second_factor: second_factor(amr(response)),
+            tenant: self.config.tenant.clone(),
+        })
+    }
+
+    fn cached(&self, digest: &str) -> Option<Option<Identity>> {
+        let cache = self.cache.lock().expect("introspection cache lock");
+        cache
+            .get(digest)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(identity, _)| identity.clone())
+    }
+
+    fn remember(&self, digest: String, identity: Option<Identity>, ttl: Duration) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().expect("introspection cache lock");
+        if cache.len() >= MAX_CACHED {
+            cache.retain(|_, (_, expires)| *expires > now);
+            if cache.len() >= MAX_CACHED {
+                return;
+            }
+        }
+        cache.insert(digest, (identity, now + ttl));
+    }
+}
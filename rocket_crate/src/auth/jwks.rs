@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rocket::serde::json::Value;
+use rocket::tokio::sync::Mutex;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::identity::{second_factor, Identity};
+use super::AuthError;
+
+// Unknown key ids trigger at most one JWKS fetch per issuer in this interval, so tokens with
+// made-up ids cannot make this server hammer the identity provider
+const MIN_REFRESH: Duration = Duration::from_secs(30);
+
+// Define an external identity provider whose tokens are accepted, as listed under `jwt.external`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalIssuer {
+    // Expected `iss` claim, such as "https://example.eu.auth0.com/"
+    pub issuer: String,
+    pub audience: String,
+    pub jwks_url: String,
+    // Dot-separated path of the claim listing the caller's roles, such as "realm_access.roles"
+    #[serde(default = "roles_claim")]
+    pub roles_claim: String,
+    // Seconds fetched keys are trusted before the key set is downloaded again
+    #[serde(default = "cache_ttl")]
+    pub cache_ttl: u64,
+    // Tenant the issuer's tokens act in; every tenant if omitted
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+fn roles_claim() -> String {
+    "roles".into()
+}
+
+fn cache_ttl() -> u64 {
+    3600
+}
+
+// Define the keys of one issuer as last fetched from its JWKS endpoint
+#[derive(Default)]
+struct KeySet {
+    keys: HashMap<String, (DecodingKey, Option<Algorithm>)>,
+    fetched_at: Option<Instant>,
+}
+
+// Define the cache of signing keys published by the external issuers, managed as state
+pub struct Jwks {
+    issuers: Vec<(ExternalIssuer, Mutex<KeySet>)>,
+    client: reqwest::Client,
+}
+
+impl Jwks {
+    pub fn new(issuers: Vec<ExternalIssuer>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("HTTP client");
+        Jwks {
+            issuers: issuers
+                .into_iter()
+                .map(|issuer| (issuer, Mutex::new(KeySet::default())))
+                .collect(),
+            client,
+        }
+    }
+
+    // Return the index of the external issuer named by the token's unverified `iss` claim, if any
+    pub fn issuer_of(&self, token: &str) -> Option<usize> {
+        let mut peek = Validation::default();
+        peek.insecure_disable_signature_validation();
+        peek.validate_exp = false;
+        peek.validate_aud = false;
+        peek.required_spec_claims.clear();
+        let claims = decode::<Value>(token, &DecodingKey::from_secret(&[]), &peek).ok()?;
+        let iss = claims.claims.get("iss")?.as_str()?;
+        self.issuers
+            .iter()
+            .position(|(issuer, _)| issuer.issuer == iss)
+    }
+
+    // Verify a token of the external issuer at `index` against its published keys
+    pub async fn verify(&self, index: usize, token: &str) -> Result<Identity, AuthError> {
+        let (issuer, keys) = &self.issuers[index];
+        let header =
+            decode_header(token).map_err(|_| AuthError::InvalidToken("token is malformed"))?;
+        // Shared-secret algorithms would let anyone holding the public key forge tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AuthError::InvalidToken("token algorithm is not accepted"));
+        }
+        let kid = header.kid.ok_or(AuthError::InvalidToken(
+            "token does not name its signing key",
+        ))?;
+
+        let (key, alg) = self.key(issuer, keys, &kid).await?;
+        if alg.is_some_and(|alg| alg != header.alg) {
+            return Err(AuthError::InvalidToken("token algorithm is not accepted"));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&issuer.issuer]);
+        validation.set_audience(&[&issuer.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<Value>(token, &key, &validation)
+            .map_err(|err| AuthError::InvalidToken(super::jwt::describe(err.kind())))?
+            .claims;
+
+        let subject = claims["sub"].as_str().unwrap_or_default().to_string();
+        let roles = issuer
+            .roles_claim
+            .split('.')
+            .try_fold(&claims, |value, part| value.get(part))
+            .and_then(Value::as_array)
+            .map(|roles| {
+                roles
+                    .iter()
+                    .filter_map(|role| role.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Identity {
+            subject,
+            roles,
+            expires_at: claims["exp"].as_u64(),
+            second_factor: second_factor(amr(&claims)),
+            tenant: issuer.tenant.clone(),
+        })
+    }
+
+    // Return the key with id `kid`, downloading the issuer's key set when the key is unknown or
+    // the cached set is older than its ttl
+    async fn key(
+        &self,
+        issuer: &ExternalIssuer,
+        keys: &Mutex<KeySet>,
+        kid: &str,
+    ) -> Result<(DecodingKey, Option<Algorithm>), AuthError> {
+        // Holding the lock while fetching makes concurrent misses share one download
+        let mut keys = keys.lock().await;
+        let age = keys.fetched_at.map(|fetched_at| fetched_at.elapsed());
+        let stale = age.is_none_or(|age| age > Duration::from_secs(issuer.cache_ttl));
+        let missing = !keys.keys.contains_key(kid);
+        let may_refresh = age.is_none_or(|age| age > MIN_REFRESH);
+
+        if (stale || missing) && may_refresh {
+            match self.fetch(issuer).await {
+                Ok(fetched) => {
+                    keys.keys = fetched;
+                    keys.fetched_at = Some(Instant::now());
+                }
+                // Keep the previous keys, so a provider outage does not reject every token
+                Err(err) => {
+                    warn!(issuer = %issuer.issuer, "failed to fetch JWKS: {}", err);
+                    if keys.fetched_at.is_none() {
+                        return Err(AuthError::InvalidToken(
+                            "token signing keys are unavailable",
+                        ));
+                    }
+                }
+            }
+        }
+
+        keys.keys.get(kid).cloned().ok_or(AuthError::InvalidToken(
+            "token signing key is not recognized",
+        ))
+    }
+
+    async fn fetch(
+        &self,
+        issuer: &ExternalIssuer,
+    ) -> Result<HashMap<String, (DecodingKey, Option<Algorithm>)>, String> {
+        let set: JwkSet = self
+            .client
+            .get(&issuer.jwks_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?
+            .json()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        // Keys without an id cannot be matched to tokens, and unsupported ones are skipped
+        Ok(set
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                let key = DecodingKey::from_jwk(jwk).ok()?;
+                let alg = jwk
+                    .common
+                    .key_algorithm
+                    .and_then(|alg| alg.to_string().parse().ok());
+                Some((kid, (key, alg)))
+            })
+            .collect())
+    }
+}
+
+// Return the authentication methods listed in a token's `amr` claim
+pub(super) fn amr(claims: &Value) -> impl Iterator<Item = &str> {
+    claims["amr"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
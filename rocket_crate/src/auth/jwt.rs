@@ -0,0 +1,109 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use super::jwks::ExternalIssuer;
+use super::AuthError;
+
+// Define the JWT settings read from the `jwt` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    pub secret: String,
+    pub issuer: String,
+    pub audience: String,
+    // Allowed clock skew in seconds when checking `exp`
+    #[serde(default)]
+    pub leeway: u64,
+    // Lifetime in seconds of the access tokens issued by `/login` and `/token/refresh`
+    pub ttl: u64,
+    // Lifetime in seconds of the refresh tokens handed out alongside them
+    pub refresh_ttl: u64,
+    // Identity providers whose tokens are accepted too, verified with their published keys
+    #[serde(default)]
+    pub external: Vec<ExternalIssuer>,
+}
+
+// Define the claims carried by the tokens this server accepts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // How the subject signed in (RFC 8176), e.g. ["pwd", "otp"] after a TOTP code
+    #[serde(default)]
+    pub amr: Vec<String>,
+    // Tenant the subject signed in through, the only one the token is accepted in
+    pub tenant: String,
+}
+
+impl JwtConfig {
+    // Sign a token for the given subject and roles of `tenant` that expires after the configured
+    // ttl, recording whether a second factor was checked at sign-in
+    pub fn issue(
+        &self,
+        tenant: &str,
+        subject: &str,
+        roles: &[String],
+        second_factor: bool,
+    ) -> String {
+        let now = now();
+        let mut amr = vec!["pwd".to_string()];
+        if second_factor {
+            amr.push("otp".into());
+        }
+        let claims = Claims {
+            roles: roles.to_vec(),
+            amr,
+            tenant: tenant.to_string(),
+            sub: subject.to_string(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now,
+            exp: now + self.ttl,
+        };
+        let key = EncodingKey::from_secret(self.secret.as_bytes());
+        encode(&Header::new(Algorithm::HS256), &claims, &key).expect("HS256 signing")
+    }
+
+    // Verify the token signature and validate the exp, iss and aud claims
+    pub fn verify(&self, token: &str) -> Result<Claims, AuthError> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = self.leeway;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let key = DecodingKey::from_secret(self.secret.as_bytes());
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|err| AuthError::InvalidToken(describe(err.kind())))
+    }
+}
+
+// Return the current unix time in seconds
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Turn a jsonwebtoken error into a reason that is safe to show to clients
+pub(super) fn describe(kind: &ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::ExpiredSignature => "token has expired",
+        ErrorKind::ImmatureSignature => "token is not valid yet",
+        ErrorKind::InvalidIssuer => "token issuer is not accepted",
+        ErrorKind::InvalidAudience => "token audience is not accepted",
+        ErrorKind::InvalidSignature => "token signature is invalid",
+        ErrorKind::InvalidAlgorithm => "token algorithm is not accepted",
+        ErrorKind::MissingRequiredClaim(_) => "token is missing a required claim",
+        _ => "token is malformed",
+    }
+}
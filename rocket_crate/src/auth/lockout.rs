@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::http::{Header, Status};
+use rocket::serde::json::{json, Value};
+use serde::Deserialize;
+
+use crate::errors::ApiError;
+
+// Accounts and addresses beyond this count make the next failure drop the stale ones first
+const MAX_TRACKED: usize = 10_000;
+
+// Define the `lockout` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct LockoutConfig {
+    // Failed sign-ins in a row after which an account is locked
+    pub max_failures: u32,
+    // Failed sign-ins from one address, across accounts, after which the address is locked
+    pub ip_max_failures: u32,
+    // Seconds the first lock lasts; every further lock doubles it, up to `max_lock`
+    pub base_lock: u64,
+    pub max_lock: u64,
+    // Seconds without failures after which an account or address starts with a clean slate
+    pub forget_after: u64,
+}
+
+// Define the failures recorded against one account or address
+#[derive(Debug)]
+struct Strikes {
+    failures: u32,
+    // Locks so far, which decide how long the next one lasts
+    locks: u32,
+    locked_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+impl Strikes {
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    // Return whether nothing happened for `forget_after`, counted from the end of the last lock
+    fn stale(&self, now: Instant, forget_after: Duration) -> bool {
+        let since = self
+            .locked_until
+            .map_or(self.last_failure, |until| until.max(self.last_failure));
+        now.saturating_duration_since(since) >= forget_after
+    }
+}
+
+// Define the failed sign-in bookkeeping per account and per client address, managed as state.
+// The address is the one `client_address` trusts, so a client cannot pick it with a header.
+pub struct Lockout {
+    config: LockoutConfig,
+    accounts: Mutex<HashMap<String, Strikes>>,
+    addresses: Mutex<HashMap<IpAddr, Strikes>>,
+}
+
+// Define what a lock applies to, as reported to the client
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Account,
+    Address,
+}
+
+impl Scope {
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Account => "account",
+            Scope::Address => "ip",
+        }
+    }
+}
+
+// Return the 423 answer for a locked account or address
+fn locked(scope: Scope, remaining: Duration) -> ApiError {
+    // Round up so clients never come back a moment too early
+    let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    let message = match scope {
+        Scope::Account => "too many failed sign-ins; the account is temporarily locked",
+        Scope::Address => "too many failed sign-ins from this address; try again later",
+    };
+    ApiError::new(Status::Locked, message)
+        .with_details(json!({
+            "lockout": { "locked": true, "scope": scope.name(), "retry_after": retry_after },
+        }))
+        .with_header(Header::new("Retry-After", retry_after.to_string()))
+}
+
+impl Lockout {
+    pub fn new(config: LockoutConfig) -> Self {
+        Lockout {
+            config,
+            accounts: Mutex::new(HashMap::new()),
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Refuse a sign-in attempt while its account or address is locked, before the password is
+    // even looked at
+    pub fn check(&self, username: &str, ip: Option<IpAddr>) -> Result<(), ApiError> {
+        let now = Instant::now();
+        let accounts = self.accounts.lock().expect("lockout lock");
+        if let Some(remaining) = accounts.get(username).and_then(|s| s.remaining(now)) {
+            return Err(locked(Scope::Account, remaining));
+        }
+        drop(accounts);
+        let addresses = self.addresses.lock().expect("lockout lock");
+        if let Some(remaining) = ip
+            .and_then(|ip| addresses.get(&ip))
+            .and_then(|s| s.remaining(now))
+        {
+            return Err(locked(Scope::Address, remaining));
+        }
+        Ok(())
+    }
+
+    // Record a failed sign-in and return `err` with the lockout state added, or the 423 answer
+    // when this failure locked the account or address
+    pub fn failed(&self, username: &str, ip: Option<IpAddr>, err: ApiError) -> ApiError {
+        let now = Instant::now();
+        let mut accounts = self.accounts.lock().expect("lockout lock");
+        let (remaining, account_lock) = self.strike(
+            &mut accounts,
+            username.to_string(),
+            self.config.max_failures,
+            now,
+        );
+        drop(accounts);
+        let address_lock = ip.and_then(|ip| {
+            let mut addresses = self.addresses.lock().expect("lockout lock");
+            self.strike(&mut addresses, ip, self.config.ip_max_failures, now)
+                .1
+        });
+        if let Some(lock) = account_lock {
+            return locked(Scope::Account, lock);
+        }
+        if let Some(lock) = address_lock {
+            return locked(Scope::Address, lock);
+        }
+
+        let mut details = match err.details.clone() {
+            Some(Value::Object(details)) => details,
+            _ => Default::default(),
+        };
+        details.insert(
+            "lockout".into(),
+            json!({ "locked": false, "attempts_remaining": remaining }),
+        );
+        err.with_details(Value::Object(details))
+    }
+
+    // Clear the failures of an account once its owner signs in
+    pub fn succeeded(&self, username: &str) {
+        self.accounts.lock().expect("lockout lock").remove(username);
+    }
+
+    // Count one failure against `key`, returning the attempts left before it is locked and the
+    // length of the lock this failure started, if any
+    fn strike<K: Eq + Hash>(
+        &self,
+        table: &mut HashMap<K, Strikes>,
+        key: K,
+        max_failures: u32,
+        now: Instant,
+    ) -> (u32, Option<Duration>) {
+        let forget_after = Duration::from_secs(self.config.forget_after);
+        if table.len() >= MAX_TRACKED {
+            table.retain(|_, strikes| !strikes.stale(now, forget_after));
+        }
+        let strikes = table.entry(key).or_insert(Strikes {
+            failures: 0,
+            locks: 0,
+            locked_until: None,
+            last_failure: now,
+        });
+        if strikes.stale(now, forget_after) {
+            strikes.failures = 0;
+            strikes.locks = 0;
+        }
+        strikes.failures += 1;
+        strikes.last_failure = now;
+
+        if strikes.failures < max_failures.max(1) {
+            return (max_failures - strikes.failures, None);
+        }
+        let seconds = self
+            .config
+            .base_lock
+            .saturating_mul(1u64 << strikes.locks.min(32))
+            .min(self.config.max_lock);
+        let lock = Duration::from_secs(seconds);
+        strikes.failures = 0;
+        strikes.locks += 1;
+        strikes.locked_until = Some(now + lock);
+        (0, Some(lock))
+    }
+}
@@ -0,0 +1,346 @@
+mod api_key;
+mod client_cert;
+pub mod csrf;
+mod identity;
+mod introspection;
+mod jwks;
+pub mod jwt;
+mod lockout;
+mod network;
+pub mod oauth;
+pub mod password;
+pub mod recovery;
+pub mod refresh;
+pub mod reset;
+pub mod routes;
+pub mod session;
+pub mod totp;
+pub mod users;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use rocket::fairing::{self, AdHoc};
+use rocket::request::Request;
+use rocket::{Build, Rocket};
+
+pub use self::api_key::ApiKey;
+use self::api_key::{ApiKeyEntry, ApiKeyStore};
+pub use self::client_cert::ClientCert;
+use self::client_cert::{ClientCertEntry, ClientCertStore};
+pub use self::identity::{Admin, AdminUser, Identity, Reader, Requires, RoleName, Verified};
+use self::introspection::{Introspection, IntrospectionConfig};
+use self::jwks::Jwks;
+use self::jwt::JwtConfig;
+use self::lockout::{Lockout, LockoutConfig};
+use self::network::NetworkPolicy;
+pub use self::network::{client_address, AdminNetwork};
+use self::oauth::{OAuthProviders, ProviderConfig};
+use self::refresh::RefreshStore;
+use self::reset::PasswordResetConfig;
+pub use self::session::SessionUser;
+use self::session::{SessionConfig, SessionStore};
+use self::totp::{Totp, TotpConfig};
+use self::users::User;
+use crate::breaker::CircuitBreakers;
+use crate::db::Db;
+use crate::i18n::Locale;
+use crate::scheduler::Scheduler;
+use rocket_db_pools::Database;
+use tracing::error;
+use utoipa::OpenApi;
+
+// Define the part of the OpenAPI document describing sign-in with tokens, two-factor enrollment,
+// recovery codes and password resets
+#[derive(OpenApi)]
+#[openapi(paths(
+    routes::login,
+    routes::refresh_token,
+    totp::enroll,
+    totp::confirm,
+    recovery::regenerate,
+    reset::forgot,
+    reset::reset
+))]
+pub struct ApiDoc;
+
+// Define the reasons a request can fail bearer authentication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    MissingHeader,
+    NotBearer,
+    InvalidToken(&'static str),
+    MissingRole(&'static str),
+    // The role is held, but acting in it requires having signed in with a second factor
+    SecondFactorRequired(&'static str),
+    // The route requires having signed in with a second factor, whatever the roles
+    SecondFactorMissing,
+    MissingApiKey,
+    InvalidApiKey,
+    MissingClientCert,
+    UnknownClientCert,
+    MissingSession,
+    // The caller used up its quota and may retry after this many seconds
+    RateLimited(u64),
+    // The identity provider that vouches for opaque tokens cannot be reached
+    ProviderUnavailable,
+    // The client address, when known, is outside the networks allowed to administer
+    NetworkDenied(Option<IpAddr>),
+    // The credentials were issued in another tenant than the one the request names
+    WrongTenant,
+}
+
+impl AuthError {
+    // Return why the request was refused, in `locale`
+    pub fn reason(&self, locale: &Locale) -> String {
+        match self {
+            AuthError::MissingHeader => locale.text("auth.missing_header"),
+            AuthError::NotBearer => locale.text("auth.not_bearer"),
+            AuthError::InvalidToken(reason) => {
+                locale.format("auth.invalid_token", &[("reason", reason)])
+            }
+            AuthError::MissingRole(role) => locale.format("auth.missing_role", &[("role", role)]),
+            AuthError::SecondFactorRequired(role) => {
+                locale.format("auth.second_factor_required", &[("role", role)])
+            }
+            AuthError::SecondFactorMissing => locale.text("auth.second_factor_missing"),
+            AuthError::MissingApiKey => locale.text("auth.missing_api_key"),
+            AuthError::InvalidApiKey => locale.text("auth.invalid_api_key"),
+            AuthError::MissingClientCert => locale.text("auth.missing_client_cert"),
+            AuthError::UnknownClientCert => locale.text("auth.unknown_client_cert"),
+            AuthError::MissingSession => locale.text("auth.missing_session"),
+            AuthError::RateLimited(retry_after) => {
+                locale.format("auth.rate_limited", &[("retry_after", retry_after)])
+            }
+            AuthError::NetworkDenied(Some(ip)) => {
+                locale.format("auth.network_denied", &[("ip", ip)])
+            }
+            AuthError::NetworkDenied(None) => locale.text("auth.network_denied_unknown"),
+            AuthError::ProviderUnavailable => locale.text("auth.provider_unavailable"),
+            AuthError::WrongTenant => locale.text("auth.wrong_tenant"),
+        }
+    }
+}
+
+// Remember the failure of the current request so the 401, 403 and 429 catchers can report it
+struct AuthFailure(Option<AuthError>);
+
+// Return why authentication failed for this request in `locale`, if a guard recorded a reason
+pub fn failure_reason(req: &Request, locale: &Locale) -> Option<String> {
+    req.local_cache(|| AuthFailure(None))
+        .0
+        .as_ref()
+        .map(|err| err.reason(locale))
+}
+
+// Load the JWT settings, refresh token store, session, lockout, password reset and TOTP
+// settings, OAuth providers, token introspection, client certificates and API keys from the
+// configuration, manage them as state, and seed the user table
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Auth", |rocket| async {
+        rocket
+            .attach(AdHoc::try_on_ignite("JWT Config", |rocket| async {
+                match rocket.figment().extract_inner::<JwtConfig>("jwt") {
+                    Ok(config) => {
+                        let refresh = RefreshStore::new(Duration::from_secs(config.refresh_ttl));
+                        if let Some(scheduler) = rocket.state::<Scheduler>() {
+                            scheduler.register(
+                                "refresh_tokens",
+                                "*/15 * * * *",
+                                refresh.purge_task(),
+                            );
+                        }
+                        let jwks = Jwks::new(config.external.clone());
+                        Ok(rocket.manage(config).manage(refresh).manage(jwks))
+                    }
+                    Err(err) => {
+                        error!("invalid `jwt` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite("Session Config", |rocket| async {
+                match rocket.figment().extract_inner::<SessionConfig>("session") {
+                    Ok(config) => match SessionStore::new(config) {
+                        Ok(store) => Ok(rocket.manage(store)),
+                        Err(err) => {
+                            error!("invalid `session` configuration: {}", err);
+                            Err(rocket)
+                        }
+                    },
+                    Err(err) => {
+                        error!("invalid `session` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite("Lockout Config", |rocket| async {
+                match rocket.figment().extract_inner::<LockoutConfig>("lockout") {
+                    Ok(config) => Ok(rocket.manage(Lockout::new(config))),
+                    Err(err) => {
+                        error!("invalid `lockout` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite(
+                "Password Reset Config",
+                |rocket| async {
+                    let config = rocket
+                        .figment()
+                        .extract_inner::<PasswordResetConfig>("password_reset");
+                    match config {
+                        Ok(config) => Ok(rocket.manage(config)),
+                        Err(err) => {
+                            error!("invalid `password_reset` configuration: {}", err);
+                            Err(rocket)
+                        }
+                    }
+                },
+            ))
+            .attach(AdHoc::try_on_ignite("TOTP Config", |rocket| async {
+                match rocket.figment().extract_inner::<TotpConfig>("totp") {
+                    Ok(config) => Ok(rocket.manage(Totp::new(config))),
+                    Err(err) => {
+                        error!("invalid `totp` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite("OAuth Providers", |rocket| async {
+                let providers = rocket
+                    .figment()
+                    .extract_inner::<HashMap<String, ProviderConfig>>("oauth");
+                match providers {
+                    Ok(providers) => Ok(rocket.manage(OAuthProviders::new(providers))),
+                    // Signing in through other sites is optional
+                    Err(err) if err.missing() => {
+                        Ok(rocket.manage(OAuthProviders::new(HashMap::new())))
+                    }
+                    Err(err) => {
+                        error!("invalid `oauth` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite(
+                "Token Introspection",
+                |rocket| async {
+                    let config = rocket
+                        .figment()
+                        .extract_inner::<IntrospectionConfig>("introspection");
+                    let breakers = rocket.state::<CircuitBreakers>().cloned();
+                    match (config, breakers) {
+                        (Ok(config), Some(breakers)) => {
+                            Ok(rocket.manage(Introspection::new(config, breakers)))
+                        }
+                        (Ok(_), None) => {
+                            error!("token introspection needs the circuit breakers stage");
+                            Err(rocket)
+                        }
+                        // Without it only self-contained JWTs are accepted
+                        (Err(err), _) if err.missing() => Ok(rocket),
+                        (Err(err), _) => {
+                            error!("invalid `introspection` configuration: {}", err);
+                            Err(rocket)
+                        }
+                    }
+                },
+            ))
+            .attach(AdHoc::try_on_ignite("User Seed", seed_users))
+            .attach(AdHoc::try_on_ignite(
+                "Client Certificates",
+                |rocket| async {
+                    let entries = rocket
+                        .figment()
+                        .extract_inner::<Vec<ClientCertEntry>>("client_certs");
+                    match entries {
+                        Ok(entries) => Ok(rocket.manage(ClientCertStore::new(entries))),
+                        // Without mutual TLS no client presents a certificate anyway
+                        Err(err) if err.missing() => Ok(rocket.manage(ClientCertStore::default())),
+                        Err(err) => {
+                            error!("invalid `client_certs` configuration: {}", err);
+                            Err(rocket)
+                        }
+                    }
+                },
+            ))
+            .attach(AdHoc::try_on_ignite("Admin Network", |rocket| async {
+                let policy = rocket
+                    .figment()
+                    .extract_inner::<NetworkPolicy>("admin_network");
+                match policy {
+                    Ok(policy) => Ok(rocket.manage(policy)),
+                    // Without a policy administrators may connect from anywhere
+                    Err(err) if err.missing() => Ok(rocket.manage(NetworkPolicy::default())),
+                    Err(err) => {
+                        error!("invalid `admin_network` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::try_on_ignite("API Keys", |rocket| async {
+                let keys = rocket
+                    .figment()
+                    .extract_inner::<Vec<ApiKeyEntry>>("api_keys");
+                match keys {
+                    Ok(keys) => Ok(rocket.manage(ApiKeyStore::new(keys))),
+                    // Machine clients are optional, so a missing table means no keys
+                    Err(err) if err.missing() => Ok(rocket.manage(ApiKeyStore::default())),
+                    Err(err) => {
+                        error!("invalid `api_keys` configuration: {}", err);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .mount(
+                "/",
+                routes![
+                    routes::login,
+                    routes::session_login,
+                    routes::logout,
+                    routes::refresh_token,
+                    reset::forgot,
+                    reset::reset,
+                    totp::enroll,
+                    totp::confirm,
+                    recovery::regenerate,
+                    oauth::authorize,
+                    oauth::callback
+                ],
+            )
+    })
+}
+
+// Insert the demo accounts listed under `users` in Rocket.toml if they do not exist yet
+async fn seed_users(rocket: Rocket<Build>) -> fairing::Result {
+    let users = match rocket.figment().extract_inner::<Vec<User>>("users") {
+        Ok(users) => users,
+        Err(err) if err.missing() => return Ok(rocket),
+        Err(err) => {
+            error!("invalid `users` configuration: {}", err);
+            return Err(rocket);
+        }
+    };
+
+    let Some(db) = Db::fetch(&rocket) else {
+        return Err(rocket);
+    };
+    let mut conn = match db.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("failed to seed users: {}", err);
+            return Err(rocket);
+        }
+    };
+
+    for user in &users {
+        if let Err(err) = users::insert_if_missing(&mut conn, user).await {
+            error!("failed to seed user `{}`: {}", user.username, err);
+            return Err(rocket);
+        }
+    }
+    drop(conn);
+    Ok(rocket)
+}
@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+
+use super::identity::fail;
+use super::AuthError;
+use crate::batch;
+
+// Define the `admin_network` table of Rocket.toml: the addresses administrators may connect from
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NetworkPolicy {
+    // Networks allowed in; when empty every address not denied is
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    // Networks refused even when an allowed network contains them
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+    // Take the client address from X-Forwarded-For, but only for requests relayed by one of
+    // `trusted_proxies`; anyone else could put any address in the header
+    #[serde(default)]
+    pub forwarded_for: bool,
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+fn contains(networks: &[IpNet], ip: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(&ip))
+}
+
+impl NetworkPolicy {
+    // Return the address of the client: the connecting peer, or behind trusted proxies the
+    // last address in X-Forwarded-For that was not added by one of them. Batch sub-requests
+    // come from loopback, so the peer of the batch request stands in for theirs.
+    fn client_address(&self, req: &Request<'_>) -> Option<IpAddr> {
+        let peer = batch::relayed_peer(req).or_else(|| req.remote().map(|remote| remote.ip()))?;
+        if !self.forwarded_for || !contains(&self.trusted_proxies, peer) {
+            return Some(peer);
+        }
+        let forwarded: Vec<IpAddr> = req
+            .headers()
+            .get("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        let client = forwarded
+            .iter()
+            .rev()
+            .find(|hop| !contains(&self.trusted_proxies, **hop))
+            .or(forwarded.first())
+            .copied();
+        Some(client.unwrap_or(peer))
+    }
+
+    fn permits(&self, ip: IpAddr) -> bool {
+        !contains(&self.deny, ip) && (self.allow.is_empty() || contains(&self.allow, ip))
+    }
+}
+
+// Return the address of the client that rate limits, lockouts and the audit log key on, trusting
+// X-Forwarded-For as the admin network does, and never a header any client can set by itself
+pub fn client_address(req: &Request<'_>) -> Option<IpAddr> {
+    match req.rocket().state::<NetworkPolicy>() {
+        Some(policy) => policy.client_address(req),
+        None => NetworkPolicy::default().client_address(req),
+    }
+}
+
+// Define a guard that only succeeds for clients connecting from the administrator networks.
+// Routes reserved to the admin role check it through `Requires<Admin>`.
+#[derive(Debug)]
+pub struct AdminNetwork;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminNetwork {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let policy = req
+            .rocket()
+            .state::<NetworkPolicy>()
+            .expect("admin network policy");
+        match policy.client_address(req) {
+            Some(ip) if policy.permits(ip) => request::Outcome::Success(AdminNetwork),
+            ip => fail(req, Status::Forbidden, AuthError::NetworkDenied(ip)),
+        }
+    }
+}
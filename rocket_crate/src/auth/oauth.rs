@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::header::{ACCEPT, USER_AGENT};
+use reqwest::Url;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::Value;
+use rocket::{time, State};
+use rocket_db_pools::sqlx;
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+
+use super::session::Sessions;
+use super::users::{self, User};
+use crate::audit::{Action, Audit};
+use crate::db::{internal_error, Db, DbConn};
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::tenant::Tenant;
+
+// Cookie remembering the `state` sent to the provider until it redirects back
+const STATE_COOKIE: &str = "oauth_state";
+
+// Minutes a visitor has to finish signing in at the provider
+const STATE_TTL_MINUTES: i64 = 10;
+
+// Define one OAuth2 provider as listed under the `oauth` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    // This server's callback, as registered with the provider
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scope: String,
+    // Profile fields holding the account's stable id and the name it is shown with
+    #[serde(default = "id_field")]
+    pub id_field: String,
+    #[serde(default = "name_field")]
+    pub name_field: String,
+}
+
+fn id_field() -> String {
+    "id".into()
+}
+
+fn name_field() -> String {
+    "login".into()
+}
+
+// Define the configured providers, keyed by the name used in `/auth/<provider>`, and the HTTP
+// client that talks to them
+pub struct OAuthProviders {
+    providers: HashMap<String, ProviderConfig>,
+    client: reqwest::Client,
+}
+
+impl OAuthProviders {
+    pub fn new(providers: HashMap<String, ProviderConfig>) -> Self {
+        OAuthProviders {
+            providers,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Return the names of the configured providers, for the sign-in page
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.providers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn get(&self, provider: &str) -> Result<&ProviderConfig, ApiError> {
+        self.providers
+            .get(provider)
+            .ok_or_else(|| ApiError::not_found(format!("no OAuth provider named `{}`", provider)))
+    }
+}
+
+// Define the part of the token endpoint's answer this server uses
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+// Return the error reported when a provider cannot be reached or answers unexpectedly
+fn provider_error(provider: &str, cause: impl ToString) -> ApiError {
+    ApiError::new(
+        Status::BadGateway,
+        format!("signing in with {} failed; try again later", provider),
+    )
+    .with_cause(cause)
+}
+
+// Read a profile field as text; GitHub ids are numbers, Google ids are strings
+fn profile_field(profile: &Value, field: &str) -> Option<String> {
+    match profile.get(field)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// Define a route handler that sends the browser to the provider's consent page, remembering a
+// random `state` so the callback can tell it started here
+#[get("/auth/<provider>")]
+pub fn authorize(
+    provider: &str,
+    providers: &State<OAuthProviders>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, ApiError> {
+    let config = providers.get(provider)?;
+    let state = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let url = Url::parse_with_params(
+        &config.authorize_url,
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("scope", config.scope.as_str()),
+            ("state", state.as_str()),
+        ],
+    )
+    .map_err(|err| ApiError::internal().with_cause(format!("authorize_url: {}", err)))?;
+
+    let cookie = Cookie::build((STATE_COOKIE, format!("{}:{}", provider, state)))
+        .path("/auth")
+        // Lax so the cookie comes back on the provider's redirect
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(STATE_TTL_MINUTES));
+    cookies.add_private(cookie);
+    Ok(Redirect::to(url.to_string()))
+}
+
+// Define a route handler the provider redirects back to: it exchanges the code for an access
+// token, fetches the profile, links it to a local user and starts a session
+#[allow(clippy::too_many_arguments)]
+#[get("/auth/<provider>/callback?<code>&<state>")]
+pub async fn callback(
+    provider: &str,
+    code: Option<&str>,
+    state: Option<&str>,
+    providers: &State<OAuthProviders>,
+    cookies: &CookieJar<'_>,
+    sessions: Sessions<'_>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+    locale: Locale,
+) -> Result<Flash<Redirect>, ApiError> {
+    let config = providers.get(provider)?;
+    let expected = cookies.get_private(STATE_COOKIE);
+    cookies.remove_private(Cookie::build(STATE_COOKIE).path("/auth"));
+
+    // A missing code means the visitor declined, or the provider reported an error
+    let (Some(code), Some(state)) = (code, state) else {
+        return Ok(Flash::error(
+            Redirect::to("/login"),
+            locale.format("flash.sign_in_cancelled", &[("provider", &provider)]),
+        ));
+    };
+    if expected.map(|cookie| cookie.value().to_string()) != Some(format!("{}:{}", provider, state))
+    {
+        return Err(ApiError::bad_request(
+            "the sign-in attempt expired or did not start here; try again",
+        ));
+    }
+
+    let token: TokenResponse = providers
+        .client
+        .post(&config.token_url)
+        .header(ACCEPT, "application/json")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| provider_error(provider, err))?
+        .json()
+        .await
+        .map_err(|err| provider_error(provider, err))?;
+
+    let profile: Value = providers
+        .client
+        .get(&config.userinfo_url)
+        .bearer_auth(&token.access_token)
+        .header(ACCEPT, "application/json")
+        // GitHub rejects API requests without a user agent
+        .header(USER_AGENT, "rocket_crate")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| provider_error(provider, err))?
+        .json()
+        .await
+        .map_err(|err| provider_error(provider, err))?;
+
+    let (Some(account_id), Some(name)) = (
+        profile_field(&profile, &config.id_field),
+        profile_field(&profile, &config.name_field),
+    ) else {
+        return Err(provider_error(
+            provider,
+            format!(
+                "profile lacks `{}` or `{}`",
+                config.id_field, config.name_field
+            ),
+        ));
+    };
+
+    let user = link(&mut db, &tenant.id, provider, &account_id, &name)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::Forbidden,
+                format!("this {} account belongs to another tenant", provider),
+            )
+        })?;
+    if !user.active {
+        return Err(ApiError::new(
+            Status::Forbidden,
+            format!("account `{}` is deactivated", user.username),
+        ));
+    }
+    // Whatever factors the provider checked are not known here
+    sessions.start(cookies, &user, false).await;
+    let detail = format!("signed in with {} account {}", provider, account_id);
+    audit
+        .record(Action::Login, Some(&user.username), detail)
+        .await;
+    let message = locale.format(
+        "flash.signed_in_with",
+        &[("user", &user.username), ("provider", &provider)],
+    );
+    Ok(Flash::success(Redirect::to("/pages"), message))
+}
+
+// Return the local user linked to the provider account, creating both on first sign-in. New
+// users are named `<provider>:<name>`, or `<provider>:<id>` when another account held that name
+// before; they get the reader role and have no usable password. Returns nothing when the
+// provider account is linked to a user of another tenant.
+async fn link(
+    conn: &mut DbConn,
+    tenant: &str,
+    provider: &str,
+    account_id: &str,
+    name: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    let linked: Option<String> = sqlx::query_scalar(
+        "SELECT username FROM oauth_accounts WHERE provider = $1 AND account_id = $2",
+    )
+    .bind(provider)
+    .bind(account_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let username = match linked {
+        Some(username) => username,
+        None => {
+            let mut username = format!("{}:{}", provider, name);
+            if users::find(conn, tenant, &username).await?.is_some() {
+                username = format!("{}:{}", provider, account_id);
+            }
+            let user = User {
+                tenant: tenant.to_string(),
+                username,
+                password_hash: String::new(),
+                roles: vec!["reader".into()],
+                active: true,
+            };
+            users::insert_if_missing(conn, &user).await?;
+            sqlx::query(
+                "INSERT INTO oauth_accounts (provider, account_id, username) VALUES ($1, $2, $3)",
+            )
+            .bind(provider)
+            .bind(account_id)
+            .bind(&user.username)
+            .execute(&mut *conn)
+            .await?;
+            user.username
+        }
+    };
+
+    users::find(conn, tenant, &username).await
+}
@@ -0,0 +1,26 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+// Hash of a random throwaway password, verified against when a username is unknown
+pub const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$BGefhY7H3IDtlZlKa8yEfQ$FKictBU7RH7DQrCqAzu8waBnO5UEj+3ESl+bBjdOM64";
+
+// Check a password against a stored PHC string; unparsable hashes never match
+pub fn verify(password: &str, phc: &str) -> bool {
+    PasswordHash::new(phc)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// Hash a password into a PHC string with a fresh random salt
+pub fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt: [u8; 16] = rand::random();
+    let salt = SaltString::encode_b64(&salt)?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
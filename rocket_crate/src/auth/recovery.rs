@@ -0,0 +1,155 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::seq::SliceRandom;
+use rocket::serde::json::Json;
+use rocket_db_pools::sqlx::{self, Connection as _};
+use rocket_db_pools::Connection;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use super::identity::Verified;
+use super::totp;
+use crate::audit::{Action, Audit};
+use crate::db::{internal_error, Db, DbConn};
+use crate::errors::ApiError;
+
+// Codes issued in a set; each signs in once
+const COUNT: usize = 10;
+
+// Characters a code is made of, lowercase letters and digits without look-alikes such as 0 and o,
+// 1 and l; ten of them carry about 49 bits, too many to guess before the lockout kicks in
+const ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+const LENGTH: usize = 10;
+
+// Define the JSON body returned whenever a new set of recovery codes is issued. They are only
+// ever shown here, as only their digests are stored.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Drop the separators and case people type codes with, so `ABCDE-FGHJK` matches `abcdefghjk`
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// Return whether a second-factor code is a recovery code rather than one from an authenticator
+pub fn is_recovery_code(code: &str) -> bool {
+    let code = normalize(code);
+    code.len() == LENGTH && code.bytes().all(|c| ALPHABET.contains(&c))
+}
+
+// Only digests are stored, so a leaked table cannot be used to sign in
+fn digest(code: &str) -> String {
+    format!("{:x}", Sha256::digest(normalize(code).as_bytes()))
+}
+
+// Generate a code, split in two halves so it can be read out and typed: `abcde-fghjk`
+fn generate() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: Vec<char> = (0..LENGTH)
+        .map(|_| char::from(*ALPHABET.choose(&mut rng).expect("non-empty alphabet")))
+        .collect();
+    let (first, second) = chars.split_at(LENGTH / 2);
+    format!(
+        "{}-{}",
+        first.iter().collect::<String>(),
+        second.iter().collect::<String>()
+    )
+}
+
+// Replace every recovery code of the user with a fresh set, returned in the clear
+pub async fn issue(conn: &mut DbConn, username: &str) -> Result<RecoveryCodes, sqlx::Error> {
+    let codes: Vec<String> = (0..COUNT).map(|_| generate()).collect();
+    let mut tx = conn.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE username = $1")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+    for code in &codes {
+        sqlx::query(
+            "INSERT INTO recovery_codes (username, code_hash, created_at) VALUES ($1, $2, $3)",
+        )
+        .bind(username)
+        .bind(digest(code))
+        .bind(now())
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(RecoveryCodes {
+        recovery_codes: codes,
+    })
+}
+
+// Use up a recovery code of the user, returning how many are left, or None when the code is not
+// one of theirs or was already used. Claiming it in one statement keeps two concurrent sign-ins
+// from both using it.
+pub async fn consume(
+    conn: &mut DbConn,
+    username: &str,
+    code: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let claimed = sqlx::query(
+        "UPDATE recovery_codes SET used_at = $1
+         WHERE username = $2 AND code_hash = $3 AND used_at = 0",
+    )
+    .bind(now())
+    .bind(username)
+    .bind(digest(code))
+    .execute(&mut *conn)
+    .await?;
+    if claimed.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let remaining: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM recovery_codes WHERE username = $1 AND used_at = 0",
+    )
+    .bind(username)
+    .fetch_one(conn)
+    .await?;
+    Ok(Some(remaining))
+}
+
+// Define a route handler that replaces the caller's recovery codes with a fresh set, such as
+// once most are used. Only callers who signed in with a second factor get new ones, so a stolen
+// password alone cannot.
+#[utoipa::path(
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The new recovery codes; the old ones no longer work", body = RecoveryCodes),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not signed in with a one-time or recovery code", body = ApiError),
+        (status = 409, description = "Two-factor sign-in is not enabled", body = ApiError),
+    )
+)]
+#[post("/2fa/recovery-codes")]
+pub async fn regenerate(
+    caller: Verified,
+    mut db: Connection<Db>,
+    audit: Audit<'_>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    let username = &caller.identity.subject;
+    totp::require_enabled(&mut db, &caller.identity).await?;
+    let codes = issue(&mut db, username).await.map_err(internal_error)?;
+    audit
+        .record(
+            Action::RecoveryCodesIssued,
+            Some(username),
+            "replaced the recovery codes",
+        )
+        .await;
+    Ok(Json(codes))
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+
+use crate::scheduler::TaskFn;
+
+// Define the reasons a refresh token can be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshError {
+    Unknown,
+    Expired,
+    Reused,
+}
+
+impl RefreshError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RefreshError::Unknown => "refresh token is not recognized",
+            RefreshError::Expired => "refresh token has expired",
+            RefreshError::Reused => {
+                "refresh token was already used; all sessions from it were revoked"
+            }
+        }
+    }
+}
+
+// Define the bookkeeping kept for every refresh token ever issued
+#[derive(Debug)]
+struct RefreshEntry {
+    // Tenant the login happened in, the only one the token is rotated in
+    tenant: String,
+    subject: String,
+    // Whether the login that started the family checked a second factor
+    second_factor: bool,
+    // Every token produced by rotating the same login shares a family id
+    family: u64,
+    expires_at: Instant,
+    revoked: bool,
+}
+
+// Define who a refresh token was issued to, looked up before rotating it
+#[derive(Debug)]
+pub struct Holder {
+    pub tenant: String,
+    pub subject: String,
+}
+
+// Define the outcome of a rotation: the holder's second factor and the token replacing it
+#[derive(Debug)]
+pub struct Rotated {
+    pub second_factor: bool,
+    pub token: String,
+}
+
+// Define an in-memory store of refresh tokens, keyed by a SHA-256 digest of the token
+#[derive(Debug)]
+pub struct RefreshStore {
+    ttl: Duration,
+    state: Arc<Mutex<StoreState>>,
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    entries: HashMap<String, RefreshEntry>,
+    next_family: u64,
+}
+
+impl RefreshStore {
+    pub fn new(ttl: Duration) -> Self {
+        RefreshStore {
+            ttl,
+            state: Arc::new(Mutex::new(StoreState::default())),
+        }
+    }
+
+    // Issue a refresh token of a user of `tenant` that starts a new rotation family
+    pub fn issue(&self, tenant: &str, subject: &str, second_factor: bool) -> String {
+        let mut state = self.state.lock().expect("refresh store lock");
+        state.next_family += 1;
+        let family = state.next_family;
+        self.insert(&mut state, tenant, subject, second_factor, family)
+    }
+
+    // Return who a refresh token was issued to, leaving it as it is, so the account can be
+    // checked before the token is used up
+    pub fn holder(&self, token: &str) -> Result<Holder, RefreshError> {
+        let state = self.state.lock().expect("refresh store lock");
+        let entry = state
+            .entries
+            .get(&digest(token))
+            .ok_or(RefreshError::Unknown)?;
+        Ok(Holder {
+            tenant: entry.tenant.clone(),
+            subject: entry.subject.clone(),
+        })
+    }
+
+    // Exchange a refresh token for a replacement, revoking the old one
+    pub fn rotate(&self, token: &str) -> Result<Rotated, RefreshError> {
+        let mut state = self.state.lock().expect("refresh store lock");
+        let entry = state
+            .entries
+            .get_mut(&digest(token))
+            .ok_or(RefreshError::Unknown)?;
+
+        if entry.revoked {
+            // A revoked token coming back means it leaked, so kill the whole family
+            let family = entry.family;
+            state
+                .entries
+                .values_mut()
+                .filter(|entry| entry.family == family)
+                .for_each(|entry| entry.revoked = true);
+            return Err(RefreshError::Reused);
+        }
+        if entry.expires_at <= Instant::now() {
+            return Err(RefreshError::Expired);
+        }
+
+        entry.revoked = true;
+        let tenant = entry.tenant.clone();
+        let subject = entry.subject.clone();
+        let second_factor = entry.second_factor;
+        let family = entry.family;
+        let token = self.insert(&mut state, &tenant, &subject, second_factor, family);
+        Ok(Rotated {
+            second_factor,
+            token,
+        })
+    }
+
+    // Forget every refresh token of `subject`, returning how many could still be rotated
+    pub fn revoke_subject(&self, subject: &str) -> usize {
+        let mut state = self.state.lock().expect("refresh store lock");
+        let live = state
+            .entries
+            .values()
+            .filter(|entry| entry.subject == subject && !entry.revoked)
+            .count();
+        state.entries.retain(|_, entry| entry.subject != subject);
+        live
+    }
+
+    // Return the scheduled task forgetting tokens past their expiry; rotating one of them can
+    // only fail, so nothing is lost but the memory they hold
+    pub fn purge_task(&self) -> TaskFn {
+        let state = self.state.clone();
+        Box::new(move || {
+            let state = state.clone();
+            Box::pin(async move {
+                let now = Instant::now();
+                let mut state = state.lock().expect("refresh store lock");
+                let before = state.entries.len();
+                state.entries.retain(|_, entry| entry.expires_at > now);
+                Ok(format!(
+                    "purged {} expired refresh tokens",
+                    before - state.entries.len()
+                ))
+            })
+        })
+    }
+
+    fn insert(
+        &self,
+        state: &mut StoreState,
+        tenant: &str,
+        subject: &str,
+        second_factor: bool,
+        family: u64,
+    ) -> String {
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 48);
+        state.entries.insert(
+            digest(&token),
+            RefreshEntry {
+                tenant: tenant.to_string(),
+                subject: subject.to_string(),
+                second_factor,
+                family,
+                expires_at: Instant::now() + self.ttl,
+                revoked: false,
+            },
+        );
+        token
+    }
+}
+
+fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
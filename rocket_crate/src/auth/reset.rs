@@ -0,0 +1,183 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::response::status::{Accepted, NoContent};
+use rocket::serde::json::Json;
+use rocket::tokio::task;
+use rocket::State;
+use rocket_db_pools::sqlx::{self, Connection as _};
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use super::password;
+use super::users;
+use crate::audit::{Action, Audit};
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+use crate::mail::{Email, Mailer};
+use crate::tenant::Tenant;
+use crate::validation::Validated;
+
+// Define the `password_reset` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordResetConfig {
+    // Seconds an emailed reset token stays valid
+    pub ttl: u64,
+}
+
+// Define the JSON body accepted by the forgot-password endpoint
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ForgotRequest {
+    username: String,
+}
+
+// Define the JSON body accepted by the reset endpoint
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ResetRequest {
+    token: String,
+    #[validate(length(min = 8, max = 128, message = "must be between 8 and 128 characters"))]
+    #[schema(min_length = 8, max_length = 128)]
+    password: String,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Only digests are stored, so a leaked table cannot be used to reset passwords
+fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Define a route handler that emails a one-time reset token. It answers 202 whether or not the
+// account exists, so it cannot be used to find out which usernames are taken.
+#[utoipa::path(
+    tag = "auth",
+    request_body = ForgotRequest,
+    responses((status = 202, description = "A reset token was emailed if the account exists"))
+)]
+#[post("/password/forgot", format = "json", data = "<request>")]
+pub async fn forgot(
+    request: Json<ForgotRequest>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    config: &State<PasswordResetConfig>,
+    mailer: &State<Mailer>,
+) -> Result<Accepted<()>, ApiError> {
+    // Deactivated accounts get no link, as if they did not exist
+    let Some(user) = users::find(&mut db, &tenant.id, &request.username)
+        .await
+        .map_err(internal_error)?
+        .filter(|user| user.active)
+    else {
+        return Ok(Accepted(()));
+    };
+
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let now = now();
+    sqlx::query("DELETE FROM password_resets WHERE expires_at <= $1")
+        .bind(now)
+        .execute(&mut **db)
+        .await
+        .map_err(internal_error)?;
+    sqlx::query(
+        "INSERT INTO password_resets (token_hash, username, expires_at, used)
+         VALUES ($1, $2, $3, 0)",
+    )
+    .bind(digest(&token))
+    .bind(&user.username)
+    .bind(now + config.ttl as i64)
+    .execute(&mut **db)
+    .await
+    .map_err(internal_error)?;
+
+    // Accounts have no email address of their own yet, so the username is the recipient
+    let email = Email {
+        to: user.username.clone(),
+        subject: "Reset your password".into(),
+        body: format!(
+            "Someone asked to reset the password of `{}`.\n\n\
+             To choose a new one, POST {{\"token\": \"{}\", \"password\": \"...\"}} to \
+             /password/reset within {} minutes. The token works once.\n\n\
+             If this was not you, ignore this email; your password is unchanged.",
+            user.username,
+            token,
+            config.ttl / 60
+        ),
+    };
+    // The caller learns nothing either way, so a delivery failure is only logged
+    if let Err(err) = mailer.send(email).await {
+        error!(username = %user.username, "failed to send password reset email: {}", err);
+    }
+    Ok(Accepted(()))
+}
+
+// Define a route handler that sets a new password with a reset token, which is used up even if
+// the account has several outstanding
+#[utoipa::path(
+    tag = "auth",
+    request_body = ResetRequest,
+    responses(
+        (status = 204, description = "The password was changed"),
+        (status = 400, description = "Unknown, used or expired token", body = ApiError),
+        (status = 422, description = "Invalid password", body = ApiError),
+    )
+)]
+#[post("/password/reset", format = "json", data = "<request>")]
+pub async fn reset(
+    request: Validated<Json<ResetRequest>>,
+    mut db: Connection<Db>,
+    audit: Audit<'_>,
+) -> Result<NoContent, ApiError> {
+    let ResetRequest { token, password } = request.into_inner();
+
+    // Argon2 is deliberately slow, so keep it off the async executor
+    let phc = task::spawn_blocking(move || password::hash(&password))
+        .await
+        .map_err(|err| ApiError::internal().with_cause(err))?
+        .map_err(|err| ApiError::internal().with_cause(err))?;
+
+    let mut tx = db.begin().await.map_err(internal_error)?;
+    // Claiming the token in one statement keeps two concurrent resets from both using it
+    let username: Option<String> = sqlx::query_scalar(
+        "UPDATE password_resets SET used = 1
+         WHERE token_hash = $1 AND used = 0 AND expires_at > $2
+         RETURNING username",
+    )
+    .bind(digest(&token))
+    .bind(now())
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error)?;
+    let Some(username) = username else {
+        return Err(ApiError::bad_request(
+            "reset token is invalid, expired or already used",
+        ));
+    };
+
+    sqlx::query("UPDATE users SET password_hash = $1 WHERE username = $2")
+        .bind(&phc)
+        .bind(&username)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    sqlx::query("UPDATE password_resets SET used = 1 WHERE username = $1")
+        .bind(&username)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+
+    info!(username = %username, "password was reset");
+    audit
+        .record(Action::PasswordReset, Some(&username), "with a reset token")
+        .await;
+    Ok(NoContent)
+}
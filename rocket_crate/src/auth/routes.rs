@@ -0,0 +1,323 @@
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::Json;
+use rocket::tokio::task;
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::csrf;
+use super::jwt::JwtConfig;
+use super::lockout::Lockout;
+use super::password;
+use super::refresh::{RefreshError, RefreshStore};
+use super::session::Sessions;
+use super::totp::{SecondFactor, Totp};
+use super::users::{self, User};
+use crate::audit::{Action, Audit};
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::tenant::Tenant;
+
+// Define the JSON body accepted by the login endpoint
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+    // Code from the authenticator app, required once two-factor sign-in is enabled
+    #[serde(default)]
+    otp: Option<String>,
+}
+
+// Define the sign-in form posted by the login page
+#[derive(Debug, FromForm)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    otp: Option<String>,
+    csrf_token: String,
+}
+
+// Define the sign-out form, which only carries the CSRF token
+#[derive(Debug, FromForm)]
+pub struct LogoutForm {
+    csrf_token: String,
+}
+
+// Define the JSON body accepted by the refresh endpoint
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+// Define the JSON body returned whenever new credentials are issued
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    refresh_token: String,
+}
+
+impl TokenResponse {
+    fn new(jwt: &JwtConfig, user: &User, second_factor: bool, refresh_token: String) -> Self {
+        TokenResponse {
+            access_token: jwt.issue(&user.tenant, &user.username, &user.roles, second_factor),
+            token_type: "Bearer",
+            expires_in: jwt.ttl,
+            refresh_token,
+        }
+    }
+}
+
+// Return the user the credentials belong to and whether a second factor was checked, auditing
+// sign-ins and failed attempts. Failed attempts count towards locking the account and the
+// client's address; while either is locked the credentials are not even checked.
+async fn authenticate(
+    db: &mut Connection<Db>,
+    tenant: &Tenant,
+    totp: &Totp,
+    lockout: &Lockout,
+    audit: &Audit<'_>,
+    credentials: LoginRequest,
+) -> Result<(User, bool), ApiError> {
+    let LoginRequest {
+        username,
+        password,
+        otp,
+    } = credentials;
+    let ip = audit.ip;
+    let failed = |err: ApiError| async {
+        audit
+            .record(Action::LoginFailed, Some(&username), err.message.clone())
+            .await;
+        err
+    };
+    if let Err(err) = lockout.check(&username, ip) {
+        return Err(failed(err).await);
+    }
+    let user = users::find(db, &tenant.id, &username)
+        .await
+        .map_err(internal_error)?;
+    let stored = user.as_ref().map(|user| user.password_hash.clone());
+
+    // Argon2 is deliberately slow, so keep it off the async executor
+    let verified = task::spawn_blocking(move || match stored {
+        Some(phc) => password::verify(&password, &phc),
+        None => {
+            // Spend the same effort for unknown users to avoid leaking which names exist
+            password::verify(&password, password::DUMMY_HASH);
+            false
+        }
+    })
+    .await
+    .unwrap_or(false);
+
+    let Some(user) = user.filter(|_| verified) else {
+        let err = ApiError::unauthorized("invalid username or password");
+        return Err(failed(lockout.failed(&username, ip, err)).await);
+    };
+    // Checked once the password is known to be right, so it reveals nothing to guessers
+    if !user.active {
+        let err = ApiError::new(Status::Forbidden, "account is deactivated");
+        return Err(failed(err).await);
+    }
+    match totp.check(db, &user, otp.as_deref()).await {
+        Ok(second_factor) => {
+            lockout.succeeded(&username);
+            let detail = match second_factor {
+                SecondFactor::None => "signed in",
+                SecondFactor::Totp => "signed in with a one-time code",
+                SecondFactor::RecoveryCode { .. } => "signed in with a recovery code",
+            };
+            audit.record(Action::Login, Some(&username), detail).await;
+            if let SecondFactor::RecoveryCode { remaining } = second_factor {
+                let detail = format!("{} recovery codes left", remaining);
+                audit
+                    .record(Action::RecoveryCodeUsed, Some(&username), detail)
+                    .await;
+            }
+            Ok((user, second_factor.proven()))
+        }
+        // Asking for the code is the normal first step, only wrong codes count as failures
+        Err(err) if otp.is_some() && err.status == Status::Unauthorized => {
+            Err(failed(lockout.failed(&username, ip, err)).await)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Define a route handler that exchanges valid credentials, plus a one-time code for accounts
+// with two-factor sign-in, for a signed token
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "An access token and the refresh token to renew it", body = TokenResponse),
+        (status = 401, description = "Wrong credentials, or a one-time code is required", body = ApiError),
+        (status = 403, description = "The account is deactivated", body = ApiError),
+        (status = 423, description = "Too many failed attempts", body = ApiError),
+    )
+)]
+#[post("/login", format = "json", data = "<credentials>")]
+pub async fn login(
+    credentials: Json<LoginRequest>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+    jwt: &State<JwtConfig>,
+    refresh: &State<RefreshStore>,
+    totp: &State<Totp>,
+    lockout: &State<Lockout>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let credentials = credentials.into_inner();
+    let (user, second_factor) =
+        authenticate(&mut db, &tenant, totp, lockout, &audit, credentials).await?;
+    let refresh_token = refresh.issue(&user.tenant, &user.username, second_factor);
+    Ok(Json(TokenResponse::new(
+        jwt,
+        &user,
+        second_factor,
+        refresh_token,
+    )))
+}
+
+// Define a route handler for browser clients that checks the login form and starts a session
+// named by an encrypted cookie, then returns to the pages
+#[allow(clippy::too_many_arguments)]
+#[post("/login", format = "form", data = "<form>")]
+pub async fn session_login(
+    form: Form<LoginForm>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    audit: Audit<'_>,
+    cookies: &CookieJar<'_>,
+    sessions: Sessions<'_>,
+    totp: &State<Totp>,
+    lockout: &State<Lockout>,
+    locale: Locale,
+) -> Result<Flash<Redirect>, ApiError> {
+    let LoginForm {
+        username,
+        password,
+        otp,
+        csrf_token,
+    } = form.into_inner();
+    // Checked on sign-in too, so another site cannot log the browser into an account it picked
+    csrf::verify(cookies, &csrf_token)?;
+
+    let credentials = LoginRequest {
+        username,
+        password,
+        // Browsers leave the code field empty rather than leaving it out
+        otp: otp.filter(|otp| !otp.trim().is_empty()),
+    };
+    match authenticate(&mut db, &tenant, totp, lockout, &audit, credentials).await {
+        Ok((user, second_factor)) => {
+            sessions.start(cookies, &user, second_factor).await;
+            let message = locale.format("flash.signed_in", &[("user", &user.username)]);
+            Ok(Flash::success(Redirect::to("/pages"), message))
+        }
+        Err(err)
+            if [Status::Unauthorized, Status::Forbidden, Status::Locked].contains(&err.status) =>
+        {
+            let mut message = err.message;
+            if let Some(first) = message.get_mut(..1) {
+                first.make_ascii_uppercase();
+            }
+            Ok(Flash::error(Redirect::to("/login"), message))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Define a route handler that ends the browser session
+#[post("/logout", data = "<form>")]
+pub async fn logout(
+    form: Form<LogoutForm>,
+    cookies: &CookieJar<'_>,
+    sessions: Sessions<'_>,
+    locale: Locale,
+) -> Result<Flash<Redirect>, ApiError> {
+    csrf::verify(cookies, &form.csrf_token)?;
+    sessions.end(cookies).await;
+    Ok(Flash::success(
+        Redirect::to("/login"),
+        locale.text("flash.signed_out"),
+    ))
+}
+
+// Define a route handler that rotates a refresh token into a fresh pair of credentials
+#[utoipa::path(
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "A new access token and the refresh token replacing the one sent", body = TokenResponse),
+        (status = 401, description = "Unknown, expired or reused refresh token", body = ApiError),
+        (status = 403, description = "The refresh token was issued for another tenant", body = ApiError),
+    )
+)]
+#[post("/token/refresh", format = "json", data = "<request>")]
+pub async fn refresh_token(
+    request: Json<RefreshRequest>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    jwt: &State<JwtConfig>,
+    refresh: &State<RefreshStore>,
+    audit: Audit<'_>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let rejected = |err: RefreshError| ApiError::unauthorized(err.reason());
+    let audited = &audit;
+    let refused = |detail: String| async move {
+        let detail = format!("refresh token rejected: {}", detail);
+        audited.record(Action::AuthFailed, None, detail).await;
+    };
+
+    // The account is checked before the token is rotated, so a refresh that is refused leaves
+    // the token to be used again
+    let holder = match refresh.holder(&request.refresh_token) {
+        Ok(holder) => holder,
+        Err(err) => {
+            refused(err.reason().to_string()).await;
+            return Err(rejected(err));
+        }
+    };
+    if holder.tenant != tenant.id {
+        refused(format!("issued in tenant `{}`", holder.tenant)).await;
+        return Err(ApiError::new(
+            Status::Forbidden,
+            "refresh token was issued for another tenant",
+        ));
+    }
+    // Re-read the account so role changes take effect on the next refresh
+    let user = users::find(&mut db, &holder.tenant, &holder.subject)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| rejected(RefreshError::Unknown))?;
+    if !user.active {
+        refused(format!("account `{}` is deactivated", user.username)).await;
+        return Err(ApiError::unauthorized("account is deactivated"));
+    }
+
+    let rotated = match refresh.rotate(&request.refresh_token) {
+        Ok(rotated) => rotated,
+        Err(err) => {
+            refused(err.reason().to_string()).await;
+            return Err(rejected(err));
+        }
+    };
+
+    audit
+        .record(Action::TokenRefreshed, Some(&user.username), "")
+        .await;
+    Ok(Json(TokenResponse::new(
+        jwt,
+        &user,
+        rotated.second_factor,
+        rotated.token,
+    )))
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use data_encoding::BASE64URL_NOPAD;
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::time;
+use rocket_db_pools::deadpool_redis::redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use super::identity::{admit, fail, Identity};
+use super::users::User;
+use super::AuthError;
+use crate::db::{self, Cache};
+
+// Define the `session` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionConfig {
+    // Seconds a browser session lasts after logging in
+    pub ttl: u64,
+    #[serde(default)]
+    pub mode: SessionMode,
+    // Passphrases sealing `cookie` sessions: the first seals new cookies, and the others still
+    // open the cookies sealed before it was added, so keys can be rotated without signing
+    // everyone out
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+// Define where browser sessions are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    // In the session store, with the cookie naming them
+    #[default]
+    Server,
+    // In the cookie itself, so any instance restores them without a store
+    Cookie,
+}
+
+// Define a live session. In `server` mode the session cookie holds only its id, in a private
+// cookie encrypted and authenticated with the secret key; in `cookie` mode it holds the session
+// sealed with the first `session.keys` key. Either way clients can neither read nor forge it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Session {
+    sub: String,
+    roles: Vec<String>,
+    exp: u64,
+    // Whether a second factor was checked at sign-in
+    #[serde(default)]
+    second_factor: bool,
+    // Tenant the user signed in through, the only one the session acts in
+    tenant: String,
+}
+
+pub const COOKIE: &str = "session";
+
+// Prefix of the Redis keys holding sessions
+const REDIS_PREFIX: &str = "session:";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Define the store of live sessions, managed as state. Sessions live in memory, so they end
+// when the server restarts, or in Redis when it is the cache backend, so every instance knows
+// them and signing out ends them everywhere. Sessions started while Redis is unreachable are
+// kept in memory. In `cookie` mode nothing is stored and the store only seals and opens cookies.
+pub struct SessionStore {
+    ttl: u64,
+    sessions: Mutex<HashMap<String, Session>>,
+    // Ciphers of the `session.keys`, newest first, when sessions are kept in cookies
+    ciphers: Option<Vec<Aes256Gcm>>,
+}
+
+impl SessionStore {
+    pub fn new(config: SessionConfig) -> Result<Self, String> {
+        let ciphers = match config.mode {
+            SessionMode::Server => None,
+            SessionMode::Cookie if config.keys.is_empty() => {
+                return Err("sessions kept in cookies need at least one key in `keys`".into())
+            }
+            // Any passphrase works as a key; the cipher key is its SHA-256 digest
+            SessionMode::Cookie => Some(
+                config
+                    .keys
+                    .iter()
+                    .map(|key| Aes256Gcm::new(&Sha256::digest(key.as_bytes())))
+                    .collect(),
+            ),
+        };
+        Ok(SessionStore {
+            ttl: config.ttl,
+            sessions: Mutex::new(HashMap::new()),
+            ciphers,
+        })
+    }
+
+    // Seal a session into a cookie value with the newest key
+    fn seal(ciphers: &[Aes256Gcm], session: &Session) -> String {
+        let nonce: [u8; 12] = rand::random();
+        let plain = serde_json::to_vec(session).expect("session serializes");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            ciphers[0]
+                .encrypt(Nonce::from_slice(&nonce), plain.as_slice())
+                .expect("AES-GCM encryption"),
+        );
+        BASE64URL_NOPAD.encode(&sealed)
+    }
+
+    // Open a sealed cookie value with whichever key sealed it, telling whether that was an
+    // older key the cookie should be sealed again with the newest one
+    fn open(ciphers: &[Aes256Gcm], value: &str) -> Option<(Session, bool)> {
+        let sealed = BASE64URL_NOPAD
+            .decode(value.as_bytes())
+            .ok()
+            .filter(|sealed| sealed.len() > 12)?;
+        let (nonce, ciphertext) = sealed.split_at(12);
+        ciphers.iter().enumerate().find_map(|(index, cipher)| {
+            let plain = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+            let session = serde_json::from_slice(&plain).ok()?;
+            Some((session, index > 0))
+        })
+    }
+
+    async fn insert(&self, cache: Option<&Cache>, id: &str, session: &Session) {
+        if let Some(cache) = cache {
+            let value = serde_json::to_string(session).expect("session serializes");
+            let stored = match cache.get().await {
+                Ok(mut conn) => conn
+                    .set_ex::<_, _, ()>(format!("{REDIS_PREFIX}{id}"), value, self.ttl)
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            match stored {
+                Ok(()) => return,
+                Err(err) => warn!("session kept in memory, Redis is unavailable: {}", err),
+            }
+        }
+        let now = now();
+        let mut sessions = self.sessions.lock().expect("session store lock");
+        sessions.retain(|_, session| session.exp > now);
+        sessions.insert(id.to_string(), session.clone());
+    }
+
+    async fn find(&self, cache: Option<&Cache>, id: &str) -> Option<Session> {
+        if let Some(cache) = cache {
+            let found = match cache.get().await {
+                Ok(mut conn) => conn
+                    .get::<_, Option<String>>(format!("{REDIS_PREFIX}{id}"))
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            match found {
+                Ok(Some(value)) => return serde_json::from_str(&value).ok(),
+                Ok(None) => {}
+                Err(err) => warn!(
+                    "looking up session in memory, Redis is unavailable: {}",
+                    err
+                ),
+            }
+        }
+        let sessions = self.sessions.lock().expect("session store lock");
+        sessions.get(id).cloned()
+    }
+
+    async fn remove(&self, cache: Option<&Cache>, id: &str) {
+        if let Some(cache) = cache {
+            let removed = match cache.get().await {
+                Ok(mut conn) => conn
+                    .del::<_, ()>(format!("{REDIS_PREFIX}{id}"))
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            if let Err(err) = removed {
+                warn!("failed to end session in Redis: {}", err);
+            }
+        }
+        self.sessions.lock().expect("session store lock").remove(id);
+    }
+
+    // Remove every session of `subject`, returning how many there were. Sessions in Redis are
+    // found by reading them all, which is fine for the rare deactivation of an account.
+    async fn remove_all(&self, cache: Option<&Cache>, subject: &str) -> usize {
+        let mut ended = 0;
+        if let Some(cache) = cache {
+            let removed = async {
+                let mut conn = cache.get().await.map_err(|err| err.to_string())?;
+                let keys: Vec<String> = {
+                    let mut iter = conn
+                        .scan_match::<_, String>(format!("{REDIS_PREFIX}*"))
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    let mut keys = Vec::new();
+                    while let Some(key) = iter.next_item().await {
+                        keys.push(key);
+                    }
+                    keys
+                };
+                let mut removed = 0;
+                for key in keys {
+                    let value: Option<String> =
+                        conn.get(&key).await.map_err(|err| err.to_string())?;
+                    let session =
+                        value.and_then(|value| serde_json::from_str::<Session>(&value).ok());
+                    if session.is_some_and(|session| session.sub == subject) {
+                        conn.del::<_, ()>(&key)
+                            .await
+                            .map_err(|err| err.to_string())?;
+                        removed += 1;
+                    }
+                }
+                Ok::<usize, String>(removed)
+            };
+            match removed.await {
+                Ok(removed) => ended += removed,
+                Err(err) => warn!("failed to end sessions in Redis: {}", err),
+            }
+        }
+        let mut sessions = self.sessions.lock().expect("session store lock");
+        let before = sessions.len();
+        sessions.retain(|_, session| session.sub != subject);
+        ended + before - sessions.len()
+    }
+}
+
+// Define a guard handing handlers the session store along with the Redis pool it uses
+pub struct Sessions<'r> {
+    store: &'r SessionStore,
+    cache: Option<&'r Cache>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Sessions<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let store = req
+            .rocket()
+            .state::<SessionStore>()
+            .expect("session store is managed");
+        request::Outcome::Success(Sessions {
+            store,
+            cache: db::shared_cache(req.rocket()),
+        })
+    }
+}
+
+impl Sessions<'_> {
+    // Start a session for `user` and set the session cookie naming it
+    pub async fn start(&self, cookies: &CookieJar<'_>, user: &User, second_factor: bool) {
+        let ttl = self.store.ttl;
+        let session = Session {
+            sub: user.username.clone(),
+            roles: user.roles.clone(),
+            exp: now() + ttl,
+            second_factor,
+            tenant: user.tenant.clone(),
+        };
+        if let Some(ciphers) = &self.store.ciphers {
+            let value = SessionStore::seal(ciphers, &session);
+            cookies.add(session_cookie(value, ttl));
+            return;
+        }
+        let id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        self.store.insert(self.cache, &id, &session).await;
+        cookies.add_private(session_cookie(id, ttl));
+    }
+
+    // End the session and remove its cookie
+    pub async fn end(&self, cookies: &CookieJar<'_>) {
+        if self.store.ciphers.is_some() {
+            cookies.remove(Cookie::build(COOKIE).path("/"));
+            return;
+        }
+        if let Some(cookie) = cookies.get_private(COOKIE) {
+            self.store.remove(self.cache, cookie.value()).await;
+        }
+        cookies.remove_private(Cookie::build(COOKIE).path("/"));
+    }
+
+    // End every session of `subject`, wherever it was started, returning how many there were.
+    // Sessions kept in cookies cannot be reached and last until they expire.
+    pub async fn end_all(&self, subject: &str) -> usize {
+        self.store.remove_all(self.cache, subject).await
+    }
+}
+
+// Build the session cookie holding `value` for `ttl` seconds, hidden from scripts as private
+// cookies are
+fn session_cookie(value: String, ttl: u64) -> Cookie<'static> {
+    Cookie::build((COOKIE, value))
+        .path("/")
+        .http_only(true)
+        // Lax so the session survives arriving from links on other sites
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(ttl as i64))
+        .build()
+}
+
+// Restore the session sealed in the cookie, sealing it again with the newest key when an older
+// one sealed it
+fn from_cookie(req: &Request<'_>, store: &SessionStore, ciphers: &[Aes256Gcm]) -> Option<Session> {
+    let cookie = req.cookies().get(COOKIE)?;
+    let (session, stale) = SessionStore::open(ciphers, cookie.value())?;
+    if stale && session.exp > now() {
+        let value = SessionStore::seal(ciphers, &session);
+        let ttl = session.exp - now();
+        req.cookies().add(session_cookie(value, ttl.min(store.ttl)));
+    }
+    Some(session)
+}
+
+// Define a guard for browser clients that restores the caller from the session cookie
+#[derive(Debug)]
+pub struct SessionUser {
+    pub identity: Identity,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionUser {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let session = match req.guard::<Sessions>().await {
+            request::Outcome::Success(sessions) => match &sessions.store.ciphers {
+                Some(ciphers) => from_cookie(req, sessions.store, ciphers),
+                None => match req.cookies().get_private(COOKIE) {
+                    Some(cookie) => sessions.store.find(sessions.cache, cookie.value()).await,
+                    None => None,
+                },
+            },
+            _ => None,
+        };
+        let Some(session) = session.filter(|session| session.exp > now()) else {
+            return fail(req, Status::Unauthorized, AuthError::MissingSession);
+        };
+
+        let identity = Identity {
+            subject: session.sub,
+            roles: session.roles,
+            expires_at: Some(session.exp),
+            second_factor: session.second_factor,
+            tenant: Some(session.tenant),
+        };
+        match admit(req, &identity).await {
+            Ok(()) => request::Outcome::Success(SessionUser { identity }),
+            Err((status, err)) => fail(req, status, err),
+        }
+    }
+}
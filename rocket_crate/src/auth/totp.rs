@@ -0,0 +1,369 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use data_encoding::{BASE32_NOPAD, BASE64};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json};
+use rocket::State;
+use rocket_db_pools::sqlx::{self, Row};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use super::identity::Identity;
+use super::recovery::{self, RecoveryCodes};
+use super::users::User;
+use crate::audit::{Action, Audit};
+use crate::db::{internal_error, Db, DbConn};
+use crate::errors::ApiError;
+
+// Codes are six digits, each valid for a 30 second step, as authenticator apps expect
+const DIGITS: u32 = 6;
+const STEP: u64 = 30;
+
+// Codes of the neighbouring steps are accepted too, to allow for clock drift on the phone
+const SKEW: u64 = 1;
+
+// Define the `totp` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct TotpConfig {
+    // Name authenticator apps show next to the account
+    pub issuer: String,
+    // Key the stored secrets are encrypted with; override with ROCKET_TOTP={encryption_key="..."}
+    pub encryption_key: String,
+}
+
+// Define the TOTP settings and the cipher protecting secrets at rest, managed as state
+pub struct Totp {
+    issuer: String,
+    cipher: Aes256Gcm,
+}
+
+// Define the JSON body returned when enrolling an authenticator
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Enrollment {
+    // Base32 secret, for apps where it is typed in
+    secret: String,
+    // `otpauth://` URI that the QR code scanned by authenticator apps encodes
+    otpauth_uri: String,
+}
+
+// Define the JSON body carrying a code from the authenticator app
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CodeRequest {
+    code: String,
+}
+
+// Define the second factor a user signed in with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    // The user has not enabled two-factor sign-in
+    None,
+    Totp,
+    // A recovery code stood in for the authenticator app, leaving this many unused
+    RecoveryCode { remaining: i64 },
+}
+
+impl SecondFactor {
+    pub fn proven(self) -> bool {
+        self != SecondFactor::None
+    }
+}
+
+// Define the second-factor state stored in the user table
+struct Enrolled {
+    // Encrypted secret, absent until the user enrolls
+    secret: Option<String>,
+    enabled: bool,
+    // Last step a code was accepted for, so every code works only once
+    last_step: i64,
+}
+
+fn step_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+        / STEP
+}
+
+// Compute the code of `step` as RFC 6238 defines it, with HMAC-SHA1 like all common apps
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+// Return the step a code matches, looking at the steps around the current one
+fn matching_step(secret: &[u8], code: &str) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let now = step_now();
+    (now.saturating_sub(SKEW)..=now + SKEW).find(|&step| code_at(secret, step) == code)
+}
+
+impl Totp {
+    pub fn new(config: TotpConfig) -> Self {
+        // Any passphrase works as configuration; the cipher key is its SHA-256 digest
+        let key = Sha256::digest(config.encryption_key.as_bytes());
+        Totp {
+            issuer: config.issuer,
+            cipher: Aes256Gcm::new(&key),
+        }
+    }
+
+    fn encrypt(&self, secret: &[u8]) -> String {
+        let nonce: [u8; 12] = rand::random();
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(Nonce::from_slice(&nonce), secret)
+                .expect("AES-GCM encryption"),
+        );
+        BASE64.encode(&sealed)
+    }
+
+    fn decrypt(&self, stored: &str) -> Result<Vec<u8>, ApiError> {
+        let sealed = BASE64
+            .decode(stored.as_bytes())
+            .ok()
+            .filter(|sealed| sealed.len() > 12)
+            .ok_or_else(|| ApiError::internal().with_cause("stored TOTP secret is malformed"))?;
+        let (nonce, ciphertext) = sealed.split_at(12);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                ApiError::internal()
+                    .with_cause("stored TOTP secret does not decrypt; was encryption_key changed?")
+            })
+    }
+
+    fn provisioning_uri(&self, username: &str, secret: &str) -> String {
+        let mut uri = Url::parse("otpauth://totp/").expect("static URI");
+        uri.path_segments_mut()
+            .expect("URI has a path")
+            .pop_if_empty()
+            .push(&format!("{}:{}", self.issuer, username));
+        uri.query_pairs_mut()
+            .append_pair("secret", secret)
+            .append_pair("issuer", &self.issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &STEP.to_string());
+        uri.to_string()
+    }
+
+    // Check the code of a user who signed in with their password: one from their authenticator
+    // app, or one of their recovery codes. Returns which second factor was checked, none for
+    // users who have not enabled it.
+    pub async fn check(
+        &self,
+        conn: &mut DbConn,
+        user: &User,
+        code: Option<&str>,
+    ) -> Result<SecondFactor, ApiError> {
+        let enrolled = load(conn, &user.username).await.map_err(internal_error)?;
+        let Some(Enrolled {
+            secret: Some(secret),
+            enabled: true,
+            last_step,
+        }) = enrolled
+        else {
+            return Ok(SecondFactor::None);
+        };
+        let Some(code) = code else {
+            return Err(ApiError::unauthorized(
+                "a one-time code from your authenticator app, or a recovery code, is required",
+            )
+            .with_details(json!({ "second_factor": "totp" })));
+        };
+        if recovery::is_recovery_code(code) {
+            return match recovery::consume(conn, &user.username, code)
+                .await
+                .map_err(internal_error)?
+            {
+                Some(remaining) => Ok(SecondFactor::RecoveryCode { remaining }),
+                None => Err(ApiError::unauthorized(
+                    "recovery code is invalid or was already used",
+                )),
+            };
+        }
+
+        let secret = self.decrypt(&secret)?;
+        let rejected = || ApiError::unauthorized("one-time code is invalid or was already used");
+        let step = matching_step(&secret, code)
+            .map(|step| step as i64)
+            .filter(|&step| step > last_step)
+            .ok_or_else(rejected)?;
+        // Checked again in the update, in case the same code is being used concurrently
+        if consume(conn, &user.username, step)
+            .await
+            .map_err(internal_error)?
+        {
+            Ok(SecondFactor::Totp)
+        } else {
+            Err(rejected())
+        }
+    }
+}
+
+async fn load(conn: &mut DbConn, username: &str) -> Result<Option<Enrolled>, sqlx::Error> {
+    let row = sqlx::query(
+        // The Any driver cannot decode NULL text, so a missing secret is read as ''
+        "SELECT COALESCE(totp_secret, '') AS totp_secret, totp_enabled, totp_last_step
+         FROM users WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_optional(conn)
+    .await?;
+    row.map(|row| {
+        Ok(Enrolled {
+            secret: Some(row.try_get::<String, _>("totp_secret")?)
+                .filter(|secret| !secret.is_empty()),
+            enabled: row.try_get::<i32, _>("totp_enabled")? != 0,
+            last_step: row.try_get("totp_last_step")?,
+        })
+    })
+    .transpose()
+}
+
+// Record `step` as used unless a code of the same or a later step was accepted meanwhile
+async fn consume(conn: &mut DbConn, username: &str, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = $1 WHERE username = $2 AND totp_last_step < $1",
+    )
+    .bind(step)
+    .bind(username)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Return the account of the caller; API keys and external tokens have none to protect
+async fn account(db: &mut DbConn, identity: &Identity) -> Result<Enrolled, ApiError> {
+    load(db, &identity.subject)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::not_found("only local user accounts can use two-factor sign-in"))
+}
+
+// Fail unless the caller has two-factor sign-in enabled
+pub async fn require_enabled(db: &mut DbConn, identity: &Identity) -> Result<(), ApiError> {
+    match account(db, identity).await?.enabled {
+        true => Ok(()),
+        false => Err(ApiError::new(
+            Status::Conflict,
+            "two-factor sign-in is not enabled for this account",
+        )),
+    }
+}
+
+// Define a route handler that generates a new TOTP secret for the caller. It only takes effect
+// once a code generated from it is confirmed, so a mistyped enrollment cannot lock anyone out.
+#[utoipa::path(
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The new secret, to add to an authenticator app", body = Enrollment),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 409, description = "Two-factor sign-in is already enabled", body = ApiError),
+    )
+)]
+#[post("/2fa/enroll")]
+pub async fn enroll(
+    identity: Identity,
+    mut db: Connection<Db>,
+    totp: &State<Totp>,
+) -> Result<Json<Enrollment>, ApiError> {
+    if account(&mut db, &identity).await?.enabled {
+        return Err(ApiError::new(
+            Status::Conflict,
+            "two-factor sign-in is already enabled for this account",
+        ));
+    }
+
+    let raw: [u8; 20] = rand::random();
+    sqlx::query("UPDATE users SET totp_secret = $1, totp_enabled = 0 WHERE username = $2")
+        .bind(totp.encrypt(&raw))
+        .bind(&identity.subject)
+        .execute(&mut **db)
+        .await
+        .map_err(internal_error)?;
+
+    let secret = BASE32_NOPAD.encode(&raw);
+    Ok(Json(Enrollment {
+        otpauth_uri: totp.provisioning_uri(&identity.subject, &secret),
+        secret,
+    }))
+}
+
+// Define a route handler that turns on two-factor sign-in once the caller proves their
+// authenticator app produces the right codes, and issues the recovery codes that stand in for
+// the app if it is lost
+#[utoipa::path(
+    tag = "auth",
+    security(("bearer" = [])),
+    request_body = CodeRequest,
+    responses(
+        (status = 200, description = "Two-factor sign-in is enabled; keep the recovery codes somewhere safe", body = RecoveryCodes),
+        (status = 401, description = "Missing credentials or wrong code", body = ApiError),
+        (status = 409, description = "Not enrolled, or already enabled", body = ApiError),
+    )
+)]
+#[post("/2fa/confirm", format = "json", data = "<request>")]
+pub async fn confirm(
+    identity: Identity,
+    request: Json<CodeRequest>,
+    mut db: Connection<Db>,
+    totp: &State<Totp>,
+    audit: Audit<'_>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    let enrolled = account(&mut db, &identity).await?;
+    if enrolled.enabled {
+        return Err(ApiError::new(
+            Status::Conflict,
+            "two-factor sign-in is already enabled for this account",
+        ));
+    }
+    let Some(secret) = enrolled.secret else {
+        return Err(ApiError::bad_request("enroll at /2fa/enroll first"));
+    };
+
+    let secret = totp.decrypt(&secret)?;
+    let Some(step) = matching_step(&secret, &request.code) else {
+        return Err(ApiError::bad_request("one-time code is invalid"));
+    };
+    sqlx::query("UPDATE users SET totp_enabled = 1, totp_last_step = $1 WHERE username = $2")
+        .bind(step as i64)
+        .bind(&identity.subject)
+        .execute(&mut **db)
+        .await
+        .map_err(internal_error)?;
+    let codes = recovery::issue(&mut db, &identity.subject)
+        .await
+        .map_err(internal_error)?;
+    audit
+        .record(
+            Action::RecoveryCodesIssued,
+            Some(&identity.subject),
+            "enabled two-factor sign-in",
+        )
+        .await;
+    Ok(Json(codes))
+}
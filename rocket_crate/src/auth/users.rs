@@ -0,0 +1,122 @@
+use rocket_db_pools::sqlx::{self, Row};
+use serde::Deserialize;
+
+use crate::db::{DbConn, DbRow};
+use crate::tenant::DEFAULT_TENANT;
+
+// Define a user account as stored in the `users` table. Usernames are unique across tenants,
+// but an account only signs in through the tenant it belongs to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+    pub username: String,
+    pub password_hash: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Deactivated accounts keep their data but can no longer sign in or refresh tokens
+    #[serde(default = "active")]
+    pub active: bool,
+}
+
+fn active() -> bool {
+    true
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.into()
+}
+
+impl User {
+    fn from_row(row: &DbRow) -> Result<User, sqlx::Error> {
+        let roles: String = row.try_get("roles")?;
+        Ok(User {
+            tenant: row.try_get("tenant_id")?,
+            username: row.try_get("username")?,
+            password_hash: row.try_get("password_hash")?,
+            roles: roles
+                .split(',')
+                .filter(|role| !role.is_empty())
+                .map(str::to_string)
+                .collect(),
+            active: row.try_get::<i32, _>("active")? != 0,
+        })
+    }
+}
+
+// Look up a user of `tenant` by username
+pub async fn find(
+    conn: &mut DbConn,
+    tenant: &str,
+    username: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT tenant_id, username, password_hash, roles, active FROM users
+         WHERE tenant_id = $1 AND username = $2",
+    )
+    .bind(tenant)
+    .bind(username)
+    .fetch_optional(conn)
+    .await?;
+    row.as_ref().map(User::from_row).transpose()
+}
+
+// List the users of `tenant` by username
+pub async fn list(conn: &mut DbConn, tenant: &str) -> Result<Vec<User>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT tenant_id, username, password_hash, roles, active FROM users
+         WHERE tenant_id = $1 ORDER BY username",
+    )
+    .bind(tenant)
+    .fetch_all(conn)
+    .await?;
+    rows.iter().map(User::from_row).collect()
+}
+
+// Insert a user unless one with the same username already exists
+pub async fn insert_if_missing(conn: &mut DbConn, user: &User) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO users (tenant_id, username, password_hash, roles, active)
+         VALUES ($1, $2, $3, $4, $5) ON CONFLICT (username) DO NOTHING",
+    )
+    .bind(&user.tenant)
+    .bind(&user.username)
+    .bind(&user.password_hash)
+    .bind(user.roles.join(","))
+    .bind(user.active as i32)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Replace the roles of a user of `tenant`
+pub async fn set_roles(
+    conn: &mut DbConn,
+    tenant: &str,
+    username: &str,
+    roles: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET roles = $1 WHERE tenant_id = $2 AND username = $3")
+        .bind(roles.join(","))
+        .bind(tenant)
+        .bind(username)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// Deactivate or reactivate a user of `tenant`
+pub async fn set_active(
+    conn: &mut DbConn,
+    tenant: &str,
+    username: &str,
+    active: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET active = $1 WHERE tenant_id = $2 AND username = $3")
+        .bind(active as i32)
+        .bind(tenant)
+        .bind(username)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
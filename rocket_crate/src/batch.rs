@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Method;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{json, Json, Value};
+use rocket::{Config, State};
+use serde::Deserialize;
+use tracing::{debug, error, warn};
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+
+// Headers of the batch request passed on to every sub-request, so they run as the same caller
+const FORWARDED_HEADERS: &[&str] = &[
+    "Authorization",
+    "Cookie",
+    "X-Api-Key",
+    "X-Tenant-Id",
+    "Host",
+    "X-Forwarded-For",
+    "Accept-Language",
+];
+
+// Headers proving a sub-request was sent by this instance for a batch request from an address
+const RELAY_HEADER: &str = "X-Batch-Relay";
+const PEER_HEADER: &str = "X-Batch-Peer";
+
+// Define the `batch` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchConfig {
+    // Sub-requests accepted in one batch
+    pub max_requests: usize,
+    // Seconds each sub-request may take
+    pub timeout: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_requests: 20,
+            timeout: 30,
+        }
+    }
+}
+
+// Define the client dispatching sub-requests back to this instance, managed as state
+pub struct Batch {
+    max_requests: usize,
+    client: reqwest::Client,
+    // Secret sent with every sub-request, so the address of the batch request can be trusted
+    token: String,
+}
+
+// Return the address the batch request came from, if `req` is one of its sub-requests; they all
+// come from loopback
+pub fn relayed_peer(req: &Request<'_>) -> Option<IpAddr> {
+    let batch = req.rocket().state::<Batch>()?;
+    let peer = req.remote()?.ip();
+    if !peer.is_loopback() || req.headers().get_one(RELAY_HEADER)? != batch.token {
+        return None;
+    }
+    req.headers().get_one(PEER_HEADER)?.parse().ok()
+}
+
+// Define a sub-request of the JSON array accepted by `POST /batch`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SubRequest {
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    // Sent as JSON when given
+    body: Option<Value>,
+}
+
+// Return whether a sub-request is kept from setting a header itself: those this instance adds to
+// every sub-request and those naming the client address, which rate limits and lockouts trust
+fn reserved(name: &str, ip_header: Option<&str>) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("x-batch-")
+        || name.starts_with("x-forwarded-")
+        || matches!(name.as_str(), "host" | "forwarded" | "x-real-ip")
+        || ip_header.is_some_and(|ip_header| ip_header.eq_ignore_ascii_case(&name))
+}
+
+impl SubRequest {
+    // Return the headers of the sub-request sent along with the inherited ones, leaving out the
+    // reserved ones
+    pub fn own_headers<'a>(
+        &'a self,
+        ip_header: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.headers
+            .iter()
+            .filter(move |(name, _)| !reserved(name, ip_header))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn validate(&self) -> Result<Method, String> {
+        let method = Method::from_str(&self.method.to_ascii_uppercase())
+            .map_err(|_| format!("unknown method `{}`", self.method))?;
+        if !self.path.starts_with('/') {
+            return Err(format!("path `{}` does not start with /", self.path));
+        }
+        if self.path == "/batch" || self.path.starts_with("/batch?") {
+            return Err("batches cannot be nested".into());
+        }
+        Ok(method)
+    }
+}
+
+// Define a guard collecting what sub-requests inherit from the batch request
+pub struct Caller {
+    headers: Vec<(String, String)>,
+    peer: Option<IpAddr>,
+    // Address Rocket took from `ip_header`, if any
+    ip: Option<IpAddr>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = FORWARDED_HEADERS
+            .iter()
+            .flat_map(|name| {
+                req.headers()
+                    .get(name)
+                    .map(move |value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+        request::Outcome::Success(Caller {
+            headers,
+            peer: req.remote().map(|remote| remote.ip()),
+            ip: req.client_ip(),
+        })
+    }
+}
+
+// Return the URL this instance can be reached at from itself
+fn local_base(config: &Config) -> String {
+    let address = match config.address {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        address => address,
+    };
+    let scheme = if config.tls_enabled() {
+        "https"
+    } else {
+        "http"
+    };
+    match address {
+        IpAddr::V6(ip) => format!("{}://[{}]:{}", scheme, ip, config.port),
+        IpAddr::V4(ip) => format!("{}://{}:{}", scheme, ip, config.port),
+    }
+}
+
+impl Batch {
+    // Run one sub-request and describe its response; failures to reach the router become 502s
+    async fn dispatch(
+        &self,
+        config: &Config,
+        caller: &Caller,
+        method: Method,
+        sub: &SubRequest,
+    ) -> Value {
+        let method = reqwest::Method::from_bytes(method.as_str().as_bytes()).expect("HTTP method");
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", local_base(config), sub.path));
+        for (name, value) in &caller.headers {
+            request = request.header(name, value);
+        }
+        let ip_header = config
+            .ip_header
+            .as_ref()
+            .map(|ip_header| ip_header.as_str());
+        for (name, value) in sub.own_headers(ip_header) {
+            request = request.header(name, value);
+        }
+        if let Some(body) = &sub.body {
+            request = request.json(body);
+        }
+
+        // Sub-requests cannot set these headers, so the values sent here are the only ones
+        request = request.header(RELAY_HEADER, &self.token);
+        if let Some(peer) = caller.peer {
+            request = request.header(PEER_HEADER, peer.to_string());
+        }
+        // Rate limits key on the address Rocket reads from `ip_header`, when one is configured
+        if let (Some(ip_header), Some(ip)) = (ip_header, caller.ip) {
+            request = request.header(ip_header, ip.to_string());
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "batch sub-request {} {} failed: {}",
+                    sub.method, sub.path, err
+                );
+                return json!({
+                    "status": 502,
+                    "headers": {},
+                    "body": { "code": "bad_gateway", "message": "sub-request failed" },
+                });
+            }
+        };
+        let status = response.status().as_u16();
+        let mut headers = BTreeMap::<String, String>::new();
+        for (name, value) in response.headers() {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            headers
+                .entry(name.as_str().to_string())
+                .and_modify(|joined| {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+        let text = response.text().await.unwrap_or_default();
+        let body = match text.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        };
+        json!({ "status": status, "headers": headers, "body": body })
+    }
+}
+
+// Define a route handler that runs an array of sub-requests one after another through the
+// router, as the same caller, and returns their responses in order
+#[utoipa::path(
+    tag = "batch",
+    request_body = Vec<SubRequest>,
+    responses(
+        (status = 200, description = "The response of every sub-request, in order", body = Value),
+        (status = 400, description = "Too few or too many sub-requests", body = ApiError),
+
+    )
+)]
+#[post("/batch", format = "json", data = "<requests>")]
+async fn run(
+    batch: &State<Batch>,
+    config: &Config,
+    caller: Caller,
+    requests: Json<Vec<SubRequest>>,
+) -> Result<Json<Value>, ApiError> {
+    if requests.is_empty() || requests.len() > batch.max_requests {
+        return Err(ApiError::bad_request(format!(
+            "a batch holds 1 to {} requests, not {}",
+            batch.max_requests,
+            requests.len()
+        ))
+        .with_details(json!({ "max_requests": batch.max_requests })));
+    }
+    let methods = requests
+        .iter()
+        .enumerate()
+        .map(|(index, sub)| {
+            sub.validate().map_err(|reason| {
+                ApiError::bad_request(format!("request {}: {}", index, reason))
+                    .with_details(json!({ "index": index }))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut responses = Vec::with_capacity(requests.len());
+    for (sub, method) in requests.iter().zip(methods) {
+        debug!("batch sub-request {} {}", method, sub.path);
+        responses.push(batch.dispatch(config, &caller, method, sub).await);
+    }
+    Ok(Json(json!({ "responses": responses })))
+}
+
+// Define the part of the OpenAPI document describing batches
+#[derive(OpenApi)]
+#[openapi(paths(run))]
+pub struct ApiDoc;
+
+// Load the batch settings and mount `POST /batch`
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Batch", |rocket| async {
+        let config = match rocket.figment().extract_inner::<BatchConfig>("batch") {
+            Ok(config) => config,
+            Err(err) if err.missing() => BatchConfig::default(),
+            Err(err) => {
+                error!("invalid `batch` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        // Sub-requests go to this very instance, whose certificate need not name the loopback
+        // address
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .expect("HTTP client");
+
+        Ok(rocket
+            .manage(Batch {
+                max_requests: config.max_requests,
+                client,
+                token: Uuid::new_v4().simple().to_string(),
+            })
+            .mount("/", routes![run]))
+    })
+}
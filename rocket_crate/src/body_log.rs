@@ -0,0 +1,301 @@
+use std::io::Cursor;
+
+use rocket::data::Data;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, HeaderMap};
+use rocket::request::Request;
+use rocket::response::Response;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::telemetry;
+
+// Replaces every redacted value
+const REDACTED: &str = "[redacted]";
+
+// Most bytes of a request body a fairing can peek at without consuming it
+const PEEK_BYTES: usize = 512;
+
+// Define the `body_log` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct BodyLogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Most bytes of a body logged; request bodies are peeked, so at most 512 of theirs
+    #[serde(default = "max_bytes")]
+    pub max_bytes: usize,
+    // Body fields whose name contains one of these, in any case, are logged as "[redacted]"
+    #[serde(default = "fields")]
+    pub fields: Vec<String>,
+    // Headers logged as "[redacted]", in any case
+    #[serde(default = "headers")]
+    pub headers: Vec<String>,
+}
+
+fn max_bytes() -> usize {
+    2048
+}
+
+fn fields() -> Vec<String> {
+    ["password", "token", "secret", "otp"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn headers() -> Vec<String> {
+    ["Authorization", "Cookie", "Set-Cookie", "X-Api-Key"]
+        .map(String::from)
+        .to_vec()
+}
+
+impl BodyLogConfig {
+    fn is_sensitive_field(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.fields
+            .iter()
+            .any(|field| name.contains(&field.to_lowercase()))
+    }
+
+    fn is_sensitive_header(&self, name: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    // Describe the headers, with the sensitive ones redacted
+    fn headers_of(&self, headers: &HeaderMap<'_>) -> String {
+        headers
+            .iter()
+            .map(|header| {
+                let name = header.name().as_str();
+                match self.is_sensitive_header(name) {
+                    true => format!("{}: {}", name, REDACTED),
+                    false => format!("{}: {}", name, header.value()),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Describe a body of `size` bytes from its first bytes, with the sensitive fields redacted.
+    // JSON cut short at the cap is redacted as text, as it no longer parses.
+    fn body_of(
+        &self,
+        content_type: Option<&ContentType>,
+        encoded: bool,
+        bytes: &[u8],
+        size: usize,
+    ) -> String {
+        if size == 0 {
+            return String::new();
+        }
+        let is_text =
+            content_type.is_some_and(|ct| is_json(ct) || ct.is_form() || ct.top() == "text");
+        if encoded || !is_text {
+            return format!("<{} bytes of {}>", size, describe(content_type, encoded));
+        }
+
+        let truncated = bytes.len() > self.max_bytes || bytes.len() < size;
+        let bytes = &bytes[..bytes.len().min(self.max_bytes)];
+        let text = String::from_utf8_lossy(bytes);
+        let redacted = match content_type {
+            Some(ct) if ct.is_form() => self.redact_form(&text),
+            Some(ct) if is_json(ct) => match serde_json::from_str::<Value>(&text) {
+                Ok(mut json) if !truncated => {
+                    self.redact_json(&mut json);
+                    json.to_string()
+                }
+                _ => self.redact_json_text(&text),
+            },
+            _ => text.into_owned(),
+        };
+        match truncated {
+            true => format!("{}... ({} bytes)", redacted, size),
+            false => redacted,
+        }
+    }
+
+    fn redact_json(&self, json: &mut Value) {
+        match json {
+            Value::Object(fields) => {
+                for (name, value) in fields.iter_mut() {
+                    match self.is_sensitive_field(name) {
+                        true => *value = Value::String(REDACTED.into()),
+                        false => self.redact_json(value),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_json(item)),
+            _ => {}
+        }
+    }
+
+    // Redact the value following each sensitive key of JSON that does not parse. A string or
+    // scalar value is replaced up to its end; an object or array value, and whatever follows, is
+    // dropped, as where it ends cannot be told safely.
+    fn redact_json_text(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('"') {
+            out.push_str(&rest[..start]);
+            let Some((key, after)) = json_string(&rest[start..]) else {
+                // An unterminated string is the truncated tail
+                out.push_str(&rest[start..]);
+                return out;
+            };
+            out.push_str(key);
+            rest = after;
+            let trimmed = rest.trim_start();
+            let Some(value) = trimmed.strip_prefix(':') else {
+                continue;
+            };
+            if !self.is_sensitive_field(&key[1..key.len() - 1]) {
+                continue;
+            }
+            out.push_str(&rest[..rest.len() - trimmed.len()]);
+            out.push(':');
+            let value = value.trim_start();
+            rest = match value.chars().next() {
+                Some('"') => json_string(value).map_or("", |(_, after)| after),
+                Some('{') | Some('[') => {
+                    out.push_str(&format!("\"{}\"", REDACTED));
+                    return out;
+                }
+                _ => value.find([',', '}', ']']).map_or("", |end| &value[end..]),
+            };
+            out.push_str(&format!("\"{}\"", REDACTED));
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn redact_form(&self, text: &str) -> String {
+        text.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if self.is_sensitive_field(name) => {
+                    format!("{}={}", name, REDACTED)
+                }
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+// Split the JSON string at the start of `text`, quotes included, from what follows it
+fn json_string(text: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (at, ch) in text.char_indices().skip(1) {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(text.split_at(at + 1)),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Return whether the body is JSON, `application/problem+json` and the like included
+fn is_json(content_type: &ContentType) -> bool {
+    content_type.is_json() || content_type.sub().as_str().ends_with("+json")
+}
+
+fn describe(content_type: Option<&ContentType>, encoded: bool) -> String {
+    match (content_type, encoded) {
+        (_, true) => "encoded data".into(),
+        (Some(ct), false) => ct.media_type().to_string(),
+        (None, false) => "data".into(),
+    }
+}
+
+// Define the fairing logging bodies; a fairing of its own, as only those peek at request bodies
+struct BodyLog(BodyLogConfig);
+
+#[rocket::async_trait]
+impl Fairing for BodyLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Body Log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        let config = &self.0;
+        let size = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse::<usize>().ok());
+        let wanted = config.max_bytes.min(PEEK_BYTES) + 1;
+        let peeked = data.peek(wanted).await.len();
+        // Without a length, a body is as long as what was peeked unless more is left to read
+        let size = size.unwrap_or(match data.peek_complete() {
+            true => peeked,
+            false => peeked + 1,
+        });
+        let peeked = data.peek(wanted).await;
+        let encoded = req.headers().contains("Content-Encoding");
+        let logged = config.body_of(req.content_type(), encoded, peeked, size);
+        telemetry::span(req).in_scope(|| {
+            info!(
+                headers = config.headers_of(req.headers()),
+                body = logged,
+                "request body"
+            )
+        });
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let config = &self.0;
+        // Streamed bodies are left alone, as reading them would buffer the stream
+        let Some(size) = res.body().preset_size() else {
+            telemetry::span(req).in_scope(|| {
+                info!(
+                    status = res.status().code,
+                    headers = config.headers_of(res.headers()),
+                    "response body streamed"
+                )
+            });
+            return;
+        };
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("failed to read the response body to log it: {}", err);
+                return;
+            }
+        };
+        let encoded = res.headers().contains("Content-Encoding");
+        let logged = config.body_of(res.content_type().as_ref(), encoded, &body, size);
+        telemetry::span(req).in_scope(|| {
+            info!(
+                status = res.status().code,
+                headers = config.headers_of(res.headers()),
+                body = logged,
+                "response body"
+            )
+        });
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// Log the headers and first bytes of every request and response body when `body_log.enabled`
+// is on, with passwords, tokens and credentials redacted so the logs can be shared. Attached
+// before compression, so responses are logged as the routes produced them.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Body Log", |rocket| async {
+        let config = match rocket.figment().extract_inner::<BodyLogConfig>("body_log") {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return Ok(rocket),
+            Err(err) if err.missing() => return Ok(rocket),
+            Err(err) => {
+                error!("invalid `body_log` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        warn!("logging request and response bodies, which slows every request down");
+        Ok(rocket.attach(BodyLog(config)))
+    })
+}
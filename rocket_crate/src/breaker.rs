@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use prometheus::{IntCounterVec, IntGaugeVec, Opts};
+use reqwest::Url;
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::metrics::Metrics;
+
+// Define the `circuit_breaker` table of Rocket.toml, applied to every host called on its own
+#[derive(Debug, Clone, Deserialize)]
+pub struct BreakerConfig {
+    // Consecutive failures after which a host is not called again for `open_seconds`
+    #[serde(default = "failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "open_seconds")]
+    pub open_seconds: u64,
+}
+
+fn failure_threshold() -> u32 {
+    5
+}
+
+fn open_seconds() -> u64 {
+    30
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failure_threshold: failure_threshold(),
+            open_seconds: open_seconds(),
+        }
+    }
+}
+
+// Define the states of a circuit, with the value `circuit_breaker_state` reports for each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Calls go through
+    Closed,
+    // One trial call went through; its outcome closes the circuit or opens it again
+    HalfOpen,
+    // Calls fail fast until `until`
+    Open,
+}
+
+impl State {
+    fn gauge(self) -> i64 {
+        match self {
+            State::Closed => 0,
+            State::HalfOpen => 1,
+            State::Open => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    failures: u32,
+    // When an open circuit lets a trial through, or a trial that never reported is replaced
+    until: Instant,
+}
+
+// Define the refusal of a call to a host whose circuit is open
+#[derive(Debug, Clone)]
+pub struct Open {
+    pub host: String,
+    pub retry_in: Duration,
+}
+
+// Define the circuit breakers of outbound HTTP calls, one per host, managed as state: after
+// repeated failures a host is not called for a while, so a dead upstream fails fast instead of
+// making every caller wait for its timeout. Clones share the circuits.
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    config: BreakerConfig,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    state: IntGaugeVec,
+    rejected: IntCounterVec,
+}
+
+impl CircuitBreakers {
+    fn new(config: BreakerConfig) -> Result<Self, prometheus::Error> {
+        let state = IntGaugeVec::new(
+            Opts::new(
+                "circuit_breaker_state",
+                "State of the circuit of an outbound host: 0 closed, 1 half-open, 2 open",
+            ),
+            &["host"],
+        )?;
+        let rejected = IntCounterVec::new(
+            Opts::new(
+                "circuit_breaker_rejections_total",
+                "Outbound calls refused because the circuit of their host was open",
+            ),
+            &["host"],
+        )?;
+        Ok(CircuitBreakers {
+            config,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+            state,
+            rejected,
+        })
+    }
+
+    // Return the host and port a URL calls, which circuits are kept by
+    pub fn host_of(url: &str) -> String {
+        match Url::parse(url) {
+            Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+                (Some(host), Some(port)) => format!("{}:{}", host, port),
+                (Some(host), None) => host.to_string(),
+                _ => url.to_string(),
+            },
+            Err(_) => url.to_string(),
+        }
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_secs(self.config.open_seconds)
+    }
+
+    fn set(&self, host: &str, circuit: &mut Circuit, state: State) {
+        circuit.state = state;
+        self.state.with_label_values(&[host]).set(state.gauge());
+    }
+
+    // Ask to call `host`, refused while its circuit is open. Once the open period is over one
+    // call is let through as a trial, and the others keep failing fast until it reports.
+    pub fn admit(&self, host: &str) -> Result<(), Open> {
+        let mut circuits = self.circuits.lock().expect("circuit breaker lock");
+        let now = Instant::now();
+        let circuit = circuits.entry(host.to_string()).or_insert(Circuit {
+            state: State::Closed,
+            failures: 0,
+            until: now,
+        });
+        match circuit.state {
+            State::Closed => Ok(()),
+            State::Open | State::HalfOpen if circuit.until <= now => {
+                circuit.until = now + self.open_for();
+                self.set(host, circuit, State::HalfOpen);
+                Ok(())
+            }
+            State::Open | State::HalfOpen => {
+                self.rejected.with_label_values(&[host]).inc();
+                Err(Open {
+                    host: host.to_string(),
+                    retry_in: circuit.until - now,
+                })
+            }
+        }
+    }
+
+    // Record that a call to `host` got an answer, closing its circuit
+    pub fn succeeded(&self, host: &str) {
+        let mut circuits = self.circuits.lock().expect("circuit breaker lock");
+        let Some(circuit) = circuits.get_mut(host) else {
+            return;
+        };
+        if circuit.state != State::Closed {
+            info!("{} answers again, closing its circuit", host);
+        }
+        circuit.failures = 0;
+        self.set(host, circuit, State::Closed);
+    }
+
+    // Record that a call to `host` failed or timed out, opening its circuit after too many
+    pub fn failed(&self, host: &str) {
+        let mut circuits = self.circuits.lock().expect("circuit breaker lock");
+        let Some(circuit) = circuits.get_mut(host) else {
+            return;
+        };
+        circuit.failures += 1;
+        let open = match circuit.state {
+            State::HalfOpen => true,
+            State::Closed => circuit.failures >= self.config.failure_threshold,
+            State::Open => false,
+        };
+        if open {
+            warn!(
+                "{} failed {} times in a row, not calling it for {}s",
+                host, circuit.failures, self.config.open_seconds
+            );
+            circuit.until = Instant::now() + self.open_for();
+            self.set(host, circuit, State::Open);
+        }
+    }
+}
+
+// Load the circuit breaker settings and manage the breakers outbound clients share, exposing
+// the state of every circuit in `/metrics`
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Circuit Breakers", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<BreakerConfig>("circuit_breaker")
+        {
+            Ok(config) => config,
+            Err(err) if err.missing() => BreakerConfig::default(),
+            Err(err) => {
+                error!("invalid `circuit_breaker` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let breakers = CircuitBreakers::new(config).expect("valid metrics");
+        if let Some(metrics) = rocket.state::<Metrics>() {
+            let registered = metrics
+                .register(Box::new(breakers.state.clone()))
+                .and_then(|()| metrics.register(Box::new(breakers.rejected.clone())));
+            if let Err(err) = registered {
+                warn!("failed to register circuit breaker metrics: {}", err);
+            }
+        }
+        Ok(rocket.manage(breakers))
+    })
+}
@@ -0,0 +1,161 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_nats::HeaderMap;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::json;
+use rocket::tokio;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{error::RecvError, Receiver};
+use rocket::Shutdown;
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::events::{AppEvent, Events};
+
+// Define the `broker` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct BrokerConfig {
+    // Off in development, where no broker runs
+    #[serde(default)]
+    pub enabled: bool,
+    pub url: String,
+    // Events are published on `<subject_prefix>.<event name>`, such as `rocket_crate.todo.created`
+    #[serde(default = "subject_prefix")]
+    pub subject_prefix: String,
+}
+
+fn subject_prefix() -> String {
+    "rocket_crate".into()
+}
+
+// Define how domain events leave the server, so another broker can be plugged in without
+// touching the code that forwards them
+#[rocket::async_trait]
+pub trait Publisher: Send + Sync {
+    // Publish `payload` on `subject`; `id` is unique per message so consumers can drop repeats
+    async fn publish(&self, subject: String, id: &str, payload: Vec<u8>) -> Result<(), String>;
+}
+
+// Define the publisher sending events to a NATS server. The client reconnects on its own and
+// holds messages published meanwhile until its buffer fills.
+pub struct NatsPublisher {
+    client: async_nats::Client,
+}
+
+impl NatsPublisher {
+    pub async fn connect(url: &str) -> Result<NatsPublisher, String> {
+        let client = async_nats::ConnectOptions::new()
+            .name("rocket_crate")
+            // Connect in the background so a broker that is down does not hold up the server
+            .retry_on_initial_connect()
+            .connect(url)
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(NatsPublisher { client })
+    }
+}
+
+#[rocket::async_trait]
+impl Publisher for NatsPublisher {
+    async fn publish(&self, subject: String, id: &str, payload: Vec<u8>) -> Result<(), String> {
+        let mut headers = HeaderMap::new();
+        // Lets JetStream streams drop messages published twice
+        headers.insert("Nats-Msg-Id", id);
+        self.client
+            .publish_with_headers(subject, headers, payload.into())
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+// Publish every application event to the broker until the server shuts down
+async fn forward(
+    publisher: Box<dyn Publisher>,
+    prefix: String,
+    mut receiver: Receiver<AppEvent>,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        let event = select! {
+            received = receiver.recv() => match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("{} events were published too fast to reach the broker", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut shutdown => break,
+        };
+        let id = Uuid::new_v4().to_string();
+        let payload = json!({
+            "id": id,
+            "event": event.name,
+            "tenant_id": event.tenant_id,
+            "published_at": now(),
+            "data": event.data,
+        });
+        let subject = format!("{}.{}", prefix, event.name);
+        match publisher
+            .publish(subject.clone(), &id, payload.to_string().into_bytes())
+            .await
+        {
+            Ok(()) => debug!("published `{}` to the broker", subject),
+            Err(err) => warn!("failed to publish `{}` to the broker: {}", subject, err),
+        }
+    }
+    info!("event publishing stopped");
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Load the broker settings and, once the server is up, publish application events such as
+// `todo.created` to the broker
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Event Broker", |rocket| async {
+        let config = match rocket.figment().extract_inner::<BrokerConfig>("broker") {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return rocket,
+            Err(err) if err.missing() => return rocket,
+            Err(err) => {
+                warn!(
+                    "events are not published, invalid `broker` configuration: {}",
+                    err
+                );
+                return rocket;
+            }
+        };
+
+        rocket.attach(AdHoc::on_liftoff("Event Broker", |rocket| {
+            Box::pin(async move {
+                let Some(events) = rocket.state::<Events>() else {
+                    warn!("events are not published, the event channels are missing");
+                    return;
+                };
+                let publisher = match NatsPublisher::connect(&config.url).await {
+                    Ok(publisher) => publisher,
+                    Err(err) => {
+                        warn!(
+                            "events are not published, invalid broker `{}`: {}",
+                            config.url, err
+                        );
+                        return;
+                    }
+                };
+                info!("publishing events to {}", config.url);
+                tokio::spawn(forward(
+                    Box::new(publisher),
+                    config.subject_prefix,
+                    events.subscribe(),
+                    rocket.shutdown(),
+                ));
+            })
+        }))
+    })
+}
@@ -0,0 +1,100 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Shutdown, State};
+use rocket_ws::{Channel, Config, Message, WebSocket};
+use serde::Serialize;
+
+use crate::auth::{Reader, Requires};
+
+// Messages a member has not received yet; slower members miss the oldest ones
+const CAPACITY: usize = 128;
+
+// Longest chat message accepted, in bytes
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+// Define a message relayed to every member of the chat room
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    // Subject of the identity that sent the message
+    pub from: String,
+    pub text: String,
+    pub sent_at: u64,
+}
+
+// Define the chat room every `/ws` connection joins, managed as state
+pub struct ChatRoom {
+    sender: broadcast::Sender<ChatMessage>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Define a route handler for the "/ws" URL pattern that upgrades to a WebSocket joined to the
+// chat room. Callers authenticate before the upgrade, so anonymous clients get a plain 401.
+// Text frames are sent to every member, the sender included, as JSON chat messages.
+#[get("/ws")]
+fn chat(
+    user: Requires<Reader>,
+    ws: WebSocket,
+    room: &State<ChatRoom>,
+    mut shutdown: Shutdown,
+) -> Channel<'static> {
+    let sender = room.sender.clone();
+    let mut receiver = room.sender.subscribe();
+    let config = Config {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..Config::default()
+    };
+
+    ws.config(config).channel(move |mut stream| {
+        Box::pin(async move {
+            loop {
+                select! {
+                    incoming = stream.next() => match incoming {
+                        Some(Ok(Message::Text(text))) => {
+                            let _ = sender.send(ChatMessage {
+                                from: user.identity.subject.clone(),
+                                text,
+                                sent_at: now(),
+                            });
+                        }
+                        // Pings are answered by the protocol layer, and binary frames are ignored
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Err(err),
+                    },
+                    relayed = receiver.recv() => match relayed {
+                        Ok(message) => {
+                            let json = serde_json::to_string(&message).unwrap_or_default();
+                            stream.send(Message::Text(json)).await?;
+                        }
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut shutdown => {
+                        stream.close(None).await?;
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+// Manage the chat room and mount the WebSocket route
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Chat", |rocket| async {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        rocket.manage(ChatRoom { sender }).mount("/", routes![chat])
+    })
+}
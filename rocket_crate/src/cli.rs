@@ -0,0 +1,78 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use rocket::figment::providers::{Env, Format, Toml};
+use rocket::figment::{Figment, Profile};
+use rocket::Config;
+
+// Define the command line; every flag given wins over Rocket.toml and `ROCKET_*` variables
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Address to listen on, such as 0.0.0.0
+    #[arg(long)]
+    pub address: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Configuration profile to select, such as debug or release
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Configuration file to read instead of Rocket.toml
+    #[arg(long, value_name = "FILE", value_parser = existing_file)]
+    pub config: Option<PathBuf>,
+    /// Apply the database migrations and exit instead of serving
+    #[arg(long)]
+    pub migrate_only: bool,
+    /// Fill the database with demo accounts and todos before serving
+    #[arg(long)]
+    pub seed: bool,
+}
+
+// Accept a configuration file only if it exists, since a missing one would be read as empty
+fn existing_file(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    match path.is_file() {
+        true => Ok(path),
+        false => Err(format!("no file at `{}`", path.display())),
+    }
+}
+
+impl Cli {
+    // Return the configuration file read at launch, as `Config::figment` finds it
+    pub fn config_file(&self) -> PathBuf {
+        match &self.config {
+            Some(path) => path.clone(),
+            None => Env::var_or("ROCKET_CONFIG", "Rocket.toml").into(),
+        }
+    }
+
+    // Return Rocket's configuration as `rocket::build` would read it, with the flags applied
+    pub fn figment(&self) -> Figment {
+        let mut figment = match &self.config {
+            // Read like `Config::figment` does, only from another file
+            Some(path) => Figment::from(Config::default())
+                .merge(Toml::file_exact(path).nested())
+                .merge(Env::prefixed("ROCKET_").ignore(&["PROFILE"]).global())
+                .select(Profile::from_env_or(
+                    "ROCKET_PROFILE",
+                    Config::DEFAULT_PROFILE,
+                )),
+            None => Config::figment(),
+        };
+        if let Some(profile) = &self.profile {
+            figment = figment.select(profile.as_str());
+        }
+        if let Some(address) = self.address {
+            figment = figment.merge(("address", address));
+        }
+        if let Some(port) = self.port {
+            figment = figment.merge(("port", port));
+        }
+        if self.seed {
+            figment = figment.merge(("seed", true));
+        }
+        figment
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rocket::data::{ByteUnit, Data};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use serde::Deserialize;
+use tracing::warn;
+
+// Define the `compression` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // Bodies smaller than this many bytes are sent as they are
+    #[serde(default = "min_size")]
+    pub min_size: usize,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn min_size() -> usize {
+    1024
+}
+
+// Define the encodings this server can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn preference(self) -> u8 {
+        match self {
+            Encoding::Brotli => 1,
+            Encoding::Gzip => 0,
+        }
+    }
+
+    fn compress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(body)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// Pick the preferred encoding the client accepts, honoring `q=0` exclusions
+fn negotiate(req: &Request<'_>) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for value in req.headers().get("Accept-Encoding") {
+        for item in value.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let encoding = match name.as_str() {
+                "br" => Encoding::Brotli,
+                "gzip" | "x-gzip" => Encoding::Gzip,
+                _ => continue,
+            };
+            // Equal weights go to brotli, which compresses JSON better
+            let better = best.is_none_or(|(current, weight)| {
+                (quality, encoding.preference()) > (weight, current.preference())
+            });
+            if quality > 0.0 && better {
+                best = Some((encoding, quality));
+            }
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+// Return whether compressing a body of this type is worth it; images and archives already are
+fn is_compressible(content_type: &ContentType) -> bool {
+    content_type.top() == "text"
+        || content_type.is_json()
+        || content_type.is_javascript()
+        || content_type.is_xml()
+        || content_type.is_svg()
+}
+
+// Define why a request body could not be read
+#[derive(Debug, Clone)]
+pub enum BodyError {
+    // The body, or what it decompressed to, is over its limit
+    TooLarge(ByteUnit),
+    // The body is sent in an encoding other than gzip
+    Unsupported(String),
+    // The body could not be read or decompressed
+    Malformed(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> Status {
+        match self {
+            BodyError::TooLarge(_) => Status::PayloadTooLarge,
+            BodyError::Unsupported(_) => Status::UnsupportedMediaType,
+            BodyError::Malformed(_) => Status::BadRequest,
+        }
+    }
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "the request body is over {}", limit),
+            BodyError::Unsupported(encoding) => write!(
+                f,
+                "request bodies in the `{}` encoding are not accepted, send them as gzip or as they are",
+                encoding
+            ),
+            BodyError::Malformed(reason) => write!(f, "failed to read request body: {}", reason),
+        }
+    }
+}
+
+// Remember why the request body was refused, for the catchers
+struct BodyFailure(Option<String>);
+
+// Return why the request body of the current request was refused, if it was
+pub fn failure_reason(req: &Request<'_>) -> Option<String> {
+    req.local_cache(|| BodyFailure(None)).0.clone()
+}
+
+// Return whether the request body is sent gzip-compressed, or the other encoding it is sent in
+fn request_encoding(req: &Request<'_>) -> Result<bool, BodyError> {
+    match req.headers().get_one("Content-Encoding").map(str::trim) {
+        None => Ok(false),
+        Some(encoding) if encoding.eq_ignore_ascii_case("identity") => Ok(false),
+        Some(encoding)
+            if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") =>
+        {
+            Ok(true)
+        }
+        Some(encoding) => Err(BodyError::Unsupported(encoding.to_string())),
+    }
+}
+
+// Return whether the request body is sent gzip-compressed
+pub fn is_gzipped(req: &Request<'_>) -> bool {
+    request_encoding(req).unwrap_or(false)
+}
+
+// Read a request body of up to `limit` bytes, decompressing it when it is sent with
+// `Content-Encoding: gzip`. What it decompresses to is held to the same limit, so a small
+// compressed body cannot inflate past what the route accepts.
+pub async fn read_body(
+    req: &Request<'_>,
+    data: Data<'_>,
+    limit: ByteUnit,
+) -> Result<Vec<u8>, BodyError> {
+    let read = async {
+        let gzipped = request_encoding(req)?;
+        let body = data
+            .open(limit)
+            .into_bytes()
+            .await
+            .map_err(|err| BodyError::Malformed(err.to_string()))?;
+        if !body.is_complete() {
+            return Err(BodyError::TooLarge(limit));
+        }
+        let body = body.into_inner();
+        if !gzipped {
+            return Ok(body);
+        }
+        let mut inflated = Vec::new();
+        GzDecoder::new(body.as_slice())
+            .take(limit.as_u64() + 1)
+            .read_to_end(&mut inflated)
+            .map_err(|err| BodyError::Malformed(format!("invalid gzip body: {}", err)))?;
+        match inflated.len() as u64 > limit.as_u64() {
+            true => Err(BodyError::TooLarge(limit)),
+            false => Ok(inflated),
+        }
+    };
+    let read = read.await;
+    if let Err(err) = &read {
+        req.local_cache(|| BodyFailure(Some(err.to_string())));
+    }
+    read
+}
+
+// Compress response bodies over the configured size with the best encoding the client accepts
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Compression", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<CompressionConfig>("compression")
+        {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return rocket,
+            Err(err) if err.missing() => return rocket,
+            Err(err) => {
+                warn!(
+                    "compression disabled, invalid `compression` configuration: {}",
+                    err
+                );
+                return rocket;
+            }
+        };
+
+        rocket
+            .manage(config)
+            .attach(AdHoc::on_response("Compression", |req, res| {
+                Box::pin(async move {
+                    let Some(config) = req.rocket().state::<CompressionConfig>() else {
+                        return;
+                    };
+                    // Byte ranges refer to the stored bytes, so ranged responses stay as they are
+                    if !res.content_type().is_some_and(|ct| is_compressible(&ct))
+                        || res.headers().contains("Content-Encoding")
+                        || res.headers().contains("Accept-Ranges")
+                    {
+                        return;
+                    }
+                    // Whether the body is compressed depends on `Accept-Encoding`, so caches must key on it
+                    res.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+
+                    let Some(encoding) = negotiate(req) else {
+                        return;
+                    };
+                    // Streamed bodies have no size up front and are left alone
+                    match res.body().preset_size() {
+                        Some(size) if size >= config.min_size => {}
+                        _ => return,
+                    }
+
+                    let body = match res.body_mut().to_bytes().await {
+                        Ok(body) => body,
+                        Err(err) => {
+                            warn!("failed to read the response body to compress it: {}", err);
+                            return;
+                        }
+                    };
+                    match encoding.compress(&body) {
+                        Ok(compressed) => {
+                            res.set_header(Header::new("Content-Encoding", encoding.name()));
+                            res.set_sized_body(compressed.len(), io::Cursor::new(compressed));
+                        }
+                        Err(err) => {
+                            warn!("failed to compress the response body: {}", err);
+                            res.set_sized_body(body.len(), io::Cursor::new(body));
+                        }
+                    }
+                })
+            }))
+    })
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use rocket::fairing::AdHoc;
+use rocket::http::ContentType;
+use rocket::Config;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::auth::jwt::JwtConfig;
+use crate::auth::totp::TotpConfig;
+use crate::delay::DelayConfig;
+use crate::files::{SignedUrlConfig, UploadConfig};
+use crate::flags::FlagConfig;
+use crate::rate_limit::{Limit, RateLimitConfig};
+use crate::signature::SigningConfig;
+
+// Signing and encryption key Rocket.toml ships with for development
+const PLACEHOLDER_SECRET: &str = "change-me-in-production";
+
+// Shortest JWT signing key accepted in the release profile, in bytes, as HS256 keys should be
+// at least as long as the hash
+const MIN_JWT_SECRET_LEN: usize = 32;
+
+// Define the settings the application relies on, extracted from the whole configuration at launch
+// with `AdHoc::config` and managed as state. The rate limits and feature flags are those of the
+// launch; a configuration reload updates the stages keeping them, not this copy.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppConfig {
+    pub jwt: JwtConfig,
+    #[serde(default)]
+    pub delay: DelayConfig,
+    pub uploads: UploadConfig,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub feature_flags: FlagConfig,
+    // Read for their keys only, which must not be the placeholder in release
+    #[serde(default)]
+    pub signed_urls: Option<SignedUrlConfig>,
+    #[serde(default)]
+    pub totp: Option<TotpConfig>,
+    #[serde(default)]
+    pub request_signing: Option<SigningConfig>,
+}
+
+impl AppConfig {
+    // Return every problem with the settings, each naming the key at fault. The JWT secret and
+    // the other keys are only held to the release profile's standards there, so development
+    // keeps the placeholder.
+    pub fn validate(&self, release: bool) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.jwt.secret.is_empty() {
+            problems.push("`jwt.secret` is empty".to_string());
+        } else if release && self.jwt.secret == PLACEHOLDER_SECRET {
+            problems.push(
+                "`jwt.secret` is the development placeholder; set ROCKET_JWT={secret=\"...\"}"
+                    .to_string(),
+            );
+        } else if release && self.jwt.secret.len() < MIN_JWT_SECRET_LEN {
+            problems.push(format!(
+                "`jwt.secret` is {} bytes long, it must be at least {}",
+                self.jwt.secret.len(),
+                MIN_JWT_SECRET_LEN
+            ));
+        }
+        if release {
+            let keys = [
+                (
+                    "signed_urls.secret",
+                    "ROCKET_SIGNED_URLS={secret=\"...\"}",
+                    self.signed_urls.as_ref().map(|config| &config.secret),
+                ),
+                (
+                    "totp.encryption_key",
+                    "ROCKET_TOTP={encryption_key=\"...\"}",
+                    self.totp.as_ref().map(|config| &config.encryption_key),
+                ),
+                (
+                    "request_signing.secret",
+                    "ROCKET_REQUEST_SIGNING={secret=\"...\"}",
+                    self.request_signing.as_ref().map(|config| &config.secret),
+                ),
+            ];
+            for (key, setting, value) in keys {
+                if value.is_some_and(|value| value == PLACEHOLDER_SECRET) {
+                    problems.push(format!(
+                        "`{}` is the development placeholder; set {}",
+                        key, setting
+                    ));
+                }
+            }
+        }
+        if self.jwt.ttl == 0 {
+            problems.push("`jwt.ttl` must be at least 1 second".to_string());
+        }
+        if self.jwt.refresh_ttl <= self.jwt.ttl {
+            problems.push(format!(
+                "`jwt.refresh_ttl` ({}s) must be longer than `jwt.ttl` ({}s)",
+                self.jwt.refresh_ttl, self.jwt.ttl
+            ));
+        }
+
+        if self.delay.max_seconds == 0 {
+            problems.push("`delay.max_seconds` must be at least 1".to_string());
+        }
+
+        problems.extend(upload_problems(&self.uploads));
+        if let Some(rate_limit) = self.rate_limit.as_ref().filter(|config| config.enabled) {
+            problems.extend(rate_limit_problems(rate_limit));
+        }
+        for name in self.feature_flags.defaults.keys() {
+            if !is_flag_name(name) {
+                problems.push(format!(
+                    "feature flag `{}` must be lowercase letters, digits, `_` or `-`",
+                    name
+                ));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems),
+        }
+    }
+}
+
+fn upload_problems(uploads: &UploadConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    if uploads.dir.as_os_str().is_empty() {
+        problems.push("`uploads.dir` is empty".to_string());
+    } else if uploads.dir.exists() && !uploads.dir.is_dir() {
+        problems.push(format!(
+            "`uploads.dir` is `{}`, which is not a directory",
+            uploads.dir.display()
+        ));
+    }
+    for media_type in &uploads.allowed_types {
+        if ContentType::parse_flexible(media_type).is_none() {
+            problems.push(format!(
+                "`uploads.allowed_types` lists `{}`, which is not a media type",
+                media_type
+            ));
+        }
+    }
+    problems
+}
+
+fn rate_limit_problems(config: &RateLimitConfig) -> Vec<String> {
+    let limit_problems = |key: String, limit: &Limit| {
+        let mut problems = Vec::new();
+        if limit.requests == 0 {
+            problems.push(format!("`{}.requests` must be at least 1", key));
+        }
+        if limit.per_seconds == 0 {
+            problems.push(format!("`{}.per_seconds` must be at least 1", key));
+        }
+        problems
+    };
+
+    let mut problems = limit_problems("rate_limit.default".into(), &config.default);
+    let mut names = HashSet::new();
+    for group in &config.groups {
+        let key = format!("rate_limit.groups.{}", group.name);
+        if !names.insert(group.name.as_str()) {
+            problems.push(format!("`{}` is defined twice", key));
+        }
+        if group.prefixes.is_empty() {
+            problems.push(format!("`{}.prefixes` is empty", key));
+        }
+        for prefix in &group.prefixes {
+            if !prefix.starts_with('/') {
+                problems.push(format!(
+                    "`{}.prefixes` lists `{}`, which does not start with `/`",
+                    key, prefix
+                ));
+            }
+        }
+        problems.extend(limit_problems(key, &group.limit));
+    }
+    problems
+}
+
+fn is_flag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_' || ch == '-')
+}
+
+// Check the settings extracted by `AdHoc::config::<AppConfig>()`, which is attached before this
+// stage, refusing to launch with every problem logged
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Application Config", |rocket| async {
+        let release = rocket.figment().profile() == Config::RELEASE_PROFILE;
+        let Some(config) = rocket.state::<AppConfig>() else {
+            error!("the application configuration was not extracted");
+            return Err(rocket);
+        };
+        match config.validate(release) {
+            Ok(()) => Ok(rocket),
+            Err(problems) => {
+                for problem in &problems {
+                    error!("invalid configuration: {}", problem);
+                }
+                error!(
+                    "refusing to launch with {} configuration problem(s)",
+                    problems.len()
+                );
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Method};
+use rocket::response::status::NoContent;
+use serde::Deserialize;
+use tracing::error;
+
+// Define the `cors` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsConfig {
+    // Origins allowed to call the API, or `"*"` for any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    // Response headers that browser scripts are allowed to read
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    // Seconds a browser may cache the answer to a preflight request
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+impl CorsConfig {
+    // Return the value of `Access-Control-Allow-Origin` for a request from `origin`, if allowed
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        let any = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        if any && !self.allow_credentials {
+            Some("*".into())
+        } else if any || self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            // Browsers reject a wildcard on credentialed requests, so echo the origin instead
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+// Define a route handler that answers every preflight request; the response fairing adds the
+// CORS headers
+#[options("/<_path..>")]
+fn preflight(_path: PathBuf) -> NoContent {
+    NoContent
+}
+
+// Load the CORS policy, answer preflight requests and add the CORS headers to every response
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("CORS", |rocket| async {
+        let config = match rocket.figment().extract_inner::<CorsConfig>("cors") {
+            Ok(config) => config,
+            // Without a policy, browsers only get the same-origin access they have by default
+            Err(err) if err.missing() => return Ok(rocket),
+            Err(err) => {
+                error!("invalid `cors` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(config)
+            .mount("/", routes![preflight])
+            .attach(AdHoc::on_response("CORS Headers", |req, res| {
+                Box::pin(async move {
+                    let Some(config) = req.rocket().state::<CorsConfig>() else {
+                        return;
+                    };
+                    let Some(origin) = req.headers().get_one("Origin") else {
+                        return;
+                    };
+                    // Responses differ per origin, so caches must not share them across origins
+                    res.adjoin_header(Header::new("Vary", "Origin"));
+                    let Some(allowed) = config.allow_origin(origin) else {
+                        return;
+                    };
+
+                    res.set_header(Header::new("Access-Control-Allow-Origin", allowed));
+                    if config.allow_credentials {
+                        res.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+                    }
+
+                    let is_preflight = req.method() == Method::Options
+                        && req.headers().contains("Access-Control-Request-Method");
+                    if is_preflight {
+                        res.set_header(Header::new(
+                            "Access-Control-Allow-Methods",
+                            config.allowed_methods.join(", "),
+                        ));
+                        res.set_header(Header::new(
+                            "Access-Control-Allow-Headers",
+                            config.allowed_headers.join(", "),
+                        ));
+                        if let Some(max_age) = config.max_age {
+                            res.set_header(Header::new(
+                                "Access-Control-Max-Age",
+                                max_age.to_string(),
+                            ));
+                        }
+                    } else if !config.expose_headers.is_empty() {
+                        res.set_header(Header::new(
+                            "Access-Control-Expose-Headers",
+                            config.expose_headers.join(", "),
+                        ));
+                    }
+                })
+            })))
+    })
+}
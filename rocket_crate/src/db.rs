@@ -0,0 +1,224 @@
+use std::convert::Infallible;
+
+use rocket::fairing::{self, AdHoc};
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Build, Phase, Rocket};
+use rocket_db_pools::Database;
+use rocket_db_pools::{deadpool_redis, sqlx};
+use serde::Deserialize;
+use sqlx::migrate::{Migrate, Migrator};
+use tracing::{error, info, warn};
+
+use crate::errors::ApiError;
+
+// Define the application database, configured under `databases.app` in Rocket.toml.
+// The URL scheme selects the backend: `sqlite://` by default, `postgres://` in the
+// `postgres` profile.
+#[derive(Database)]
+#[database("app")]
+pub struct Db(sqlx::AnyPool);
+
+// Define the Redis server shared by every instance, configured under `databases.cache`.
+// It is optional: without it, state that needs to be shared is simply not shared.
+#[derive(Database, Clone)]
+#[database("cache")]
+pub struct Cache(deadpool_redis::Pool);
+
+// Define where sessions, rate limit counters and cached responses are kept, chosen by the
+// `cache_backend` setting. With "redis" they are shared by every instance through `Cache`, and
+// kept in memory for as long as Redis cannot be reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    #[default]
+    Memory,
+    Redis,
+}
+
+// Return the Redis pool when state is kept in Redis
+pub fn shared_cache<P: Phase>(rocket: &Rocket<P>) -> Option<&Cache> {
+    match rocket.state::<CacheBackend>() {
+        Some(CacheBackend::Redis) => Cache::fetch(rocket),
+        _ => None,
+    }
+}
+
+// Define a guard handing handlers the Redis pool when `cache_backend` is "redis"; it holds
+// nothing when state is kept in memory
+pub struct SharedCache<'r>(pub Option<&'r Cache>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SharedCache<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(SharedCache(shared_cache(req.rocket())))
+    }
+}
+
+// Define the connection and row types that the data layer functions operate on
+pub type DbConn = sqlx::AnyConnection;
+pub type DbRow = sqlx::any::AnyRow;
+
+// Define the database backends the schema is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn of(conn: &DbConn) -> Option<Backend> {
+        match conn.backend_name() {
+            "SQLite" => Some(Backend::Sqlite),
+            "PostgreSQL" => Some(Backend::Postgres),
+            _ => None,
+        }
+    }
+
+    // Return the migrations written for this backend, embedded in the binary from
+    // `migrations/<backend>`
+    fn migrator(self) -> &'static Migrator {
+        match self {
+            Backend::Sqlite => &SQLITE_MIGRATIONS,
+            Backend::Postgres => &POSTGRES_MIGRATIONS,
+        }
+    }
+}
+
+static SQLITE_MIGRATIONS: Migrator = sqlx::migrate!("migrations/sqlite");
+static POSTGRES_MIGRATIONS: Migrator = sqlx::migrate!("migrations/postgres");
+
+// Define the columns added to existing tables before migrations were introduced, as
+// (table, column, definition); databases that predate a column get it added at startup. New
+// columns belong in a migration.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("users", "totp_secret", "TEXT"),
+    ("users", "totp_enabled", "INTEGER NOT NULL DEFAULT 0"),
+    ("users", "totp_last_step", "BIGINT NOT NULL DEFAULT 0"),
+    ("users", "tenant_id", "TEXT NOT NULL DEFAULT 'default'"),
+    ("users", "active", "INTEGER NOT NULL DEFAULT 1"),
+    ("todos", "tenant_id", "TEXT NOT NULL DEFAULT 'default'"),
+];
+
+// Hide the details of a database failure from the client, they are only logged
+pub fn internal_error(err: sqlx::Error) -> ApiError {
+    ApiError::internal().with_cause(format!("database error: {}", err))
+}
+
+// Apply the pending migrations before the server starts accepting requests, refusing to start
+// when the database was migrated by a newer build, then add any missing legacy columns
+async fn migrate(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(db) = Db::fetch(&rocket) else {
+        return Err(rocket);
+    };
+    let mut conn = match db.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("failed to connect to the database: {}", err);
+            return Err(rocket);
+        }
+    };
+    let Some(backend) = Backend::of(&conn) else {
+        error!("unsupported database backend `{}`", conn.backend_name());
+        return Err(rocket);
+    };
+
+    let migrator = backend.migrator();
+    let pending = match pending_migrations(&mut conn, migrator).await {
+        Ok(pending) => pending,
+        Err(err) => {
+            error!("{}", err);
+            return Err(rocket);
+        }
+    };
+    if let Err(err) = migrator.run_direct(&mut *conn).await {
+        error!("failed to migrate the database: {}", err);
+        return Err(rocket);
+    }
+    if pending > 0 {
+        info!("applied {} database migrations", pending);
+    }
+    for (table, column, definition) in ADDED_COLUMNS {
+        // Selecting the column fails on both backends when it does not exist yet
+        let probe = format!("SELECT {column} FROM {table} LIMIT 0");
+        if sqlx::query(&probe).execute(&mut *conn).await.is_ok() {
+            continue;
+        }
+        let alter = format!("ALTER TABLE {table} ADD COLUMN {column} {definition}");
+        if let Err(err) = sqlx::query(&alter).execute(&mut *conn).await {
+            error!("failed to add column `{}.{}`: {}", table, column, err);
+            return Err(rocket);
+        }
+    }
+    drop(conn);
+    Ok(rocket)
+}
+
+// Return how many migrations the database lacks. Fails when it has migrations applied that this
+// build does not know, which means a newer build migrated it and this one may no longer read or
+// write it correctly.
+async fn pending_migrations(conn: &mut DbConn, migrator: &Migrator) -> Result<usize, String> {
+    conn.ensure_migrations_table()
+        .await
+        .map_err(|err| format!("failed to create the migrations table: {}", err))?;
+    let applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|err| format!("failed to read the applied migrations: {}", err))?
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    let latest = migrator
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+    if let Some(ahead) = applied.iter().filter(|version| **version > latest).max() {
+        return Err(format!(
+            "the database schema is at migration {}, ahead of this build, which knows migrations \
+             up to {}; run a build at least as new as the one that migrated it",
+            ahead, latest
+        ));
+    }
+    Ok(migrator
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .count())
+}
+
+// Install the SQLite and Postgres drivers, attach the database pool and migrate it,
+// then attach the Redis pool if one is configured and choose where shared state is kept
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Database", |rocket| async {
+        sqlx::any::install_default_drivers();
+        let rocket = rocket
+            .attach(Db::init())
+            .attach(AdHoc::try_on_ignite("Database Migrations", migrate));
+        let has_cache = rocket.figment().contains("databases.cache");
+        let rocket = match has_cache {
+            true => rocket.attach(Cache::init()),
+            false => rocket,
+        };
+
+        let backend = match rocket
+            .figment()
+            .extract_inner::<CacheBackend>("cache_backend")
+        {
+            Ok(backend) => backend,
+            Err(err) if err.missing() => CacheBackend::Memory,
+            Err(err) => {
+                warn!("keeping state in memory, invalid `cache_backend`: {}", err);
+                CacheBackend::Memory
+            }
+        };
+        let backend = match backend {
+            CacheBackend::Redis if !has_cache => {
+                warn!("keeping state in memory, `databases.cache` is not configured");
+                CacheBackend::Memory
+            }
+            backend => backend,
+        };
+        rocket.manage(backend)
+    })
+}
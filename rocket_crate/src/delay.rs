@@ -0,0 +1,161 @@
+use prometheus::IntGauge;
+use rand::Rng;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::FromParam;
+use rocket::tokio::select;
+use rocket::tokio::time::{sleep, Duration};
+use rocket::{Shutdown, State};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, warn};
+
+use crate::errors::ApiError;
+use crate::metrics::Metrics;
+
+// Define the `delay` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct DelayConfig {
+    // Longest sleep a request may ask for
+    pub max_seconds: u64,
+}
+
+impl Default for DelayConfig {
+    fn default() -> Self {
+        DelayConfig { max_seconds: 30 }
+    }
+}
+
+// Define the state of the delay route: its limit and how many requests are sleeping right now
+pub struct Delays {
+    max_seconds: u64,
+    sleeping: IntGauge,
+}
+
+// Count a request as sleeping until it is dropped, including when the client goes away mid-sleep
+struct Sleeping<'a>(&'a IntGauge);
+
+impl<'a> Sleeping<'a> {
+    fn start(gauge: &'a IntGauge) -> Self {
+        gauge.inc();
+        Sleeping(gauge)
+    }
+}
+
+impl Drop for Sleeping<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+// Define the length of a delay as written in the URL: whole seconds such as `2` or `2s`, or
+// milliseconds such as `250ms`
+#[derive(Debug, Clone, Copy)]
+struct DelayParam {
+    duration: Duration,
+    millis: bool,
+}
+
+impl<'a> FromParam<'a> for DelayParam {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let (amount, millis) = match param.strip_suffix("ms") {
+            Some(amount) => (amount, true),
+            None => (param.strip_suffix('s').unwrap_or(param), false),
+        };
+        let amount: u64 = amount.parse().map_err(|_| param)?;
+        let duration = match millis {
+            true => Duration::from_millis(amount),
+            false => Duration::from_secs(amount),
+        };
+        Ok(DelayParam { duration, millis })
+    }
+}
+
+// Spread a delay uniformly over ±`percent` of its length, so clients see realistic variance
+fn jittered(delay: Duration, percent: u8) -> Duration {
+    let spread = f64::from(percent) / 100.0;
+    let factor = 1.0 + rand::thread_rng().gen_range(-spread..=spread);
+    delay.mul_f64(factor)
+}
+
+// Define a route handler for the "/delay/<delay>?<jitter>" URL pattern, where `jitter` is a
+// percentage from 0 to 100 the delay may randomly vary by
+#[get("/delay/<delay>?<jitter>")]
+async fn delay(
+    delay: Result<DelayParam, &str>,
+    jitter: Option<u8>,
+    delays: &State<Delays>,
+    shutdown: Shutdown,
+) -> Result<String, ApiError> {
+    let out_of_range = || {
+        ApiError::bad_request(format!(
+            "delay must be whole seconds such as `2s` or milliseconds such as `250ms`, up to {} seconds",
+            delays.max_seconds
+        ))
+        .with_details(json!({ "max_seconds": delays.max_seconds }))
+    };
+    let delay = delay.map_err(|_| out_of_range())?;
+    let max = Duration::from_secs(delays.max_seconds);
+    if delay.duration > max {
+        return Err(out_of_range());
+    }
+    let duration = match jitter {
+        None | Some(0) => delay.duration,
+        Some(percent @ 1..=100) => jittered(delay.duration, percent).min(max),
+        Some(_) => {
+            return Err(ApiError::bad_request(
+                "jitter must be a percentage from 0 to 100",
+            ))
+        }
+    };
+
+    let _sleeping = Sleeping::start(&delays.sleeping);
+    // Asynchronously wait for the specified duration, giving up as soon as the server stops so
+    // the delay does not hold up the shutdown grace period
+    select! {
+        // Format a response string indicating the delay, in milliseconds unless whole seconds
+        // were asked for and slept
+        _ = sleep(duration) => Ok(match delay.millis || duration != delay.duration {
+            true => format!("Delayed response for {} milliseconds", duration.as_millis()),
+            false => format!("Delayed response for {} seconds", duration.as_secs()),
+        }),
+        _ = shutdown => Err(ApiError::new(
+            Status::ServiceUnavailable,
+            "server is shutting down, delay aborted",
+        )),
+    }
+}
+
+// Load the delay limit, publish the sleeping gauge and mount the delay route
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Delay", |rocket| async {
+        let config = match rocket.figment().extract_inner::<DelayConfig>("delay") {
+            Ok(config) => config,
+            Err(err) if err.missing() => DelayConfig::default(),
+            Err(err) => {
+                error!("invalid `delay` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        let sleeping = IntGauge::new(
+            "delay_requests_sleeping",
+            "Requests to /delay waiting out their delay",
+        )
+        .expect("valid metric");
+        if let Some(metrics) = rocket.state::<Metrics>() {
+            if let Err(err) = metrics.register(Box::new(sleeping.clone())) {
+                warn!("failed to register delay metrics: {}", err);
+            }
+        }
+
+        Ok(rocket
+            .manage(Delays {
+                max_seconds: config.max_seconds,
+                sleeping,
+            })
+            .mount("/", routes![delay]))
+    })
+}
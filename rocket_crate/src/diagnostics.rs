@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use data_encoding::BASE64;
+use rocket::data::{ByteUnit, Data};
+use rocket::fairing::AdHoc;
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::status;
+use rocket::route::{self, Handler, Route};
+use rocket::serde::json::{json, Json, Value};
+
+use crate::errors::ApiError;
+use crate::flags::{Diagnostics, Flag};
+
+// Define a guard collecting the request headers by lowercase name; repeated headers are joined
+// with ", " as HTTP allows
+pub struct RequestHeaders(BTreeMap<String, String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestHeaders {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let mut headers = BTreeMap::<String, String>::new();
+        for header in req.headers().iter() {
+            headers
+                .entry(header.name().as_str().to_ascii_lowercase())
+                .and_modify(|value| {
+                    value.push_str(", ");
+                    value.push_str(header.value());
+                })
+                .or_insert_with(|| header.value().to_string());
+        }
+        request::Outcome::Success(RequestHeaders(headers))
+    }
+}
+
+// Define a route handler for the "/status/<code>" URL pattern that answers with the status asked
+// for and an empty body, so clients can be tested against any response code
+#[get("/status/<code>")]
+fn status_code(_flag: Flag<Diagnostics>, code: u16) -> Result<status::Custom<()>, ApiError> {
+    if !(200..=599).contains(&code) {
+        return Err(ApiError::bad_request(format!(
+            "status must be from 200 to 599, not {}",
+            code
+        )));
+    }
+    Ok(status::Custom(Status::new(code), ()))
+}
+
+// Define a route handler for the "/headers" URL pattern that echoes the request headers
+#[get("/headers")]
+fn headers(_flag: Flag<Diagnostics>, headers: RequestHeaders) -> Json<Value> {
+    Json(json!({ "headers": headers.0 }))
+}
+
+// Define a route handler for the "/ip" URL pattern that returns the client address, taken from
+// the configured `ip_header` when the server runs behind a proxy
+#[get("/ip")]
+fn ip(_flag: Flag<Diagnostics>, ip: Option<IpAddr>) -> Json<Value> {
+    Json(json!({ "origin": ip }))
+}
+
+// Define a route handler for the "/user-agent" URL pattern that returns the client's User-Agent
+#[get("/user-agent")]
+fn user_agent(_flag: Flag<Diagnostics>, headers: RequestHeaders) -> Json<Value> {
+    Json(json!({ "user-agent": headers.0.get("user-agent") }))
+}
+
+// Bodies echoed by `/echo` are cut off after the `echo` limit, 64 KiB unless configured
+const ECHO_BODY_LIMIT: ByteUnit = ByteUnit::Kibibyte(64);
+
+// Define the handler of "/echo", which answers any method with a JSON description of the
+// request. OPTIONS is left to the CORS preflight route.
+#[derive(Clone)]
+struct Echo;
+
+#[rocket::async_trait]
+impl Handler for Echo {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        if req.guard::<Flag<Diagnostics>>().await.is_forward() {
+            return route::Outcome::forward(data, Status::NotFound);
+        }
+        let limit = req.limits().get("echo").unwrap_or(ECHO_BODY_LIMIT);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                let err = ApiError::bad_request(format!("failed to read request body: {}", err));
+                return route::Outcome::from(req, err);
+            }
+        };
+        let truncated = !body.is_complete();
+        let body = body.into_inner();
+        // Text is echoed as is; anything else is base64-encoded so the JSON stays valid
+        let (body, encoding) = match String::from_utf8(body) {
+            Ok(text) => (text, "utf-8"),
+            Err(err) => (BASE64.encode(err.as_bytes()), "base64"),
+        };
+
+        let query: Vec<(&str, &str)> = req
+            .query_fields()
+            .map(|field| (field.name.as_name().as_str(), field.value))
+            .collect();
+        let headers = match RequestHeaders::from_request(req).await {
+            request::Outcome::Success(headers) => headers.0,
+            _ => BTreeMap::new(),
+        };
+        let echo = json!({
+            "method": req.method().as_str(),
+            "path": req.uri().path().as_str(),
+            "query": query,
+            "headers": headers,
+            "body": body,
+            "body_encoding": encoding,
+            "truncated": truncated,
+        });
+        route::Outcome::from(req, Json(echo))
+    }
+}
+
+fn echo_routes() -> Vec<Route> {
+    [
+        Method::Get,
+        Method::Post,
+        Method::Put,
+        Method::Patch,
+        Method::Delete,
+    ]
+    .into_iter()
+    .map(|method| Route::new(method, "/echo", Echo))
+    .collect()
+}
+
+// Mount the httpbin-style routes used to test HTTP clients against this server, served while
+// the `diagnostics` flag is on
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Diagnostics", |rocket| async {
+        rocket
+            .mount("/", routes![status_code, headers, ip, user_agent])
+            .mount("/", echo_routes())
+    })
+}
@@ -0,0 +1,434 @@
+use std::convert::Infallible;
+
+use rocket::data::ByteUnit;
+use rocket::http::{Header, MediaType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::{json, Json, Value};
+use tracing::error;
+use utoipa::openapi::schema::{AdditionalProperties, ObjectBuilder, Schema, Type};
+use utoipa::openapi::RefOr;
+
+use crate::i18n::Locale;
+use crate::request_id::RequestId;
+use crate::{auth, compression, quota, telemetry, tenant, validation, versioning};
+
+// Define the error returned by handlers, rendered as the common JSON error envelope
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: Status,
+    pub message: String,
+    // Extra members merged into the envelope, such as per-field validation errors
+    pub details: Option<Value>,
+    // Extra response headers, such as `Retry-After`
+    pub headers: Vec<Header<'static>>,
+    // Internal cause, logged along with the request id but never sent to the client
+    pub cause: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+            details: None,
+            headers: Vec::new(),
+            cause: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::new(Status::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        ApiError::new(Status::Unauthorized, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(Status::NotFound, message)
+    }
+
+    pub fn internal() -> Self {
+        ApiError::new(
+            Status::InternalServerError,
+            "the request could not be completed",
+        )
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_header(mut self, header: Header<'static>) -> Self {
+        self.headers.push(header);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: impl ToString) -> Self {
+        self.cause = Some(cause.to_string());
+        self
+    }
+
+    // Build the envelope: {"code", "message", "request_id", ...details}
+    pub fn to_json(&self, req: &Request<'_>) -> Value {
+        let mut body = json!({
+            "code": code(self.status),
+            "message": self.message,
+            "request_id": RequestId::of(req),
+        });
+        if let (Some(body), Some(Value::Object(details))) = (body.as_object_mut(), &self.details) {
+            body.extend(details.clone());
+        }
+        body
+    }
+}
+
+// Describe the error envelope in the OpenAPI document; details such as per-field validation
+// errors are extra members
+impl utoipa::PartialSchema for ApiError {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .property("code", ObjectBuilder::new().schema_type(Type::String))
+            .required("code")
+            .property("message", ObjectBuilder::new().schema_type(Type::String))
+            .required("message")
+            .property("request_id", ObjectBuilder::new().schema_type(Type::String))
+            .required("request_id")
+            .additional_properties(Some(AdditionalProperties::FreeForm(true)))
+            .into()
+    }
+}
+
+impl utoipa::ToSchema for ApiError {}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        if let Some(cause) = &self.cause {
+            telemetry::span(req).in_scope(|| error!("{}", cause));
+        }
+        let body = Json(self.to_json(req));
+        let mut response = response::Response::build_from(body.respond_to(req)?);
+        response.status(self.status);
+        for header in self.headers {
+            response.header(header);
+        }
+        response.ok()
+    }
+}
+
+// Turn a status into a stable machine-readable code, e.g. 422 => "unprocessable_entity"
+pub fn code(status: Status) -> String {
+    status
+        .reason_lossy()
+        .to_ascii_lowercase()
+        .replace([' ', '-'], "_")
+        .replace('\'', "")
+}
+
+// Define a catcher for the 400 status code, which says why the tenant was refused when that was
+// the problem. Catchers answer in the locale of the request.
+#[catch(400)]
+pub fn bad_request(req: &Request) -> ApiError {
+    let message = tenant::failure_reason(req)
+        .or_else(|| compression::failure_reason(req))
+        .unwrap_or_else(|| Locale::of(req).text("errors.bad_request"));
+    ApiError::bad_request(message)
+}
+
+// Define a catcher for the 401 status code that explains why authentication failed
+#[catch(401)]
+pub fn unauthorized(req: &Request) -> ApiError {
+    let locale = Locale::of(req);
+    let message =
+        auth::failure_reason(req, &locale).unwrap_or_else(|| locale.text("errors.unauthorized"));
+    ApiError::unauthorized(message)
+}
+
+// Define a catcher for the 403 status code that explains which permission was missing
+#[catch(403)]
+pub fn forbidden(req: &Request) -> ApiError {
+    let locale = Locale::of(req);
+    let message =
+        auth::failure_reason(req, &locale).unwrap_or_else(|| locale.text("errors.forbidden"));
+    ApiError::new(Status::Forbidden, message)
+}
+
+// Routes suggested by the 404 catcher at most
+const MAX_SUGGESTIONS: usize = 5;
+
+// Return the number of single-character edits turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Return how far a request path is from a route's path, edited segment by segment: a dynamic
+// segment such as `<id>` matches any one segment and a trailing `<path..>` any number of them,
+// while a segment added or dropped costs its length
+fn route_distance(route: &[&str], path: &[&str]) -> usize {
+    let dynamic = |segment: &str| segment.starts_with('<') && segment.ends_with('>');
+    let mut previous: Vec<usize> = std::iter::once(0)
+        .chain(path.iter().scan(0, |cost, segment| {
+            *cost += segment.len().max(1);
+            Some(*cost)
+        }))
+        .collect();
+    for segment in route {
+        let rest = segment.starts_with('<') && segment.ends_with("..>");
+        let dropped = match rest {
+            true => 0,
+            false => segment.len().max(1),
+        };
+        let mut current = vec![previous[0] + dropped];
+        for (j, requested) in path.iter().enumerate() {
+            let cost = match (rest, dynamic(segment)) {
+                (true, _) => previous[j].min(current[j]),
+                (false, true) => previous[j],
+                (false, false) => previous[j] + edit_distance(segment, requested),
+            }
+            .min(previous[j + 1] + dropped)
+            .min(current[j] + requested.len().max(1));
+            current.push(cost);
+        }
+        previous = current;
+    }
+    previous[path.len()]
+}
+
+// Return the mounted routes closest to the request's path, same-method ones first, when none
+// matched it; a route that matched and answered 404 was missing the resource, not the route
+fn suggestions(req: &Request<'_>) -> Vec<Value> {
+    if req.route().is_some() {
+        return Vec::new();
+    }
+    let path: Vec<&str> = req.uri().path().segments().collect();
+    let mut near: Vec<(usize, String, String)> = req
+        .rocket()
+        .routes()
+        .filter_map(|route| {
+            let segments: Vec<&str> = route
+                .uri
+                .path()
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .collect();
+            // Routes matching any path, such as the CORS preflight, are close to everything
+            let fixed: usize = segments
+                .iter()
+                .filter(|segment| !segment.starts_with('<'))
+                .map(|segment| segment.len())
+                .sum();
+            if fixed == 0 {
+                return None;
+            }
+            // Typos in up to a third of the route's fixed text still count as near
+            let threshold = (fixed / 3).max(2);
+            let distance = route_distance(&segments, &path);
+            (distance <= threshold).then(|| {
+                let other_method = usize::from(route.method != req.method());
+                let method = route.method.as_str().to_string();
+                (
+                    distance + other_method,
+                    method,
+                    route.uri.path().to_string(),
+                )
+            })
+        })
+        .collect();
+    near.sort();
+    near.dedup_by(|a, b| (&a.1, &a.2) == (&b.1, &b.2));
+    near.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, method, path)| json!({ "method": method, "path": path }))
+        .collect()
+}
+
+// Define a catcher for the 404 status code that names the requested path and, when no route
+// matched it, lists the mounted routes it comes close to
+#[catch(404)]
+pub fn not_found(req: &Request) -> ApiError {
+    let path = req.uri().path();
+    ApiError::not_found(Locale::of(req).format(
+        "errors.no_route",
+        &[("method", &req.method()), ("path", &path)],
+    ))
+    .with_details(json!({ "path": path.as_str(), "did_you_mean": suggestions(req) }))
+}
+
+// Define a catcher for the 406 status code, which lists the supported API versions when an
+// unsupported one was asked for
+#[catch(406)]
+pub fn not_acceptable(req: &Request) -> ApiError {
+    versioning::failure(req).unwrap_or_else(|| {
+        ApiError::new(
+            Status::NotAcceptable,
+            Locale::of(req).text("errors.not_acceptable"),
+        )
+    })
+}
+
+// Define a catcher for the 422 status code that lists what was wrong with the request
+#[catch(422)]
+pub fn unprocessable_entity(req: &Request) -> ApiError {
+    validation::failure(req).unwrap_or_else(|| {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            Locale::of(req).text("errors.unprocessable_entity"),
+        )
+    })
+}
+
+// Return the names of Rocket's limits for bodies of a media type, which data guards read them from.
+// Each file of an upload is held to `file`, and the whole upload to `data-form`.
+fn media_limits(media_type: &MediaType) -> &'static [&'static str] {
+    if media_type.is_json() {
+        &["json"]
+    } else if media_type.is_msgpack() {
+        &["msgpack"]
+    } else if media_type.is_form() {
+        &["form"]
+    } else if media_type.is_form_data() {
+        &["file", "data-form"]
+    } else {
+        &[]
+    }
+}
+
+// Define a guard holding the limits the request body is held to, with their names: a limit named
+// after the first path segment, such as `graphql`, or else those of the body's media type
+pub struct BodyLimits(Vec<(String, ByteUnit)>);
+
+impl BodyLimits {
+    pub fn of(req: &Request<'_>) -> BodyLimits {
+        let limits = req.limits();
+        if let Some(segment) = req.uri().path().segments().get(0) {
+            if let Some(limit) = limits.get(segment) {
+                return BodyLimits(vec![(segment.to_string(), limit)]);
+            }
+        }
+        let Some(content_type) = req.content_type() else {
+            return BodyLimits(Vec::new());
+        };
+        BodyLimits(
+            media_limits(content_type.media_type())
+                .iter()
+                .filter_map(|name| limits.get(name).map(|limit| (name.to_string(), limit)))
+                .collect(),
+        )
+    }
+
+    // Return the 413 refusing a body over these limits, naming them
+    pub fn exceeded(&self) -> ApiError {
+        let message = match self.0.as_slice() {
+            [] => "the request body is too large".to_string(),
+            limits => {
+                let named: Vec<String> = limits
+                    .iter()
+                    .map(|(name, limit)| format!("the {} `{}` limit", limit, name))
+                    .collect();
+                format!("the request body is over {}", named.join(" or "))
+            }
+        };
+        let details: Vec<Value> = self
+            .0
+            .iter()
+            .map(|(name, limit)| {
+                json!({ "name": name, "bytes": limit.as_u64(), "size": limit.to_string() })
+            })
+            .collect();
+        ApiError::new(Status::PayloadTooLarge, message).with_details(json!({ "limits": details }))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BodyLimits {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(BodyLimits::of(req))
+    }
+}
+
+// Define a catcher for the 413 status code that says which configured limit the body went over
+#[catch(413)]
+pub fn payload_too_large(req: &Request) -> ApiError {
+    BodyLimits::of(req).exceeded()
+}
+
+// Define a catcher for the 429 status code that tells the caller when to come back
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> ApiError {
+    match quota::of(req) {
+        Some(quota) if quota.exceeded => quota.error(),
+        _ => {
+            let locale = Locale::of(req);
+            let message = auth::failure_reason(req, &locale)
+                .unwrap_or_else(|| locale.text("errors.too_many_requests"));
+            ApiError::new(Status::TooManyRequests, message)
+        }
+    }
+}
+
+// Define a catcher for the 503 status code, raised when a dependency such as the identity
+// provider is down
+#[catch(503)]
+pub fn service_unavailable(req: &Request) -> ApiError {
+    let locale = Locale::of(req);
+    let message = auth::failure_reason(req, &locale)
+        .unwrap_or_else(|| locale.text("errors.service_unavailable"));
+    ApiError::new(Status::ServiceUnavailable, message)
+}
+
+// Define a catcher for the 500 status code
+#[catch(500)]
+pub fn internal_error(req: &Request) -> ApiError {
+    ApiError::new(
+        Status::InternalServerError,
+        Locale::of(req).text("errors.internal"),
+    )
+}
+
+// Define a catcher for the 415 status code, which says which encoding was refused when that was
+// the problem
+#[catch(415)]
+pub fn unsupported_media_type(req: &Request) -> ApiError {
+    let message = compression::failure_reason(req)
+        .unwrap_or_else(|| Locale::of(req).text("errors.unsupported_media_type"));
+    ApiError::new(Status::UnsupportedMediaType, message)
+}
+
+// Define a fallback catcher so every other error status also gets a JSON body
+#[catch(default)]
+pub fn default(status: Status, _req: &Request) -> ApiError {
+    ApiError::new(status, status.reason_lossy())
+}
+
+// Return every catcher defined above
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        forbidden,
+        not_found,
+        not_acceptable,
+        payload_too_large,
+        unsupported_media_type,
+        unprocessable_entity,
+        too_many_requests,
+        service_unavailable,
+        internal_error,
+        default
+    ]
+}
@@ -0,0 +1,55 @@
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::negotiate;
+
+// Define a negotiated responder that tags the body with an ETag and answers 304 Not Modified when
+// the client's `If-None-Match` already names it. The tag is weak because compression may
+// re-encode the body on the way out without changing what it means.
+pub struct ETagged<T>(pub T);
+
+// Return the ETag of a serialized body
+fn tag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("W/\"{}\"", hex)
+}
+
+// Return whether `If-None-Match` matches `etag`, comparing weakly as RFC 9110 requires
+fn matches(req: &Request<'_>, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    req.headers()
+        .get("If-None-Match")
+        .flat_map(|value| value.split(','))
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for ETagged<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (content_type, body) = match negotiate::encode(req, &self.0) {
+            Ok(encoded) => encoded,
+            Err(error) => return error.respond_to(req),
+        };
+        // Each representation hashes differently, so the tag also tells them apart
+        let etag = tag(&body);
+
+        let mut response = Response::build();
+        response
+            .header(Header::new("ETag", etag.clone()))
+            .header(Header::new("Vary", "Accept"));
+        if matches(req, &etag) {
+            response.status(Status::NotModified);
+        } else {
+            response
+                .header(content_type)
+                .sized_body(body.len(), std::io::Cursor::new(body));
+        }
+        response.ok()
+    }
+}
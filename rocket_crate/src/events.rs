@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::{Json, Value};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::tokio::sync::watch;
+use rocket::tokio::time::sleep;
+use rocket::{Shutdown, State};
+use serde::Serialize;
+
+use crate::auth::{Reader, Requires};
+use crate::tenant::Tenant;
+
+// Events a subscriber has not read yet; slower subscribers skip the oldest ones
+const CAPACITY: usize = 64;
+
+// Idle streams get a comment this often so proxies do not close them
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+// Long polls wait this long unless the client asks otherwise, and never longer than the maximum
+const DEFAULT_POLL_TIMEOUT: u64 = 30;
+const MAX_POLL_TIMEOUT: u64 = 60;
+
+// Define an application event pushed to the `/events` subscribers of its tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct AppEvent {
+    // The SSE event name, such as `todo.created`
+    pub name: &'static str,
+    // Tenant whose data changed, the only one whose subscribers see the event
+    pub tenant_id: String,
+    pub data: Value,
+}
+
+// Define the channels handlers publish application events on, managed as state: a broadcast
+// for streams that must see every event, and a watch holding the latest one for long polls.
+// Clones publish on the same channels, for servers running beside Rocket.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<AppEvent>,
+    latest: watch::Sender<Option<AppEvent>>,
+}
+
+impl Events {
+    // Publish an event of `tenant` to the current subscribers; it is dropped when nobody listens
+    pub fn publish(&self, tenant: &str, name: &'static str, data: impl Serialize) {
+        let data = serde_json::to_value(data).unwrap_or(Value::Null);
+        let event = AppEvent {
+            name,
+            tenant_id: tenant.to_string(),
+            data,
+        };
+        self.latest.send_replace(Some(event.clone()));
+        let _ = self.sender.send(event);
+    }
+
+    // Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.sender.subscribe()
+    }
+}
+
+// Define a route handler for the "/events" URL pattern that streams the application events of
+// the caller's tenant as Server-Sent Events until the client disconnects or the server shuts down
+#[get("/events")]
+fn events(
+    _reader: Requires<Reader>,
+    tenant: Tenant,
+    events: &State<Events>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut receiver = events.sender.subscribe();
+    EventStream! {
+        loop {
+            let event = select! {
+                received = receiver.recv() => match received {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        yield Event::comment(format!("{} events skipped", skipped));
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            if event.tenant_id != tenant.id {
+                continue;
+            }
+            yield Event::json(&event.data).event(event.name);
+        }
+    }
+    .heartbeat(HEARTBEAT)
+}
+
+// Define the outcome of a long poll: the next event, or nothing once the timeout passed
+#[derive(Responder)]
+enum Poll {
+    Event(Json<AppEvent>),
+    Timeout(status::NoContent),
+}
+
+// Define a route handler for the "/poll?<timeout>" URL pattern that waits up to `timeout`
+// seconds for the next application event of the caller's tenant, answering 204 No Content if
+// none arrives in time
+#[get("/poll?<timeout>")]
+async fn poll(
+    _reader: Requires<Reader>,
+    tenant: Tenant,
+    timeout: Option<u64>,
+    events: &State<Events>,
+    mut shutdown: Shutdown,
+) -> Poll {
+    let timeout = timeout
+        .unwrap_or(DEFAULT_POLL_TIMEOUT)
+        .min(MAX_POLL_TIMEOUT);
+    // A fresh receiver has already seen the current event, so only later ones wake it
+    let mut latest = events.latest.subscribe();
+    let next = async {
+        while latest.changed().await.is_ok() {
+            let event = latest.borrow_and_update().clone();
+            // Events of other tenants are skipped, waiting on for one of this tenant
+            if let Some(event) = event.filter(|event| event.tenant_id == tenant.id) {
+                return Some(event);
+            }
+        }
+        None
+    };
+    select! {
+        event = next => match event {
+            Some(event) => Poll::Event(Json(event)),
+            None => Poll::Timeout(status::NoContent),
+        },
+        _ = sleep(Duration::from_secs(timeout)) => Poll::Timeout(status::NoContent),
+        _ = &mut shutdown => Poll::Timeout(status::NoContent),
+    }
+}
+
+// Manage the event channels and mount the stream and long-poll routes
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Events", |rocket| async {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let (latest, _) = watch::channel(None);
+        rocket
+            .manage(Events { sender, latest })
+            .mount("/", routes![events, poll])
+    })
+}
@@ -0,0 +1,133 @@
+use rand::Rng;
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Status};
+use rocket::tokio::time::{sleep, Duration};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::errors::ApiError;
+use crate::intercept;
+
+// Define the `faults` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Only paths starting with one of these prefixes are affected
+    pub prefixes: Vec<String>,
+    // Chance from 0 to 1 that a request is held back, and for how long
+    #[serde(default)]
+    pub latency_probability: f64,
+    #[serde(default)]
+    pub min_latency_ms: u64,
+    #[serde(default)]
+    pub max_latency_ms: u64,
+    // Chance from 0 to 1 that a request is answered with one of `error_statuses` instead
+    #[serde(default)]
+    pub error_probability: f64,
+    #[serde(default = "error_statuses")]
+    pub error_statuses: Vec<u16>,
+}
+
+fn error_statuses() -> Vec<u16> {
+    vec![500, 503]
+}
+
+impl FaultConfig {
+    fn validate(&self) -> Result<(), String> {
+        for (name, probability) in [
+            ("latency_probability", self.latency_probability),
+            ("error_probability", self.error_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!("`{}` must be from 0 to 1", name));
+            }
+        }
+        if self.min_latency_ms > self.max_latency_ms {
+            return Err("`min_latency_ms` is greater than `max_latency_ms`".into());
+        }
+        if self.error_statuses.is_empty() {
+            return Err("`error_statuses` is empty".into());
+        }
+        if let Some(status) = self
+            .error_statuses
+            .iter()
+            .find(|status| !(400..=599).contains(*status))
+        {
+            return Err(format!("`error_statuses` holds {}, not an error", status));
+        }
+        Ok(())
+    }
+
+    fn applies_to(&self, path: &str) -> bool {
+        self.prefixes.iter().any(|prefix| path.starts_with(prefix))
+    }
+}
+
+// Inject random latency and errors into the configured routes so clients can be tested against
+// a slow or failing server. Off unless `faults.enabled` is set.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Fault Injection", |rocket| async {
+        let config = match rocket.figment().extract_inner::<FaultConfig>("faults") {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return rocket,
+            Err(err) if err.missing() => return rocket,
+            Err(err) => {
+                warn!(
+                    "fault injection disabled, invalid `faults` configuration: {}",
+                    err
+                );
+                return rocket;
+            }
+        };
+        if let Err(err) = config.validate() {
+            warn!(
+                "fault injection disabled, invalid `faults` configuration: {}",
+                err
+            );
+            return rocket;
+        }
+        warn!(
+            "fault injection enabled for {}: {}% delayed, {}% failed",
+            config.prefixes.join(", "),
+            config.latency_probability * 100.0,
+            config.error_probability * 100.0
+        );
+
+        rocket
+            .manage(config)
+            .attach(AdHoc::on_request("Fault Injection", |req, _| {
+                Box::pin(async move {
+                    let Some(config) = req.rocket().state::<FaultConfig>() else {
+                        return;
+                    };
+                    if !config.applies_to(req.uri().path().as_str()) {
+                        return;
+                    }
+
+                    // The generator is not `Send`, so it is never held across the sleep
+                    let (latency, status) = {
+                        let mut rng = rand::thread_rng();
+                        let latency = rng
+                            .gen_bool(config.latency_probability)
+                            .then(|| rng.gen_range(config.min_latency_ms..=config.max_latency_ms));
+                        let status = rng.gen_bool(config.error_probability).then(|| {
+                            config.error_statuses[rng.gen_range(0..config.error_statuses.len())]
+                        });
+                        (latency, status)
+                    };
+
+                    if let Some(latency) = latency {
+                        debug!("injecting {}ms of latency into {}", latency, req.uri());
+                        sleep(Duration::from_millis(latency)).await;
+                    }
+                    if let Some(status) = status {
+                        debug!("injecting a {} response into {}", status, req.uri());
+                        let error = ApiError::new(Status::new(status), "injected fault")
+                            .with_header(Header::new("X-Fault-Injected", "true"));
+                        intercept::reject(req, error);
+                    }
+                })
+            }))
+    })
+}
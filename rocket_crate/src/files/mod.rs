@@ -0,0 +1,71 @@
+mod range;
+mod routes;
+mod signed;
+pub mod store;
+mod upload;
+
+use std::path::PathBuf;
+
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use tracing::error;
+use utoipa::OpenApi;
+
+pub use self::signed::SignedUrlConfig;
+
+// Define the `uploads` table of Rocket.toml. The size of each file and of a whole upload is
+// capped by the `file` and `data-form` entries of the `limits` table.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadConfig {
+    // Directory the uploaded files are stored in, created on launch
+    pub dir: PathBuf,
+    // Media types accepted for uploads, such as "image/png"
+    pub allowed_types: Vec<String>,
+}
+
+// Define the part of the OpenAPI document describing file uploads, downloads and signed links
+#[derive(OpenApi)]
+#[openapi(paths(routes::upload, routes::download, routes::share))]
+pub struct ApiDoc;
+
+// Load the upload and link signing settings, create the upload directory and mount the file
+// routes
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Files", |rocket| async {
+        let config = match rocket.figment().extract_inner::<UploadConfig>("uploads") {
+            Ok(config) => config,
+            Err(err) => {
+                error!("invalid `uploads` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let signing = match rocket
+            .figment()
+            .extract_inner::<SignedUrlConfig>("signed_urls")
+        {
+            Ok(signing) => signing,
+            Err(err) => {
+                error!("invalid `signed_urls` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        if let Err(err) = rocket::tokio::fs::create_dir_all(&config.dir).await {
+            error!(
+                "failed to create upload directory `{}`: {}",
+                config.dir.display(),
+                err
+            );
+            return Err(rocket);
+        }
+
+        Ok(rocket.manage(config).manage(signing).mount(
+            "/",
+            routes![
+                routes::upload,
+                routes::download,
+                routes::shared_download,
+                routes::share
+            ],
+        ))
+    })
+}
@@ -0,0 +1,161 @@
+use std::io::SeekFrom;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::store::StoredFile;
+use crate::errors::ApiError;
+use crate::transfer::Transfers;
+
+// Define the single byte range a client asked for, before the file size is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeSpec {
+    // `bytes=start-` or `bytes=start-end`, with `end` inclusive
+    From(u64, Option<u64>),
+    // `bytes=-length`, the last `length` bytes
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    fn parse(value: &str) -> Option<RangeSpec> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        // Several ranges would need a multipart body; answering with the whole file is allowed
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            return end.parse().ok().map(RangeSpec::Suffix);
+        }
+        let start = start.parse().ok()?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse().ok()?),
+        };
+        Some(RangeSpec::From(start, end))
+    }
+
+    // Turn the range into inclusive offsets within a file of `size` bytes, if any byte is in it
+    fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        match self {
+            RangeSpec::From(start, end) if start < size => {
+                let end = end.unwrap_or(u64::MAX).min(size - 1);
+                (start <= end).then_some((start, end))
+            }
+            RangeSpec::Suffix(length) if length > 0 && size > 0 => {
+                Some((size.saturating_sub(length), size - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Define a guard reading the `Range` header; malformed values are ignored as the RFC requires
+pub struct ByteRange(pub Option<RangeSpec>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ByteRange {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let range = req.headers().get_one("Range").and_then(RangeSpec::parse);
+        request::Outcome::Success(ByteRange(range))
+    }
+}
+
+// Define the response to a download: the whole file, or the part of it the client asked for
+pub struct Download {
+    file: StoredFile,
+    body: File,
+    size: u64,
+    // Inclusive byte offsets of a partial response
+    range: Option<(u64, u64)>,
+}
+
+impl Download {
+    // Open the stored file and resolve the requested range against its size
+    pub async fn open(
+        file: StoredFile,
+        mut body: File,
+        range: Option<RangeSpec>,
+    ) -> Result<Download, ApiError> {
+        let size = body
+            .metadata()
+            .await
+            .map_err(|err| ApiError::internal().with_cause(err))?
+            .len();
+        let range = match range {
+            None => None,
+            Some(spec) => match spec.resolve(size) {
+                Some(range) => Some(range),
+                None => {
+                    return Err(ApiError::new(
+                        Status::RangeNotSatisfiable,
+                        format!("the file is only {} bytes long", size),
+                    )
+                    .with_header(Header::new("Content-Range", format!("bytes */{}", size))));
+                }
+            },
+        };
+        if let Some((start, _)) = range {
+            body.seek(SeekFrom::Start(start))
+                .await
+                .map_err(|err| ApiError::internal().with_cause(err))?;
+        }
+        Ok(Download {
+            file,
+            body,
+            size,
+            range,
+        })
+    }
+}
+
+// Quote a file name for `Content-Disposition`, dropping what would end the quoted string
+fn disposition(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\\') && !c.is_control())
+        .collect();
+    format!("attachment; filename=\"{}\"", name)
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let transfers = req.rocket().state::<Transfers>().expect("transfer state");
+        let body = transfers.meter("download", "sent", self.body);
+        let mut response = Response::build();
+        response
+            .header(Header::new("Accept-Ranges", "bytes"))
+            .header(Header::new(
+                "Content-Disposition",
+                disposition(&self.file.name),
+            ));
+        if let Some(content_type) = ContentType::parse_flexible(&self.file.content_type) {
+            response.header(content_type);
+        }
+
+        match self.range {
+            Some((start, end)) => {
+                let length = end - start + 1;
+                response
+                    .status(Status::PartialContent)
+                    .header(Header::new(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, self.size),
+                    ))
+                    // `Take` cannot seek, so the length is announced by hand instead of measured
+                    .header(Header::new("Content-Length", length.to_string()))
+                    .streamed_body(body.take(length));
+            }
+            None => {
+                response.sized_body(self.size as usize, body);
+            }
+        }
+        response.max_chunk_size(transfers.chunk_size).ok()
+    }
+}
@@ -0,0 +1,192 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::range::{ByteRange, Download};
+use super::signed::{SignatureError, SignedUrl, SignedUrlConfig};
+use super::store::{self, StoredFile};
+use super::upload::UploadBody;
+use super::UploadConfig;
+use crate::auth::{Admin, Identity, Reader, Requires, RoleName};
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+
+type ApiResult<T> = Result<T, ApiError>;
+
+// Define a route handler that stores an uploaded file and returns its metadata.
+// Rocket streams the file to a temporary location while parsing the form, rejecting it with
+// 413 once it goes over the `file` limit or the whole form over `data-form`. A form sent with
+// `Content-Encoding: gzip` is held to the same limits once decompressed.
+#[utoipa::path(
+    tag = "files",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content_type = "multipart/form-data", description = "A form whose `file` field holds the file"),
+    responses(
+        (status = 201, description = "The stored file", body = StoredFile, headers(("Location" = String))),
+        (status = 413, description = "The file is over the upload limit", body = ApiError),
+        (status = 415, description = "The media type is not accepted", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[post("/files", data = "<upload>")]
+pub async fn upload(
+    reader: Requires<Reader>,
+    mut db: Connection<Db>,
+    config: &State<UploadConfig>,
+    upload: Result<UploadBody<'_>, ApiError>,
+) -> ApiResult<status::Created<Json<StoredFile>>> {
+    let mut file = upload?;
+    let content_type = file
+        .content_type()
+        .map(|ct| format!("{}/{}", ct.top(), ct.sub()).to_ascii_lowercase())
+        .unwrap_or_default();
+    if !config.allowed_types.contains(&content_type) {
+        return Err(ApiError::new(
+            Status::UnsupportedMediaType,
+            format!(
+                "files of type `{}` are not accepted, use one of: {}",
+                content_type,
+                config.allowed_types.join(", ")
+            ),
+        ));
+    }
+
+    // The client's file name is only kept as metadata; Rocket strips any path from it
+    let extension = file.content_type().and_then(|ct| ct.extension());
+    let name = match (file.name(), extension) {
+        (Some(stem), Some(extension)) => format!("{}.{}", stem, extension),
+        (Some(stem), None) => stem.to_string(),
+        (None, _) => "upload".to_string(),
+    };
+
+    let id = Uuid::new_v4().to_string();
+    let path = config.dir.join(&id);
+    let size = file.len() as i64;
+    file.persist(&path).await.map_err(|err| {
+        ApiError::internal().with_cause(format!("failed to store upload: {}", err))
+    })?;
+
+    let owner = &reader.identity.subject;
+    match store::create(&mut db, &id, &name, &content_type, size, owner).await {
+        Ok(stored) => Ok(status::Created::new(uri!(download(&id)).to_string()).body(Json(stored))),
+        Err(err) => {
+            // Without its metadata row the file could never be found again
+            let _ = rocket::tokio::fs::remove_file(&path).await;
+            Err(internal_error(err))
+        }
+    }
+}
+
+// Return the stored file if the caller owns it or is an administrator. Other users' files are
+// reported as missing rather than forbidden, so ids cannot be probed.
+async fn owned_file(
+    db: &mut Connection<Db>,
+    identity: &Identity,
+    id: &str,
+) -> ApiResult<StoredFile> {
+    let not_found = || ApiError::not_found(format!("file {} does not exist", id));
+    let file = store::get(db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    if file.owner != identity.subject && !identity.has_role(Admin::NAME) {
+        return Err(not_found());
+    }
+    Ok(file)
+}
+
+// Send a stored file, honoring `Range` requests so downloads can resume and media can seek
+async fn send(config: &UploadConfig, file: StoredFile, range: ByteRange) -> ApiResult<Download> {
+    let body = rocket::tokio::fs::File::open(config.dir.join(&file.id))
+        .await
+        .map_err(|err| {
+            ApiError::internal().with_cause(format!("stored file is missing: {}", err))
+        })?;
+    Download::open(file, body, range.0).await
+}
+
+// Define a route handler that sends a stored file to its owner or an administrator
+#[utoipa::path(
+    tag = "files",
+    security(("bearer" = []), ("api_key" = [])),
+    params(
+        ("Range" = Option<String>, Header, description = "One byte range, such as `bytes=0-1023`"),
+        ("expires" = Option<u64>, Query, description = "Expiry of a signed link, which needs no credentials"),
+        ("signature" = Option<String>, Query, description = "Signature of a signed link"),
+    ),
+    responses(
+        (status = 200, description = "The file", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range of the file", content_type = "application/octet-stream"),
+        (status = 404, description = "No such file of the caller", body = ApiError),
+        (status = 416, description = "The range is outside the file", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[get("/files/<id>", rank = 2)]
+pub async fn download(
+    reader: Requires<Reader>,
+    mut db: Connection<Db>,
+    config: &State<UploadConfig>,
+    id: &str,
+    range: ByteRange,
+) -> ApiResult<Download> {
+    let file = owned_file(&mut db, &reader.identity, id).await?;
+    send(config, file, range).await
+}
+
+// Define a route handler that sends a stored file to anyone holding a signed link to it; requests
+// without a signature fall through to the authenticated download
+#[get("/files/<id>", rank = 1)]
+pub async fn shared_download(
+    signed: Result<SignedUrl, SignatureError>,
+    mut db: Connection<Db>,
+    config: &State<UploadConfig>,
+    id: &str,
+    range: ByteRange,
+) -> ApiResult<Download> {
+    signed.map_err(|err| ApiError::new(Status::Forbidden, err.reason()))?;
+    let file = store::get(&mut db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| ApiError::not_found(format!("file {} does not exist", id)))?;
+    send(config, file, range).await
+}
+
+// Define the JSON body returned for a new signed link
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SharedLink {
+    url: String,
+    expires_at: u64,
+}
+
+// Define a route handler that gives the owner of a file, or an administrator, a link to it that
+// works without a bearer token until it expires, after `ttl` seconds when given
+#[utoipa::path(
+    tag = "files",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "A signed link to the file", body = SharedLink),
+        (status = 404, description = "No such file of the caller", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[post("/files/<id>/share?<ttl>")]
+pub async fn share(
+    reader: Requires<Reader>,
+    mut db: Connection<Db>,
+    signing: &State<SignedUrlConfig>,
+    id: &str,
+    ttl: Option<u64>,
+) -> ApiResult<Json<SharedLink>> {
+    let file = owned_file(&mut db, &reader.identity, id).await?;
+    let (url, expires_at) = signing.sign(&uri!(download(&file.id)).to_string(), ttl);
+    Ok(Json(SharedLink { url, expires_at }))
+}
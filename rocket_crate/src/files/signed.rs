@@ -0,0 +1,111 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+use sha2::Sha256;
+
+// Define the `signed_urls` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedUrlConfig {
+    // Key the links are signed with; override with ROCKET_SIGNED_URLS={secret="..."}
+    pub secret: String,
+    // Seconds a link stays valid when the caller does not ask for less, and the most allowed
+    pub default_ttl: u64,
+    pub max_ttl: u64,
+}
+
+// Define the reasons a signed link is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    Invalid,
+    Expired,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl SignatureError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SignatureError::Invalid => "link signature is invalid",
+            SignatureError::Expired => "link has expired",
+        }
+    }
+}
+
+impl SignedUrlConfig {
+    fn mac(&self, path: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts any key length");
+        mac.update(format!("GET\n{}\n{}", path, expires).as_bytes());
+        mac
+    }
+
+    // Return a link to `path` that works without credentials for `ttl` seconds, capped at
+    // `max_ttl`, along with the unix time it expires at
+    pub fn sign(&self, path: &str, ttl: Option<u64>) -> (String, u64) {
+        let ttl = ttl.unwrap_or(self.default_ttl).min(self.max_ttl);
+        let expires = now() + ttl;
+        let signature = HEXLOWER.encode(&self.mac(path, expires).finalize().into_bytes());
+        (
+            format!("{}?expires={}&signature={}", path, expires, signature),
+            expires,
+        )
+    }
+
+    fn verify(&self, path: &str, expires: u64, signature: &str) -> Result<(), SignatureError> {
+        let signature = HEXLOWER_PERMISSIVE
+            .decode(signature.as_bytes())
+            .map_err(|_| SignatureError::Invalid)?;
+        // Compared in constant time, so the signature cannot be guessed byte by byte
+        self.mac(path, expires)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+        if expires <= now() {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+}
+
+// Define a guard for requests made through a signed link: it forwards requests without a
+// signature, so the route with regular authentication can serve them, and fails with 403 for
+// tampered or expired links
+#[derive(Debug)]
+pub struct SignedUrl;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignedUrl {
+    type Error = SignatureError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let (Some(expires), Some(signature)) = (
+            req.query_value::<&str>("expires"),
+            req.query_value::<&str>("signature"),
+        ) else {
+            return request::Outcome::Forward(Status::Unauthorized);
+        };
+        let config = req
+            .rocket()
+            .state::<SignedUrlConfig>()
+            .expect("signed url config");
+        let result = match (expires, signature) {
+            (Ok(expires), Ok(signature)) => match expires.parse() {
+                Ok(expires) => config.verify(req.uri().path().as_str(), expires, signature),
+                Err(_) => Err(SignatureError::Invalid),
+            },
+            _ => Err(SignatureError::Invalid),
+        };
+        match result {
+            Ok(()) => request::Outcome::Success(SignedUrl),
+            Err(err) => request::Outcome::Error((Status::Forbidden, err)),
+        }
+    }
+}
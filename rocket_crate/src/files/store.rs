@@ -0,0 +1,71 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket_db_pools::sqlx::{self, Row};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::{DbConn, DbRow};
+
+// Define the metadata of an uploaded file as stored in the `files` table and returned by the API
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StoredFile {
+    pub id: String,
+    pub name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub owner: String,
+    pub created_at: i64,
+}
+
+const COLUMNS: &str = "id, name, content_type, size, owner, created_at";
+
+impl StoredFile {
+    fn from_row(row: &DbRow) -> Result<StoredFile, sqlx::Error> {
+        Ok(StoredFile {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            content_type: row.try_get("content_type")?,
+            size: row.try_get("size")?,
+            owner: row.try_get("owner")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+pub async fn create(
+    conn: &mut DbConn,
+    id: &str,
+    name: &str,
+    content_type: &str,
+    size: i64,
+    owner: &str,
+) -> Result<StoredFile, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "INSERT INTO files (id, name, content_type, size, owner, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING {COLUMNS}"
+    ))
+    .bind(id)
+    .bind(name)
+    .bind(content_type)
+    .bind(size)
+    .bind(owner)
+    .bind(now())
+    .fetch_one(conn)
+    .await?;
+    StoredFile::from_row(&row)
+}
+
+pub async fn get(conn: &mut DbConn, id: &str) -> Result<Option<StoredFile>, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT {COLUMNS} FROM files WHERE id = $1"))
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    row.as_ref().map(StoredFile::from_row).transpose()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
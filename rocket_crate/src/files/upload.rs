@@ -0,0 +1,166 @@
+use std::io;
+use std::path::Path;
+
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::form::{self, Form};
+use rocket::fs::{FileName, TempFile};
+use rocket::futures::stream;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+
+use crate::compression::{self, BodyError};
+use crate::errors::{ApiError, BodyLimits};
+
+// Define the multipart form accepted by `POST /files`
+#[derive(FromForm)]
+pub struct Upload<'r> {
+    file: TempFile<'r>,
+}
+
+// Define a file decompressed from a gzip-compressed upload and held in memory, which its
+// `data-form` limit keeps small enough
+pub struct InflatedFile {
+    name: Option<String>,
+    content_type: Option<ContentType>,
+    content: Vec<u8>,
+}
+
+// Define the body of `POST /files`: a multipart form as Rocket parses it while it arrives, or one
+// sent with `Content-Encoding: gzip`, which is decompressed whole and parsed here since Rocket's
+// form parser only reads bodies as they are
+pub enum UploadBody<'r> {
+    Form(Form<Upload<'r>>),
+    Inflated(InflatedFile),
+}
+
+impl UploadBody<'_> {
+    pub fn content_type(&self) -> Option<&ContentType> {
+        match self {
+            UploadBody::Form(upload) => upload.file.content_type(),
+            UploadBody::Inflated(file) => file.content_type.as_ref(),
+        }
+    }
+
+    // Return the client's file name, stripped of any path and extension
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            UploadBody::Form(upload) => upload.file.name(),
+            UploadBody::Inflated(file) => file.name.as_deref(),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            UploadBody::Form(upload) => upload.file.len(),
+            UploadBody::Inflated(file) => file.content.len() as u64,
+        }
+    }
+
+    // Store the file at `path`
+    pub async fn persist(&mut self, path: &Path) -> io::Result<()> {
+        match self {
+            UploadBody::Form(upload) => upload.file.move_copy_to(path).await,
+            UploadBody::Inflated(file) => rocket::tokio::fs::write(path, &file.content).await,
+        }
+    }
+}
+
+// Turn the errors of a form Rocket parsed into the error refusing the upload. A form cut off at
+// its limit also misses its fields, which Rocket would report instead.
+fn form_error(req: &Request<'_>, errors: form::Errors<'_>) -> ApiError {
+    if errors
+        .iter()
+        .any(|error| error.status() == Status::PayloadTooLarge)
+    {
+        return BodyLimits::of(req).exceeded();
+    }
+    let problems: Vec<String> = errors
+        .iter()
+        .map(|error| match &error.name {
+            Some(name) => format!("{}: {}", name, error),
+            None => error.to_string(),
+        })
+        .collect();
+    ApiError::new(
+        errors.status(),
+        format!("invalid upload: {}", problems.join("; ")),
+    )
+}
+
+// Decompress a gzip-compressed upload, held to the `data-form` limit once decompressed, and
+// take its `file` field, held to the `file` limit of its type as Rocket would
+async fn inflate(req: &Request<'_>, data: Data<'_>) -> Result<InflatedFile, ApiError> {
+    let limits = req.limits();
+    let form_limit = limits.get("data-form").unwrap_or(Limits::DATA_FORM);
+    let body = compression::read_body(req, data, form_limit)
+        .await
+        .map_err(|err| match err {
+            BodyError::TooLarge(_) => BodyLimits::of(req).exceeded(),
+            err => ApiError::new(err.status(), err.to_string()),
+        })?;
+    let boundary = req
+        .content_type()
+        .filter(|content_type| content_type.is_form_data())
+        .and_then(|content_type| content_type.param("boundary"))
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::UnsupportedMediaType,
+                "uploads must be multipart/form-data with a boundary",
+            )
+        })?
+        .to_string();
+
+    let malformed = |err: multer::Error| ApiError::bad_request(format!("invalid upload: {}", err));
+    let body = stream::once(async move { Ok::<_, io::Error>(body) });
+    let mut multipart = multer::Multipart::new(body, boundary);
+    while let Some(field) = multipart.next_field().await.map_err(malformed)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field
+            .file_name()
+            .and_then(|name| FileName::new(name).as_str())
+            .map(str::to_string);
+        let content_type = field
+            .content_type()
+            .and_then(|mime| ContentType::parse_flexible(mime.as_ref()));
+        let extension = content_type.as_ref().and_then(|ct| ct.extension());
+        let file_limit = extension
+            .and_then(|extension| limits.find(["file", extension.as_str()]))
+            .or_else(|| limits.get("file"))
+            .unwrap_or(Limits::FILE);
+        let content = field.bytes().await.map_err(malformed)?;
+        if content.len() as u64 > file_limit.as_u64() {
+            return Err(BodyLimits::of(req).exceeded());
+        }
+        return Ok(InflatedFile {
+            name,
+            content_type,
+            content: content.to_vec(),
+        });
+    }
+    Err(ApiError::new(
+        Status::UnprocessableEntity,
+        "invalid upload: file: missing",
+    ))
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for UploadBody<'r> {
+    type Error = ApiError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let upload = match compression::is_gzipped(req) {
+            true => inflate(req, data).await.map(UploadBody::Inflated),
+            false => match Form::<Upload<'r>>::from_data(req, data).await {
+                data::Outcome::Success(form) => Ok(UploadBody::Form(form)),
+                data::Outcome::Error((_, errors)) => Err(form_error(req, errors)),
+                data::Outcome::Forward(forward) => return data::Outcome::Forward(forward),
+            },
+        };
+        match upload {
+            Ok(upload) => data::Outcome::Success(upload),
+            Err(err) => data::Outcome::Error((err.status, err)),
+        }
+    }
+}
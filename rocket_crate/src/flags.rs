@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+use arc_swap::ArcSwap;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+use rocket_db_pools::deadpool_redis::redis;
+use rocket_db_pools::Database;
+use serde::Deserialize;
+use tracing::{info, warn};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::AdminUser;
+use crate::db::Cache;
+use crate::errors::ApiError;
+
+// Redis hash holding the toggled flags when they are shared between instances
+const REDIS_KEY: &str = "feature_flags";
+
+// Define the `feature_flags` table of Rocket.toml
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlagConfig {
+    // Keep toggles in Redis so every instance sees them and they survive restarts
+    #[serde(default)]
+    pub shared: bool,
+    // Every known flag and whether it is on until toggled
+    pub defaults: HashMap<String, bool>,
+}
+
+// Define the store of feature flags: the configured defaults and the toggles made at runtime.
+// Clones share both.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    shared: bool,
+    // Swapped when the configuration is reloaded
+    defaults: Arc<ArcSwap<BTreeMap<String, bool>>>,
+    // Toggles made through this instance, used when Redis is off or unreachable
+    toggled: Arc<RwLock<BTreeMap<String, bool>>>,
+}
+
+impl FeatureFlags {
+    // Return the current value of every flag; toggles stored in Redis win over local ones
+    async fn load(&self, cache: Option<&Cache>) -> BTreeMap<String, bool> {
+        let defaults = self.defaults.load_full();
+        let mut flags = (*defaults).clone();
+        flags.extend(self.toggled.read().expect("flags lock").clone());
+        if let (true, Some(cache)) = (self.shared, cache) {
+            let stored = match cache.get().await {
+                Ok(mut conn) => {
+                    redis::cmd("HGETALL")
+                        .arg(REDIS_KEY)
+                        .query_async::<_, HashMap<String, bool>>(&mut *conn)
+                        .await
+                }
+                Err(err) => {
+                    warn!("using local feature flags, Redis is unavailable: {}", err);
+                    Ok(HashMap::new())
+                }
+            };
+            match stored {
+                Ok(stored) => flags.extend(
+                    stored
+                        .into_iter()
+                        .filter(|(name, _)| defaults.contains_key(name)),
+                ),
+                Err(err) => warn!("using local feature flags, Redis command failed: {}", err),
+            }
+        }
+        flags
+    }
+
+    // Turn `name` on or off, locally and in Redis when shared
+    async fn set(&self, cache: Option<&Cache>, name: &str, enabled: bool) -> Result<(), ApiError> {
+        if let (true, Some(cache)) = (self.shared, cache) {
+            let mut conn = cache.get().await.map_err(|err| {
+                ApiError::new(
+                    Status::ServiceUnavailable,
+                    "feature flags store is unavailable",
+                )
+                .with_cause(err)
+            })?;
+            redis::cmd("HSET")
+                .arg(REDIS_KEY)
+                .arg(name)
+                .arg(enabled)
+                .query_async::<_, ()>(&mut *conn)
+                .await
+                .map_err(|err| ApiError::internal().with_cause(err))?;
+        }
+        self.toggled
+            .write()
+            .expect("flags lock")
+            .insert(name.to_string(), enabled);
+        Ok(())
+    }
+
+    // Replace the known flags and their defaults, forgetting local toggles of flags no longer
+    // known; toggles of the others still win over their new defaults
+    pub fn set_defaults(&self, defaults: HashMap<String, bool>) {
+        let defaults: BTreeMap<String, bool> = defaults.into_iter().collect();
+        self.toggled
+            .write()
+            .expect("flags lock")
+            .retain(|name, _| defaults.contains_key(name));
+        self.defaults.store(Arc::new(defaults));
+    }
+}
+
+// Define the value of every flag as seen by the current request, so handlers can branch on them
+pub struct Flags(BTreeMap<String, bool>);
+
+impl Flags {
+    // Whether `name` is on; flags missing from the configuration are off
+    pub fn enabled(&self, name: &str) -> bool {
+        self.0.get(name).copied().unwrap_or(false)
+    }
+}
+
+// Flags are read once per request, however many guards ask for them
+struct Loaded(Option<BTreeMap<String, bool>>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Flags {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let loaded = req
+            .local_cache_async(async {
+                let Some(flags) = req.rocket().state::<FeatureFlags>() else {
+                    return Loaded(None);
+                };
+                Loaded(Some(flags.load(Cache::fetch(req.rocket())).await))
+            })
+            .await;
+        request::Outcome::Success(Flags(loaded.0.clone().unwrap_or_default()))
+    }
+}
+
+// Define a flag that a route can be switched on and off with
+pub trait FlagName {
+    const NAME: &'static str;
+}
+
+// Define the flags known to this application
+pub struct Diagnostics;
+
+impl FlagName for Diagnostics {
+    const NAME: &'static str = "diagnostics";
+}
+
+// Define a guard that only succeeds while the flag `F` is on; routes behind a disabled flag
+// answer 404 as if they did not exist
+pub struct Flag<F: FlagName>(PhantomData<F>);
+
+#[rocket::async_trait]
+impl<'r, F: FlagName> FromRequest<'r> for Flag<F> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.guard::<Flags>().await {
+            request::Outcome::Success(flags) if flags.enabled(F::NAME) => {
+                request::Outcome::Success(Flag(PhantomData))
+            }
+            _ => request::Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+// Define the JSON body accepted by `PUT /admin/flags/<name>`
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = FlagToggle)]
+pub struct Toggle {
+    enabled: bool,
+}
+
+// Define a route handler that lists every flag with its current and default value
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "list_flags",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every flag with its current and default value", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/flags")]
+async fn list(
+    _admin: AdminUser,
+    store: &State<FeatureFlags>,
+    cache: Option<&Cache>,
+) -> Json<Value> {
+    let defaults = store.defaults.load_full();
+    let flags: serde_json::Map<String, Value> = store
+        .load(cache)
+        .await
+        .into_iter()
+        .map(|(name, enabled)| {
+            let default = defaults.get(&name).copied().unwrap_or_default();
+            (name, json!({ "enabled": enabled, "default": default }))
+        })
+        .collect();
+    Json(json!({ "flags": flags }))
+}
+
+// Define a route handler that turns a configured flag on or off
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "toggle_flag",
+    security(("bearer" = [])),
+    request_body = Toggle,
+    responses(
+        (status = 200, description = "The flag with its new value", body = Value),
+        (status = 404, description = "No such flag", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[put("/admin/flags/<name>", format = "json", data = "<toggle>")]
+async fn toggle(
+    admin: AdminUser,
+    store: &State<FeatureFlags>,
+    cache: Option<&Cache>,
+    name: &str,
+    toggle: Json<Toggle>,
+) -> Result<Json<Value>, ApiError> {
+    if !store.defaults.load().contains_key(name) {
+        return Err(ApiError::not_found(format!(
+            "flag `{}` does not exist",
+            name
+        )));
+    }
+    store.set(cache, name, toggle.enabled).await?;
+    info!(
+        enabled = toggle.enabled,
+        "feature flag `{}` switched by {}", name, admin.identity.subject
+    );
+    Ok(Json(json!({ "name": name, "enabled": toggle.enabled })))
+}
+
+// Define the part of the OpenAPI document describing the feature flag switches
+#[derive(OpenApi)]
+#[openapi(paths(list, toggle))]
+pub struct ApiDoc;
+
+// Read the `feature_flags` table, where none means no flags
+pub fn config(figment: &Figment) -> Result<FlagConfig, String> {
+    match figment.extract_inner::<FlagConfig>("feature_flags") {
+        Ok(config) => Ok(config),
+        Err(err) if err.missing() => Ok(FlagConfig::default()),
+        Err(err) => Err(format!("invalid `feature_flags` configuration: {}", err)),
+    }
+}
+
+// Load the configured flags and mount the routes that toggle them
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Feature Flags", |rocket| async {
+        let config = config(rocket.figment()).unwrap_or_else(|err| {
+            warn!("all feature flags off, {}", err);
+            FlagConfig::default()
+        });
+        if config.shared && !rocket.figment().contains("databases.cache") {
+            warn!("feature flags are not shared, `databases.cache` is not configured");
+        }
+
+        rocket
+            .manage(FeatureFlags {
+                shared: config.shared,
+                defaults: Arc::new(ArcSwap::from_pointee(config.defaults.into_iter().collect())),
+                toggled: Arc::new(RwLock::new(BTreeMap::new())),
+            })
+            .mount("/", routes![list, toggle])
+    })
+}
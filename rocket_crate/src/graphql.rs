@@ -0,0 +1,401 @@
+use std::sync::{Arc, Mutex};
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql::http::MultipartOptions;
+use async_graphql::{Context, EmptySubscription, ErrorExtensions, Object, Schema, SimpleObject};
+use async_graphql_rocket::GraphQLResponse;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::content::RawHtml;
+use rocket::State;
+use rocket_db_pools::sqlx::pool::PoolConnection;
+use rocket_db_pools::sqlx::{Any, AnyPool};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{error, warn};
+use validator::Validate;
+
+use crate::admin::UserSummary;
+use crate::auth::users;
+use crate::auth::{Admin, AdminNetwork, AuthError, Identity, RoleName};
+use crate::db::{internal_error, Db, SharedCache};
+use crate::errors::{self, ApiError, BodyLimits};
+use crate::events::Events;
+use crate::i18n::Locale;
+use crate::pagination::{Order, Pagination};
+use crate::response_cache::ResponseCache;
+use crate::tenant::Tenant;
+use crate::todos::store::{self, NewTodo, Todo, TodoPatch};
+use crate::todos::CACHE_PREFIX;
+use crate::validation;
+
+// Define the `graphql` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct GraphQlConfig {
+    // Serve the GraphiQL playground at `GET /graphql`; on in the `debug` profile only
+    #[serde(default)]
+    pub playground: bool,
+    // Deepest nesting of fields a query may select
+    #[serde(default = "max_depth")]
+    pub max_depth: usize,
+    // Most fields a query may select in total, counting each field as 1
+    #[serde(default = "max_complexity")]
+    pub max_complexity: usize,
+}
+
+fn max_depth() -> usize {
+    10
+}
+
+fn max_complexity() -> usize {
+    200
+}
+
+impl Default for GraphQlConfig {
+    fn default() -> Self {
+        GraphQlConfig {
+            playground: false,
+            max_depth: max_depth(),
+            max_complexity: max_complexity(),
+        }
+    }
+}
+
+pub type AppSchema = Schema<Query, Mutation, EmptySubscription>;
+
+// Define what resolvers know about the request they answer: the tenant, who the caller is as the
+// REST guards see it, and the events the mutations published, sent once the query has run
+struct Scope {
+    pool: AnyPool,
+    tenant: String,
+    identity: Result<Identity, AuthError>,
+    network: Result<AdminNetwork, AuthError>,
+    // Locale the errors of resolvers are written in
+    locale: Locale,
+    events: Mutex<Vec<(&'static str, Value)>>,
+}
+
+impl Scope {
+    fn of<'a>(ctx: &Context<'a>) -> &'a Scope {
+        ctx.data_unchecked::<Arc<Scope>>()
+    }
+
+    async fn conn(&self) -> Result<PoolConnection<Any>, async_graphql::Error> {
+        self.pool
+            .acquire()
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))
+    }
+
+    // Return the caller, or why its credentials were refused
+    fn identity(&self) -> Result<&Identity, ApiError> {
+        self.identity.as_ref().map_err(|err| {
+            let status = match err {
+                AuthError::ProviderUnavailable => Status::ServiceUnavailable,
+                AuthError::RateLimited(_) => Status::TooManyRequests,
+                _ => Status::Unauthorized,
+            };
+            ApiError::new(status, err.reason(&self.locale))
+        })
+    }
+
+    // Return the caller if it may act in the role `R`, by the same rules as `Requires<R>`
+    fn requires<R: RoleName>(&self) -> Result<&Identity, ApiError> {
+        if let (true, Err(err)) = (R::ADMIN_NETWORK, &self.network) {
+            return Err(ApiError::new(Status::Forbidden, err.reason(&self.locale)));
+        }
+        let identity = self.identity()?;
+        if !identity.has_role(R::NAME) {
+            Err(ApiError::new(
+                Status::Forbidden,
+                AuthError::MissingRole(R::NAME).reason(&self.locale),
+            ))
+        } else if R::SECOND_FACTOR && !identity.second_factor {
+            Err(ApiError::new(
+                Status::Forbidden,
+                AuthError::SecondFactorRequired(R::NAME).reason(&self.locale),
+            ))
+        } else {
+            Ok(identity)
+        }
+    }
+
+    fn publish(&self, name: &'static str, data: impl serde::Serialize) {
+        let data = serde_json::to_value(data).unwrap_or(Value::Null);
+        self.events
+            .lock()
+            .expect("graphql events lock")
+            .push((name, data));
+    }
+}
+
+// Turn an API error into a GraphQL error carrying the same code and details as the REST envelope
+fn graphql_error(err: ApiError) -> async_graphql::Error {
+    if let Some(cause) = &err.cause {
+        error!("{}", cause);
+    }
+    async_graphql::Error::new(err.message).extend_with(|_, extensions| {
+        extensions.set("code", errors::code(err.status));
+        if let Some(Value::Object(details)) = err.details {
+            for (name, value) in details {
+                if let Ok(value) = async_graphql::Value::from_json(value) {
+                    extensions.set(name, value);
+                }
+            }
+        }
+    })
+}
+
+// Run the `validator` rules of an input like the `Validated` guard does for JSON bodies
+fn validate(input: &impl Validate) -> Result<(), async_graphql::Error> {
+    input.validate().map_err(|errors| {
+        let fields = validation::field_messages(&errors);
+        graphql_error(
+            ApiError::new(Status::UnprocessableEntity, "the input failed validation")
+                .with_details(serde_json::json!({ "fields": fields })),
+        )
+    })
+}
+
+// Define one page of todos with the number of todos matching the filter
+#[derive(Debug, SimpleObject)]
+pub struct TodoPage {
+    items: Vec<Todo>,
+    total: i64,
+    page: u32,
+    per_page: u32,
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    // One page of the tenant's todos, like GET /todos
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1, validator(minimum = 1))] page: u32,
+        #[graphql(default = 20, validator(minimum = 1, maximum = 100))] per_page: u32,
+        sort: Option<String>,
+        #[graphql(default_with = "Order::Asc")] order: Order,
+        filter: Option<String>,
+    ) -> async_graphql::Result<TodoPage> {
+        let scope = Scope::of(ctx);
+        scope.identity().map_err(graphql_error)?;
+        let pagination = Pagination {
+            page,
+            per_page,
+            sort,
+            order,
+            filter,
+            cursor: None,
+        };
+        let sort = pagination
+            .sort_column(store::SORTABLE)
+            .map_err(|err| graphql_error(ApiError::bad_request(err)))?;
+        let mut conn = scope.conn().await?;
+        let (items, total) = store::list(&mut conn, &scope.tenant, &pagination, sort, None)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))?;
+        Ok(TodoPage {
+            items,
+            total,
+            page,
+            per_page,
+        })
+    }
+
+    // A todo of the tenant, or null when there is no such todo
+    async fn todo(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<Todo>> {
+        let scope = Scope::of(ctx);
+        scope.identity().map_err(graphql_error)?;
+        let mut conn = scope.conn().await?;
+        store::get(&mut conn, &scope.tenant, id)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))
+    }
+
+    // The caller, as recovered from its bearer token or API key
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<Identity> {
+        let identity = Scope::of(ctx).identity().map_err(graphql_error)?;
+        Ok(identity.clone())
+    }
+
+    // The users of the tenant, for administrators, like GET /admin/users
+    async fn users(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<UserSummary>> {
+        let scope = Scope::of(ctx);
+        scope.requires::<Admin>().map_err(graphql_error)?;
+        let mut conn = scope.conn().await?;
+        let users = users::list(&mut conn, &scope.tenant)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))?;
+        Ok(users.into_iter().map(UserSummary::from).collect())
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    // Create a todo, like POST /todos
+    async fn create_todo(&self, ctx: &Context<'_>, input: NewTodo) -> async_graphql::Result<Todo> {
+        validate(&input)?;
+        let scope = Scope::of(ctx);
+        scope.identity().map_err(graphql_error)?;
+        let mut conn = scope.conn().await?;
+        let todo = store::create(&mut conn, &scope.tenant, &input)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))?;
+        scope.publish("todo.created", &todo);
+        Ok(todo)
+    }
+
+    // Update the given fields of a todo, like PATCH /todos/<id>
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: TodoPatch,
+    ) -> async_graphql::Result<Todo> {
+        validate(&input)?;
+        let scope = Scope::of(ctx);
+        scope.identity().map_err(graphql_error)?;
+        let mut conn = scope.conn().await?;
+        let todo = store::update(&mut conn, &scope.tenant, id, &input)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))?
+            .ok_or_else(|| {
+                graphql_error(ApiError::not_found(format!("todo {} does not exist", id)))
+            })?;
+        scope.publish("todo.updated", &todo);
+        Ok(todo)
+    }
+
+    // Delete a todo, like DELETE /todos/<id>; false when there was no such todo
+    async fn delete_todo(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<bool> {
+        let scope = Scope::of(ctx);
+        scope.identity().map_err(graphql_error)?;
+        let mut conn = scope.conn().await?;
+        let deleted = store::delete(&mut conn, &scope.tenant, id)
+            .await
+            .map_err(|err| graphql_error(internal_error(err)))?;
+        if deleted {
+            scope.publish("todo.deleted", serde_json::json!({ "id": id }));
+        }
+        Ok(deleted)
+    }
+}
+
+// Define a route handler that runs a GraphQL query or mutation. Credentials are checked by the
+// same guards as the REST routes, but refusing them is left to the fields that need them, so a
+// query of fields that need none, such as introspection, still runs anonymously.
+#[allow(clippy::too_many_arguments)]
+#[post("/graphql", data = "<data>")]
+async fn graphql(
+    data: Data<'_>,
+    content_type: Option<&ContentType>,
+    limits: &Limits,
+    body_limits: BodyLimits,
+    schema: &State<AppSchema>,
+    tenant: Tenant,
+    identity: Result<Identity, AuthError>,
+    network: Result<AdminNetwork, AuthError>,
+    locale: Locale,
+    db: &State<Db>,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    events: &State<Events>,
+) -> Result<GraphQLResponse, ApiError> {
+    // The body is read whole first, so a query cut off at the `graphql` limit is refused with 413
+    // rather than reported as unparsable
+    let limit = limits.get("graphql").unwrap_or(128.kibibytes());
+    let body =
+        data.open(limit).into_bytes().await.map_err(|err| {
+            ApiError::bad_request(format!("failed to read request body: {}", err))
+        })?;
+    if !body.is_complete() {
+        return Err(body_limits.exceeded());
+    }
+    let request = async_graphql::http::receive_body(
+        content_type.map(|content_type| content_type.to_string()),
+        body.into_inner().as_slice(),
+        MultipartOptions::default(),
+    )
+    .await
+    .map_err(|err| ApiError::bad_request(format!("invalid GraphQL request: {}", err)))?;
+
+    let scope = Arc::new(Scope {
+        pool: AnyPool::clone(db),
+        tenant: tenant.id,
+        identity,
+        network,
+        locale,
+        events: Mutex::new(Vec::new()),
+    });
+    let response = schema.execute(request.data(scope.clone())).await;
+
+    let published = std::mem::take(&mut *scope.events.lock().expect("graphql events lock"));
+    if !published.is_empty() {
+        cache.invalidate(shared.0, CACHE_PREFIX).await;
+    }
+    for (name, data) in published {
+        events.publish(&scope.tenant, name, data);
+    }
+    Ok(response.into())
+}
+
+// Define the GraphiQL page, which loads its scripts and styles from unpkg.com. Shield keeps
+// headers a response already has, so this policy replaces the stricter configured one.
+#[derive(Responder)]
+struct Playground {
+    page: RawHtml<String>,
+    policy: Header<'static>,
+}
+
+// Define a route handler that serves the GraphiQL playground
+#[get("/graphql")]
+fn playground() -> Playground {
+    Playground {
+        page: RawHtml(
+            GraphiQLSource::build()
+                .endpoint("/graphql")
+                .title("rocket_crate GraphQL")
+                .finish(),
+        ),
+        policy: Header::new(
+            "Content-Security-Policy",
+            "default-src 'self'; script-src 'self' 'unsafe-inline' https://unpkg.com; \
+             style-src 'self' 'unsafe-inline' https://unpkg.com; \
+             font-src 'self' data: https://unpkg.com; img-src 'self' data:",
+        ),
+    }
+}
+
+// Build the GraphQL schema over the todo and user data and mount `POST /graphql`, with the
+// GraphiQL playground at `GET /graphql` when `graphql.playground` is set
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("GraphQL", |rocket| async {
+        let config = match rocket.figment().extract_inner::<GraphQlConfig>("graphql") {
+            Ok(config) => config,
+            Err(err) if err.missing() => GraphQlConfig::default(),
+            Err(err) => {
+                warn!(
+                    "invalid `graphql` configuration, using the defaults: {}",
+                    err
+                );
+                GraphQlConfig::default()
+            }
+        };
+        let schema = Schema::build(Query, Mutation, EmptySubscription)
+            .limit_depth(config.max_depth)
+            .limit_complexity(config.max_complexity)
+            .finish();
+
+        let rocket = rocket.manage(schema).mount("/", routes![graphql]);
+        match config.playground {
+            true => rocket.mount("/", routes![playground]),
+            false => rocket,
+        }
+    })
+}
@@ -0,0 +1,294 @@
+use std::net::{IpAddr, SocketAddr};
+
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+use rocket_db_pools::sqlx::pool::PoolConnection;
+use rocket_db_pools::sqlx::{self, Any, AnyPool};
+use rocket_db_pools::Database;
+use serde::Deserialize;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+use validator::Validate;
+
+use crate::auth::jwt::JwtConfig;
+use crate::auth::AuthError;
+use crate::db::{self, Cache, Db};
+use crate::events::Events;
+use crate::pagination::{Order, Pagination};
+use crate::response_cache::ResponseCache;
+use crate::tenant::Tenants;
+use crate::todos::store::{self, NewTodo, TodoPatch};
+use crate::todos::CACHE_PREFIX;
+use crate::validation;
+
+// Define the messages and service generated from proto/todos.proto
+pub mod proto {
+    tonic::include_proto!("todos.v1");
+}
+
+use self::proto::todos_server::{Todos, TodosServer};
+
+// Define the `grpc` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrpcConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Listens on Rocket's `address` unless given its own
+    pub address: Option<IpAddr>,
+    pub port: u16,
+}
+
+// Define the todo service, holding handles to the state the REST routes use: the database pool,
+// tenant registry, token settings, event channels and response cache
+#[derive(Clone)]
+struct TodoService {
+    pool: AnyPool,
+    tenants: Tenants,
+    jwt: JwtConfig,
+    events: Events,
+    cache: ResponseCache,
+    shared: Option<Cache>,
+}
+
+impl TodoService {
+    // Resolve the tenant named by the tenant header of the REST API, sent as metadata, for a
+    // caller holding a bearer token this server issued in that tenant
+    fn tenant<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let metadata = request.metadata();
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("a bearer token is required"))?;
+        let claims = self.jwt.verify(token.trim()).map_err(|err| match err {
+            AuthError::InvalidToken(reason) => Status::unauthenticated(reason),
+            _ => Status::unauthenticated("the bearer token is invalid"),
+        })?;
+
+        let named = metadata
+            .get(self.tenants.header())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let tenant = self
+            .tenants
+            .check(named)
+            .map_err(Status::invalid_argument)?;
+        if tenant != claims.tenant {
+            return Err(Status::permission_denied(
+                "the token was issued for another tenant",
+            ));
+        }
+        Ok(tenant)
+    }
+
+    async fn conn(&self) -> Result<PoolConnection<Any>, Status> {
+        self.pool.acquire().await.map_err(internal)
+    }
+
+    // Drop the cached REST responses and publish the change, as the REST routes do
+    async fn changed(&self, tenant: &str, name: &'static str, data: impl serde::Serialize) {
+        self.cache
+            .invalidate(self.shared.as_ref(), CACHE_PREFIX)
+            .await;
+        self.events.publish(tenant, name, data);
+    }
+}
+
+fn internal(err: sqlx::Error) -> Status {
+    error!("gRPC call failed: {}", err);
+    Status::internal("internal server error")
+}
+
+fn not_found(id: i64) -> Status {
+    Status::not_found(format!("todo {} does not exist", id))
+}
+
+// Refuse inputs breaking their `validator` rules, naming each field and what it broke
+fn validate(input: &impl Validate) -> Result<(), Status> {
+    input.validate().map_err(|errors| {
+        let fields: Vec<String> = validation::field_messages(&errors)
+            .into_iter()
+            .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+            .collect();
+        Status::invalid_argument(format!("invalid fields: {}", fields.join("; ")))
+    })
+}
+
+impl From<store::Todo> for proto::Todo {
+    fn from(todo: store::Todo) -> Self {
+        proto::Todo {
+            id: todo.id,
+            title: todo.title,
+            completed: todo.completed,
+            created_at: todo.created_at,
+            updated_at: todo.updated_at,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Todos for TodoService {
+    async fn list_todos(
+        &self,
+        request: Request<proto::ListTodosRequest>,
+    ) -> Result<Response<proto::ListTodosResponse>, Status> {
+        let tenant = self.tenant(&request)?;
+        let request = request.into_inner();
+        // Zero and empty fields mean the defaults of GET /todos
+        let pagination = Pagination {
+            page: request.page.max(1),
+            per_page: match request.per_page {
+                0 => 20,
+                per_page => per_page.min(100),
+            },
+            sort: Some(request.sort).filter(|sort| !sort.is_empty()),
+            order: match request.descending {
+                true => Order::Desc,
+                false => Order::Asc,
+            },
+            filter: Some(request.filter).filter(|filter| !filter.is_empty()),
+            cursor: None,
+        };
+        let sort = pagination
+            .sort_column(store::SORTABLE)
+            .map_err(Status::invalid_argument)?;
+        let mut conn = self.conn().await?;
+        let (todos, total) = store::list(&mut conn, &tenant, &pagination, sort, None)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::ListTodosResponse {
+            todos: todos.into_iter().map(proto::Todo::from).collect(),
+            total,
+        }))
+    }
+
+    async fn get_todo(
+        &self,
+        request: Request<proto::GetTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let tenant = self.tenant(&request)?;
+        let id = request.into_inner().id;
+        let mut conn = self.conn().await?;
+        match store::get(&mut conn, &tenant, id).await.map_err(internal)? {
+            Some(todo) => Ok(Response::new(todo.into())),
+            None => Err(not_found(id)),
+        }
+    }
+
+    async fn create_todo(
+        &self,
+        request: Request<proto::CreateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let tenant = self.tenant(&request)?;
+        let request = request.into_inner();
+        let todo = NewTodo {
+            title: request.title,
+            completed: request.completed,
+        };
+        validate(&todo)?;
+        let mut conn = self.conn().await?;
+        let todo = store::create(&mut conn, &tenant, &todo)
+            .await
+            .map_err(internal)?;
+        self.changed(&tenant, "todo.created", &todo).await;
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn update_todo(
+        &self,
+        request: Request<proto::UpdateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let tenant = self.tenant(&request)?;
+        let request = request.into_inner();
+        let patch = TodoPatch {
+            title: request.title,
+            completed: request.completed,
+        };
+        validate(&patch)?;
+        let mut conn = self.conn().await?;
+        match store::update(&mut conn, &tenant, request.id, &patch)
+            .await
+            .map_err(internal)?
+        {
+            Some(todo) => {
+                self.changed(&tenant, "todo.updated", &todo).await;
+                Ok(Response::new(todo.into()))
+            }
+            None => Err(not_found(request.id)),
+        }
+    }
+
+    async fn delete_todo(
+        &self,
+        request: Request<proto::DeleteTodoRequest>,
+    ) -> Result<Response<proto::DeleteTodoResponse>, Status> {
+        let tenant = self.tenant(&request)?;
+        let id = request.into_inner().id;
+        let mut conn = self.conn().await?;
+        match store::delete(&mut conn, &tenant, id)
+            .await
+            .map_err(internal)?
+        {
+            true => {
+                self.changed(&tenant, "todo.deleted", serde_json::json!({ "id": id }))
+                    .await;
+                Ok(Response::new(proto::DeleteTodoResponse {}))
+            }
+            false => Err(not_found(id)),
+        }
+    }
+}
+
+// Serve the todo service over gRPC on a second port once Rocket is up, sharing its database
+// pool and state, until the server shuts down
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("gRPC", |rocket| async {
+        let config = match rocket.figment().extract_inner::<GrpcConfig>("grpc") {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return rocket,
+            Err(err) if err.missing() => return rocket,
+            Err(err) => {
+                warn!("gRPC is not served, invalid `grpc` configuration: {}", err);
+                return rocket;
+            }
+        };
+
+        rocket.attach(AdHoc::on_liftoff("gRPC", move |rocket| {
+            Box::pin(async move {
+                let (Some(db), Some(tenants), Some(jwt), Some(events), Some(cache)) = (
+                    Db::fetch(rocket),
+                    rocket.state::<Tenants>(),
+                    rocket.state::<JwtConfig>(),
+                    rocket.state::<Events>(),
+                    rocket.state::<ResponseCache>(),
+                ) else {
+                    warn!("gRPC is not served, the application state is missing");
+                    return;
+                };
+                let service = TodoService {
+                    pool: AnyPool::clone(db),
+                    tenants: tenants.clone(),
+                    jwt: jwt.clone(),
+                    events: events.clone(),
+                    cache: cache.clone(),
+                    shared: db::shared_cache(rocket).cloned(),
+                };
+                let address = config.address.unwrap_or(rocket.config().address);
+                let address = SocketAddr::new(address, config.port);
+                let shutdown = rocket.shutdown();
+
+                info!("serving gRPC on {}", address);
+                tokio::spawn(async move {
+                    let served = tonic::transport::Server::builder()
+                        .add_service(TodosServer::new(service))
+                        .serve_with_shutdown(address, shutdown)
+                        .await;
+                    match served {
+                        Ok(()) => info!("gRPC server stopped"),
+                        Err(err) => error!("gRPC server on {} failed: {}", address, err),
+                    }
+                });
+            })
+        }))
+    })
+}
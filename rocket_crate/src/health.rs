@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{json, Value};
+use rocket::tokio::time::timeout;
+use rocket_db_pools::deadpool_redis::redis;
+use rocket_db_pools::sqlx;
+use utoipa::OpenApi;
+
+use crate::db::{Cache, Db};
+
+// Components that do not answer within this time are reported as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Define the result of probing one dependency
+struct Check {
+    name: &'static str,
+    // A required component being down makes the instance not ready; others only degrade it
+    required: bool,
+    error: Option<String>,
+}
+
+impl Check {
+    async fn run<F, E>(name: &'static str, required: bool, probe: F) -> Check
+    where
+        F: Future<Output = Result<(), E>>,
+        E: ToString,
+    {
+        let error = match timeout(CHECK_TIMEOUT, probe).await {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())),
+        };
+        Check {
+            name,
+            required,
+            error,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match &self.error {
+            None => json!({ "status": "up", "required": self.required }),
+            Some(error) => json!({ "status": "down", "required": self.required, "error": error }),
+        }
+    }
+}
+
+// Define a route handler for the liveness probe, which only shows the process is serving
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, description = "The process is serving", body = Value))
+)]
+#[get("/healthz")]
+fn healthz() -> Value {
+    json!({ "status": "ok" })
+}
+
+// Define a route handler for the readiness probe, which checks every backing service
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready, possibly with optional components down", body = Value),
+        (status = 503, description = "A required component is down", body = Value),
+    )
+)]
+#[get("/readyz")]
+async fn readyz(db: &Db, cache: Option<&Cache>) -> status::Custom<Value> {
+    let mut checks = vec![
+        Check::run("database", true, async {
+            sqlx::query("SELECT 1").execute(&**db).await.map(drop)
+        })
+        .await,
+    ];
+    // The cache is only attached when `databases.cache` is configured
+    if let Some(cache) = cache {
+        checks.push(
+            Check::run("cache", false, async {
+                let mut conn = cache.get().await.map_err(|err| err.to_string())?;
+                redis::cmd("PING")
+                    .query_async::<_, String>(&mut *conn)
+                    .await
+                    .map(drop)
+                    .map_err(|err| err.to_string())
+            })
+            .await,
+        );
+    }
+
+    let down = |required: bool| {
+        checks
+            .iter()
+            .any(|check| check.error.is_some() && check.required == required)
+    };
+    let (status, summary) = if down(true) {
+        (Status::ServiceUnavailable, "unavailable")
+    } else if down(false) {
+        (Status::Ok, "degraded")
+    } else {
+        (Status::Ok, "ok")
+    };
+
+    let components: serde_json::Map<String, Value> = checks
+        .iter()
+        .map(|check| (check.name.to_string(), check.to_json()))
+        .collect();
+    status::Custom(
+        status,
+        json!({ "status": summary, "components": components }),
+    )
+}
+
+// Define the part of the OpenAPI document describing the probes
+#[derive(OpenApi)]
+#[openapi(paths(healthz, readyz))]
+pub struct ApiDoc;
+
+// Mount the liveness and readiness probes
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Health", |rocket| async {
+        rocket.mount("/", routes![healthz, readyz])
+    })
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::http::Header;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+use tracing::{error, info};
+
+// Define the `i18n` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct I18nConfig {
+    // Directory of the message catalogs, one `<language>.toml` file per locale such as `ar.toml`
+    #[serde(default = "dir")]
+    pub dir: PathBuf,
+    // Locale of requests asking for none the catalogs cover, whose catalog also fills in the
+    // messages the others lack
+    #[serde(default = "default_locale")]
+    pub default: String,
+    // Query parameter naming a locale, which wins over `Accept-Language`, as in `?lang=ar`
+    #[serde(default = "param")]
+    pub param: String,
+}
+
+fn dir() -> PathBuf {
+    "locales".into()
+}
+
+fn default_locale() -> String {
+    "en".into()
+}
+
+fn param() -> String {
+    "lang".into()
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        I18nConfig {
+            dir: dir(),
+            default: default_locale(),
+            param: param(),
+        }
+    }
+}
+
+// Messages of a catalog keyed `<table>.<name>`, such as `errors.not_found`
+type Catalog = HashMap<String, String>;
+
+// Define the message catalogs, managed as state. Clones share the catalogs.
+#[derive(Clone)]
+pub struct I18n {
+    default: String,
+    param: String,
+    catalogs: Arc<HashMap<String, Catalog>>,
+}
+
+impl I18n {
+    async fn load(config: I18nConfig) -> Result<I18n, String> {
+        let unreadable = |err: std::io::Error| {
+            format!(
+                "failed to read catalogs in `{}`: {}",
+                config.dir.display(),
+                err
+            )
+        };
+        let mut entries = rocket::tokio::fs::read_dir(&config.dir)
+            .await
+            .map_err(unreadable)?;
+        let mut catalogs = HashMap::new();
+        while let Some(entry) = entries.next_entry().await.map_err(unreadable)? {
+            let path = entry.path();
+            let language = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("toml") => path.file_stem().and_then(|stem| stem.to_str()),
+                _ => None,
+            };
+            let Some(language) = language else {
+                continue;
+            };
+            let tables: HashMap<String, HashMap<String, String>> = Figment::from(Toml::file(&path))
+                .extract()
+                .map_err(|err| format!("invalid catalog `{}`: {}", path.display(), err))?;
+            let catalog: Catalog = tables
+                .into_iter()
+                .flat_map(|(table, messages)| {
+                    messages
+                        .into_iter()
+                        .map(move |(name, text)| (format!("{}.{}", table, name), text))
+                })
+                .collect();
+            catalogs.insert(language.to_ascii_lowercase(), catalog);
+        }
+        let default = config.default.to_ascii_lowercase();
+        if !catalogs.contains_key(&default) {
+            return Err(format!(
+                "no catalog for the default locale `{}` in `{}`",
+                default,
+                config.dir.display()
+            ));
+        }
+        Ok(I18n {
+            default,
+            param: config.param,
+            catalogs: Arc::new(catalogs),
+        })
+    }
+
+    // Return the locale whose catalog covers a language tag, so `ar-EG` is answered in `ar`
+    fn covering(&self, tag: &str) -> Option<String> {
+        let tag = tag.trim().to_ascii_lowercase();
+        if tag == "*" {
+            return Some(self.default.clone());
+        }
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        let covered = [tag.as_str(), language]
+            .into_iter()
+            .find(|candidate| self.catalogs.contains_key(*candidate))
+            .map(str::to_string);
+        covered
+    }
+
+    // Pick the locale of a request: the one its query parameter names, else the language it
+    // prefers most in `Accept-Language` that a catalog covers, else the default
+    fn negotiate(&self, req: &Request<'_>) -> String {
+        let named = req.query_value::<&str>(&self.param).and_then(Result::ok);
+        if let Some(locale) = named.and_then(|tag| self.covering(tag)) {
+            return locale;
+        }
+        let mut ranges: Vec<(f32, &str)> = req
+            .headers()
+            .get("Accept-Language")
+            .flat_map(|value| value.split(','))
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = match parts.find_map(|part| part.trim().strip_prefix("q=")) {
+                    Some(quality) => quality.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                (!tag.is_empty() && quality > 0.0).then_some((quality, tag))
+            })
+            .collect();
+        // The sort is stable, so languages of equal quality keep the client's order
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges
+            .into_iter()
+            .find_map(|(_, tag)| self.covering(tag))
+            .unwrap_or_else(|| self.default.clone())
+    }
+
+    fn text(&self, locale: &str, key: &str) -> Option<&str> {
+        [locale, self.default.as_str()]
+            .into_iter()
+            .find_map(|locale| self.catalogs.get(locale)?.get(key))
+            .map(String::as_str)
+    }
+}
+
+// Remember the locale picked for the current request, so its messages and its
+// `Content-Language` header agree
+struct Negotiated(Option<String>);
+
+// Define a guard for the locale a request is answered in, by the rules of `I18n::negotiate`
+#[derive(Clone)]
+pub struct Locale {
+    pub tag: String,
+    i18n: Option<I18n>,
+}
+
+impl Locale {
+    // Return the locale of the current request, picked once per request
+    pub fn of(req: &Request<'_>) -> Locale {
+        let i18n = req.rocket().state::<I18n>().cloned();
+        let negotiated = req.local_cache(|| {
+            Negotiated(Some(match &i18n {
+                Some(i18n) => i18n.negotiate(req),
+                None => default_locale(),
+            }))
+        });
+        Locale {
+            tag: negotiated.0.clone().unwrap_or_else(default_locale),
+            i18n,
+        }
+    }
+
+    // Return the default locale, for messages written to logs rather than sent to the client
+    pub fn server(req: &Request<'_>) -> Locale {
+        let i18n = req.rocket().state::<I18n>().cloned();
+        Locale {
+            tag: i18n
+                .as_ref()
+                .map_or_else(default_locale, |i18n| i18n.default.clone()),
+            i18n,
+        }
+    }
+
+    // Return the message `key`, such as `errors.not_found`, or the key itself when no catalog
+    // has it
+    pub fn text(&self, key: &str) -> String {
+        self.format(key, &[])
+    }
+
+    // Return the message `key` with each `{name}` in it replaced by the value given for `name`
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let Some(text) = self
+            .i18n
+            .as_ref()
+            .and_then(|i18n| i18n.text(&self.tag, key))
+        else {
+            return key.to_string();
+        };
+        args.iter().fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+    }
+
+    // Return the messages of a catalog table by name, such as the `pages` table the templates
+    // read, with those of the default catalog filling in the gaps
+    pub fn table(&self, table: &str) -> HashMap<String, String> {
+        let Some(i18n) = &self.i18n else {
+            return HashMap::new();
+        };
+        let prefix = format!("{}.", table);
+        [i18n.default.as_str(), self.tag.as_str()]
+            .into_iter()
+            .filter_map(|locale| i18n.catalogs.get(locale))
+            .flatten()
+            .filter_map(|(key, text)| Some((key.strip_prefix(&prefix)?.to_string(), text.clone())))
+            .collect()
+    }
+
+    // Return the direction the locale is written in, `ltr` or `rtl`, for the `dir` of pages
+    pub fn direction(&self) -> String {
+        match self.text("locale.direction").as_str() {
+            "rtl" => "rtl".into(),
+            _ => "ltr".into(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Locale {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Locale::of(req))
+    }
+}
+
+// Load the message catalogs and tell clients the language of every response a locale was picked
+// for, with `Content-Language` and `Vary: Accept-Language`
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("I18n", |rocket| async {
+        let config = match rocket.figment().extract_inner::<I18nConfig>("i18n") {
+            Ok(config) => config,
+            Err(err) if err.missing() => I18nConfig::default(),
+            Err(err) => {
+                error!("invalid `i18n` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let i18n = match I18n::load(config).await {
+            Ok(i18n) => i18n,
+            Err(err) => {
+                error!("{}", err);
+                return Err(rocket);
+            }
+        };
+        let mut locales: Vec<&str> = i18n.catalogs.keys().map(String::as_str).collect();
+        locales.sort_unstable();
+        info!(
+            "answering in {}, by default {}",
+            locales.join(", "),
+            i18n.default
+        );
+
+        Ok(rocket
+            .manage(i18n)
+            .attach(AdHoc::on_response("Content-Language", |req, res| {
+                Box::pin(async move {
+                    if let Some(locale) = &req.local_cache(|| Negotiated(None)).0 {
+                        res.set_header(Header::new("Content-Language", locale.clone()));
+                        res.adjoin_header(Header::new("Vary", "Accept-Language"));
+                    }
+                })
+            })))
+    })
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::data::Data;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::request::Request;
+use rocket::Response;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, warn};
+
+use crate::auth::session;
+use crate::errors::ApiError;
+use crate::intercept::{self, Canned};
+use crate::scheduler::{Scheduler, TaskFn};
+
+// Header clients send to make a POST or PUT safe to retry, and the header marking replays
+const KEY_HEADER: &str = "Idempotency-Key";
+const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+// Keys longer than this are refused rather than stored
+const MAX_KEY_LEN: usize = 255;
+
+// Most bytes of a request body a fairing can peek at without consuming it
+const PEEK_BYTES: usize = 512;
+
+// Define the `idempotency` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdempotencyConfig {
+    // Seconds a recorded response is replayed for before the key can be used again
+    pub ttl: u64,
+    // Keys beyond this count are not recorded until expired ones are evicted
+    pub max_entries: usize,
+}
+
+enum Progress {
+    // The first request with the key has not been answered yet
+    InFlight,
+    Done(Canned),
+}
+
+struct Entry {
+    // Method and URI of the request that first used the key, and the digest telling it apart
+    // from other requests to them
+    request: String,
+    fingerprint: String,
+    progress: Progress,
+    expires_at: Instant,
+}
+
+// Define the store of responses recorded per idempotency key, managed as state.
+// Each instance records on its own, so retries must reach the instance that saw the first try.
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+// Define what to do with a request carrying an idempotency key
+enum Lookup {
+    // First use of the key: run the request and record its response
+    Record,
+    Replay(Canned),
+    Reject(ApiError),
+}
+
+impl IdempotencyStore {
+    fn begin(&self, key: &str, request: &str, fingerprint: &str) -> Lookup {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("idempotency lock");
+        if let Some(entry) = entries.get(key).filter(|entry| entry.expires_at > now) {
+            if entry.request != request {
+                return Lookup::Reject(ApiError::new(
+                    Status::UnprocessableEntity,
+                    format!("{} was already used for {}", KEY_HEADER, entry.request),
+                ));
+            }
+            if entry.fingerprint != fingerprint {
+                return Lookup::Reject(ApiError::new(
+                    Status::UnprocessableEntity,
+                    format!(
+                        "{} was already used for {} with a different body",
+                        KEY_HEADER, entry.request
+                    ),
+                ));
+            }
+            return match &entry.progress {
+                Progress::InFlight => Lookup::Reject(
+                    ApiError::new(
+                        Status::Conflict,
+                        format!("a request with this {} is still in progress", KEY_HEADER),
+                    )
+                    .with_header(Header::new("Retry-After", "1")),
+                ),
+                Progress::Done(response) => Lookup::Replay(response.clone()),
+            };
+        }
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, entry| entry.expires_at > now);
+            if entries.len() >= self.max_entries {
+                warn!("idempotency store is full, not recording {}", request);
+                return Lookup::Reject(ApiError::new(
+                    Status::ServiceUnavailable,
+                    format!("too many {} values in use, retry later", KEY_HEADER),
+                ));
+            }
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                request: request.to_string(),
+                fingerprint: fingerprint.to_string(),
+                progress: Progress::InFlight,
+                expires_at: now + self.ttl,
+            },
+        );
+        Lookup::Record
+    }
+
+    // Return the scheduled task dropping keys past their TTL, which are otherwise only evicted
+    // once the store is full
+    fn purge_task(&self) -> TaskFn {
+        let entries = self.entries.clone();
+        Box::new(move || {
+            let entries = entries.clone();
+            Box::pin(async move {
+                let now = Instant::now();
+                let mut entries = entries.lock().expect("idempotency lock");
+                let before = entries.len();
+                entries.retain(|_, entry| entry.expires_at > now);
+                Ok(format!(
+                    "purged {} expired idempotency keys",
+                    before - entries.len()
+                ))
+            })
+        })
+    }
+
+    // Keep `response` for replays, or forget the key when the request should be retried for real
+    fn finish(&self, key: &str, response: Option<Canned>) {
+        let mut entries = self.entries.lock().expect("idempotency lock");
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.progress = Progress::Done(response);
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+}
+
+// Scope the key to the credentials of the caller, so one client can never be answered with a
+// response recorded for another
+fn scoped_key(req: &Request<'_>, key: &str) -> String {
+    let mut digest = Sha256::new();
+    for name in ["Authorization", "X-Api-Key", "X-Tenant-Id", "Host"] {
+        digest.update(req.headers().get_one(name).unwrap_or_default());
+        digest.update([0]);
+    }
+    let session = req.cookies().get(session::COOKIE);
+    digest.update(session.map(|cookie| cookie.value()).unwrap_or_default());
+    digest.update([0]);
+    digest.update(key);
+    format!("{:x}", digest.finalize())
+}
+
+// Digest the request's body so a key reused for another body is refused. Fairings only see the
+// first bytes of a body, so a longer one is told apart by those and its declared length.
+async fn fingerprint(req: &Request<'_>, data: &mut Data<'_>) -> String {
+    let mut digest = Sha256::new();
+    for name in ["Content-Type", "Content-Length", "Content-Encoding"] {
+        digest.update(req.headers().get_one(name).unwrap_or_default());
+        digest.update([0]);
+    }
+    digest.update(data.peek(PEEK_BYTES).await);
+    format!("{:x}", digest.finalize())
+}
+
+// Server errors and rate limiting are not recorded, so a retry runs the request again
+fn recordable(status: Status) -> bool {
+    status.code < 500 && status != Status::TooManyRequests
+}
+
+// Remember the key of a request whose response is being recorded
+struct Recording(Option<String>);
+
+async fn record(res: &mut Response<'_>) -> Option<Canned> {
+    let body = match res.body_mut().to_bytes().await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("failed to read the response to record it: {}", err);
+            return None;
+        }
+    };
+    res.set_sized_body(body.len(), Cursor::new(body.clone()));
+    let headers = res
+        .headers()
+        .iter()
+        .map(|header| Header::new(header.name().to_string(), header.value().to_string()))
+        .collect();
+    Some(Canned {
+        status: res.status(),
+        headers,
+        body,
+    })
+}
+
+// Define the fairing recording and replaying responses; a fairing of its own, as only those peek
+// at request bodies
+struct IdempotencyKeys;
+
+#[rocket::async_trait]
+impl Fairing for IdempotencyKeys {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency Keys",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        if !matches!(req.method(), Method::Post | Method::Put) {
+            return;
+        }
+        let Some(key) = req.headers().get_one(KEY_HEADER).map(str::to_string) else {
+            return;
+        };
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            let error = ApiError::bad_request(format!(
+                "{} must be 1 to {} characters",
+                KEY_HEADER, MAX_KEY_LEN
+            ));
+            return intercept::reject(req, error);
+        }
+        let Some(store) = req.rocket().state::<IdempotencyStore>() else {
+            return;
+        };
+        let scoped = scoped_key(req, &key);
+        let request = format!("{} {}", req.method(), req.uri());
+        let fingerprint = fingerprint(req, data).await;
+        match store.begin(&scoped, &request, &fingerprint) {
+            Lookup::Record => {
+                req.local_cache(|| Recording(Some(scoped)));
+            }
+            Lookup::Replay(mut response) => {
+                debug!("replaying the recorded response to {}", request);
+                response.headers.push(Header::new(REPLAYED_HEADER, "true"));
+                intercept::answer(req, response);
+            }
+            Lookup::Reject(error) => intercept::reject(req, error),
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(key) = req.local_cache(|| Recording(None)).0.as_ref() else {
+            return;
+        };
+        let Some(store) = req.rocket().state::<IdempotencyStore>() else {
+            return;
+        };
+        let response = match recordable(res.status()) {
+            true => record(res).await,
+            false => None,
+        };
+        store.finish(key, response);
+    }
+}
+
+// Record the response to POST and PUT requests carrying an Idempotency-Key header and answer
+// retries with the same key and body with that response instead of running them again. An
+// invalid table refuses to launch rather than letting retries run twice.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Idempotency", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<IdempotencyConfig>("idempotency")
+        {
+            Ok(config) => config,
+            Err(err) if err.missing() => return Ok(rocket),
+            Err(err) => {
+                error!("invalid `idempotency` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        info!(
+            "replaying responses to idempotent retries for {}s",
+            config.ttl
+        );
+
+        let store = IdempotencyStore {
+            ttl: Duration::from_secs(config.ttl),
+            max_entries: config.max_entries,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        };
+        if let Some(scheduler) = rocket.state::<Scheduler>() {
+            scheduler.register("idempotency_keys", "*/5 * * * *", store.purge_task());
+        }
+
+        Ok(rocket.manage(store).attach(IdempotencyKeys))
+    })
+}
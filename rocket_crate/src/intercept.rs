@@ -0,0 +1,82 @@
+use std::io::Cursor;
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Method, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+
+use crate::errors::ApiError;
+
+// Define a complete response kept aside to be sent again, such as a recorded reply
+#[derive(Debug, Clone)]
+pub struct Canned {
+    pub status: Status,
+    pub headers: Vec<Header<'static>>,
+    pub body: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for Canned {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(self.status);
+        for header in self.headers {
+            response.header(header);
+        }
+        response.sized_body(self.body.len(), Cursor::new(self.body));
+        Ok(response.finalize())
+    }
+}
+
+// Define the response a fairing decided on before routing
+#[derive(Debug, Clone)]
+enum Reply {
+    Error(ApiError),
+    Canned(Canned),
+}
+
+// Remember the response a fairing decided on before routing
+struct Intercepted(Option<Reply>);
+
+// Fairings cannot respond on their own, so the request is rewritten to an internal route that
+// sends the stored reply
+fn intercept(req: &mut Request<'_>, reply: Reply) {
+    req.local_cache(|| Intercepted(Some(reply)));
+    req.set_method(Method::Get);
+    req.set_uri(uri!("/__intercepted"));
+}
+
+// Answer the request with `error` instead of routing it to its handler
+pub fn reject(req: &mut Request<'_>, error: ApiError) {
+    intercept(req, Reply::Error(error));
+}
+
+// Answer the request with a response sent before instead of routing it to its handler
+pub fn answer(req: &mut Request<'_>, response: Canned) {
+    intercept(req, Reply::Canned(response));
+}
+
+// Define a responder that replays the reply stored by `reject` or `answer`
+struct Replay;
+
+impl<'r> Responder<'r, 'static> for Replay {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match req.local_cache(|| Intercepted(None)).0.clone() {
+            Some(Reply::Error(error)) => error.respond_to(req),
+            Some(Reply::Canned(response)) => response.respond_to(req),
+            None => ApiError::not_found("no route matches GET /__intercepted").respond_to(req),
+        }
+    }
+}
+
+// Define the internal route handler that answers intercepted requests
+#[get("/__intercepted")]
+fn intercepted() -> Replay {
+    Replay
+}
+
+// Mount the internal route used by `reject`
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Intercept", |rocket| async {
+        rocket.mount("/", routes![intercepted])
+    })
+}
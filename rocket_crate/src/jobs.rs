@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Status};
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::select;
+use rocket::tokio::sync::Semaphore;
+use rocket::tokio::{self, time::sleep};
+use rocket::{Shutdown, State};
+use rocket_db_pools::sqlx::AnyPool;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::auth::{Reader, Requires};
+use crate::db::Db;
+use crate::errors::ApiError;
+use crate::tenant::Tenant;
+use crate::todos::store as todos;
+
+// Longest sleep a `delay` job may ask for; unlike GET /delay it holds no connection open
+const MAX_JOB_DELAY: Duration = Duration::from_secs(3600);
+
+// Define the `jobs` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+    // Jobs running at the same time; later ones wait queued
+    pub workers: usize,
+    // Seconds a finished job is kept for lookups at least
+    pub retention: u64,
+    // Jobs kept at once, queued, running or finished; more are refused until some expire
+    pub max_jobs: usize,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        JobConfig {
+            workers: 4,
+            retention: 3600,
+            max_jobs: 1000,
+        }
+    }
+}
+
+// Define the work a job does, as sent in the body of `POST /jobs`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobSpec {
+    // Sleep, the background counterpart of GET /delay
+    Delay { milliseconds: u64 },
+    // Collect every todo of the tenant the job was created in
+    TodoExport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+// Define a job as reported by `GET /jobs/<id>`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub spec: JobSpec,
+    pub status: JobStatus,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    // Subject of the caller who created it; nobody else can look it up
+    #[serde(skip)]
+    owner: String,
+    #[serde(skip)]
+    tenant: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Define the job queue, managed as state: every known job by id, and the permits that let at
+// most `workers` of them run at once. Jobs live in memory and do not survive a restart.
+pub struct Jobs {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+    permits: Arc<Semaphore>,
+    retention: u64,
+    max_jobs: usize,
+}
+
+impl Jobs {
+    fn get(&self, id: &str, owner: &str) -> Option<Job> {
+        let jobs = self.jobs.lock().expect("jobs lock");
+        jobs.get(id).filter(|job| job.owner == owner).cloned()
+    }
+
+    fn owned_by(&self, owner: &str) -> Vec<Job> {
+        let jobs = self.jobs.lock().expect("jobs lock");
+        let mut owned: Vec<Job> = jobs
+            .values()
+            .filter(|job| job.owner == owner)
+            .cloned()
+            .collect();
+        owned.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        owned
+    }
+
+    // Store a new queued job, first dropping finished jobs past their retention
+    fn insert(&self, job: Job) -> Result<(), ApiError> {
+        let mut jobs = self.jobs.lock().expect("jobs lock");
+        let expired = now().saturating_sub(self.retention);
+        jobs.retain(|_, job| job.finished_at.is_none_or(|at| at > expired));
+        if jobs.len() >= self.max_jobs {
+            return Err(ApiError::new(
+                Status::ServiceUnavailable,
+                "too many jobs, retry later",
+            ));
+        }
+        jobs.insert(job.id.clone(), job);
+        Ok(())
+    }
+}
+
+// Update a job in place; it may have been evicted in the meantime
+fn update(jobs: &Mutex<HashMap<String, Job>>, id: &str, change: impl FnOnce(&mut Job)) {
+    if let Some(job) = jobs.lock().expect("jobs lock").get_mut(id) {
+        change(job);
+    }
+}
+
+async fn execute(spec: &JobSpec, tenant: &str, pool: &AnyPool) -> Result<Value, String> {
+    match spec {
+        JobSpec::Delay { milliseconds } => {
+            sleep(Duration::from_millis(*milliseconds)).await;
+            Ok(json!({ "slept_ms": milliseconds }))
+        }
+        JobSpec::TodoExport => {
+            let mut conn = pool.acquire().await.map_err(|err| err.to_string())?;
+            let todos = todos::all(&mut conn, tenant)
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok(json!({ "count": todos.len(), "todos": todos }))
+        }
+    }
+}
+
+// Wait for a free worker, then run the job and record how it ended
+async fn run(
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+    permits: Arc<Semaphore>,
+    pool: AnyPool,
+    id: String,
+    mut shutdown: Shutdown,
+) {
+    let Some((spec, tenant)) = jobs
+        .lock()
+        .expect("jobs lock")
+        .get(&id)
+        .map(|job| (job.spec.clone(), job.tenant.clone()))
+    else {
+        return;
+    };
+    let outcome = select! {
+        permit = permits.acquire_owned() => {
+            let Ok(_permit) = permit else { return };
+            update(&jobs, &id, |job| {
+                job.status = JobStatus::Running;
+                job.started_at = Some(now());
+            });
+            debug!("running job {}", id);
+            select! {
+                outcome = execute(&spec, &tenant, &pool) => outcome,
+                _ = &mut shutdown => Err("the server shut down before the job finished".into()),
+            }
+        },
+        _ = &mut shutdown => Err("the server shut down before the job started".into()),
+    };
+    update(&jobs, &id, |job| {
+        job.finished_at = Some(now());
+        match outcome {
+            Ok(result) => {
+                job.status = JobStatus::Done;
+                job.result = Some(result);
+            }
+            Err(error) => {
+                warn!("job {} failed: {}", job.id, error);
+                job.status = JobStatus::Failed;
+                job.error = Some(error);
+            }
+        }
+    });
+}
+
+// Define the answer to a queued job: 202 Accepted with a Location to follow its status at
+#[derive(Responder)]
+#[response(status = 202)]
+struct Queued {
+    job: Json<Job>,
+    location: Header<'static>,
+}
+
+// Define a route handler that queues a job and points to where its status can be followed
+#[utoipa::path(
+    tag = "jobs",
+    operation_id = "create_job",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = JobSpec,
+    responses(
+        (status = 202, description = "The queued job", body = Job, headers(("Location" = String))),
+        (status = 400, description = "The delay is too long", body = ApiError),
+        (status = 503, description = "Too many jobs are kept", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[post("/jobs", format = "json", data = "<spec>")]
+fn create(
+    reader: Requires<Reader>,
+    tenant: Tenant,
+    spec: Json<JobSpec>,
+    jobs: &State<Jobs>,
+    db: &State<Db>,
+    shutdown: Shutdown,
+) -> Result<Queued, ApiError> {
+    let spec = spec.into_inner();
+    if let JobSpec::Delay { milliseconds } = spec {
+        if Duration::from_millis(milliseconds) > MAX_JOB_DELAY {
+            return Err(ApiError::bad_request(format!(
+                "delay jobs sleep at most {}s",
+                MAX_JOB_DELAY.as_secs()
+            )));
+        }
+    }
+    let job = Job {
+        id: Uuid::new_v4().to_string(),
+        spec,
+        status: JobStatus::Queued,
+        result: None,
+        error: None,
+        created_at: now(),
+        started_at: None,
+        finished_at: None,
+        owner: reader.identity.subject,
+        tenant: tenant.id,
+    };
+    jobs.insert(job.clone())?;
+    tokio::spawn(run(
+        jobs.jobs.clone(),
+        jobs.permits.clone(),
+        AnyPool::clone(db),
+        job.id.clone(),
+        shutdown,
+    ));
+    let location = uri!(get(&job.id)).to_string();
+    Ok(Queued {
+        job: Json(job),
+        location: Header::new("Location", location),
+    })
+}
+
+// Define a route handler that lists the caller's jobs, newest first
+#[utoipa::path(
+    tag = "jobs",
+    operation_id = "list_jobs",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "The jobs of the caller, newest first", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[get("/jobs")]
+fn list(reader: Requires<Reader>, jobs: &State<Jobs>) -> Json<Value> {
+    Json(json!({ "jobs": jobs.owned_by(&reader.identity.subject) }))
+}
+
+// Define a route handler that reports the status of one of the caller's jobs, with its result
+// once done
+#[utoipa::path(
+    tag = "jobs",
+    operation_id = "get_job",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "The job, with its result once done", body = Job),
+        (status = 404, description = "No such job of the caller", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[get("/jobs/<id>")]
+fn get(reader: Requires<Reader>, id: &str, jobs: &State<Jobs>) -> Result<Json<Job>, ApiError> {
+    jobs.get(id, &reader.identity.subject)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("job {} does not exist", id)))
+}
+
+// Define the part of the OpenAPI document describing background jobs
+#[derive(OpenApi)]
+#[openapi(paths(create, list, get))]
+pub struct ApiDoc;
+
+// Load the job settings, manage the queue and mount its routes
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Jobs", |rocket| async {
+        let config = match rocket.figment().extract_inner::<JobConfig>("jobs") {
+            Ok(config) => config,
+            Err(err) if err.missing() => JobConfig::default(),
+            Err(err) => {
+                error!("invalid `jobs` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(Jobs {
+                jobs: Arc::new(Mutex::new(HashMap::new())),
+                permits: Arc::new(Semaphore::new(config.workers.max(1))),
+                retention: config.retention,
+                max_jobs: config.max_jobs,
+            })
+            .mount("/", routes![create, list, get]))
+    })
+}
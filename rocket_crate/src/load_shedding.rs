@@ -0,0 +1,131 @@
+use prometheus::IntCounter;
+use rocket::fairing::AdHoc;
+use rocket::http::{Header, Status};
+use rocket::serde::json::json;
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::errors::ApiError;
+use crate::intercept;
+use crate::metrics::Metrics;
+
+// Define the `load_shedding` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoadSheddingConfig {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // Requests handled at once beyond which new ones are refused
+    pub max_in_flight: i64,
+    // Seconds refused clients are told to wait before retrying
+    #[serde(default = "retry_after")]
+    pub retry_after: u64,
+    // Path prefixes served whatever the load, such as health probes and sign-in
+    #[serde(default = "protected")]
+    pub protected: Vec<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn retry_after() -> u64 {
+    5
+}
+
+fn protected() -> Vec<String> {
+    [
+        "/healthz",
+        "/readyz",
+        "/metrics",
+        "/login",
+        "/logout",
+        "/token",
+        "/password",
+        "/auth",
+        "/2fa",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+// Define the load shedder, refusing low-priority requests while too many are in flight so the
+// protected ones keep answering quickly
+struct LoadShedder {
+    config: LoadSheddingConfig,
+    shed: IntCounter,
+}
+
+impl LoadShedder {
+    fn protects(&self, path: &str) -> bool {
+        self.config
+            .protected
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    fn error(&self, in_flight: i64) -> ApiError {
+        let retry_after = self.config.retry_after;
+        ApiError::new(
+            Status::ServiceUnavailable,
+            format!("the server is overloaded, retry in {}s", retry_after),
+        )
+        .with_details(json!({
+            "load_shedding": { "in_flight": in_flight, "max_in_flight": self.config.max_in_flight }
+        }))
+        .with_header(Header::new("Retry-After", retry_after.to_string()))
+    }
+}
+
+// Answer requests outside the protected paths with 503 while more than `max_in_flight` are being
+// handled, counting them in `http_requests_shed_total`. The count is the one `/metrics` reports,
+// which includes the request being checked.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Load Shedding", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<LoadSheddingConfig>("load_shedding")
+        {
+            Ok(config) if config.enabled => config,
+            Ok(_) => return Ok(rocket),
+            Err(err) if err.missing() => return Ok(rocket),
+            Err(err) => {
+                error!("invalid `load_shedding` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let Some(metrics) = rocket.state::<Metrics>() else {
+            warn!("no load is shed, the metrics counting requests in flight are missing");
+            return Ok(rocket);
+        };
+        let shed = IntCounter::new(
+            "http_requests_shed_total",
+            "Requests refused because too many were in flight",
+        )
+        .expect("valid metric");
+        if let Err(err) = metrics.register(Box::new(shed.clone())) {
+            warn!("failed to register load shedding metrics: {}", err);
+        }
+
+        Ok(rocket
+            .manage(LoadShedder { config, shed })
+            .attach(AdHoc::on_request("Load Shedding", |req, _| {
+                Box::pin(async move {
+                    let (Some(shedder), Some(metrics)) = (
+                        req.rocket().state::<LoadShedder>(),
+                        req.rocket().state::<Metrics>(),
+                    ) else {
+                        return;
+                    };
+                    let in_flight = metrics.in_flight();
+                    if in_flight <= shedder.config.max_in_flight
+                        || shedder.protects(req.uri().path().as_str())
+                    {
+                        return;
+                    }
+                    shedder.shed.inc();
+                    let error = shedder.error(in_flight);
+                    intercept::reject(req, error);
+                })
+            })))
+    })
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use tracing::{error, info};
+
+// Define the `mail` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailConfig {
+    // How messages leave the server: "log" or "smtp"
+    pub transport: String,
+    pub from: String,
+    // Required by the "smtp" transport
+    pub smtp: Option<SmtpConfig>,
+}
+
+// Define the `mail.smtp` table of Rocket.toml; pass the password through the environment
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    // Defaults to the usual port of the TLS mode: 587, 465 or 25
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+// Define how the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    // Upgrade a plain connection with STARTTLS, refusing servers that do not offer it
+    #[default]
+    Starttls,
+    // Connect with TLS from the start
+    Tls,
+    // Send in the clear, for local relays and development servers only
+    None,
+}
+
+// Define an email ready to be handed to a transport
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Define how emails are delivered, so SMTP or an email API can be plugged in without touching
+// the code that sends them
+#[rocket::async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String>;
+}
+
+// Define the development transport, which writes emails to the log instead of sending them
+pub struct LogTransport;
+
+#[rocket::async_trait]
+impl Transport for LogTransport {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        info!(
+            from,
+            to = %email.to,
+            subject = %email.subject,
+            "email not sent, logged instead:\n{}",
+            email.body
+        );
+        Ok(())
+    }
+}
+
+// Define the transport that sends emails through an SMTP server
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(config: &SmtpConfig) -> Result<SmtpTransport, String> {
+        let builder = match config.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(|err| err.to_string())?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .map_err(|err| err.to_string())?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        let builder = match config.port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let builder = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            (None, None) => builder,
+            _ => return Err("set both `username` and `password`, or neither".into()),
+        };
+        Ok(SmtpTransport {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Transport for SmtpTransport {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        let from: Mailbox = from
+            .parse()
+            .map_err(|err| format!("invalid sender `{}`: {}", from, err))?;
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|err| format!("invalid recipient `{}`: {}", email.to, err))?;
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(&email.subject)
+            .body(email.body.clone())
+            .map_err(|err| err.to_string())?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+// Define the mailer handlers send emails through, managed as state. Clones share the
+// transport, so background workers can hold one.
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    transport: Arc<dyn Transport>,
+}
+
+impl Mailer {
+    pub async fn send(&self, email: Email) -> Result<(), String> {
+        self.transport.send(&self.from, &email).await
+    }
+}
+
+// Load the mail settings and manage the mailer with the configured transport
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Mail", |rocket| async {
+        let config = match rocket.figment().extract_inner::<MailConfig>("mail") {
+            Ok(config) => config,
+            Err(err) => {
+                error!("invalid `mail` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let transport: Arc<dyn Transport> = match (config.transport.as_str(), &config.smtp) {
+            ("log", _) => Arc::new(LogTransport),
+            ("smtp", Some(smtp)) => match SmtpTransport::new(smtp) {
+                Ok(transport) => {
+                    info!("sending email through {}", smtp.host);
+                    Arc::new(transport)
+                }
+                Err(err) => {
+                    error!("invalid `mail.smtp` configuration: {}", err);
+                    return Err(rocket);
+                }
+            },
+            ("smtp", None) => {
+                error!("the smtp mail transport needs a `mail.smtp` table");
+                return Err(rocket);
+            }
+            (other, _) => {
+                error!(
+                    "unknown mail transport `{}`; expected \"log\" or \"smtp\"",
+                    other
+                );
+                return Err(rocket);
+            }
+        };
+        Ok(rocket.manage(Mailer {
+            from: config.from,
+            transport,
+        }))
+    })
+}
@@ -1,10 +1,89 @@
 #[macro_use]
 extern crate rocket;
 
+mod access_log;
+mod admin;
+mod assets;
+mod audit;
+mod auth;
+mod batch;
+mod body_log;
+mod breaker;
+mod broker;
+mod chat;
+mod cli;
+mod compression;
+mod config;
+mod cors;
+mod db;
+mod delay;
+mod diagnostics;
+mod errors;
+mod etag;
+mod events;
+mod faults;
+mod files;
+mod flags;
+mod graphql;
+mod grpc;
+mod health;
+mod i18n;
+mod idempotency;
+mod intercept;
+mod jobs;
+mod load_shedding;
+mod mail;
+mod maintenance;
+mod metrics;
+mod negotiate;
+mod openapi;
+mod pages;
+mod pagination;
+mod proxy;
+mod quota;
+mod rate_limit;
+mod reload;
+mod request_id;
+mod response_cache;
+mod route_table;
+mod scheduler;
+mod security;
+mod seed;
+mod shutdown;
+mod signature;
+mod telemetry;
+mod tenant;
+#[cfg(test)]
+mod tests;
+mod tls;
+mod todos;
+mod transfer;
+mod validation;
+mod versioning;
+mod webhooks;
+
+use clap::Parser;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
 use rocket::form::Form;
+use rocket::http::{ContentType, Status};
 use rocket::request::FlashMessage;
 use rocket::response::content::RawHtml;
-use rocket::response::{self, Redirect};
+use rocket::response::stream::TextStream;
+use rocket::response::{self, status, Redirect};
+use rocket::serde::json::{json, Value};
+use rocket::State;
+use utoipa::OpenApi;
+
+use auth::{
+    Admin, AdminNetwork, AdminUser, ApiKey, ClientCert, Reader, Requires, RoleName, Verified,
+};
+use cli::Cli;
+use config::AppConfig;
+use errors::ApiError;
+use events::Events;
+use signature::{SignatureError, Signed};
+use tenant::Tenant;
 
 // Define a struct to represent the form data
 #[derive(FromForm)]
@@ -49,6 +128,7 @@ fn index(flash: Option<FlashMessage>) -> RawHtml<String> {
 }
 
 // Define a route handler for form submission
+#[allow(clippy::result_large_err)]
 #[post("/submit", data = "<user_form>")]
 fn submit(user_form: Form<UserForm>) -> Result<String, rocket::response::Flash<Redirect>> {
     // Access the form data from the `user_form` parameter
@@ -68,7 +148,262 @@ fn submit(user_form: Form<UserForm>) -> Result<String, rocket::response::Flash<R
     Ok(response)
 }
 
-#[launch]
-fn rocket() -> _ {
-    rocket::build().mount("/", routes![index, submit])
+// Rows generated per chunk of the CSV stream, and the most one request may ask for
+const CSV_CHUNK_ROWS: u64 = 500;
+const CSV_MAX_ROWS: u64 = 10_000_000;
+
+// Define a route handler for the "/stream?<rows>" URL pattern that generates a CSV of `rows` lines
+// chunk by chunk. The next chunk is only built once the client has taken the previous one, so
+// memory stays flat however many rows are asked for.
+#[get("/stream?<rows>")]
+fn stream(rows: Option<u64>) -> (ContentType, TextStream![String]) {
+    let rows = rows.unwrap_or(1000).min(CSV_MAX_ROWS);
+    let body = TextStream! {
+        yield "id,name,value\n".to_string();
+        let mut start = 0;
+        while start < rows {
+            let end = (start + CSV_CHUNK_ROWS).min(rows);
+            let chunk: String = (start..end)
+                .map(|id| format!("{},row-{},{}\n", id, id, id * 7 % 1000))
+                .collect();
+            yield chunk;
+            start = end;
+        }
+    };
+    (ContentType::CSV, body)
+}
+
+// Define a route handler for the "/protected" URL pattern that requires at least read access
+#[utoipa::path(
+    tag = "examples",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Who the caller is and how long the token lasts", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "The `reader` role is missing", body = ApiError),
+    )
+)]
+#[get("/protected")]
+fn protected_route(reader: Requires<Reader>) -> status::Custom<Value> {
+    let identity = reader.identity;
+    let access = if identity.has_role(Admin::NAME) {
+        "admin"
+    } else {
+        "read-only"
+    };
+    status::Custom(
+        Status::Ok,
+        json!({
+            "message": "Access granted",
+            "subject": identity.subject,
+            "roles": identity.roles,
+            "access": access,
+            "expires_at": identity.expires_at
+        }),
+    )
+}
+
+// Define a route handler for the "/protected/admin" URL pattern that requires the admin role
+#[utoipa::path(
+    tag = "examples",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The administrator", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code", body = ApiError),
+    )
+)]
+#[get("/protected/admin")]
+fn protected_admin(admin: AdminUser) -> Value {
+    json!({
+        "message": "Administrator access granted",
+        "subject": admin.identity.subject
+    })
+}
+
+// Define a route handler for the "/protected/2fa" URL pattern that requires having signed in
+// with a second factor, whatever the caller's roles
+#[utoipa::path(
+    tag = "examples",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The caller, who signed in with a one-time or recovery code", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Signed in without a second factor", body = ApiError),
+    )
+)]
+#[get("/protected/2fa")]
+fn protected_second_factor(caller: Verified) -> Value {
+    json!({
+        "message": "Second factor verified",
+        "subject": caller.identity.subject,
+        "roles": caller.identity.roles
+    })
+}
+
+// Define a route handler for the "/protected/machine" URL pattern that only accepts API keys
+#[utoipa::path(
+    tag = "examples",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The client the key belongs to", body = Value),
+        (status = 401, description = "Missing or unknown API key", body = ApiError),
+    )
+)]
+#[get("/protected/machine")]
+fn protected_machine(key: ApiKey) -> Value {
+    json!({
+        "message": "Machine access granted",
+        "subject": key.identity.subject,
+        "roles": key.identity.roles
+    })
+}
+
+// Define a route handler for the "/protected/internal" URL pattern, reserved to internal services
+// on the administrator networks presenting a TLS client certificate with the admin role
+#[utoipa::path(
+    tag = "examples",
+    responses(
+        (status = 200, description = "The service the client certificate belongs to", body = Value),
+        (status = 401, description = "Missing or unknown client certificate", body = ApiError),
+        (status = 403, description = "Outside the admin network or without the `admin` role", body = ApiError),
+    )
+)]
+#[get("/protected/internal")]
+fn protected_internal(_network: AdminNetwork, cert: ClientCert) -> Result<Value, ApiError> {
+    if !cert.identity.has_role(Admin::NAME) {
+        return Err(ApiError::new(
+            Status::Forbidden,
+            format!("the `{}` role is required", Admin::NAME),
+        ));
+    }
+    Ok(json!({
+        "message": "Internal access granted",
+        "subject": cert.identity.subject,
+        "roles": cert.identity.roles,
+        "expires_at": cert.identity.expires_at
+    }))
+}
+
+// Define a route handler for the "/webhooks/inbound" URL pattern that takes events pushed by
+// external systems, accepted only when signed with the shared secret, and publishes them to the
+// tenant the request names
+#[utoipa::path(
+    tag = "webhooks",
+    request_body(content = Value, description = "Any JSON event, signed with the shared secret"),
+    responses(
+        (status = 202, description = "The event was published"),
+        (status = 401, description = "Missing or invalid signature", body = ApiError),
+    )
+)]
+#[post("/webhooks/inbound", format = "json", data = "<event>")]
+fn inbound_webhook(
+    tenant: Tenant,
+    event: Result<Signed<Value>, SignatureError>,
+    events: &State<Events>,
+) -> Result<status::Accepted<()>, ApiError> {
+    let Signed(event) = event?;
+    events.publish(&tenant.id, "webhook.received", event);
+    Ok(status::Accepted(()))
+}
+
+// Define the part of the OpenAPI document describing the example routes above
+#[derive(OpenApi)]
+#[openapi(paths(
+    protected_route,
+    protected_admin,
+    protected_second_factor,
+    protected_machine,
+    protected_internal,
+    inbound_webhook
+))]
+struct ApiDoc;
+
+// Serve the application as the command line asks, or with `--migrate-only` apply the database
+// migrations and exit
+#[rocket::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), rocket::Error> {
+    let cli = Cli::parse();
+    telemetry::init();
+    let rocket = rocket(cli.figment()).manage(cli.clone());
+    if cli.migrate_only {
+        // Igniting runs every stage's checks, the database migrations among them, but serves nothing
+        rocket.ignite().await?;
+        tracing::info!("database migrated, exiting as asked by --migrate-only");
+        return Ok(());
+    }
+    rocket.launch().await.map(drop)
+}
+
+fn rocket(figment: Figment) -> rocket::Rocket<rocket::Build> {
+    rocket::custom(figment)
+        .attach(tls::stage())
+        .attach(scheduler::stage())
+        .attach(request_id::stage())
+        .attach(i18n::stage())
+        .attach(telemetry::stage())
+        .attach(AdHoc::config::<AppConfig>())
+        .attach(config::stage())
+        .attach(idempotency::stage())
+        .attach(body_log::stage())
+        .attach(compression::stage())
+        .attach(access_log::stage())
+        .attach(metrics::stage())
+        .attach(response_cache::stage())
+        .attach(breaker::stage())
+        .attach(transfer::stage())
+        .attach(shutdown::stage())
+        .attach(cors::stage())
+        .attach(security::stage())
+        .attach(intercept::stage())
+        .attach(maintenance::stage())
+        .attach(load_shedding::stage())
+        .attach(rate_limit::stage())
+        .attach(quota::stage())
+        .attach(faults::stage())
+        .attach(signature::stage())
+        .attach(tenant::stage())
+        .attach(versioning::stage())
+        .attach(db::stage())
+        .attach(mail::stage())
+        .attach(flags::stage())
+        .attach(reload::stage())
+        .attach(auth::stage())
+        .attach(audit::stage())
+        .attach(admin::stage())
+        .attach(events::stage())
+        .attach(webhooks::stage())
+        .attach(broker::stage())
+        .attach(chat::stage())
+        .attach(todos::stage())
+        .attach(seed::stage())
+        .attach(graphql::stage())
+        .attach(grpc::stage())
+        .attach(pages::stage())
+        .attach(files::stage())
+        .attach(batch::stage())
+        .attach(delay::stage())
+        .attach(jobs::stage())
+        .attach(diagnostics::stage())
+        .attach(proxy::stage())
+        .attach(assets::stage())
+        .attach(health::stage())
+        .attach(openapi::stage())
+        .attach(route_table::stage())
+        .mount(
+            "/",
+            routes![
+                index,
+                submit,
+                stream,
+                protected_route,
+                protected_admin,
+                protected_second_factor,
+                protected_machine,
+                protected_internal,
+                inbound_webhook
+            ],
+        )
+        .register("/", errors::catchers())
 }
@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::{Header, Status};
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::AdminUser;
+use crate::errors::ApiError;
+use crate::intercept;
+
+// Paths still served during maintenance: the health probes, so orchestrators do not restart the
+// instance, the toggle and the configuration reload, so an administrator can end it, and the
+// shutdown
+const EXEMPT_PATHS: &[&str] = &[
+    "/healthz",
+    "/readyz",
+    "/admin/maintenance",
+    "/admin/config/reload",
+    "/admin/shutdown",
+];
+
+// Define the `maintenance` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceConfig {
+    // Start in maintenance mode
+    #[serde(default)]
+    pub enabled: bool,
+    // Seconds clients are told to wait before retrying
+    pub retry_after: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            enabled: false,
+            retry_after: 300,
+        }
+    }
+}
+
+// Define the maintenance flag shared by the fairing and the admin routes. Clones share it.
+#[derive(Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    retry_after: Arc<AtomicU64>,
+}
+
+impl Maintenance {
+    fn to_json(&self) -> Value {
+        json!({
+            "enabled": self.enabled.load(Ordering::Relaxed),
+            "retry_after": self.retry_after.load(Ordering::Relaxed),
+        })
+    }
+
+    fn error(&self) -> ApiError {
+        let retry_after = self.retry_after.load(Ordering::Relaxed);
+        ApiError::new(
+            Status::ServiceUnavailable,
+            format!("down for maintenance, retry in {}s", retry_after),
+        )
+        .with_details(json!({ "maintenance": { "retry_after": retry_after } }))
+        .with_header(Header::new("Retry-After", retry_after.to_string()))
+    }
+
+    // Apply a reloaded configuration, which switches maintenance mode to its `enabled` whatever
+    // an administrator toggled since
+    pub fn reconfigure(&self, config: &MaintenanceConfig) {
+        self.retry_after
+            .store(config.retry_after, Ordering::Relaxed);
+        let was = self.enabled.swap(config.enabled, Ordering::Relaxed);
+        if was != config.enabled {
+            info!(
+                enabled = config.enabled,
+                "maintenance mode switched by a configuration reload"
+            );
+        }
+    }
+}
+
+// Define the JSON body accepted by `PUT /admin/maintenance`
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = MaintenanceToggle)]
+pub struct Toggle {
+    enabled: bool,
+}
+
+// Define a route handler that reports whether maintenance mode is on
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "maintenance_status",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Whether maintenance mode is on", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/maintenance")]
+fn status(_admin: AdminUser, maintenance: &State<Maintenance>) -> Json<Value> {
+    Json(maintenance.to_json())
+}
+
+// Define a route handler that turns maintenance mode on or off
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "toggle_maintenance",
+    security(("bearer" = [])),
+    request_body = Toggle,
+    responses(
+        (status = 200, description = "Whether maintenance mode is now on", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[put("/admin/maintenance", format = "json", data = "<toggle>")]
+fn toggle(admin: AdminUser, maintenance: &State<Maintenance>, toggle: Json<Toggle>) -> Json<Value> {
+    let was = maintenance.enabled.swap(toggle.enabled, Ordering::Relaxed);
+    if was != toggle.enabled {
+        info!(
+            enabled = toggle.enabled,
+            "maintenance mode switched by {}", admin.identity.subject
+        );
+    }
+    Json(maintenance.to_json())
+}
+
+// Define the part of the OpenAPI document describing the maintenance switch
+#[derive(OpenApi)]
+#[openapi(paths(status, toggle))]
+pub struct ApiDoc;
+
+// Read the `maintenance` table, where none means the defaults
+pub fn config(figment: &Figment) -> Result<MaintenanceConfig, String> {
+    match figment.extract_inner::<MaintenanceConfig>("maintenance") {
+        Ok(config) => Ok(config),
+        Err(err) if err.missing() => Ok(MaintenanceConfig::default()),
+        Err(err) => Err(format!("invalid `maintenance` configuration: {}", err)),
+    }
+}
+
+// Answer every request except the exempt ones with 503 while maintenance mode is on
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Maintenance", |rocket| async {
+        let config = match config(rocket.figment()) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("{}", err);
+                return Err(rocket);
+            }
+        };
+        if config.enabled {
+            warn!("starting in maintenance mode");
+        }
+
+        Ok(rocket
+            .manage(Maintenance {
+                enabled: Arc::new(AtomicBool::new(config.enabled)),
+                retry_after: Arc::new(AtomicU64::new(config.retry_after)),
+            })
+            .mount("/", routes![status, toggle])
+            .attach(AdHoc::on_request("Maintenance Mode", |req, _| {
+                Box::pin(async move {
+                    let Some(maintenance) = req.rocket().state::<Maintenance>() else {
+                        return;
+                    };
+                    if !maintenance.enabled.load(Ordering::Relaxed)
+                        || EXEMPT_PATHS.contains(&req.uri().path().as_str())
+                    {
+                        return;
+                    }
+                    let error = maintenance.error();
+                    intercept::reject(req, error);
+                })
+            })))
+    })
+}
@@ -0,0 +1,337 @@
+use prometheus::proto::MetricType;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::ContentType;
+use rocket::serde::json::{json, Value};
+use rocket::tokio::select;
+use rocket::tokio::time::{interval, MissedTickBehavior};
+use rocket::{Shutdown, State};
+use rocket_ws::{Channel, Message, WebSocket};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::access_log;
+use crate::auth::AdminUser;
+use crate::telemetry;
+
+// Define the `metrics` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsConfig {
+    // Milliseconds after which a request is logged as slow; 0 logs none
+    #[serde(default = "slow_request_ms")]
+    pub slow_request_ms: u64,
+    // Seconds between the snapshots pushed to `/ws/metrics` clients
+    #[serde(default = "live_interval")]
+    pub live_interval: u64,
+}
+
+fn slow_request_ms() -> u64 {
+    1000
+}
+
+fn live_interval() -> u64 {
+    5
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            slow_request_ms: slow_request_ms(),
+            live_interval: live_interval(),
+        }
+    }
+}
+
+// Define the metrics collected for every request, exposed by `/metrics`. Clones share the
+// metrics, so the live stream keeps reading them after its request is answered.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    in_flight: IntGauge,
+    latency: HistogramVec,
+    slow: Option<Duration>,
+    live_interval: Duration,
+}
+
+// Define the request counts a live snapshot compares with the previous one
+#[derive(Debug, Clone, Copy, Default)]
+struct Totals {
+    requests: u64,
+    // Requests answered with a 5xx status
+    errors: u64,
+}
+
+impl Metrics {
+    fn new(config: &MetricsConfig) -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests handled, by route and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let in_flight = IntGauge::new("http_requests_in_flight", "Requests being handled")?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from receiving a request to sending its response head, by route and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        Ok(Metrics {
+            registry,
+            requests,
+            in_flight,
+            latency,
+            slow: Some(Duration::from_millis(config.slow_request_ms))
+                .filter(|slow| !slow.is_zero()),
+            live_interval: Duration::from_secs(config.live_interval.max(1)),
+        })
+    }
+
+    // Return how many requests are being handled right now
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.get()
+    }
+
+    // Register a metric owned by another module so `/metrics` exposes it too
+    pub fn register(&self, collector: Box<dyn Collector>) -> Result<(), prometheus::Error> {
+        self.registry.register(collector)
+    }
+
+    // Render every metric in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding");
+        String::from_utf8(buffer).expect("text encoding is UTF-8")
+    }
+
+    // Return the current value of every metric as JSON, by name, for `GET /admin/metrics`.
+    // Histograms are reduced to their sample count and sum.
+    pub fn snapshot(&self) -> Value {
+        let families: serde_json::Map<String, Value> = self
+            .registry
+            .gather()
+            .iter()
+            .map(|family| {
+                let kind = family.get_field_type();
+                let samples: Vec<Value> = family
+                    .get_metric()
+                    .iter()
+                    .map(|metric| {
+                        let labels: serde_json::Map<String, Value> = metric
+                            .get_label()
+                            .iter()
+                            .map(|label| (label.name().into(), label.value().into()))
+                            .collect();
+                        match kind {
+                            MetricType::COUNTER => {
+                                json!({ "labels": labels, "value": metric.get_counter().get_value() })
+                            }
+                            MetricType::GAUGE => {
+                                json!({ "labels": labels, "value": metric.get_gauge().get_value() })
+                            }
+                            MetricType::HISTOGRAM => {
+                                let histogram = metric.get_histogram();
+                                json!({
+                                    "labels": labels,
+                                    "count": histogram.get_sample_count(),
+                                    "sum": histogram.get_sample_sum(),
+                                })
+                            }
+                            // No summaries or untyped metrics are registered
+                            _ => json!({ "labels": labels }),
+                        }
+                    })
+                    .collect();
+                let kind = format!("{:?}", kind).to_lowercase();
+                (
+                    family.name().to_string(),
+                    json!({ "help": family.help(), "type": kind, "samples": samples }),
+                )
+            })
+            .collect();
+        Value::Object(families)
+    }
+
+    // Return how many requests were handled, and how many of them failed, since launch
+    fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for family in self.requests.collect() {
+            for metric in family.get_metric() {
+                let count = metric.get_counter().get_value() as u64;
+                let failed = metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.name() == "status" && label.value().starts_with('5'));
+                totals.requests += count;
+                if failed {
+                    totals.errors += count;
+                }
+            }
+        }
+        totals
+    }
+
+    // Describe the traffic since the `previous` totals, taken `elapsed` ago, for `/ws/metrics`
+    fn live_snapshot(&self, previous: Totals, current: Totals, elapsed: Duration) -> Value {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let requests = current.requests.saturating_sub(previous.requests);
+        let errors = current.errors.saturating_sub(previous.errors);
+        let error_rate = match requests {
+            0 => 0.0,
+            requests => errors as f64 / requests as f64,
+        };
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        json!({
+            "at": at,
+            "interval": elapsed.as_secs_f64(),
+            "requests_per_second": requests as f64 / seconds,
+            "errors_per_second": errors as f64 / seconds,
+            "error_rate": error_rate,
+            "in_flight": self.in_flight(),
+            "requests_total": current.requests,
+            "errors_total": current.errors,
+        })
+    }
+}
+
+// Define a route handler for the "/ws/metrics" URL pattern that upgrades to a WebSocket pushing
+// a JSON snapshot of the request rate, requests in flight and error rate every `live_interval`
+// seconds. Errors are requests answered with a 5xx status; the first snapshot covers the time
+// since the previous one, or since the connection opened.
+#[get("/ws/metrics")]
+fn live(
+    _admin: AdminUser,
+    ws: WebSocket,
+    metrics: &State<Metrics>,
+    mut shutdown: Shutdown,
+) -> Channel<'static> {
+    let metrics = metrics.inner().clone();
+
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let mut ticks = interval(metrics.live_interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes right away
+            ticks.tick().await;
+            let mut previous = (metrics.totals(), Instant::now());
+            loop {
+                select! {
+                    _ = ticks.tick() => {
+                        let current = (metrics.totals(), Instant::now());
+                        let snapshot =
+                            metrics.live_snapshot(previous.0, current.0, current.1 - previous.1);
+                        stream.send(Message::Text(snapshot.to_string())).await?;
+                        previous = current;
+                    }
+                    // Nothing is read from clients, but closing frames end the stream
+                    incoming = stream.next() => match incoming {
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Err(err),
+                    },
+                    _ = &mut shutdown => {
+                        stream.close(None).await?;
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+// Define a route handler for the "/metrics" URL pattern scraped by Prometheus
+#[get("/metrics")]
+fn metrics(metrics: &State<Metrics>) -> (ContentType, String) {
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, metrics.render())
+}
+
+// Count requests, track how many are in flight and time them per route and status, warning about
+// those slower than `slow_request_ms`, and stream live snapshots of them to administrators
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Metrics", |rocket| async {
+        let config = match rocket.figment().extract_inner::<MetricsConfig>("metrics") {
+            Ok(config) => config,
+            Err(err) if err.missing() => MetricsConfig::default(),
+            Err(err) => {
+                error!("invalid `metrics` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let metrics = match Metrics::new(&config) {
+            Ok(metrics) => metrics,
+            Err(err) => {
+                error!("failed to register metrics: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(metrics)
+            .mount("/", routes![metrics, live])
+            .attach(AdHoc::on_request("Metrics In Flight", |req, _| {
+                Box::pin(async move {
+                    if let Some(metrics) = req.rocket().state::<Metrics>() {
+                        metrics.in_flight.inc();
+                    }
+                })
+            }))
+            .attach(AdHoc::on_response("Metrics", |req, res| {
+                Box::pin(async move {
+                    let Some(metrics) = req.rocket().state::<Metrics>() else {
+                        return;
+                    };
+                    // Unmatched paths share one label so scanners cannot blow up the series count
+                    let route = req.route().map_or("unmatched", |route| route.uri.as_str());
+                    let method = req.method().as_str();
+                    let status = res.status().code.to_string();
+                    let elapsed = access_log::started(req).elapsed();
+
+                    metrics.in_flight.dec();
+                    metrics
+                        .requests
+                        .with_label_values(&[method, route, &status])
+                        .inc();
+                    metrics
+                        .latency
+                        .with_label_values(&[method, route, &status])
+                        .observe(elapsed.as_secs_f64());
+
+                    // The request span adds the request id, path, route and user; the query is
+                    // left out as it may carry signatures
+                    if let Some(slow) = metrics.slow.filter(|slow| elapsed > *slow) {
+                        telemetry::span(req).in_scope(|| {
+                            warn!(
+                                status = res.status().code,
+                                elapsed_ms = elapsed.as_millis() as u64,
+                                threshold_ms = slow.as_millis() as u64,
+                                client_ip = req.client_ip().map(|ip| ip.to_string()),
+                                user_agent = req.headers().get_one("User-Agent"),
+                                "slow request"
+                            )
+                        });
+                    }
+                })
+            })))
+    })
+}
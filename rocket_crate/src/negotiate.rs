@@ -0,0 +1,102 @@
+use rocket::http::{ContentType, Header, MediaType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+
+use crate::errors::ApiError;
+
+// Define the representations response bodies can be serialized to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Xml,
+}
+
+impl Format {
+    // Return the format a media type from `Accept` names; wildcards get JSON
+    fn of(media_type: &MediaType) -> Option<Format> {
+        match (media_type.top().as_str(), media_type.sub().as_str()) {
+            ("*", "*") | ("application", "*") | ("application", "json") => Some(Format::Json),
+            ("application", "msgpack") | ("application", "x-msgpack") => Some(Format::MessagePack),
+            ("application", "xml") | ("text", "xml") => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            Format::Json => ContentType::JSON,
+            Format::MessagePack => ContentType::MsgPack,
+            Format::Xml => ContentType::new("application", "xml"),
+        }
+    }
+
+    // Pick the format the client weighs highest, or `None` when it accepts none of them.
+    // Clients that send no `Accept` header get JSON.
+    pub fn negotiate(req: &Request<'_>) -> Option<Format> {
+        let Some(accept) = req.accept() else {
+            return Some(Format::Json);
+        };
+        let mut best: Option<(Format, f32)> = None;
+        for item in accept.iter() {
+            let weight = item.weight_or(1.0);
+            let Some(format) = Format::of(item.media_type()) else {
+                continue;
+            };
+            // The first of equally weighted types wins, as the client listed it first
+            if weight > 0.0 && best.is_none_or(|(_, current)| weight > current) {
+                best = Some((format, weight));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            Format::MessagePack => {
+                rocket::serde::msgpack::to_vec(value).map_err(|err| err.to_string())
+            }
+            Format::Xml => quick_xml::se::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+// Serialize `value` in the format the request accepts, failing with 406 Not Acceptable when it
+// accepts none this server produces
+pub fn encode<T: Serialize>(
+    req: &Request<'_>,
+    value: &T,
+) -> Result<(ContentType, Vec<u8>), ApiError> {
+    let format = Format::negotiate(req).ok_or_else(|| {
+        ApiError::new(
+            Status::NotAcceptable,
+            "this resource is available as application/json, application/msgpack or application/xml",
+        )
+    })?;
+    let body = format.encode(value).map_err(|err| {
+        ApiError::internal().with_cause(format!("{:?} encoding: {}", format, err))
+    })?;
+    Ok((format.content_type(), body))
+}
+
+// Define a responder that serializes its value as JSON, MessagePack or XML, as `Accept` asks
+pub struct Negotiated<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Negotiated<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (content_type, body) = match encode(req, &self.0) {
+            Ok(encoded) => encoded,
+            Err(error) => return error.respond_to(req),
+        };
+        Response::build()
+            .header(content_type)
+            // The representation depends on `Accept`, so caches must key on it
+            .header(Header::new("Vary", "Accept"))
+            .sized_body(body.len(), std::io::Cursor::new(body))
+            .ok()
+    }
+}
@@ -0,0 +1,80 @@
+use rocket::fairing::AdHoc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{admin, audit, auth, batch, files, flags, health, jobs, maintenance, reload};
+use crate::{route_table, scheduler, shutdown, todos, webhooks, ApiDoc as DemoApiDoc};
+
+// Define the top of the OpenAPI document; every module describes its own routes and the stage
+// merges them in
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "rocket_crate",
+        description = "JSON API of the Rocket examples. Requests name their tenant with the \
+                       configured tenant header or subdomain, and errors share one envelope."
+    ),
+    modifiers(&SecuritySchemes)
+)]
+struct ApiDoc;
+
+// Declare the credentials routes refer to in their `security` requirements
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+    }
+}
+
+// Build the OpenAPI document of every JSON route
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    for part in [
+        DemoApiDoc::openapi(),
+        health::ApiDoc::openapi(),
+        auth::ApiDoc::openapi(),
+        admin::ApiDoc::openapi(),
+        audit::ApiDoc::openapi(),
+        flags::ApiDoc::openapi(),
+        maintenance::ApiDoc::openapi(),
+        reload::ApiDoc::openapi(),
+        route_table::ApiDoc::openapi(),
+        scheduler::ApiDoc::openapi(),
+        shutdown::ApiDoc::openapi(),
+        todos::ApiDoc::openapi(),
+        files::ApiDoc::openapi(),
+        batch::ApiDoc::openapi(),
+        jobs::ApiDoc::openapi(),
+        webhooks::ApiDoc::openapi(),
+    ] {
+        doc.merge(part);
+    }
+    // The version comes from Cargo.toml, which names no license
+    doc.info.license = None;
+    doc
+}
+
+// Serve the OpenAPI document at `/openapi.json` and Swagger UI, whose assets are compiled into
+// the binary, at `/swagger-ui/` to explore it
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("OpenAPI", |rocket| async {
+        rocket.mount(
+            "/",
+            utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/<_..>").url("/openapi.json", document()),
+        )
+    })
+}
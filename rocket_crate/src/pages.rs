@@ -0,0 +1,141 @@
+use rocket::fairing::AdHoc;
+use rocket::http::CookieJar;
+use rocket::request::FlashMessage;
+use rocket::response::Redirect;
+use rocket::serde::json::{json, Value};
+use rocket::State;
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::{context, Template};
+
+use crate::auth::oauth::OAuthProviders;
+use crate::auth::{csrf, SessionUser};
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+use crate::i18n::Locale;
+use crate::pagination::{Order, Page, Pagination};
+use crate::tenant::Tenant;
+use crate::todos::store;
+
+// Todos shown on the home page
+const RECENT: u32 = 5;
+
+// Define what a page of todos answers: the page, or a redirect to the sign-in form for visitors
+// without a session in the tenant, as todos are only shown to the tenant's users
+type Rendered = Result<Template, Redirect>;
+
+// Return the name shown in the navigation of signed-in visitors
+fn signed_in(user: &SessionUser) -> Option<&str> {
+    Some(user.identity.subject.as_str())
+}
+
+// Return what the templates need to speak the locale of the request: its tag and direction for
+// the `lang` and `dir` attributes, and the `pages` messages of its catalog
+fn texts(locale: &Locale) -> Value {
+    json!({ "tag": locale.tag, "dir": locale.direction(), "t": locale.table("pages") })
+}
+
+// Define a route handler for the "/pages" URL pattern that renders the home page with the most
+// recently updated todos of signed-in visitors
+#[get("/pages")]
+async fn index(
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    user: Option<SessionUser>,
+    flash: Option<FlashMessage<'_>>,
+    cookies: &CookieJar<'_>,
+    locale: Locale,
+) -> Result<Rendered, ApiError> {
+    let Some(user) = user else {
+        return Ok(Err(Redirect::to("/login")));
+    };
+    let recent = Pagination {
+        page: 1,
+        per_page: RECENT,
+        sort: None,
+        order: Order::Desc,
+        filter: None,
+        cursor: None,
+    };
+    let (todos, total) = store::list(&mut db, &tenant.id, &recent, "updated_at", None)
+        .await
+        .map_err(internal_error)?;
+    Ok(Ok(Template::render(
+        "pages/index",
+        context! {
+            recent: todos,
+            total,
+            user: signed_in(&user),
+            flash,
+            csrf: csrf::token(cookies),
+            locale: texts(&locale),
+        },
+    )))
+}
+
+// Define a route handler for the "/pages/todos" URL pattern that renders one page of todos,
+// taking the same paging, sorting and filtering parameters as the JSON list, for signed-in
+// visitors
+#[get("/pages/todos?<pagination..>")]
+async fn todos(
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    pagination: Pagination,
+    user: Option<SessionUser>,
+    cookies: &CookieJar<'_>,
+    locale: Locale,
+) -> Result<Rendered, ApiError> {
+    let Some(user) = user else {
+        return Ok(Err(Redirect::to("/login")));
+    };
+    let sort = pagination
+        .sort_column(store::SORTABLE)
+        .map_err(ApiError::bad_request)?;
+    let after = pagination
+        .after::<store::Todo>(sort)
+        .map_err(ApiError::bad_request)?;
+    let (todos, total) = store::list(&mut db, &tenant.id, &pagination, sort, after.as_ref())
+        .await
+        .map_err(internal_error)?;
+    let page = Page::new(todos, total, &pagination, "/pages/todos", sort);
+    let pages =
+        (total.max(1) + i64::from(pagination.per_page) - 1) / i64::from(pagination.per_page);
+    Ok(Ok(Template::render(
+        "pages/todos",
+        context! {
+            page,
+            pages,
+            user: signed_in(&user),
+            csrf: csrf::token(cookies),
+            locale: texts(&locale),
+        },
+    )))
+}
+
+// Define a route handler for the "/login" URL pattern that renders the sign-in form, which posts
+// to the session login, along with a link to each configured OAuth provider
+#[get("/login")]
+fn login(
+    flash: Option<FlashMessage<'_>>,
+    cookies: &CookieJar<'_>,
+    providers: &State<OAuthProviders>,
+    locale: Locale,
+) -> Template {
+    Template::render(
+        "pages/login",
+        context! {
+            flash,
+            csrf: csrf::token(cookies),
+            providers: providers.names(),
+            locale: texts(&locale),
+        },
+    )
+}
+
+// Render templates from the `templates` directory and mount the HTML pages
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Pages", |rocket| async {
+        rocket
+            .attach(Template::fairing())
+            .mount("/", routes![index, todos, login])
+    })
+}
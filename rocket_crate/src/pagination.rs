@@ -0,0 +1,280 @@
+use async_graphql::Enum;
+use data_encoding::BASE64URL_NOPAD;
+use rocket::http::RawStr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// Define the sort direction accepted by the `order` query parameter
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField, ToSchema, Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order {
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+}
+
+// Define the paging, sorting and filtering query parameters shared by list endpoints
+#[derive(Debug, Clone, FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    #[field(default = 1, validate = range(1..))]
+    #[param(default = 1, minimum = 1)]
+    pub page: u32,
+    #[field(default = 20, validate = range(1..=100))]
+    #[param(default = 20, minimum = 1, maximum = 100)]
+    pub per_page: u32,
+    pub sort: Option<String>,
+    #[field(default = Order::Asc)]
+    #[param(inline, default = "asc")]
+    pub order: Order,
+    pub filter: Option<String>,
+    // Opaque `next_cursor` of a previous response; the page after it is returned instead of `page`
+    pub cursor: Option<String>,
+}
+
+// Define the value of the sort column a cursor resumes after
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CursorValue {
+    Int(i64),
+    Text(String),
+}
+
+// Define the position a cursor encodes: the sort it was made for, and the sort value and key of
+// the last item returned. Resuming after the last item seen rather than skipping an offset keeps
+// iteration stable while items are added or removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    sort: String,
+    order: Order,
+    pub value: CursorValue,
+    pub key: i64,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(&serde_json::to_vec(self).expect("cursor serializes"))
+    }
+
+    fn decode(cursor: &str) -> Option<Cursor> {
+        let json = BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+// Define a resource listed with cursors: its unique key, the tie-breaker of every sort, and the
+// value of each of its sortable columns
+pub trait Keyed {
+    // Sortable columns holding text; every other column sorts as an integer
+    const TEXT_COLUMNS: &'static [&'static str];
+
+    fn key(&self) -> i64;
+    fn sort_value(&self, column: &str) -> CursorValue;
+}
+
+impl Pagination {
+    pub fn offset(&self) -> i64 {
+        match self.cursor {
+            Some(_) => 0,
+            None => i64::from(self.page - 1) * i64::from(self.per_page),
+        }
+    }
+
+    // Return how many items to fetch; after a cursor one more than a page, which tells
+    // `Page::new` whether another page follows
+    pub fn limit(&self) -> i64 {
+        match self.cursor {
+            Some(_) => i64::from(self.per_page) + 1,
+            None => i64::from(self.per_page),
+        }
+    }
+
+    // Decode the cursor to resume after a `T`, which must have been made for the same sort and
+    // order and hold a value of the type of the sort column
+    pub fn after<T: Keyed>(&self, sort: &str) -> Result<Option<Cursor>, String> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        let Some(cursor) = Cursor::decode(cursor) else {
+            return Err("the cursor is malformed".to_string());
+        };
+        if cursor.sort != sort || cursor.order != self.order {
+            return Err(
+                "the cursor was made for another sort; send it with the sort and order it came with"
+                    .to_string(),
+            );
+        }
+        match (&cursor.value, T::TEXT_COLUMNS.contains(&sort)) {
+            (CursorValue::Text(_), true) | (CursorValue::Int(_), false) => Ok(Some(cursor)),
+            _ => Err("the cursor is malformed".to_string()),
+        }
+    }
+
+    // Pick the requested sort column if the resource allows it, else the first allowed column
+    pub fn sort_column(&self, allowed: &[&'static str]) -> Result<&'static str, String> {
+        match &self.sort {
+            None => Ok(allowed[0]),
+            Some(sort) => allowed
+                .iter()
+                .find(|column| **column == sort.as_str())
+                .copied()
+                .ok_or_else(|| format!("cannot sort by `{}`; expected one of {:?}", sort, allowed)),
+        }
+    }
+
+    // Build a link to the given page, or to the page after `cursor`, or to the first page when
+    // neither is given, keeping the current sort, order and filter
+    fn link(&self, base: &str, page: Option<u32>, cursor: Option<&str>) -> String {
+        let mut link = match (cursor, page) {
+            (Some(cursor), _) => format!("{}?cursor={}&per_page={}", base, cursor, self.per_page),
+            (None, Some(page)) => format!("{}?page={}&per_page={}", base, page, self.per_page),
+            (None, None) => format!("{}?per_page={}", base, self.per_page),
+        };
+        if let Some(sort) = &self.sort {
+            link.push_str(&format!("&sort={}", RawStr::new(sort).percent_encode()));
+        }
+        if self.order == Order::Desc {
+            link.push_str("&order=desc");
+        }
+        if let Some(filter) = &self.filter {
+            link.push_str(&format!("&filter={}", RawStr::new(filter).percent_encode()));
+        }
+        link
+    }
+}
+
+// Define the links to neighbouring pages included in every list response
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub current: String,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+// Define the envelope wrapping one page of a list endpoint's results. `next_cursor` resumes
+// after the last item, whether the page was asked for by number or by cursor.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub links: Links,
+}
+
+impl<T: Keyed> Page<T> {
+    // Wrap the items fetched for `pagination`, sorted by `sort`
+    pub fn new(
+        mut data: Vec<T>,
+        total: i64,
+        pagination: &Pagination,
+        base: &str,
+        sort: &str,
+    ) -> Self {
+        let page = pagination.page;
+        let (has_next, next_cursor) = next_cursor(&mut data, total, pagination, sort);
+        let links = match &pagination.cursor {
+            // Cursors only lead forward
+            Some(cursor) => Links {
+                current: pagination.link(base, Some(page), Some(cursor)),
+                next: next_cursor
+                    .as_deref()
+                    .map(|next| pagination.link(base, Some(page), Some(next))),
+                prev: None,
+            },
+            None => Links {
+                current: pagination.link(base, Some(page), None),
+                next: has_next.then(|| pagination.link(base, Some(page + 1), None)),
+                prev: (page > 1).then(|| pagination.link(base, Some(page - 1), None)),
+            },
+        };
+        Page {
+            links,
+            data,
+            page,
+            per_page: pagination.per_page,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+// Trim the items fetched for `pagination` to one page, and return whether another page follows
+// along with the cursor resuming after this one
+fn next_cursor<T: Keyed>(
+    data: &mut Vec<T>,
+    total: i64,
+    pagination: &Pagination,
+    sort: &str,
+) -> (bool, Option<String>) {
+    let has_next = match pagination.cursor {
+        Some(_) => data.len() as i64 > i64::from(pagination.per_page),
+        None => pagination.offset() + (data.len() as i64) < total,
+    };
+    data.truncate(pagination.per_page as usize);
+    let next_cursor = data.last().filter(|_| has_next).map(|last| {
+        Cursor {
+            sort: sort.to_string(),
+            order: pagination.order,
+            value: last.sort_value(sort),
+            key: last.key(),
+        }
+        .encode()
+    });
+    (has_next, next_cursor)
+}
+
+// Define the links of a list paged by cursor only, which lead forward
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CursorLinks {
+    #[serde(rename = "self")]
+    pub current: String,
+    pub next: Option<String>,
+}
+
+// Define the envelope of list endpoints paged by cursor only, without page numbers; the first
+// page is the one without a cursor
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CursorPage<T> {
+    pub data: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub links: CursorLinks,
+}
+
+impl<T: Keyed> CursorPage<T> {
+    // Wrap the items fetched for `pagination`, sorted by `sort`; its page number must be the first
+    pub fn new(
+        mut data: Vec<T>,
+        total: i64,
+        pagination: &Pagination,
+        base: &str,
+        sort: &str,
+    ) -> Self {
+        let (_, next_cursor) = next_cursor(&mut data, total, pagination, sort);
+        CursorPage {
+            links: CursorLinks {
+                current: pagination.link(base, None, pagination.cursor.as_deref()),
+                next: next_cursor
+                    .as_deref()
+                    .map(|next| pagination.link(base, None, Some(next))),
+            },
+            data,
+            total,
+            next_cursor,
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::io;
+use std::pin::pin;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rocket::data::{ByteUnit, Data};
+use rocket::fairing::AdHoc;
+use rocket::futures::{stream, StreamExt, TryStreamExt};
+use rocket::http::{Header, Method, RawStr, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::route::{self, Handler, Route};
+use rocket::tokio::select;
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::time::error::Elapsed;
+use rocket::tokio::time::timeout;
+use serde::Deserialize;
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{debug, warn};
+
+use crate::breaker::CircuitBreakers;
+use crate::errors::ApiError;
+use crate::transfer::Transfers;
+
+// Request bodies forwarded by `/proxy` are streamed up to the `proxy` limit, 1 MiB unless
+// configured
+const PROXY_BODY_LIMIT: ByteUnit = ByteUnit::Mebibyte(1);
+
+// Headers describing a single connection rather than the request, which proxies must not forward
+// (RFC 9110, section 7.6.1), along with those the client and Rocket set again for each hop
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+// Headers carrying the client's credentials for this service, such as its bearer token, session
+// cookie or API key, which the upstream has no business seeing
+const CREDENTIALS: &[&str] = &["authorization", "cookie", "x-api-key", "x-csrf-token"];
+
+// Define the `proxy` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyConfig {
+    // Base URL requests to `/proxy/<path>` are forwarded to, as `<upstream>/<path>`
+    pub upstream: String,
+    // Seconds to wait for the upstream to connect and start answering, and between the chunks
+    // of its body
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+// Define the handler of "/proxy/<target..>", which forwards any method to the upstream, streaming
+// the body both ways so neither is ever held in memory whole. OPTIONS is left to the CORS
+// preflight route.
+#[derive(Clone)]
+struct Proxy {
+    upstream: String,
+    timeout: Duration,
+    client: reqwest::Client,
+    breakers: CircuitBreakers,
+    transfers: Transfers,
+    host: String,
+}
+
+impl Proxy {
+    // Build the upstream URL from the path after `/proxy` and the query, kept percent-encoded
+    fn target(&self, req: &Request<'_>) -> Result<String, ApiError> {
+        let path = req.uri().path();
+        let path = path.as_str().strip_prefix("/proxy").unwrap_or_default();
+        // Dot segments would let the client climb out of the upstream base path
+        if path.split('/').any(|segment| {
+            let segment = RawStr::new(segment).percent_decode_lossy();
+            segment == "." || segment == ".."
+        }) {
+            return Err(ApiError::bad_request(
+                "proxied paths must not hold `.` or `..` segments",
+            ));
+        }
+        let mut target = format!("{}{}", self.upstream.trim_end_matches('/'), path);
+        if let Some(query) = req.uri().query() {
+            target.push('?');
+            target.push_str(query.as_str());
+        }
+        Ok(target)
+    }
+
+    // Copy the end-to-end headers of the request, but for the client's credentials, and say who
+    // it was forwarded for
+    fn headers(&self, req: &Request<'_>) -> reqwest::header::HeaderMap {
+        // Headers that `Connection` names are hop-by-hop too
+        let connection: Vec<String> = req
+            .headers()
+            .get("Connection")
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+        let mut headers = reqwest::header::HeaderMap::new();
+        for header in req.headers().iter() {
+            let name = header.name().as_str().to_ascii_lowercase();
+            if HOP_BY_HOP.contains(&name.as_str())
+                || CREDENTIALS.contains(&name.as_str())
+                || connection.contains(&name)
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+                reqwest::header::HeaderValue::from_str(header.value()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+
+        let proto = match req.rocket().config().tls_enabled() {
+            true => "https",
+            false => "http",
+        };
+        let forwarded = [
+            ("x-forwarded-for", req.client_ip().map(|ip| ip.to_string())),
+            ("x-forwarded-host", req.host().map(|host| host.to_string())),
+            ("x-forwarded-proto", Some(proto.to_string())),
+        ];
+        for (name, value) in forwarded {
+            if let Some(Ok(value)) = value.map(|value| value.parse()) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    // Stream the request body to the upstream through `tx` as it arrives, `transfers.chunk_size`
+    // bytes at a time. A body over `limit` or cut off by the client is failed, so the upstream
+    // never takes a part for the whole.
+    async fn pump(
+        &self,
+        data: Data<'_>,
+        limit: ByteUnit,
+        tx: mpsc::Sender<io::Result<Bytes>>,
+    ) -> Result<(), ApiError> {
+        // Read one byte past the limit to tell a body at the limit from one over it
+        let body = self
+            .transfers
+            .meter("proxy", "received", data.open(limit + 1));
+        let mut chunks = ReaderStream::with_capacity(body, self.transfers.chunk_size);
+        let mut read = 0;
+        while let Some(chunk) = chunks.next().await {
+            let failure = match chunk {
+                Ok(chunk) if read + chunk.len() as u64 <= limit.as_u64() => {
+                    read += chunk.len() as u64;
+                    match tx.send(Ok(chunk)).await {
+                        Ok(()) => continue,
+                        // The upstream stopped reading, and its answer says why
+                        Err(_) => return Ok(()),
+                    }
+                }
+                Ok(_) => ApiError::new(
+                    Status::PayloadTooLarge,
+                    format!("proxied request bodies are limited to {}", limit),
+                ),
+                Err(err) => ApiError::bad_request(format!("failed to read request body: {}", err)),
+            };
+            let _ = tx
+                .send(Err(io::Error::other("the request body was cut off")))
+                .await;
+            return Err(failure);
+        }
+        Ok(())
+    }
+
+    // Take the upstream's answer, counting failures against its circuit
+    fn answered(
+        &self,
+        target: &str,
+        answer: Result<reqwest::Result<reqwest::Response>, Elapsed>,
+    ) -> Result<reqwest::Response, ApiError> {
+        match answer {
+            Ok(Ok(upstream)) => {
+                match upstream.status().is_server_error() {
+                    true => self.breakers.failed(&self.host),
+                    false => self.breakers.succeeded(&self.host),
+                }
+                Ok(upstream)
+            }
+            Ok(Err(err)) if !err.is_timeout() => {
+                self.breakers.failed(&self.host);
+                Err(
+                    ApiError::new(Status::BadGateway, "the upstream is unavailable")
+                        .with_cause(format!("proxying to {} failed: {}", target, err)),
+                )
+            }
+            _ => {
+                self.breakers.failed(&self.host);
+                Err(ApiError::new(
+                    Status::GatewayTimeout,
+                    format!(
+                        "the upstream did not answer within {}s",
+                        self.timeout.as_secs()
+                    ),
+                ))
+            }
+        }
+    }
+
+    async fn forward<'r>(
+        &self,
+        req: &'r Request<'_>,
+        data: Data<'r>,
+    ) -> Result<Response<'static>, ApiError> {
+        let target = self.target(req)?;
+        let limit = req.limits().get("proxy").unwrap_or(PROXY_BODY_LIMIT);
+        let length = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+        if length.is_some_and(|length| length > limit.as_u64()) {
+            return Err(ApiError::new(
+                Status::PayloadTooLarge,
+                format!("proxied request bodies are limited to {}", limit),
+            ));
+        }
+        let method =
+            reqwest::Method::from_bytes(req.method().as_str().as_bytes()).expect("HTTP method");
+
+        // While the upstream's circuit is open requests fail at once instead of waiting on it
+        if let Err(open) = self.breakers.admit(&self.host) {
+            let retry_in = open.retry_in.as_secs() + 1;
+            return Err(ApiError::new(
+                Status::ServiceUnavailable,
+                format!("the upstream is failing, retry in {}s", retry_in),
+            )
+            .with_header(Header::new("Retry-After", retry_in.to_string())));
+        }
+
+        debug!("proxying {} {} to {}", req.method(), req.uri(), target);
+        let mut request = self
+            .client
+            .request(method, &target)
+            .headers(self.headers(req));
+        let has_body =
+            length.is_some_and(|length| length > 0) || req.headers().contains("Transfer-Encoding");
+        let answer = match has_body {
+            false => timeout(self.timeout, request.send()).await,
+            true => {
+                // The body goes through a channel, as the upstream request outlives `data`
+                let (tx, rx) = mpsc::channel(2);
+                let receiver = stream::unfold(rx, |mut rx| async move {
+                    rx.recv().await.map(|chunk| (chunk, rx))
+                });
+                if let Some(length) = length {
+                    request = request.header("content-length", length);
+                }
+                let mut sent = pin!(request.body(reqwest::Body::wrap_stream(receiver)).send());
+                select! {
+                    pumped = self.pump(data, limit, tx) => {
+                        pumped?;
+                        // The upstream has `timeout` to answer once it has the whole body
+                        timeout(self.timeout, sent).await
+                    }
+                    // The upstream may answer, an error say, before reading the whole body
+                    answer = &mut sent => Ok(answer),
+                }
+            }
+        };
+        let upstream = self.answered(&target, answer)?;
+
+        let mut response = Response::build();
+        response.status(Status::new(upstream.status().as_u16()));
+        for (name, value) in upstream.headers() {
+            if HOP_BY_HOP.contains(&name.as_str()) {
+                continue;
+            }
+            if let Ok(value) = value.to_str() {
+                response.header_adjoin(Header::new(name.as_str().to_string(), value.to_string()));
+            }
+        }
+        // The body is passed on as it arrives; the client sees it cut off if the upstream fails
+        let url = upstream.url().to_string();
+        let body = upstream.bytes_stream().map_err(move |err| {
+            warn!("proxied body from {} broke off: {}", url, err);
+            io::Error::other(err)
+        });
+        response
+            .streamed_body(
+                self.transfers
+                    .meter("proxy", "sent", StreamReader::new(body)),
+            )
+            .max_chunk_size(self.transfers.chunk_size);
+        Ok(response.finalize())
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for Proxy {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match self.forward(req, data).await {
+            Ok(response) => route::Outcome::Success(response),
+            Err(err) => route::Outcome::from(req, err),
+        }
+    }
+}
+
+fn proxy_routes(proxy: Proxy) -> Vec<Route> {
+    [
+        Method::Get,
+        Method::Post,
+        Method::Put,
+        Method::Patch,
+        Method::Delete,
+    ]
+    .into_iter()
+    .map(|method| Route::new(method, "/proxy/<target..>", proxy.clone()))
+    .collect()
+}
+
+// Load the proxy settings and mount `/proxy/<target..>`, a thin gateway to the `upstream`;
+// without a `proxy` table nothing is mounted
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Proxy", |rocket| async {
+        let config = match rocket.figment().extract_inner::<ProxyConfig>("proxy") {
+            Ok(config) => config,
+            Err(err) if err.missing() => return rocket,
+            Err(err) => {
+                warn!("nothing is proxied, invalid `proxy` configuration: {}", err);
+                return rocket;
+            }
+        };
+        let Some(breakers) = rocket.state::<CircuitBreakers>().cloned() else {
+            warn!("nothing is proxied, the circuit breakers are missing");
+            return rocket;
+        };
+        let Some(transfers) = rocket.state::<Transfers>().cloned() else {
+            warn!("nothing is proxied, the transfer state is missing");
+            return rocket;
+        };
+        let timeout = Duration::from_secs(config.timeout);
+        // Redirects are the client's to follow, and bodies pass through still encoded
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("HTTP client");
+
+        rocket.mount(
+            "/",
+            proxy_routes(Proxy {
+                host: CircuitBreakers::host_of(&config.upstream),
+                upstream: config.upstream,
+                timeout,
+                client,
+                breakers,
+                transfers,
+            }),
+        )
+    })
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::request::Request;
+use rocket_db_pools::deadpool_redis::redis;
+use tracing::{error, warn};
+
+use crate::db::{self, Cache};
+use crate::errors::ApiError;
+use crate::rate_limit::{self, Limit};
+use crate::telemetry;
+
+// Callers tracked in memory before windows that ended are dropped
+const MAX_TRACKED: usize = 10_000;
+
+// Define the fixed window limit per authenticated caller, read from `user_rate_limit`, with the
+// windows counted in memory when Redis is not the cache backend or cannot be reached
+#[derive(Debug)]
+pub struct UserRateLimit {
+    limit: Limit,
+    // Start of the current window and requests counted in it, by subject
+    windows: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl UserRateLimit {
+    fn count_in_memory(&self, subject: &str, start: u64) -> u64 {
+        let mut windows = self.windows.lock().expect("user rate limit lock");
+        if windows.len() >= MAX_TRACKED {
+            windows.retain(|_, (window, _)| *window == start);
+        }
+        let window = windows.entry(subject.to_string()).or_insert((start, 0));
+        if window.0 != start {
+            *window = (start, 0);
+        }
+        window.1 += 1;
+        window.1
+    }
+}
+
+// Define what is known about the caller's quota after charging the current request
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub limit: Limit,
+    pub remaining: u32,
+    // Seconds until the current window ends and the quota is restored
+    pub reset: u64,
+    // Whether the current request went over the limit
+    pub exceeded: bool,
+}
+
+impl Quota {
+    // Return the 429 refusing a request over the quota
+    pub fn error(&self) -> ApiError {
+        rate_limit::exceeded("user", "user", self.limit, self.reset, self.reset)
+    }
+}
+
+// Remember the quota of the current request so guards and fairings charge it only once
+struct Charged(Option<Quota>);
+
+// Count this request against the quota of `subject`, once per request.
+// With Redis as the cache backend counters survive restarts and are shared by every instance;
+// otherwise, or while Redis is unreachable, each instance counts on its own.
+pub async fn charge(req: &Request<'_>, subject: &str) -> Option<Quota> {
+    req.local_cache_async(async { Charged(count(req, subject).await) })
+        .await
+        .0
+}
+
+// Return the quota charged by the current request, if any
+pub fn of(req: &Request<'_>) -> Option<Quota> {
+    req.local_cache(|| Charged(None)).0
+}
+
+async fn count(req: &Request<'_>, subject: &str) -> Option<Quota> {
+    let state = req.rocket().state::<UserRateLimit>()?;
+    let limit = state.limit;
+    let window = limit.per_seconds.max(1);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let start = now - now % window;
+
+    let used = match db::shared_cache(req.rocket()) {
+        Some(cache) => match count_in_redis(cache, subject, start, window).await {
+            Ok(used) => used,
+            Err(err) => {
+                telemetry::span(req).in_scope(|| {
+                    warn!(
+                        "user rate limit counted in memory, Redis is unavailable: {}",
+                        err
+                    )
+                });
+                state.count_in_memory(subject, start)
+            }
+        },
+        None => state.count_in_memory(subject, start),
+    };
+
+    let allowed = u64::from(limit.requests);
+    Some(Quota {
+        limit,
+        remaining: allowed.saturating_sub(used) as u32,
+        reset: start + window - now,
+        exceeded: used > allowed,
+    })
+}
+
+async fn count_in_redis(
+    cache: &Cache,
+    subject: &str,
+    start: u64,
+    window: u64,
+) -> Result<u64, String> {
+    let mut conn = cache.get().await.map_err(|err| err.to_string())?;
+    let key = format!("ratelimit:user:{}:{}", subject, start);
+    let (used,): (u64,) = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .expire(&key, window as i64)
+        .ignore()
+        .query_async(&mut *conn)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(used)
+}
+
+// Load the per-user limit and report the remaining quota on every authenticated response,
+// refusing to launch with an invalid limit rather than leaving callers unlimited
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("User Rate Limiting", |rocket| async {
+        let limit = match rocket.figment().extract_inner::<Limit>("user_rate_limit") {
+            Ok(limit) => limit,
+            Err(err) if err.missing() => return Ok(rocket),
+            Err(err) => {
+                error!("invalid `user_rate_limit` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(UserRateLimit {
+                limit,
+                windows: Mutex::new(HashMap::new()),
+            })
+            .attach(AdHoc::on_response("Rate Limit Headers", |req, res| {
+                Box::pin(async move {
+                    if let Some(quota) = of(req) {
+                        for header in
+                            rate_limit::headers(quota.limit.requests, quota.remaining, quota.reset)
+                        {
+                            res.set_header(header);
+                        }
+                    }
+                })
+            })))
+    })
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use arc_swap::ArcSwapOption;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::{Header, Status};
+use rocket::serde::json::json;
+use rocket_db_pools::deadpool_redis::redis;
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::auth;
+use crate::db::{self, Cache};
+use crate::errors::ApiError;
+use crate::intercept;
+
+// Define a limit of `requests` per `per_seconds`, refilled continuously
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    pub requests: u32,
+    pub per_seconds: u64,
+}
+
+impl Limit {
+    fn refill_per_second(&self) -> f64 {
+        f64::from(self.requests) / self.per_seconds.max(1) as f64
+    }
+}
+
+// Define a group of routes, selected by path prefix, that share a limit
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteGroup {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+// Define the `rate_limit` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // Limit applied to paths that no group claims
+    pub default: Limit,
+    #[serde(default)]
+    pub groups: Vec<RouteGroup>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+// Define a token bucket holding up to `limit.requests` tokens
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Define the outcome of charging a request against its bucket
+pub enum Decision {
+    Allowed,
+    // The client must wait `retry_after` seconds before the next token is available, and
+    // `reset` seconds before the whole limit is
+    Limited {
+        group: String,
+        limit: Limit,
+        retry_after: u64,
+        reset: u64,
+    },
+}
+
+// Return the headers describing a limit: how many requests it allows, how many are left and the
+// seconds until it is fully restored
+pub fn headers(limit: u32, remaining: u32, reset: u64) -> [Header<'static>; 3] {
+    [
+        Header::new("X-RateLimit-Limit", limit.to_string()),
+        Header::new("X-RateLimit-Remaining", remaining.to_string()),
+        Header::new("X-RateLimit-Reset", reset.to_string()),
+    ]
+}
+
+// Return the 429 refusing a request over `limit`: it says which limit was hit, by client IP or
+// by caller (`scope`) and by name, and when to come back
+pub fn exceeded(scope: &str, name: &str, limit: Limit, retry_after: u64, reset: u64) -> ApiError {
+    let mut error = ApiError::new(
+        Status::TooManyRequests,
+        format!(
+            "rate limit for `{}` exceeded, retry in {}s",
+            name, retry_after
+        ),
+    )
+    .with_details(json!({
+        "rate_limit": {
+            "scope": scope,
+            "name": name,
+            "limit": limit.requests,
+            "per_seconds": limit.per_seconds,
+            "remaining": 0,
+            "reset": reset,
+            "retry_after": retry_after,
+        }
+    }))
+    .with_header(Header::new("Retry-After", retry_after.to_string()));
+    for header in headers(limit.requests, 0, reset) {
+        error = error.with_header(header);
+    }
+    error
+}
+
+// Define the per-client-IP rate limiter managed as state. Buckets live in memory, one set per
+// instance; with Redis as the cache backend every instance counts in shared fixed windows of
+// `per_seconds` instead, and falls back to its buckets while Redis is unreachable. Clones share
+// the limits and the buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    // Limits in force, swapped when the configuration is reloaded; none while disabled
+    config: Arc<ArcSwapOption<RateLimitConfig>>,
+    buckets: Arc<Mutex<HashMap<(usize, IpAddr), Bucket>>>,
+}
+
+// Buckets are pruned once this many clients are being tracked
+const MAX_TRACKED: usize = 10_000;
+
+impl RateLimiter {
+    pub fn new(config: Option<RateLimitConfig>) -> Self {
+        RateLimiter {
+            config: Arc::new(ArcSwapOption::new(config.map(Arc::new))),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Put other limits in force, or none to stop limiting. Buckets are kept per group, so they
+    // start over full.
+    pub fn reconfigure(&self, config: Option<RateLimitConfig>) {
+        self.config.store(config.map(Arc::new));
+        self.buckets.lock().expect("rate limiter lock").clear();
+    }
+
+    // Find the group claiming `path`, where index `groups.len()` stands for the default limit
+    fn group_for<'c>(config: &'c RateLimitConfig, path: &str) -> (usize, &'c str, Limit) {
+        config
+            .groups
+            .iter()
+            .enumerate()
+            .find(|(_, group)| group.prefixes.iter().any(|prefix| path.starts_with(prefix)))
+            .map(|(index, group)| (index, group.name.as_str(), group.limit))
+            .unwrap_or((config.groups.len(), "default", config.default))
+    }
+
+    // Charge a request from `ip` to `path` against the limit of the group it belongs to
+    pub async fn check(&self, cache: Option<&Cache>, ip: IpAddr, path: &str) -> Decision {
+        let Some(config) = self.config.load_full() else {
+            return Decision::Allowed;
+        };
+        let (index, name, limit) = RateLimiter::group_for(&config, path);
+        if let Some(cache) = cache {
+            match count_in_window(cache, name, ip, limit).await {
+                Ok(decision) => return decision,
+                Err(err) => warn!("rate limiting in memory, Redis is unavailable: {}", err),
+            }
+        }
+        self.take(index, name, limit, ip)
+    }
+
+    // Take one token from the bucket of `ip` for the group at `index`
+    fn take(&self, index: usize, name: &str, limit: Limit, ip: IpAddr) -> Decision {
+        let capacity = f64::from(limit.requests);
+        let rate = limit.refill_per_second();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().expect("rate limiter lock");
+        if buckets.len() >= MAX_TRACKED {
+            // Forget clients whose buckets have refilled completely, they lose nothing
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry((index, ip)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allowed
+        } else {
+            Decision::Limited {
+                group: name.to_string(),
+                limit,
+                retry_after: ((1.0 - bucket.tokens) / rate).ceil() as u64,
+                reset: ((capacity - bucket.tokens) / rate).ceil() as u64,
+            }
+        }
+    }
+}
+
+// Count the request in the current window of `limit` in Redis
+async fn count_in_window(
+    cache: &Cache,
+    group: &str,
+    ip: IpAddr,
+    limit: Limit,
+) -> Result<Decision, String> {
+    let mut conn = cache.get().await.map_err(|err| err.to_string())?;
+    let window = limit.per_seconds.max(1);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let start = now - now % window;
+    let key = format!("ratelimit:ip:{}:{}:{}", group, ip, start);
+    let (used,): (u64,) = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .expire(&key, window as i64)
+        .ignore()
+        .query_async(&mut *conn)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(match used > u64::from(limit.requests) {
+        false => Decision::Allowed,
+        true => Decision::Limited {
+            group: group.to_string(),
+            limit,
+            retry_after: start + window - now,
+            reset: start + window - now,
+        },
+    })
+}
+
+// Read the `rate_limit` table, where none means rate limiting is off
+pub fn config(figment: &Figment) -> Result<Option<RateLimitConfig>, String> {
+    match figment.extract_inner::<RateLimitConfig>("rate_limit") {
+        Ok(config) => Ok(Some(config).filter(|config| config.enabled)),
+        Err(err) if err.missing() => Ok(None),
+        Err(err) => Err(format!("invalid `rate_limit` configuration: {}", err)),
+    }
+}
+
+// Load the rate limits and reject clients that exceed them with 429 before routing, saying
+// which limit they hit and when to come back. The limiter is managed even while disabled so a
+// reloaded configuration can turn it on; an invalid table refuses to launch rather than leaving
+// the server unlimited.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Rate Limiting", |rocket| async {
+        let config = match config(rocket.figment()) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("{}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(RateLimiter::new(config))
+            .attach(AdHoc::on_request("Rate Limiter", |req, _| {
+                Box::pin(async move {
+                    let Some(limiter) = req.rocket().state::<RateLimiter>() else {
+                        return;
+                    };
+                    let Some(ip) = auth::client_address(req) else {
+                        return;
+                    };
+                    if let Decision::Limited {
+                        group,
+                        limit,
+                        retry_after,
+                        reset,
+                    } = limiter
+                        .check(
+                            db::shared_cache(req.rocket()),
+                            ip,
+                            req.uri().path().as_str(),
+                        )
+                        .await
+                    {
+                        let error = exceeded("ip", &group, limit, retry_after, reset);
+                        intercept::reject(req, error);
+                    }
+                })
+            })))
+    })
+}
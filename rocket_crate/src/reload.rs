@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::{self, select, time::sleep};
+use rocket::{Config, Shutdown, State};
+use serde::Deserialize;
+use tracing::{info, warn};
+use utoipa::OpenApi;
+
+use crate::auth::AdminUser;
+use crate::cli::Cli;
+use crate::errors::ApiError;
+use crate::flags::{self, FeatureFlags};
+use crate::maintenance::{self, Maintenance, MaintenanceConfig};
+use crate::rate_limit::{self, RateLimitConfig, RateLimiter};
+use crate::telemetry;
+
+// Define the `reload` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReloadConfig {
+    // Reload the runtime settings whenever the configuration file changes
+    #[serde(default)]
+    pub watch: bool,
+    // Seconds between checks of the configuration file while watching it
+    #[serde(default = "interval")]
+    pub interval: u64,
+}
+
+fn interval() -> u64 {
+    2
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            watch: false,
+            interval: interval(),
+        }
+    }
+}
+
+// Define the settings that change without a restart, as read from the configuration
+struct Settings {
+    rate_limit: Option<RateLimitConfig>,
+    feature_flags: HashMap<String, bool>,
+    maintenance: MaintenanceConfig,
+    log_filter: Option<String>,
+}
+
+impl Settings {
+    // Read every runtime setting, or every problem found, so a bad file changes nothing
+    fn read(figment: &Figment) -> Result<Settings, Vec<String>> {
+        match (
+            rate_limit::config(figment),
+            flags::config(figment),
+            maintenance::config(figment),
+            telemetry::log_filter(figment),
+        ) {
+            (Ok(rate_limit), Ok(flags), Ok(maintenance), Ok(log_filter)) => Ok(Settings {
+                rate_limit,
+                feature_flags: flags.defaults,
+                maintenance,
+                log_filter,
+            }),
+            (rate_limit, flags, maintenance, log_filter) => Err([
+                rate_limit.err(),
+                flags.err(),
+                maintenance.err(),
+                log_filter.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
+    }
+}
+
+// Define the handles on the state the runtime settings are kept in, managed as state so the
+// admin route and the file watcher reload the same. Clones share the state.
+#[derive(Clone)]
+pub struct Reloader {
+    // Command line the server was started with, which says where the configuration is read from
+    cli: Option<Cli>,
+    limiter: Option<RateLimiter>,
+    flags: Option<FeatureFlags>,
+    maintenance: Option<Maintenance>,
+}
+
+impl Reloader {
+    // Read the configuration again, from the same sources as at launch
+    fn figment(&self) -> Figment {
+        match &self.cli {
+            Some(cli) => cli.figment(),
+            None => Config::figment(),
+        }
+    }
+
+    // Return the configuration file watched for changes
+    fn file(&self) -> PathBuf {
+        match &self.cli {
+            Some(cli) => cli.config_file(),
+            None => PathBuf::from("Rocket.toml"),
+        }
+    }
+
+    // Read the runtime settings again and put them in force, describing them; nothing changes
+    // when any of them is invalid
+    fn reload(&self) -> Result<Value, Vec<String>> {
+        let settings = Settings::read(&self.figment())?;
+        if let Some(limiter) = &self.limiter {
+            limiter.reconfigure(settings.rate_limit.clone());
+        }
+        if let Some(flags) = &self.flags {
+            flags.set_defaults(settings.feature_flags.clone());
+        }
+        if let Some(maintenance) = &self.maintenance {
+            maintenance.reconfigure(&settings.maintenance);
+        }
+        if let Some(filter) = &settings.log_filter {
+            if let Err(err) = telemetry::set_filter(filter) {
+                warn!("{}", err);
+            }
+        }
+        Ok(json!({
+            "rate_limit": { "enabled": settings.rate_limit.is_some() },
+            "feature_flags": settings.feature_flags,
+            "maintenance": {
+                "enabled": settings.maintenance.enabled,
+                "retry_after": settings.maintenance.retry_after,
+            },
+            "log_filter": settings.log_filter,
+        }))
+    }
+}
+
+// Define a route handler that reloads the rate limits, feature flag defaults, maintenance mode
+// and log filter from the configuration without a restart
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "reload_config",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The runtime settings now in force", body = Value),
+        (status = 422, description = "The configuration is invalid; nothing changed", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/admin/config/reload")]
+fn reload(admin: AdminUser, reloader: &State<Reloader>) -> Result<Json<Value>, ApiError> {
+    match reloader.reload() {
+        Ok(settings) => {
+            info!("configuration reloaded by {}", admin.identity.subject);
+            Ok(Json(settings))
+        }
+        Err(problems) => Err(ApiError::new(
+            Status::UnprocessableEntity,
+            "the configuration is invalid, nothing was reloaded",
+        )
+        .with_details(json!({ "problems": problems }))),
+    }
+}
+
+// Define the part of the OpenAPI document describing the configuration reload
+#[derive(OpenApi)]
+#[openapi(paths(reload))]
+pub struct ApiDoc;
+
+// Return when the configuration file was last changed, if it can be read
+fn modified(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+// Check the configuration file every `interval` and reload the runtime settings each time it
+// changed, until the server shuts down
+async fn watch(reloader: Reloader, interval: Duration, mut shutdown: Shutdown) {
+    let file = reloader.file();
+    let Some(mut seen) = modified(&file) else {
+        warn!(
+            "not watching the configuration, `{}` cannot be read",
+            file.display()
+        );
+        return;
+    };
+    info!("watching `{}` for configuration changes", file.display());
+    loop {
+        select! {
+            _ = sleep(interval) => {}
+            _ = &mut shutdown => return,
+        }
+        let Some(changed) = modified(&file).filter(|changed| *changed != seen) else {
+            continue;
+        };
+        seen = changed;
+        match reloader.reload() {
+            Ok(_) => info!("configuration reloaded, `{}` changed", file.display()),
+            Err(problems) => warn!(
+                "kept the runtime settings, `{}` is invalid: {}",
+                file.display(),
+                problems.join("; ")
+            ),
+        }
+    }
+}
+
+// Mount the route reloading the runtime settings, and watch the configuration file for changes
+// when `reload.watch` is on. Attached after the stages keeping those settings.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Configuration Reload", |rocket| async {
+        let config = match rocket.figment().extract_inner::<ReloadConfig>("reload") {
+            Ok(config) => config,
+            Err(err) if err.missing() => ReloadConfig::default(),
+            Err(err) => {
+                warn!(
+                    "not watching the configuration, invalid `reload` configuration: {}",
+                    err
+                );
+                ReloadConfig::default()
+            }
+        };
+        let reloader = Reloader {
+            cli: rocket.state::<Cli>().cloned(),
+            limiter: rocket.state::<RateLimiter>().cloned(),
+            flags: rocket.state::<FeatureFlags>().cloned(),
+            maintenance: rocket.state::<Maintenance>().cloned(),
+        };
+
+        let rocket = rocket.manage(reloader.clone()).mount("/", routes![reload]);
+        match config.watch {
+            true => rocket.attach(AdHoc::on_liftoff("Configuration Watcher", move |rocket| {
+                Box::pin(async move {
+                    let interval = Duration::from_secs(config.interval.max(1));
+                    tokio::spawn(watch(reloader, interval, rocket.shutdown()));
+                })
+            })),
+            false => rocket,
+        }
+    })
+}
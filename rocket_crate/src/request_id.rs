@@ -0,0 +1,54 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Header;
+use rocket::request::Request;
+use uuid::Uuid;
+
+// Define the identifier attached to every request, taken from the client or generated
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r str {
+        &req.local_cache(|| {
+            let id = req
+                .headers()
+                .get_one(Self::HEADER)
+                .filter(|id| is_valid(id))
+                .map(String::from)
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            RequestId(id)
+        })
+        .0
+    }
+}
+
+// Accept client ids that are short and safe to copy into headers and log lines
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+// Settle the id of every request before routing and echo it back in the response
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Request Id", |rocket| async {
+        rocket
+            .attach(AdHoc::on_request("Request Id", |req, _| {
+                Box::pin(async move {
+                    RequestId::of(req);
+                })
+            }))
+            .attach(AdHoc::on_response("Request Id Header", |req, res| {
+                Box::pin(async move {
+                    res.set_header(Header::new(
+                        RequestId::HEADER,
+                        RequestId::of(req).to_string(),
+                    ));
+                })
+            }))
+    })
+}
@@ -0,0 +1,270 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use prometheus::{IntCounterVec, Opts};
+use rocket::fairing::AdHoc;
+use rocket::request::{self, FromRequest, Request};
+use rocket_db_pools::deadpool_redis::redis::{self, AsyncCommands};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::db::Cache;
+use crate::metrics::Metrics;
+use crate::tenant::Tenant;
+use crate::versioning;
+
+// Define the `response_cache` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseCacheConfig {
+    // Seconds a cached response is served before the route runs again
+    pub ttl: u64,
+    // Entries beyond this count are not cached until expired ones are evicted
+    pub max_entries: usize,
+}
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires_at: Instant,
+}
+
+// Prefix of the Redis keys holding cached results as JSON
+const REDIS_PREFIX: &str = "response_cache:";
+
+// Define a cache of route results keyed by request path and query, managed as state. In memory
+// each instance caches on its own, so the TTL bounds how stale another instance's writes
+// appear; in Redis every instance shares the entries and sees invalidations at once. Clones
+// share the entries.
+#[derive(Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    lookups: IntCounterVec,
+}
+
+impl ResponseCache {
+    // Return the cached value for `key` if it is fresh and of type `T`, from Redis when `cache`
+    // is given and reachable
+    pub async fn get<T>(&self, cache: Option<&Cache>, key: &CacheKey) -> Option<T>
+    where
+        T: Clone + DeserializeOwned + Send + Sync + 'static,
+    {
+        let value = match self.ttl.is_zero() {
+            true => None,
+            false => match redis_get(cache, key).await {
+                Some(stored) => stored,
+                None => self.memory_get(key),
+            },
+        };
+        let outcome = if value.is_some() { "hit" } else { "miss" };
+        self.lookups.with_label_values(&[outcome]).inc();
+        value
+    }
+
+    fn memory_get<T: Clone + Send + Sync + 'static>(&self, key: &CacheKey) -> Option<T> {
+        let entries = self.entries.lock().expect("response cache lock");
+        entries
+            .get(&key.0)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .and_then(|entry| entry.value.downcast_ref::<T>().cloned())
+    }
+
+    pub async fn put<T>(&self, cache: Option<&Cache>, key: &CacheKey, value: T)
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        if self.ttl.is_zero() || redis_put(cache, key, &value, self.ttl).await {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("response cache lock");
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, entry| entry.expires_at > now);
+            if entries.len() >= self.max_entries {
+                return;
+            }
+        }
+        entries.insert(
+            key.0.clone(),
+            Entry {
+                value: Arc::new(value),
+                expires_at: now + self.ttl,
+            },
+        );
+    }
+
+    // Drop every entry whose path starts with `prefix`, called by routes that change the data
+    pub async fn invalidate(&self, cache: Option<&Cache>, prefix: &str) {
+        redis_invalidate(cache, prefix).await;
+        // Entries stored while Redis was unreachable live in memory
+        let mut entries = self.entries.lock().expect("response cache lock");
+        entries.retain(|key, _| !key.starts_with(prefix));
+    }
+}
+
+// Look `key` up in Redis: None when it cannot be asked, Some(None) on a miss
+async fn redis_get<T: DeserializeOwned>(
+    cache: Option<&Cache>,
+    key: &CacheKey,
+) -> Option<Option<T>> {
+    let mut conn = cache?
+        .get()
+        .await
+        .map_err(|err| warn!("response cache in memory, Redis is unavailable: {}", err))
+        .ok()?;
+    let stored: Option<String> = conn
+        .get(format!("{REDIS_PREFIX}{}", key.0))
+        .await
+        .map_err(|err| warn!("response cache in memory, Redis command failed: {}", err))
+        .ok()?;
+    Some(stored.and_then(|stored| serde_json::from_str(&stored).ok()))
+}
+
+// Store `value` in Redis, returning whether it was
+async fn redis_put<T: Serialize>(
+    cache: Option<&Cache>,
+    key: &CacheKey,
+    value: &T,
+    ttl: Duration,
+) -> bool {
+    let Some(cache) = cache else {
+        return false;
+    };
+    let Ok(value) = serde_json::to_string(value) else {
+        return false;
+    };
+    let stored = match cache.get().await {
+        Ok(mut conn) => conn
+            .set_ex::<_, _, ()>(format!("{REDIS_PREFIX}{}", key.0), value, ttl.as_secs())
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match stored {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("response cache in memory, Redis is unavailable: {}", err);
+            false
+        }
+    }
+}
+
+// Delete the Redis entries whose key starts with `prefix`
+async fn redis_invalidate(cache: Option<&Cache>, prefix: &str) {
+    let Some(cache) = cache else {
+        return;
+    };
+    let deleted = async {
+        let mut conn = cache.get().await.map_err(|err| err.to_string())?;
+        let pattern = format!("{REDIS_PREFIX}{}*", glob_escape(prefix));
+        let keys: Vec<String> = {
+            let mut iter = conn
+                .scan_match::<_, String>(pattern)
+                .await
+                .map_err(|err| err.to_string())?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        if !keys.is_empty() {
+            redis::cmd("DEL")
+                .arg(&keys)
+                .query_async::<_, ()>(&mut *conn)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        Ok::<(), String>(())
+    };
+    if let Err(err) = deleted.await {
+        warn!("failed to invalidate cached responses in Redis: {}", err);
+    }
+}
+
+// Escape the characters Redis patterns give a meaning to
+fn glob_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Define a guard deriving the cache key of a request from its path and query, followed by its
+// tenant so tenants never get each other's cached responses. Versioned routes key by the path
+// without its version prefix followed by the version, so every version lives under the same
+// prefix; their version guard must come first, for the version to be known.
+pub struct CacheKey(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CacheKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let tenant = match req.guard::<Tenant>().await {
+            request::Outcome::Success(tenant) => tenant.id,
+            _ => String::new(),
+        };
+        let uri = req.uri().to_string();
+        let key = match versioning::of(req) {
+            Some(version) => format!(
+                "{} {} v{}",
+                versioning::unprefixed(&uri),
+                tenant,
+                version.number()
+            ),
+            None => format!("{} {}", uri, tenant),
+        };
+        request::Outcome::Success(CacheKey(key))
+    }
+}
+
+// Load the cache settings and manage the cache, counting hits and misses in `/metrics`
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Response Cache", |rocket| async {
+        // Without a cache table nothing is stored, so routes always run
+        let disabled = ResponseCacheConfig {
+            ttl: 0,
+            max_entries: 0,
+        };
+        let config = match rocket
+            .figment()
+            .extract_inner::<ResponseCacheConfig>("response_cache")
+        {
+            Ok(config) => config,
+            Err(err) if err.missing() => disabled,
+            Err(err) => {
+                error!("invalid `response_cache` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        let lookups = IntCounterVec::new(
+            Opts::new(
+                "response_cache_lookups_total",
+                "Response cache lookups, by hit or miss",
+            ),
+            &["outcome"],
+        )
+        .expect("valid metric");
+        if let Some(metrics) = rocket.state::<Metrics>() {
+            if let Err(err) = metrics.register(Box::new(lookups.clone())) {
+                warn!("failed to register response cache metrics: {}", err);
+            }
+        }
+
+        Ok(rocket.manage(ResponseCache {
+            ttl: Duration::from_secs(config.ttl),
+            max_entries: config.max_entries,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            lookups,
+        }))
+    })
+}
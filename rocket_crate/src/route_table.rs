@@ -0,0 +1,228 @@
+use rocket::fairing::{self, AdHoc, Fairing, Info, Kind};
+use rocket::serde::json::Json;
+use rocket::{Build, Orbit, Rocket, Route, State};
+use serde::Serialize;
+use tracing::info;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::auth::AdminUser;
+use crate::errors::ApiError;
+
+// Define a guard a route declares in its URI, which is all Rocket tells of a route's guards:
+// request and data guards are only known to the handler
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UriGuard {
+    name: String,
+    // `param` for a segment such as `<id>`, `segments` for `<path..>`, `query` for `<q>` and
+    // `query_form` for `<pagination..>`
+    kind: &'static str,
+}
+
+// Define a mounted route as listed in the route table
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RouteEntry {
+    method: String,
+    // Full URI of the route, mount base included, such as `/v2/todos?<pagination..>`
+    uri: String,
+    base: String,
+    rank: isize,
+    // Name of the handler function, absent for routes built by hand such as the proxy's
+    name: Option<String>,
+    // Media type the route is restricted to, such as `application/json`
+    format: Option<String>,
+    guards: Vec<UriGuard>,
+    // Routes of the same method that match some of the same requests, tried in rank order;
+    // listed as `METHOD uri (rank N)`
+    collides_with: Vec<String>,
+}
+
+// Define a registered catcher as listed in the route table
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CatcherEntry {
+    // Status caught, absent for the default catcher
+    code: Option<u16>,
+    base: String,
+    name: Option<String>,
+}
+
+// Define the route table as it stood once every stage had mounted its routes, managed as state
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RouteTable {
+    routes: Vec<RouteEntry>,
+    catchers: Vec<CatcherEntry>,
+}
+
+fn is_dynamic(segment: &str) -> bool {
+    segment.starts_with('<') && segment.ends_with('>')
+}
+
+fn guards(route: &Route) -> Vec<UriGuard> {
+    let path = route
+        .uri
+        .path()
+        .split('/')
+        .filter(|segment| is_dynamic(segment))
+        .map(|segment| (segment, "segments", "param"));
+    let query = route
+        .uri
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter(|segment| is_dynamic(segment))
+        .map(|segment| (segment, "query_form", "query"));
+    path.chain(query)
+        .map(|(segment, trailing, single)| {
+            let name = &segment[1..segment.len() - 1];
+            match name.strip_suffix("..") {
+                Some(name) => UriGuard {
+                    name: name.to_string(),
+                    kind: trailing,
+                },
+                None => UriGuard {
+                    name: name.to_string(),
+                    kind: single,
+                },
+            }
+        })
+        .collect()
+}
+
+// Return whether two routes can match the same request, as Rocket judges collisions: the same
+// method, paths whose segments pairwise match, and formats that do not rule each other out.
+// Queries are left out, as Rocket leaves them out too.
+fn collide(a: &Route, b: &Route) -> bool {
+    if a.method != b.method {
+        return false;
+    }
+    if let (Some(a), Some(b)) = (&a.format, &b.format) {
+        if a != b && !a.is_any() && !b.is_any() {
+            return false;
+        }
+    }
+    let segments = |route: &Route| -> Vec<String> {
+        route
+            .uri
+            .path()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let (a, b) = (segments(a), segments(b));
+    let trailing = |segment: &String| is_dynamic(segment) && segment.ends_with("..>");
+    let mut i = 0;
+    loop {
+        match (a.get(i), b.get(i)) {
+            (Some(x), _) if trailing(x) => return true,
+            (_, Some(y)) if trailing(y) => return true,
+            (Some(x), Some(y)) if x == y || is_dynamic(x) || is_dynamic(y) => i += 1,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+impl RouteTable {
+    fn of(rocket: &Rocket<Build>) -> RouteTable {
+        let mounted: Vec<&Route> = rocket.routes().collect();
+        let mut routes: Vec<RouteEntry> = mounted
+            .iter()
+            .map(|route| RouteEntry {
+                method: route.method.as_str().to_string(),
+                uri: route.uri.to_string(),
+                base: route.uri.base().to_string(),
+                rank: route.rank,
+                name: route.name.as_ref().map(|name| name.to_string()),
+                format: route.format.as_ref().map(|format| format.to_string()),
+                guards: guards(route),
+                collides_with: mounted
+                    .iter()
+                    .filter(|other| !std::ptr::eq(**other, *route) && collide(route, other))
+                    .map(|other| format!("{} {} (rank {})", other.method, other.uri, other.rank))
+                    .collect(),
+            })
+            .collect();
+        routes.sort_by(|a, b| (&a.uri, &a.method, a.rank).cmp(&(&b.uri, &b.method, b.rank)));
+        let catchers = rocket
+            .catchers()
+            .map(|catcher| CatcherEntry {
+                code: catcher.code,
+                base: catcher.base.to_string(),
+                name: catcher.name.as_ref().map(|name| name.to_string()),
+            })
+            .collect();
+        RouteTable { routes, catchers }
+    }
+}
+
+// Define a route handler that lists every mounted route, with its rank, URI guards and the routes
+// it collides with, and every catcher, to debug requests that never reach the route meant for them
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "list_routes",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The mounted routes and registered catchers", body = RouteTable),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/routes")]
+fn list(_admin: AdminUser, table: &State<RouteTable>) -> Json<RouteTable> {
+    Json(table.inner().clone())
+}
+
+// Define the part of the OpenAPI document describing the route table
+#[derive(OpenApi)]
+#[openapi(paths(list))]
+pub struct ApiDoc;
+
+// Define the fairing assembling the route table at ignite, once every stage has mounted its
+// routes, and announcing what is served at liftoff
+struct Introspection;
+
+#[rocket::async_trait]
+impl Fairing for Introspection {
+    fn info(&self) -> Info {
+        Info {
+            name: "Route Table",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let table = RouteTable::of(&rocket);
+        Ok(rocket.manage(table))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let config = rocket.config();
+        let scheme = match config.tls_enabled() {
+            true => "https",
+            false => "http",
+        };
+        let (routes, catchers) = match rocket.state::<RouteTable>() {
+            Some(table) => (table.routes.len(), table.catchers.len()),
+            None => (rocket.routes().count(), rocket.catchers().count()),
+        };
+        info!(
+            "{} {} serving {} routes and {} catchers at {}://{}:{} with the `{}` profile; the route table is at GET /admin/routes",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            routes,
+            catchers,
+            scheme,
+            config.address,
+            config.port,
+            rocket.figment().profile()
+        );
+    }
+}
+
+// Mount the route table, assembled by a fairing this stage attaches; attached fairings run after
+// every stage attached before, so the table holds all their routes
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Route Table", |rocket| async {
+        rocket.mount("/", routes![list]).attach(Introspection)
+    })
+}
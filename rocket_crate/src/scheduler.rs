@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rocket::fairing::AdHoc;
+use rocket::futures::future::BoxFuture;
+use rocket::serde::json::{json, Json, Value};
+use rocket::time::{Duration, OffsetDateTime};
+use rocket::tokio::{self, select, time::sleep};
+use rocket::{Shutdown, State};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+use utoipa::OpenApi;
+
+use crate::auth::AdminUser;
+use crate::errors::ApiError;
+
+// Define the `scheduler` table of Rocket.toml: a cron expression per task, replacing the
+// schedule the task was registered with, or "off" to never run it
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(default)]
+    pub tasks: HashMap<String, String>,
+}
+
+// Define the values one field of a cron expression matches
+#[derive(Debug, Clone)]
+struct Field(Vec<bool>);
+
+impl Field {
+    // Parse `*`, `5`, `1-5`, `*/15`, `10-40/10` and comma-separated lists of them, for values
+    // from `min` to `max`
+    fn parse(field: &str, min: u32, max: u32) -> Result<Field, String> {
+        let mut matches = vec![false; max as usize + 1];
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse().map_err(|_| bad(part))?),
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((start, end)) => (
+                        start.parse().map_err(|_| bad(part))?,
+                        end.parse().map_err(|_| bad(part))?,
+                    ),
+                    None => {
+                        let value = range.parse().map_err(|_| bad(part))?;
+                        // `5/10` means from 5 to the end, every 10
+                        (value, if step > 1 { max } else { value })
+                    }
+                },
+            };
+            if start < min || end > max || start > end || step == 0 {
+                return Err(format!("`{}` is outside {}-{}", part, min, max));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                matches[value as usize] = true;
+            }
+        }
+        Ok(Field(matches))
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.0.get(value as usize).copied().unwrap_or(false)
+    }
+}
+
+fn bad(part: &str) -> String {
+    format!("`{}` is not a number, range or step", part)
+}
+
+// Define a parsed cron expression: minute, hour, day of month, month and day of week (0 is
+// Sunday), evaluated in UTC
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expression: String,
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+    // Whether both day fields are restricted, in which case a day matching either one runs, as
+    // in cron: `0 0 1 * 1` runs on the 1st and on every Monday
+    either_day: bool,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Schedule, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "`{}` does not have the 5 fields minute, hour, day, month and weekday",
+                expression
+            ));
+        };
+        Ok(Schedule {
+            // A field starting with `*`, such as `*/2`, is not a restriction, as in cron
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+            expression: expression.to_string(),
+            minute: Field::parse(minute, 0, 59)?,
+            hour: Field::parse(hour, 0, 23)?,
+            day: Field::parse(day, 1, 31)?,
+            month: Field::parse(month, 1, 12)?,
+            weekday: Field::parse(weekday, 0, 7).map(|mut field| {
+                // 7 is Sunday too
+                field.0[0] |= field.0[7];
+                field
+            })?,
+        })
+    }
+
+    fn matches(&self, at: OffsetDateTime) -> bool {
+        let day = self.day.matches(at.day().into());
+        let weekday = self
+            .weekday
+            .matches(at.weekday().number_days_from_sunday().into());
+        let day = match self.either_day {
+            true => day || weekday,
+            false => day && weekday,
+        };
+        self.minute.matches(at.minute().into())
+            && self.hour.matches(at.hour().into())
+            && self.month.matches(u8::from(at.month()).into())
+            && day
+    }
+
+    // Return the first minute after `after` that the schedule matches, looking a year ahead
+    pub fn next(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let mut at = start_of_minute(after) + Duration::MINUTE;
+        let limit = at + Duration::days(366);
+        while at < limit {
+            if self.matches(at) {
+                return Some(at);
+            }
+            at += Duration::MINUTE;
+        }
+        None
+    }
+}
+
+fn start_of_minute(at: OffsetDateTime) -> OffsetDateTime {
+    at.replace_second(0)
+        .and_then(|at| at.replace_nanosecond(0))
+        .expect("valid time")
+}
+
+// Define the work of a task; it returns what it did or why it failed. Tasks run after the
+// server is up, outside of any request, so they capture shared handles to the state they need.
+pub type TaskFn = Box<dyn Fn() -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+// Define how the last run of a task went, as reported by `GET /admin/scheduler`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TaskStatus {
+    pub running: bool,
+    pub runs: u64,
+    pub last_run_at: Option<i64>,
+    pub last_duration_ms: Option<u128>,
+    pub last_ok: Option<bool>,
+    pub last_message: Option<String>,
+}
+
+struct Task {
+    name: &'static str,
+    schedule: Option<Schedule>,
+    run: TaskFn,
+    status: Mutex<TaskStatus>,
+}
+
+impl Task {
+    // Run the task unless it is still running from an earlier minute
+    async fn run(&self, at: OffsetDateTime) {
+        {
+            let mut status = self.status.lock().expect("task status lock");
+            if status.running {
+                warn!(
+                    "skipping task `{}`, its last run has not finished",
+                    self.name
+                );
+                return;
+            }
+            status.running = true;
+        }
+        let started = Instant::now();
+        debug!("running task `{}`", self.name);
+        let outcome = (self.run)().await;
+
+        let mut status = self.status.lock().expect("task status lock");
+        status.running = false;
+        status.runs += 1;
+        status.last_run_at = Some(at.unix_timestamp());
+        status.last_duration_ms = Some(started.elapsed().as_millis());
+        status.last_ok = Some(outcome.is_ok());
+        match outcome {
+            Ok(message) => {
+                debug!("task `{}` done: {}", self.name, message);
+                status.last_message = Some(message);
+            }
+            Err(message) => {
+                warn!("task `{}` failed: {}", self.name, message);
+                status.last_message = Some(message);
+            }
+        }
+    }
+}
+
+// Define the scheduler other modules register their recurring tasks with, managed as state.
+// Tasks are registered while the server ignites and run once it is up.
+pub struct Scheduler {
+    overrides: HashMap<String, String>,
+    tasks: Mutex<Vec<Arc<Task>>>,
+}
+
+impl Scheduler {
+    // Register a task run on `schedule` unless the `scheduler.tasks` table says otherwise
+    pub fn register(&self, name: &'static str, schedule: &str, run: TaskFn) {
+        let expression = self
+            .overrides
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or(schedule);
+        let schedule = match expression {
+            "off" => None,
+            expression => match Schedule::parse(expression) {
+                Ok(schedule) => Some(schedule),
+                Err(err) => {
+                    error!("task `{}` will not run, invalid schedule: {}", name, err);
+                    None
+                }
+            },
+        };
+        self.tasks
+            .lock()
+            .expect("scheduler lock")
+            .push(Arc::new(Task {
+                name,
+                schedule,
+                run,
+                status: Mutex::new(TaskStatus::default()),
+            }));
+    }
+}
+
+// Wake up at the start of every minute and start the tasks whose schedule matches it
+async fn run(tasks: Vec<Arc<Task>>, mut shutdown: Shutdown) {
+    loop {
+        let now = OffsetDateTime::now_utc();
+        let next = start_of_minute(now) + Duration::MINUTE;
+        select! {
+            _ = sleep((next - now).unsigned_abs()) => {}
+            _ = &mut shutdown => break,
+        }
+        for task in &tasks {
+            if task.schedule.as_ref().is_some_and(|s| s.matches(next)) {
+                let task = task.clone();
+                tokio::spawn(async move { task.run(next).await });
+            }
+        }
+    }
+    info!("scheduler stopped");
+}
+
+// Define a route handler that lists the scheduled tasks with how their last run went
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "list_tasks",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every task with its schedule and how its last run went", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/admin/scheduler")]
+fn list(_admin: AdminUser, scheduler: &State<Scheduler>) -> Json<Value> {
+    let now = OffsetDateTime::now_utc();
+    let tasks: BTreeMap<&str, Value> = scheduler
+        .tasks
+        .lock()
+        .expect("scheduler lock")
+        .iter()
+        .map(|task| {
+            let schedule = task.schedule.as_ref();
+            let status = task.status.lock().expect("task status lock").clone();
+            let next_run_at = schedule
+                .and_then(|schedule| schedule.next(now))
+                .map(|at| at.unix_timestamp());
+            (
+                task.name,
+                json!({
+                    "schedule": schedule.map(|schedule| &schedule.expression),
+                    "next_run_at": next_run_at,
+                    "status": status,
+                }),
+            )
+        })
+        .collect();
+    Json(json!({ "tasks": tasks }))
+}
+
+// Define the part of the OpenAPI document describing the scheduler status
+#[derive(OpenApi)]
+#[openapi(paths(list))]
+pub struct ApiDoc;
+
+// Manage the scheduler so later stages can register tasks, then run them every minute their
+// schedule matches once the server is up
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Scheduler", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<SchedulerConfig>("scheduler")
+        {
+            Ok(config) => config,
+            Err(err) if err.missing() => SchedulerConfig::default(),
+            Err(err) => {
+                warn!(
+                    "invalid `scheduler` configuration, using the default schedules: {}",
+                    err
+                );
+                SchedulerConfig::default()
+            }
+        };
+
+        rocket
+            .manage(Scheduler {
+                overrides: config.tasks,
+                tasks: Mutex::new(Vec::new()),
+            })
+            .mount("/", routes![list])
+            .attach(AdHoc::on_liftoff("Scheduler", |rocket| {
+                Box::pin(async move {
+                    let scheduler = rocket.state::<Scheduler>().expect("scheduler");
+                    let tasks = scheduler.tasks.lock().expect("scheduler lock").clone();
+                    let known: Vec<&str> = tasks.iter().map(|task| task.name).collect();
+                    for name in scheduler.overrides.keys() {
+                        if !known.contains(&name.as_str()) {
+                            warn!("`scheduler.tasks` names unknown task `{}`", name);
+                        }
+                    }
+                    info!("scheduling {} tasks", tasks.len());
+                    tokio::spawn(run(tasks, rocket.shutdown()));
+                })
+            }))
+    })
+}
@@ -0,0 +1,141 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Header;
+use rocket::shield::{Frame, Hsts, Policy, Referrer, Shield};
+use rocket::time::Duration;
+use serde::Deserialize;
+use tracing::error;
+
+// Define the `security_headers` table of Rocket.toml. Every header can be turned off on its own,
+// which is how the `debug` profile relaxes them for local development over plain HTTP:
+// `hsts.max_age = 0`, `content_security_policy = ""`, `frame_options = "off"` and
+// `referrer_policy = "off"`, or `enabled = false` to send none of Shield's headers at all.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecurityHeaders {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // Sent as `Content-Security-Policy`; empty to leave it out
+    #[serde(default)]
+    pub content_security_policy: String,
+    #[serde(default)]
+    pub hsts: HstsConfig,
+    #[serde(default)]
+    pub frame_options: FrameOptions,
+    #[serde(default)]
+    pub referrer_policy: ReferrerPolicy,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+// Define the `Strict-Transport-Security` settings, where a `max_age` of 0 leaves the header out
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct HstsConfig {
+    pub max_age: i64,
+    #[serde(default)]
+    pub include_subdomains: bool,
+    #[serde(default)]
+    pub preload: bool,
+}
+
+// Define the values accepted for `X-Frame-Options`
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameOptions {
+    Deny,
+    #[default]
+    SameOrigin,
+    Off,
+}
+
+// Define the values accepted for `Referrer-Policy`
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    #[default]
+    StrictOriginWhenCrossOrigin,
+    Off,
+}
+
+// Define the `Content-Security-Policy` header, which Shield has no built-in policy for
+#[derive(Debug, Clone, Default)]
+struct ContentSecurityPolicy(String);
+
+impl Policy for ContentSecurityPolicy {
+    const NAME: &'static str = "Content-Security-Policy";
+
+    fn header(&self) -> Header<'static> {
+        Header::new(Self::NAME, self.0.clone())
+    }
+}
+
+impl SecurityHeaders {
+    // Build the Shield that adds the configured headers to every response
+    fn shield(&self) -> Shield {
+        if !self.enabled {
+            return Shield::new();
+        }
+
+        let mut shield = Shield::default();
+        if !self.content_security_policy.is_empty() {
+            shield = shield.enable(ContentSecurityPolicy(self.content_security_policy.clone()));
+        }
+
+        let HstsConfig {
+            max_age,
+            include_subdomains,
+            preload,
+        } = self.hsts;
+        if max_age > 0 {
+            let max_age = Duration::seconds(max_age);
+            shield = shield.enable(match (preload, include_subdomains) {
+                (true, _) => Hsts::Preload(max_age),
+                (false, true) => Hsts::IncludeSubDomains(max_age),
+                (false, false) => Hsts::Enable(max_age),
+            });
+        }
+
+        shield = match self.frame_options {
+            FrameOptions::Deny => shield.enable(Frame::Deny),
+            FrameOptions::SameOrigin => shield.enable(Frame::SameOrigin),
+            FrameOptions::Off => shield.disable::<Frame>(),
+        };
+
+        let referrer = match self.referrer_policy {
+            ReferrerPolicy::NoReferrer => Referrer::NoReferrer,
+            ReferrerPolicy::NoReferrerWhenDowngrade => Referrer::NoReferrerWhenDowngrade,
+            ReferrerPolicy::Origin => Referrer::Origin,
+            ReferrerPolicy::OriginWhenCrossOrigin => Referrer::OriginWhenCrossOrigin,
+            ReferrerPolicy::SameOrigin => Referrer::SameOrigin,
+            ReferrerPolicy::StrictOrigin => Referrer::StrictOrigin,
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => Referrer::StrictOriginWhenCrossOrigin,
+            ReferrerPolicy::Off => return shield,
+        };
+        shield.enable(referrer)
+    }
+}
+
+// Replace Rocket's default Shield with one configured from the `security_headers` table,
+// refusing to launch with an invalid table rather than without the configured headers
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Security Headers", |rocket| async {
+        match rocket
+            .figment()
+            .extract_inner::<SecurityHeaders>("security_headers")
+        {
+            Ok(headers) => Ok(rocket.attach(headers.shield())),
+            // Without the table Rocket keeps its default Shield
+            Err(err) if err.missing() => Ok(rocket),
+            Err(err) => {
+                error!("invalid `security_headers` configuration: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,139 @@
+use rocket::fairing::{self, AdHoc};
+use rocket::{Build, Rocket};
+use rocket_db_pools::{sqlx, Database};
+use tracing::{error, info};
+
+use crate::auth::jwt::JwtConfig;
+use crate::auth::password;
+use crate::auth::refresh::RefreshStore;
+use crate::auth::users::{self, User};
+use crate::db::{Db, DbConn};
+use crate::tenant::DEFAULT_TENANT;
+use crate::todos::store::{self, NewTodo};
+
+// Password of every demo account
+const DEMO_PASSWORD: &str = "demo";
+
+// Define the demo accounts as (username, roles)
+const DEMO_USERS: &[(&str, &[&str])] =
+    &[("demo", &["reader"]), ("demo-admin", &["reader", "admin"])];
+
+// Define the demo todos as (title, completed)
+const DEMO_TODOS: &[(&str, bool)] = &[
+    ("Read the README", true),
+    ("Sign in as demo with password demo", false),
+    ("Create a todo through POST /todos", false),
+    ("Explore the API at /swagger-ui", false),
+];
+
+// Return whether demo data was asked for, with `seed = true` in the configuration, as `--seed`
+// and `ROCKET_SEED=true` set it
+fn requested(rocket: &Rocket<Build>) -> bool {
+    rocket
+        .figment()
+        .extract_inner::<bool>("seed")
+        .unwrap_or(false)
+}
+
+async fn insert_users(conn: &mut DbConn) -> Result<Vec<User>, String> {
+    let password_hash = password::hash(DEMO_PASSWORD)
+        .map_err(|err| format!("failed to hash the demo password: {}", err))?;
+    let mut seeded = Vec::new();
+    for (username, roles) in DEMO_USERS {
+        let user = User {
+            tenant: DEFAULT_TENANT.into(),
+            username: username.to_string(),
+            password_hash: password_hash.clone(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+            active: true,
+        };
+        users::insert_if_missing(conn, &user)
+            .await
+            .map_err(|err| format!("failed to seed user `{}`: {}", username, err))?;
+        // An account of that name made before keeps its password and roles
+        if let Some(user) = users::find(conn, DEFAULT_TENANT, username)
+            .await
+            .map_err(|err| format!("failed to seed user `{}`: {}", username, err))?
+        {
+            seeded.push(user);
+        }
+    }
+    Ok(seeded)
+}
+
+// Insert the demo todos unless the default tenant has todos already, so seeding twice does not
+// duplicate them
+async fn insert_todos(conn: &mut DbConn) -> Result<usize, sqlx::Error> {
+    if !store::all(conn, DEFAULT_TENANT).await?.is_empty() {
+        return Ok(0);
+    }
+    for (title, completed) in DEMO_TODOS {
+        let todo = NewTodo {
+            title: title.to_string(),
+            completed: *completed,
+        };
+        store::create(conn, DEFAULT_TENANT, &todo).await?;
+    }
+    Ok(DEMO_TODOS.len())
+}
+
+// Insert the demo accounts and todos, then log a token pair for each account so the protected
+// routes can be called right away
+async fn seed(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(db) = Db::fetch(&rocket) else {
+        return Err(rocket);
+    };
+    let mut conn = match db.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("failed to seed demo data: {}", err);
+            return Err(rocket);
+        }
+    };
+    let seeded = match insert_users(&mut conn).await {
+        Ok(seeded) => seeded,
+        Err(err) => {
+            error!("{}", err);
+            return Err(rocket);
+        }
+    };
+    match insert_todos(&mut conn).await {
+        Ok(0) => info!("the `{}` tenant has todos already", DEFAULT_TENANT),
+        Ok(count) => info!("seeded {} demo todos", count),
+        Err(err) => {
+            error!("failed to seed demo todos: {}", err);
+            return Err(rocket);
+        }
+    }
+    drop(conn);
+
+    let (Some(jwt), Some(refresh)) = (rocket.state::<JwtConfig>(), rocket.state::<RefreshStore>())
+    else {
+        error!("seeding demo data needs the auth stage");
+        return Err(rocket);
+    };
+    for user in &seeded {
+        // No second factor was checked, so routes demanding one still refuse these tokens
+        info!(
+            "demo account `{}` (password `{}`, roles {}): access token {} refresh token {}",
+            user.username,
+            DEMO_PASSWORD,
+            user.roles.join(","),
+            jwt.issue(&user.tenant, &user.username, &user.roles, false),
+            refresh.issue(&user.tenant, &user.username, false)
+        );
+    }
+    Ok(rocket)
+}
+
+// Fill the database with demo accounts and todos when asked with `--seed` or `ROCKET_SEED=true`.
+// The seeding is attached on ignite so it runs after the database is migrated and the token
+// settings are loaded.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Seed", |rocket| async {
+        match requested(&rocket) {
+            true => rocket.attach(AdHoc::try_on_ignite("Demo Data", seed)),
+            false => rocket,
+        }
+    })
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::response::status::Accepted;
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::time::{sleep, Instant};
+use rocket::{Config, Shutdown};
+use tracing::{info, warn};
+use utoipa::OpenApi;
+
+use crate::auth::AdminUser;
+use crate::errors::ApiError;
+use crate::metrics::Metrics;
+
+// How often the number of in-flight requests is checked while draining
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Define a route handler that shuts the server down as Ctrl-C would, for scripts and test
+// harnesses driving it. The answer is sent while the server drains.
+#[utoipa::path(
+    tag = "admin",
+    operation_id = "shutdown",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "The server is shutting down", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/admin/shutdown")]
+fn shutdown(admin: AdminUser, shutdown: Shutdown, config: &Config) -> Accepted<Json<Value>> {
+    warn!("shutdown requested by {}", admin.identity.subject);
+    shutdown.notify();
+    Accepted(Json(json!({
+        "shutting_down": true,
+        "grace": config.shutdown.grace,
+    })))
+}
+
+// Define the part of the OpenAPI document describing the remote shutdown
+#[derive(OpenApi)]
+#[openapi(paths(shutdown))]
+pub struct ApiDoc;
+
+// Mount the route shutting the server down, and wait for in-flight requests to finish once
+// shutdown starts, up to the configured grace period. Shutdown fairings run in the order they
+// were attached, so attaching this stage before the database lets draining requests keep their
+// pools until they are done.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Shutdown", |rocket| async {
+        rocket.mount("/", routes![shutdown]).attach(drain())
+    })
+}
+
+// Wait for in-flight requests to finish, up to the grace period
+fn drain() -> AdHoc {
+    AdHoc::on_shutdown("Drain", |rocket| {
+        Box::pin(async move {
+            let Some(metrics) = rocket.state::<Metrics>() else {
+                return;
+            };
+            let grace = Duration::from_secs(u64::from(rocket.config().shutdown.grace));
+            let deadline = Instant::now() + grace;
+
+            let pending = metrics.in_flight();
+            if pending > 0 {
+                info!("draining {} in-flight requests", pending);
+            }
+            while metrics.in_flight() > 0 && Instant::now() < deadline {
+                sleep(POLL_INTERVAL).await;
+            }
+            match metrics.in_flight() {
+                0 => info!("all requests drained"),
+                left => warn!("{} requests still running after {:?}", left, grace),
+            }
+        })
+    })
+}
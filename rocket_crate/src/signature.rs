@@ -0,0 +1,178 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use hmac::{Hmac, Mac};
+use rocket::data::{self, Data, FromData, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::Request;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::error;
+
+use crate::errors::ApiError;
+
+// Header carrying `sha256=<hex HMAC>` and header carrying the unix time the request was signed
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+// Define the `request_signing` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningConfig {
+    // Secret shared with the senders; override with ROCKET_REQUEST_SIGNING={secret="..."}
+    pub secret: String,
+    // Seconds a signed request may be early or late before it is refused as stale
+    pub tolerance: u64,
+}
+
+// Define the reasons a signed request is refused
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Stale,
+    Invalid,
+    // The body could not be read, or was not the JSON the route expects
+    Body(Status, String),
+}
+
+impl SignatureError {
+    fn status(&self) -> Status {
+        match self {
+            SignatureError::Body(status, _) => *status,
+            _ => Status::Unauthorized,
+        }
+    }
+
+    fn reason(&self) -> String {
+        match self {
+            SignatureError::Missing => format!(
+                "missing {} or {} header",
+                SIGNATURE_HEADER, TIMESTAMP_HEADER
+            ),
+            SignatureError::Stale => "request timestamp is too old or in the future".into(),
+            SignatureError::Invalid => "request signature does not match".into(),
+            SignatureError::Body(_, reason) => reason.clone(),
+        }
+    }
+}
+
+impl From<SignatureError> for ApiError {
+    fn from(err: SignatureError) -> Self {
+        ApiError::new(err.status(), err.reason())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Return the HMAC-SHA256 keyed with `secret` over "<METHOD>\n<path and query>\n<timestamp>\n<body>"
+fn mac(secret: &str, method: &str, uri: &str, timestamp: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}\n{}\n", method, uri, timestamp).as_bytes());
+    mac.update(body);
+    mac
+}
+
+// Return the X-Signature value of a request, so this server signs what it sends the way it
+// checks what it receives
+pub fn sign(secret: &str, method: &str, uri: &str, timestamp: &str, body: &[u8]) -> String {
+    let signature = mac(secret, method, uri, timestamp, body).finalize();
+    format!("sha256={}", HEXLOWER.encode(&signature.into_bytes()))
+}
+
+impl SigningConfig {
+    // Check a signature over "<METHOD>\n<path and query>\n<timestamp>\n<body>"
+    fn verify(
+        &self,
+        req: &Request<'_>,
+        body: &[u8],
+        timestamp: &str,
+        signature: &str,
+    ) -> Result<(), SignatureError> {
+        let signed_at: u64 = timestamp.parse().map_err(|_| SignatureError::Stale)?;
+        if now().abs_diff(signed_at) > self.tolerance {
+            return Err(SignatureError::Stale);
+        }
+        let signature = signature
+            .strip_prefix("sha256=")
+            .and_then(|hex| HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok())
+            .ok_or(SignatureError::Invalid)?;
+
+        let uri = req.uri().to_string();
+        let mac = mac(&self.secret, req.method().as_str(), &uri, timestamp, body);
+        // Compared in constant time, so the signature cannot be guessed byte by byte
+        mac.verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)
+    }
+}
+
+// Define a data guard for requests from systems sharing the signing secret, such as webhook
+// senders: the JSON body is only deserialized once the signature over the method, URI,
+// timestamp and body matches
+#[derive(Debug)]
+pub struct Signed<T>(pub T);
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for Signed<T> {
+    type Error = SignatureError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let fail = |err: SignatureError| data::Outcome::Error((err.status(), err));
+        let headers = req.headers();
+        let (Some(signature), Some(timestamp)) = (
+            headers.get_one(SIGNATURE_HEADER),
+            headers.get_one(TIMESTAMP_HEADER),
+        ) else {
+            return fail(SignatureError::Missing);
+        };
+
+        let limit = req.limits().get("json").unwrap_or(1.mebibytes());
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                return fail(SignatureError::Body(
+                    Status::PayloadTooLarge,
+                    format!("request body is larger than {}", limit),
+                ))
+            }
+            Err(err) => return fail(SignatureError::Body(Status::BadRequest, err.to_string())),
+        };
+
+        let config = req
+            .rocket()
+            .state::<SigningConfig>()
+            .expect("request signing config");
+        if let Err(err) = config.verify(req, &body, timestamp, signature) {
+            return fail(err);
+        }
+        match serde_json::from_slice(&body) {
+            Ok(value) => data::Outcome::Success(Signed(value)),
+            Err(err) => fail(SignatureError::Body(
+                Status::UnprocessableEntity,
+                err.to_string(),
+            )),
+        }
+    }
+}
+
+// Load the secret that signed requests are checked with
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Request Signing", |rocket| async {
+        match rocket
+            .figment()
+            .extract_inner::<SigningConfig>("request_signing")
+        {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(err) => {
+                error!("invalid `request_signing` configuration: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,104 @@
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::request::Request;
+use tracing::field::Empty;
+use tracing::{info_span, warn, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::request_id::RequestId;
+
+// Handle swapping the filter of the installed subscriber
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Install the tracing subscriber, filtered by `RUST_LOG` (e.g. `RUST_LOG=rocket_crate=debug`).
+// It is installed before Rocket builds so Rocket sends its own log records to it as well.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_ansi(io::stdout().is_terminal()))
+        .init();
+    let _ = FILTER.set(handle);
+}
+
+// Replace the filter of the installed subscriber with one written like `RUST_LOG`, such as
+// `info,rocket_crate=debug`
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|err| format!("invalid log filter `{}`: {}", directives, err))?;
+    let handle = FILTER
+        .get()
+        .ok_or("the tracing subscriber is not installed")?;
+    handle
+        .reload(filter)
+        .map_err(|err| format!("failed to swap the log filter: {}", err))
+}
+
+// Read the `log_filter` setting, written like `RUST_LOG`, checking that it parses
+pub fn log_filter(figment: &Figment) -> Result<Option<String>, String> {
+    match figment.extract_inner::<String>("log_filter") {
+        Ok(directives) => match EnvFilter::try_new(&directives) {
+            Ok(_) => Ok(Some(directives)),
+            Err(err) => Err(format!("invalid log filter `{}`: {}", directives, err)),
+        },
+        Err(err) if err.missing() => Ok(None),
+        Err(err) => Err(format!("invalid `log_filter` configuration: {}", err)),
+    }
+}
+
+// Define the span covering one request; fields are recorded as they become known
+struct RequestSpan(Span);
+
+// Return the span of the current request, opening it on first use
+pub fn span<'r>(req: &'r Request<'_>) -> &'r Span {
+    &req.local_cache(|| {
+        RequestSpan(info_span!(
+            "request",
+            request_id = %RequestId::of(req),
+            method = %req.method(),
+            path = %req.uri().path(),
+            route = Empty,
+            user_id = Empty,
+        ))
+    })
+    .0
+}
+
+// Record the authenticated caller on the span of the current request
+pub fn record_user(req: &Request<'_>, subject: &str) {
+    span(req).record("user_id", subject);
+}
+
+// Apply the configured log filter, then open the span of every request before routing and
+// record the route that handled it
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Telemetry", |rocket| async {
+        // `RUST_LOG` wins over the configured filter at launch
+        if std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() {
+            let applied = log_filter(rocket.figment())
+                .and_then(|filter| filter.map_or(Ok(()), |filter| set_filter(&filter)));
+            if let Err(err) = applied {
+                warn!("keeping the default log filter, {}", err);
+            }
+        }
+        rocket
+            .attach(AdHoc::on_request("Request Span", |req, _| {
+                Box::pin(async move {
+                    span(req);
+                })
+            }))
+            .attach(AdHoc::on_response("Request Route", |req, _| {
+                Box::pin(async move {
+                    if let Some(route) = req.route() {
+                        span(req).record("route", route.uri.as_str());
+                    }
+                })
+            }))
+    })
+}
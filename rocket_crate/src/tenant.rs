@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+use tracing::{error, info};
+
+// Tenant everything belongs to when multi-tenancy is not configured, and the tenant of rows
+// created before it was
+pub const DEFAULT_TENANT: &str = "default";
+
+// Define a tenant as listed in the `tenancy.tenants` array of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantEntry {
+    pub id: String,
+}
+
+// Define the `tenancy` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenancyConfig {
+    // Header naming the tenant of a request
+    #[serde(default = "header")]
+    pub header: String,
+    // Domain whose subdomains name tenants, so `acme.example.com` is tenant `acme` when this is
+    // `example.com`; the header wins when both are given
+    pub base_domain: Option<String>,
+    // Tenant of requests that name none; without one such requests are refused
+    pub default: Option<String>,
+    pub tenants: Vec<TenantEntry>,
+}
+
+fn header() -> String {
+    "X-Tenant-Id".into()
+}
+
+// Define the registry of known tenants and how requests name theirs
+#[derive(Debug, Clone)]
+pub struct Tenants {
+    header: String,
+    base_domain: Option<String>,
+    default: Option<String>,
+    known: HashSet<String>,
+}
+
+impl Tenants {
+    fn single() -> Self {
+        Tenants {
+            header: header(),
+            base_domain: None,
+            default: Some(DEFAULT_TENANT.into()),
+            known: HashSet::from([DEFAULT_TENANT.to_string()]),
+        }
+    }
+
+    fn resolve(&self, req: &Request<'_>) -> Result<String, String> {
+        let subdomain = || {
+            let base = self.base_domain.as_deref()?;
+            let host = req.host()?.domain().as_str().to_ascii_lowercase();
+            let tenant = host.strip_suffix(base)?.strip_suffix('.')?;
+            (!tenant.is_empty() && !tenant.contains('.')).then(|| tenant.to_string())
+        };
+        let named = req
+            .headers()
+            .get_one(&self.header)
+            .map(str::to_string)
+            .or_else(subdomain);
+        self.check(named)
+    }
+
+    // Return the header naming the tenant of a request
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    // Accept the tenant a caller named if it is known, or the default tenant when it named none
+    pub fn check(&self, named: Option<String>) -> Result<String, String> {
+        let tenant = named
+            .or_else(|| self.default.clone())
+            .ok_or_else(|| format!("missing {} header", self.header))?;
+        match self.known.contains(&tenant) {
+            true => Ok(tenant),
+            false => Err(format!("unknown tenant `{}`", tenant)),
+        }
+    }
+}
+
+// Remember why the tenant of this request could not be resolved, for the 400 catcher
+struct TenantFailure(Option<String>);
+
+// Return why the tenant of this request could not be resolved, if it could not
+pub fn failure_reason(req: &Request) -> Option<String> {
+    req.local_cache(|| TenantFailure(None)).0.clone()
+}
+
+// Define a guard for the tenant a request acts in; every todo and user it reads or writes
+// belongs to that tenant
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Tenant {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let tenants = req.rocket().state::<Tenants>().expect("tenant registry");
+        match tenants.resolve(req) {
+            Ok(id) => request::Outcome::Success(Tenant { id }),
+            Err(reason) => {
+                req.local_cache(|| TenantFailure(Some(reason.clone())));
+                request::Outcome::Error((Status::BadRequest, reason))
+            }
+        }
+    }
+}
+
+// Load the tenant registry; without a `tenancy` table everything belongs to one tenant
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Tenancy", |rocket| async {
+        let config = match rocket.figment().extract_inner::<TenancyConfig>("tenancy") {
+            Ok(config) => config,
+            Err(err) if err.missing() => return Ok(rocket.manage(Tenants::single())),
+            Err(err) => {
+                error!("invalid `tenancy` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let known: HashSet<String> = config.tenants.into_iter().map(|t| t.id).collect();
+        if let Some(default) = config.default.as_ref().filter(|id| !known.contains(*id)) {
+            error!(
+                "default tenant `{}` is not listed in `tenancy.tenants`",
+                default
+            );
+            return Err(rocket);
+        }
+        info!("serving {} tenants", known.len());
+
+        Ok(rocket.manage(Tenants {
+            header: config.header,
+            base_domain: config.base_domain.map(|domain| domain.to_ascii_lowercase()),
+            default: config.default,
+            known,
+        }))
+    })
+}
@@ -0,0 +1,465 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use rocket::figment::providers::Serialized;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{json, Value};
+use rocket::time::{Date, Duration, Month, OffsetDateTime};
+use rocket::Config;
+
+// Numbers the in-memory databases, so tests running side by side each get their own
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+// Return the configuration of the `test` profile, with an in-memory database no other test sees
+// and a fresh secret key, which profiles other than debug need. The database is named as a `file:`
+// URI so every connection of the pool opens the same one.
+fn figment() -> Figment {
+    let database = DATABASES.fetch_add(1, Ordering::Relaxed);
+    let url = format!(
+        "sqlite:file:rocket_crate_test_{}?mode=memory&cache=shared",
+        database
+    );
+    let secret_key = BASE64.encode(&rand::random::<[u8; 32]>());
+    Config::figment()
+        .select("test")
+        .merge(Serialized::global("databases.app.url", url))
+        .merge(Serialized::global("secret_key", secret_key))
+}
+
+async fn client() -> Client {
+    Client::tracked(crate::rocket(figment()))
+        .await
+        .expect("valid rocket")
+}
+
+async fn json(response: LocalResponse<'_>) -> Value {
+    response.into_json().await.expect("JSON body")
+}
+
+// Sign in as one of the accounts of Rocket.toml and return its access token
+async fn token(client: &Client, username: &str, password: &str) -> String {
+    let response = client
+        .post("/login")
+        .header(ContentType::JSON)
+        .body(json!({ "username": username, "password": password }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    json(response).await["access_token"]
+        .as_str()
+        .expect("access token")
+        .to_string()
+}
+
+// Sign in as the demo reader of the default tenant, whose token the todo routes accept
+async fn bearer(client: &Client) -> Header<'static> {
+    let token = token(client, "sabry", "password").await;
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+#[rocket::async_test]
+async fn protected_route_refuses_missing_credentials() {
+    let client = client().await;
+    let response = client.get("/protected").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(json(response).await["code"], "unauthorized");
+}
+
+#[rocket::async_test]
+async fn protected_route_refuses_malformed_tokens() {
+    let client = client().await;
+    for authorization in ["Bearer not-a-jwt", "Bearer ", "Basic c2Ficnk6cGFzc3dvcmQ="] {
+        let response = client
+            .get("/protected")
+            .header(Header::new("Authorization", authorization))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized, "{}", authorization);
+    }
+}
+
+#[rocket::async_test]
+async fn protected_route_accepts_a_valid_token() {
+    let client = client().await;
+    let token = token(&client, "sabry", "password").await;
+    let response = client
+        .get("/protected")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = json(response).await;
+    assert_eq!(body["subject"], "sabry");
+    assert_eq!(body["access"], "read-only");
+}
+
+#[rocket::async_test]
+async fn login_refuses_a_wrong_password() {
+    let client = client().await;
+    let response = client
+        .post("/login")
+        .header(ContentType::JSON)
+        .body(json!({ "username": "sabry", "password": "wrong" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn delay_answers_within_the_cap() {
+    let client = client().await;
+    let response = client.get("/delay/250ms").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("Delayed response for 250 milliseconds")
+    );
+}
+
+#[rocket::async_test]
+async fn delay_refuses_delays_over_the_cap() {
+    let client = client().await;
+    for delay in ["31", "31s", "30001ms", "soon"] {
+        let response = client.get(format!("/delay/{}", delay)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", delay);
+        assert_eq!(json(response).await["max_seconds"], 30);
+    }
+}
+
+#[rocket::async_test]
+async fn rate_limits_ignore_a_spoofed_client_address() {
+    let client = client().await;
+    let mut statuses = Vec::new();
+    for n in 1..=6 {
+        let response = client
+            .get("/delay/soon")
+            .remote("192.0.2.10:4000".parse().expect("socket address"))
+            .header(Header::new("X-Real-IP", format!("198.51.100.{}", n)))
+            .header(Header::new("X-Forwarded-For", format!("203.0.113.{}", n)))
+            .dispatch()
+            .await;
+        statuses.push(response.status());
+    }
+    // The `delay` group allows 5 requests a minute
+    assert_eq!(statuses.last(), Some(&Status::TooManyRequests));
+}
+
+#[rocket::async_test]
+async fn unknown_paths_answer_404_with_suggestions() {
+    let client = client().await;
+    let response = client.get("/todoz").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body = json(response).await;
+    assert_eq!(body["code"], "not_found");
+    assert_eq!(body["path"], "/todoz");
+    let suggested = body["did_you_mean"].as_array().expect("suggestions");
+    assert!(suggested.iter().any(|route| route["path"] == "/todos"));
+}
+
+#[rocket::async_test]
+async fn invalid_bodies_answer_422_naming_the_field() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+    let response = client
+        .post("/todos")
+        .header(auth.clone())
+        .header(ContentType::JSON)
+        .body(json!({ "title": "" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body = json(response).await;
+    assert_eq!(body["code"], "unprocessable_entity");
+    assert!(body.to_string().contains("title"));
+}
+
+#[rocket::async_test]
+async fn todos_can_be_created_read_updated_and_deleted() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+
+    let response = client
+        .post("/todos")
+        .header(auth.clone())
+        .header(ContentType::JSON)
+        .body(json!({ "title": "Write the tests" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let created = json(response).await;
+    assert_eq!(created["title"], "Write the tests");
+    assert_eq!(created["completed"], false);
+    let path = format!("/todos/{}", created["id"]);
+
+    let response = client.get(&path).header(auth.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(json(response).await["title"], "Write the tests");
+
+    let response = client
+        .patch(&path)
+        .header(auth.clone())
+        .header(ContentType::JSON)
+        .body(json!({ "completed": true }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let updated = json(response).await;
+    assert_eq!(updated["completed"], true);
+    assert_eq!(updated["title"], "Write the tests");
+
+    let response = client.get("/todos").header(auth.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        json(response).await["data"].as_array().map(Vec::len),
+        Some(1)
+    );
+
+    let response = client.delete(&path).header(auth.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::NoContent);
+
+    let response = client.get(&path).header(auth.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn todos_are_only_served_to_credentials_of_their_tenant() {
+    let client = client().await;
+    let response = client.get("/todos").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let auth = bearer(&client).await;
+    let response = client
+        .get("/todos")
+        .header(auth.clone())
+        .header(Header::new("X-Tenant-Id", "acme"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(json(response).await["code"], "forbidden");
+}
+
+#[rocket::async_test]
+async fn idempotency_keys_replay_the_same_body_and_refuse_another() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+    let create = |title: &str| {
+        client
+            .post("/todos")
+            .header(auth.clone())
+            .header(ContentType::JSON)
+            .header(Header::new("Idempotency-Key", "create-once"))
+            .body(json!({ "title": title }).to_string())
+    };
+
+    let response = create("Pay the rent").dispatch().await;
+    assert_eq!(response.status(), Status::Created);
+    let created = json(response).await;
+
+    let response = create("Pay the rent").dispatch().await;
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(
+        response.headers().get_one("Idempotent-Replayed"),
+        Some("true")
+    );
+    assert_eq!(json(response).await["id"], created["id"]);
+
+    let response = create("Pay the bills").dispatch().await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[rocket::async_test]
+async fn cursors_page_through_todos_while_they_are_deleted() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+    for n in 1..=5 {
+        let response = client
+            .post("/todos")
+            .header(auth.clone())
+            .header(ContentType::JSON)
+            .body(json!({ "title": format!("Todo {}", n) }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    let mut seen = Vec::new();
+    let mut uri = "/todos?per_page=2".to_string();
+    loop {
+        let response = client
+            .get(uri.as_str())
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let page = json(response).await;
+        let todos = page["data"].as_array().expect("todos");
+        seen.extend(todos.iter().map(|todo| todo["id"].as_i64().expect("id")));
+        // After the first page is deleted, an offset would skip the todo that moved onto it
+        if seen.len() == 2 {
+            for id in &seen {
+                let response = client
+                    .delete(format!("/todos/{}", id))
+                    .header(auth.clone())
+                    .dispatch()
+                    .await;
+                assert_eq!(response.status(), Status::NoContent);
+            }
+        }
+        match page["next_cursor"].as_str() {
+            Some(cursor) => uri = format!("/todos?per_page=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+
+    let response = client
+        .get("/todos?cursor=not-a-cursor")
+        .header(auth.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // A text value cannot resume a sort by id
+    let cursor = BASE64URL_NOPAD.encode(br#"{"sort":"id","order":"asc","value":"x","key":1}"#);
+    let response = client
+        .get(format!("/todos?cursor={}", cursor))
+        .header(auth.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn v1_responses_point_to_their_v2_successor() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+    let response = client
+        .get("/v1/todos")
+        .header(auth.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("API-Version"), Some("1"));
+    assert_eq!(response.headers().get_one("Deprecation"), Some("true"));
+    assert_eq!(
+        response.headers().get_one("Link"),
+        Some("</v2/todos>; rel=\"successor-version\"")
+    );
+    assert_eq!(json(response).await["page"], 1);
+
+    let response = client
+        .get("/v2/todos")
+        .header(auth.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("API-Version"), Some("2"));
+    assert_eq!(response.headers().get_one("Deprecation"), None);
+    let body = json(response).await;
+    assert!(body.get("page").is_none());
+    assert_eq!(body["links"]["self"], "/v2/todos?per_page=20");
+
+    let response = client
+        .get("/v2/todos?page=2")
+        .header(auth.clone())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn accept_version_picks_the_version_of_unprefixed_paths() {
+    let client = client().await;
+    let auth = bearer(&client).await;
+    let response = client.get("/todos").header(auth.clone()).dispatch().await;
+    assert_eq!(response.headers().get_one("API-Version"), Some("1"));
+
+    for version in ["2", "v2", "2.0"] {
+        let response = client
+            .get("/todos")
+            .header(auth.clone())
+            .header(Header::new("Accept-Version", version))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok, "{}", version);
+        assert_eq!(response.headers().get_one("API-Version"), Some("2"));
+        assert!(json(response).await.get("page").is_none());
+    }
+
+    let response = client
+        .get("/todos")
+        .header(auth.clone())
+        .header(Header::new("Accept-Version", "3"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotAcceptable);
+    assert_eq!(json(response).await["supported"], json!(["1", "2"]));
+}
+
+#[rocket::async_test]
+async fn events_are_only_streamed_to_readers_of_their_tenant() {
+    let client = client().await;
+    for uri in ["/events", "/poll?timeout=1"] {
+        let response = client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized, "{}", uri);
+    }
+
+    let auth = bearer(&client).await;
+    let response = client
+        .get("/poll?timeout=1")
+        .header(auth.clone())
+        .header(Header::new("X-Tenant-Id", "acme"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let response = client.get("/poll?timeout=1").header(auth).dispatch().await;
+    assert_eq!(response.status(), Status::NoContent);
+}
+
+#[test]
+fn batch_sub_requests_cannot_pick_their_client_address() {
+    let sub: crate::batch::SubRequest = serde_json::from_value(json!({
+        "method": "POST",
+        "path": "/login",
+        "headers": {
+            "X-Real-IP": "198.51.100.1",
+            "X-Forwarded-For": "198.51.100.2",
+            "X-Client-Address": "198.51.100.3",
+            "Host": "acme.localhost",
+            "X-Batch-Peer": "198.51.100.4",
+            "Accept": "application/json",
+        },
+    }))
+    .expect("valid sub-request");
+    let sent: Vec<_> = sub.own_headers(Some("X-Client-Address")).collect();
+    assert_eq!(sent, vec![("Accept", "application/json")]);
+}
+
+// Return midnight UTC on a day of September 2026, whose 1st is a Tuesday
+fn september(day: u8) -> OffsetDateTime {
+    Date::from_calendar_date(2026, Month::September, day)
+        .expect("valid date")
+        .midnight()
+        .assume_utc()
+}
+
+#[test]
+fn schedules_restricting_both_days_run_on_either() {
+    let schedule = crate::scheduler::Schedule::parse("0 0 1 * 1").expect("valid schedule");
+    // The 1st, then the following Mondays
+    let mut at = september(1) - Duration::MINUTE;
+    let mut runs = Vec::new();
+    for _ in 0..3 {
+        at = schedule.next(at).expect("a next run");
+        runs.push(at);
+    }
+    assert_eq!(runs, vec![september(1), september(7), september(14)]);
+
+    let schedule = crate::scheduler::Schedule::parse("0 0 */2 * 1").expect("valid schedule");
+    let at = schedule.next(september(1)).expect("a next run");
+    assert_eq!(at, september(7));
+}
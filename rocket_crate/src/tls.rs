@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use rocket::config::TlsConfig;
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+// Define the `https` table of Rocket.toml
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HttpsPolicy {
+    // Refuse to start without TLS, as release builds do
+    #[serde(default)]
+    pub required: bool,
+}
+
+// Check that a PEM file configured for TLS can be read and holds what it should, so the server
+// fails at startup with a clear message instead of on the first handshake
+fn check_pem(setting: &str, path: &Path, marker: &str) -> Result<(), String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "`{}` file `{}` cannot be read: {}",
+            setting,
+            path.display(),
+            err
+        )
+    })?;
+    if !contents.contains(marker) {
+        return Err(format!(
+            "`{}` file `{}` does not contain a PEM {}",
+            setting,
+            path.display(),
+            marker.trim_matches('-')
+        ));
+    }
+    Ok(())
+}
+
+// Enforce the profile's HTTPS policy and check the configured certificate files before launch
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("TLS", |rocket| async {
+        let policy = match rocket.figment().extract_inner::<HttpsPolicy>("https") {
+            Ok(policy) => policy,
+            Err(err) if err.missing() => HttpsPolicy::default(),
+            Err(err) => {
+                error!("invalid `https` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let profile = rocket.figment().profile().to_string();
+
+        // Rocket's own defaults hold `tls = None`, so a profile without it extracts as `None`
+        let tls = match rocket.figment().extract_inner::<Option<TlsConfig>>("tls") {
+            Ok(tls) => tls,
+            Err(err) => {
+                error!("invalid `tls` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+        let Some(tls) = tls else {
+            if policy.required {
+                error!(
+                    "the `{}` profile requires TLS but `tls.certs` and `tls.key` are not set; \
+                     set them in Rocket.toml or with ROCKET_TLS={{certs=\"...\",key=\"...\"}}",
+                    profile
+                );
+                return Err(rocket);
+            }
+            if rocket.figment().contains("tls.mutual") {
+                warn!("`tls.mutual` is ignored because TLS is off; set `tls.certs` and `tls.key`");
+            }
+            return Ok(rocket);
+        };
+
+        // Certificates given inline as bytes are checked by Rocket when it starts listening
+        let mut checks = Vec::new();
+        if let Some(path) = tls.certs().left() {
+            checks.push(("tls.certs", path, "-----BEGIN CERTIFICATE-----"));
+        }
+        if let Some(path) = tls.key().left() {
+            checks.push(("tls.key", path, "PRIVATE KEY-----"));
+        }
+        if let Some(path) = tls.mutual().and_then(|mutual| mutual.ca_certs().left()) {
+            checks.push(("tls.mutual.ca_certs", path, "-----BEGIN CERTIFICATE-----"));
+        }
+        for (setting, path, marker) in checks {
+            if let Err(err) = check_pem(setting, &path, marker) {
+                error!("{}", err);
+                return Err(rocket);
+            }
+        }
+
+        info!(
+            mutual = tls.mutual().is_some(),
+            "serving HTTPS in the `{}` profile", profile
+        );
+        Ok(rocket)
+    })
+}
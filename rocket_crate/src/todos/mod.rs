@@ -0,0 +1,50 @@
+mod routes;
+pub mod store;
+mod v1;
+mod v2;
+
+pub use self::routes::CACHE_PREFIX;
+
+use rocket::fairing::AdHoc;
+use rocket::Route;
+use utoipa::OpenApi;
+
+use crate::versioning::Version;
+
+// Define the part of the OpenAPI document describing the todo resource
+#[derive(OpenApi)]
+#[openapi(paths(
+    v1::list,
+    v2::list,
+    routes::get,
+    routes::create,
+    routes::replace,
+    routes::update,
+    routes::delete
+))]
+pub struct ApiDoc;
+
+// Return the routes every version shares
+fn shared() -> Vec<Route> {
+    routes![
+        routes::get,
+        routes::create,
+        routes::replace,
+        routes::update,
+        routes::delete
+    ]
+}
+
+// Mount the todo resource routes of each version under its prefix, and those of every version
+// without a prefix, where Accept-Version picks between them
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Todos", |rocket| async {
+        rocket
+            .mount(Version::V1.prefix(), routes![v1::list])
+            .mount(Version::V1.prefix(), shared())
+            .mount(Version::V2.prefix(), routes![v2::list])
+            .mount(Version::V2.prefix(), shared())
+            .mount("/", routes![v1::list, v2::list])
+            .mount("/", shared())
+    })
+}
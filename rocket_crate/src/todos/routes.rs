@@ -0,0 +1,232 @@
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use super::store::{self, NewTodo, Todo, TodoPatch};
+use crate::auth::Identity;
+use crate::db::{internal_error, Cache, Db, SharedCache};
+use crate::errors::ApiError;
+use crate::etag::ETagged;
+use crate::events::Events;
+use crate::negotiate::Negotiated;
+use crate::response_cache::{CacheKey, ResponseCache};
+use crate::tenant::Tenant;
+use crate::validation::Validated;
+use crate::versioning::ApiVersion;
+
+type ApiResult<T> = Result<T, ApiError>;
+
+// Every cached todo response lives under this path, so writes drop them all at once
+pub const CACHE_PREFIX: &str = "/todos";
+
+// Define a route handler that fetches a single todo, tagged so clients can revalidate it
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "get_todo",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "The todo", body = Todo),
+        (status = 304, description = "The todo has not changed since the given ETag"),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 404, description = "No such todo", body = ApiError),
+    )
+)]
+#[get("/todos/<id>")]
+pub async fn get(
+    _version: ApiVersion,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    id: i64,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    key: CacheKey,
+) -> ApiResult<ETagged<Todo>> {
+    if let Some(todo) = cache.get::<Todo>(shared.0, &key).await {
+        return Ok(ETagged(todo));
+    }
+    match store::get(&mut db, &tenant.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(todo) => {
+            cache.put(shared.0, &key, todo.clone()).await;
+            Ok(ETagged(todo))
+        }
+        None => Err(not_found(id)),
+    }
+}
+
+// Define a route handler that creates a todo and points to it with a Location header
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "create_todo",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = NewTodo,
+    responses(
+        (status = 201, description = "The created todo", body = Todo, headers(("Location" = String))),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 422, description = "Invalid todo", body = ApiError),
+    )
+)]
+#[post("/todos", format = "json", data = "<todo>")]
+pub async fn create(
+    version: ApiVersion,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    todo: Validated<Json<NewTodo>>,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    events: &State<Events>,
+) -> ApiResult<status::Created<Negotiated<Todo>>> {
+    let todo = store::create(&mut db, &tenant.id, &todo.into_inner())
+        .await
+        .map_err(internal_error)?;
+    cache.invalidate(shared.0, CACHE_PREFIX).await;
+    events.publish(&tenant.id, "todo.created", &todo);
+    let location = format!("{}{}", version.base(), uri!(get(todo.id)));
+    Ok(status::Created::new(location).body(Negotiated(todo)))
+}
+
+// Define a route handler that replaces every field of a todo
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "replace_todo",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = NewTodo,
+    responses(
+        (status = 200, description = "The replaced todo", body = Todo),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 404, description = "No such todo", body = ApiError),
+        (status = 422, description = "Invalid todo", body = ApiError),
+    )
+)]
+#[put("/todos/<id>", format = "json", data = "<todo>")]
+pub async fn replace(
+    _version: ApiVersion,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    id: i64,
+    todo: Validated<Json<NewTodo>>,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    events: &State<Events>,
+) -> ApiResult<Negotiated<Todo>> {
+    let NewTodo { title, completed } = todo.into_inner();
+    let patch = TodoPatch {
+        title: Some(title),
+        completed: Some(completed),
+    };
+    apply(&mut db, &tenant, id, &patch, cache, shared.0, events).await
+}
+
+// Define a route handler that updates only the fields present in the body
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "update_todo",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body = TodoPatch,
+    responses(
+        (status = 200, description = "The updated todo", body = Todo),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 404, description = "No such todo", body = ApiError),
+        (status = 422, description = "Invalid fields", body = ApiError),
+    )
+)]
+#[patch("/todos/<id>", format = "json", data = "<patch>")]
+pub async fn update(
+    _version: ApiVersion,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    id: i64,
+    patch: Validated<Json<TodoPatch>>,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    events: &State<Events>,
+) -> ApiResult<Negotiated<Todo>> {
+    apply(
+        &mut db,
+        &tenant,
+        id,
+        &patch.into_inner(),
+        cache,
+        shared.0,
+        events,
+    )
+    .await
+}
+
+// Define a route handler that deletes a todo
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "delete_todo",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 204, description = "The todo was deleted"),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 404, description = "No such todo", body = ApiError),
+    )
+)]
+#[delete("/todos/<id>")]
+pub async fn delete(
+    _version: ApiVersion,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    id: i64,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    events: &State<Events>,
+) -> ApiResult<status::NoContent> {
+    match store::delete(&mut db, &tenant.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        true => {
+            cache.invalidate(shared.0, CACHE_PREFIX).await;
+            events.publish(&tenant.id, "todo.deleted", serde_json::json!({ "id": id }));
+            Ok(status::NoContent)
+        }
+        false => Err(not_found(id)),
+    }
+}
+
+async fn apply(
+    db: &mut Connection<Db>,
+    tenant: &Tenant,
+    id: i64,
+    patch: &TodoPatch,
+    cache: &ResponseCache,
+    shared: Option<&Cache>,
+    events: &Events,
+) -> ApiResult<Negotiated<Todo>> {
+    match store::update(db, &tenant.id, id, patch)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(todo) => {
+            cache.invalidate(shared, CACHE_PREFIX).await;
+            events.publish(&tenant.id, "todo.updated", &todo);
+            Ok(Negotiated(todo))
+        }
+        None => Err(not_found(id)),
+    }
+}
+
+fn not_found(id: i64) -> ApiError {
+    ApiError::not_found(format!("todo {} does not exist", id))
+}
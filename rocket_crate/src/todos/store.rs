@@ -0,0 +1,210 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_graphql::{InputObject, SimpleObject};
+use rocket_db_pools::sqlx::{self, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::{DbConn, DbRow};
+use crate::pagination::{Cursor, CursorValue, Keyed, Order, Pagination};
+
+// Define a todo item as stored in the `todos` table and returned by the API
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct Todo {
+    pub id: i64,
+    pub title: String,
+    pub completed: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// Define the JSON body accepted when creating a todo
+#[derive(Debug, Deserialize, Validate, ToSchema, InputObject)]
+pub struct NewTodo {
+    #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+    #[schema(min_length = 1, max_length = 200)]
+    pub title: String,
+    #[serde(default)]
+    #[graphql(default)]
+    pub completed: bool,
+}
+
+// Define the JSON body accepted by PATCH, where every field is optional
+#[derive(Debug, Deserialize, Validate, ToSchema, InputObject)]
+pub struct TodoPatch {
+    #[validate(length(min = 1, max = 200, message = "must be between 1 and 200 characters"))]
+    #[schema(min_length = 1, max_length = 200)]
+    pub title: Option<String>,
+    pub completed: Option<bool>,
+}
+
+const COLUMNS: &str = "id, title, completed, created_at, updated_at";
+
+impl Todo {
+    fn from_row(row: &DbRow) -> Result<Todo, sqlx::Error> {
+        // Booleans are stored as integers so the same schema works on SQLite and Postgres
+        let completed: i32 = row.try_get("completed")?;
+        Ok(Todo {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            completed: completed != 0,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+impl Keyed for Todo {
+    const TEXT_COLUMNS: &'static [&'static str] = &["title"];
+
+    fn key(&self) -> i64 {
+        self.id
+    }
+
+    fn sort_value(&self, column: &str) -> CursorValue {
+        match column {
+            "title" => CursorValue::Text(self.title.clone()),
+            // Stored as an integer, and compared as one
+            "completed" => CursorValue::Int(self.completed as i64),
+            "created_at" => CursorValue::Int(self.created_at),
+            "updated_at" => CursorValue::Int(self.updated_at),
+            _ => CursorValue::Int(self.id),
+        }
+    }
+}
+
+// Define the columns that list requests may sort by; the first one is the default
+pub const SORTABLE: &[&str] = &["id", "title", "completed", "created_at", "updated_at"];
+
+// Fetch one page of the tenant's todos whose title contains `filter`, or the page after the
+// cursor `after`, along with the total match count. Every query below is scoped to one tenant, so
+// tenants never see each other's todos.
+pub async fn list(
+    conn: &mut DbConn,
+    tenant: &str,
+    pagination: &Pagination,
+    sort: &str,
+    after: Option<&Cursor>,
+) -> Result<(Vec<Todo>, i64), sqlx::Error> {
+    let pattern = format!("%{}%", pagination.filter.as_deref().unwrap_or_default());
+
+    let total: i64 = sqlx::query(
+        "SELECT COUNT(*) AS total FROM todos WHERE tenant_id = $1 AND LOWER(title) LIKE LOWER($2)",
+    )
+    .bind(tenant)
+    .bind(&pattern)
+    .fetch_one(&mut *conn)
+    .await?
+    .try_get("total")?;
+
+    // Resume after the last todo seen, comparing the sort column and then the id as they are
+    // ordered by
+    let resume = match (after, pagination.order) {
+        (None, _) => "",
+        (Some(_), Order::Asc) => "AND ({sort}, id) > ($5, $6)",
+        (Some(_), Order::Desc) => "AND ({sort}, id) < ($5, $6)",
+    }
+    .replace("{sort}", sort);
+    // `sort` comes from SORTABLE, so it is safe to splice into the statement
+    let statement = format!(
+        "SELECT {COLUMNS} FROM todos WHERE tenant_id = $1 AND LOWER(title) LIKE LOWER($2) {resume}
+         ORDER BY {sort} {order}, id {order} LIMIT $3 OFFSET $4",
+        order = pagination.order.as_sql()
+    );
+    let mut query = sqlx::query(&statement)
+        .bind(tenant)
+        .bind(&pattern)
+        .bind(pagination.limit())
+        .bind(pagination.offset());
+    if let Some(after) = after {
+        query = match &after.value {
+            CursorValue::Int(value) => query.bind(*value),
+            CursorValue::Text(value) => query.bind(value.clone()),
+        }
+        .bind(after.key);
+    }
+    let rows = query.fetch_all(conn).await?;
+
+    let todos = rows.iter().map(Todo::from_row).collect::<Result<_, _>>()?;
+    Ok((todos, total))
+}
+
+// Fetch every todo of the tenant, oldest first, for exports
+pub async fn all(conn: &mut DbConn, tenant: &str) -> Result<Vec<Todo>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {COLUMNS} FROM todos WHERE tenant_id = $1 ORDER BY id"
+    ))
+    .bind(tenant)
+    .fetch_all(conn)
+    .await?;
+    rows.iter().map(Todo::from_row).collect()
+}
+
+pub async fn get(conn: &mut DbConn, tenant: &str, id: i64) -> Result<Option<Todo>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "SELECT {COLUMNS} FROM todos WHERE tenant_id = $1 AND id = $2"
+    ))
+    .bind(tenant)
+    .bind(id)
+    .fetch_optional(conn)
+    .await?;
+    row.as_ref().map(Todo::from_row).transpose()
+}
+
+pub async fn create(conn: &mut DbConn, tenant: &str, todo: &NewTodo) -> Result<Todo, sqlx::Error> {
+    let now = now();
+    let row = sqlx::query(&format!(
+        "INSERT INTO todos (tenant_id, title, completed, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $4) RETURNING {COLUMNS}"
+    ))
+    .bind(tenant)
+    .bind(&todo.title)
+    .bind(todo.completed as i32)
+    .bind(now)
+    .fetch_one(conn)
+    .await?;
+    Todo::from_row(&row)
+}
+
+// Apply the given fields to a todo; `None` leaves the stored value untouched.
+// The casts give Postgres a type for parameters that are bound as NULL.
+pub async fn update(
+    conn: &mut DbConn,
+    tenant: &str,
+    id: i64,
+    patch: &TodoPatch,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "UPDATE todos
+         SET title = COALESCE(CAST($1 AS TEXT), title),
+             completed = COALESCE(CAST($2 AS INTEGER), completed),
+             updated_at = $3
+         WHERE tenant_id = $4 AND id = $5 RETURNING {COLUMNS}"
+    ))
+    .bind(patch.title.as_deref())
+    .bind(patch.completed.map(i32::from))
+    .bind(now())
+    .bind(tenant)
+    .bind(id)
+    .fetch_optional(conn)
+    .await?;
+    row.as_ref().map(Todo::from_row).transpose()
+}
+
+// Delete a todo, returning whether it existed
+pub async fn delete(conn: &mut DbConn, tenant: &str, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM todos WHERE tenant_id = $1 AND id = $2")
+        .bind(tenant)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
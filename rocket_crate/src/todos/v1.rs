@@ -0,0 +1,59 @@
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use super::store::{self, Todo};
+use crate::auth::Identity;
+use crate::db::{internal_error, Db, SharedCache};
+use crate::errors::ApiError;
+use crate::etag::ETagged;
+use crate::pagination::{Page, Pagination};
+use crate::response_cache::{CacheKey, ResponseCache};
+use crate::tenant::Tenant;
+use crate::versioning::{self, Accepts};
+
+// Define a route handler that lists one page of todos, by number or after a cursor, tagged so
+// polling clients can revalidate. Deprecated in favour of the v2 list, which pages by cursor only.
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    operation_id = "list_todos",
+    security(("bearer" = []), ("api_key" = [])),
+    params(Pagination),
+    responses(
+        (status = 200, description = "One page of todos", body = Page<Todo>),
+        (status = 304, description = "The page has not changed since the given ETag"),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 400, description = "Unknown sort column, or a malformed cursor or one made for another sort", body = ApiError),
+        (status = 406, description = "Accept-Version names an unsupported version", body = ApiError),
+    )
+)]
+#[get("/todos?<pagination..>")]
+pub async fn list(
+    version: Accepts<versioning::V1>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    pagination: Pagination,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    key: CacheKey,
+) -> Result<ETagged<Page<Todo>>, ApiError> {
+    if let Some(page) = cache.get::<Page<Todo>>(shared.0, &key).await {
+        return Ok(ETagged(page));
+    }
+    let sort = pagination
+        .sort_column(store::SORTABLE)
+        .map_err(ApiError::bad_request)?;
+    let after = pagination
+        .after::<Todo>(sort)
+        .map_err(ApiError::bad_request)?;
+
+    let (todos, total) = store::list(&mut db, &tenant.id, &pagination, sort, after.as_ref())
+        .await
+        .map_err(internal_error)?;
+    let base = format!("{}/todos", version.api.base());
+    let page = Page::new(todos, total, &pagination, &base, sort);
+    cache.put(shared.0, &key, page.clone()).await;
+    Ok(ETagged(page))
+}
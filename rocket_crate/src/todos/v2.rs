@@ -0,0 +1,64 @@
+use rocket::State;
+use rocket_db_pools::Connection;
+
+use super::store::{self, Todo};
+use crate::auth::Identity;
+use crate::db::{internal_error, Db, SharedCache};
+use crate::errors::ApiError;
+use crate::etag::ETagged;
+use crate::pagination::{CursorPage, Pagination};
+use crate::response_cache::{CacheKey, ResponseCache};
+use crate::tenant::Tenant;
+use crate::versioning::{self, Accepts};
+
+// Define a route handler that lists todos a page at a time by cursor alone: the first page is
+// the one without a cursor, and each page's `next_cursor` leads to the next. Page numbers skip
+// or repeat todos as others are added or removed, so they are refused.
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    tag = "todos",
+    path = "/v2/todos",
+    operation_id = "list_todos_v2",
+    params(Pagination),
+    responses(
+        (status = 200, description = "One page of todos", body = CursorPage<Todo>),
+        (status = 304, description = "The page has not changed since the given ETag"),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Credentials issued in another tenant", body = ApiError),
+        (status = 400, description = "A page number, an unknown sort column, or a malformed cursor or one made for another sort", body = ApiError),
+    )
+)]
+#[get("/todos?<pagination..>", rank = 2)]
+pub async fn list(
+    version: Accepts<versioning::V2>,
+    mut db: Connection<Db>,
+    tenant: Tenant,
+    _caller: Identity,
+    pagination: Pagination,
+    cache: &State<ResponseCache>,
+    shared: SharedCache<'_>,
+    key: CacheKey,
+) -> Result<ETagged<CursorPage<Todo>>, ApiError> {
+    if pagination.page != 1 {
+        return Err(ApiError::bad_request(
+            "v2 lists page by cursor only; follow `next_cursor` instead of `page`",
+        ));
+    }
+    if let Some(page) = cache.get::<CursorPage<Todo>>(shared.0, &key).await {
+        return Ok(ETagged(page));
+    }
+    let sort = pagination
+        .sort_column(store::SORTABLE)
+        .map_err(ApiError::bad_request)?;
+    let after = pagination
+        .after::<Todo>(sort)
+        .map_err(ApiError::bad_request)?;
+
+    let (todos, total) = store::list(&mut db, &tenant.id, &pagination, sort, after.as_ref())
+        .await
+        .map_err(internal_error)?;
+    let base = format!("{}/todos", version.api.base());
+    let page = CursorPage::new(todos, total, &pagination, &base, sort);
+    cache.put(shared.0, &key, page.clone()).await;
+    Ok(ETagged(page))
+}
@@ -0,0 +1,143 @@
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+use rocket::data::ByteUnit;
+use rocket::fairing::AdHoc;
+use rocket::tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+// Define the `transfers` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferConfig {
+    // Most bytes read and written at once while streaming a proxied body or a download; larger
+    // chunks mean fewer writes, smaller ones less memory per transfer
+    #[serde(default = "chunk_size")]
+    pub chunk_size: ByteUnit,
+}
+
+fn chunk_size() -> ByteUnit {
+    ByteUnit::Kibibyte(64)
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        TransferConfig {
+            chunk_size: chunk_size(),
+        }
+    }
+}
+
+// Define the state shared by streamed transfers: the chunk size, and the bytes moved and
+// transfers under way by kind, such as `proxy` or `download`. Clones share the counters.
+#[derive(Clone)]
+pub struct Transfers {
+    pub chunk_size: usize,
+    bytes: IntCounterVec,
+    active: IntGaugeVec,
+}
+
+impl Transfers {
+    // Count the bytes of a body of `kind`, `received` from or `sent` to the client, as they are
+    // read from `inner`; the transfer is counted as under way until the reader is dropped
+    pub fn meter<R>(&self, kind: &str, direction: &str, inner: R) -> Metered<R> {
+        let active = self.active.with_label_values(&[kind]);
+        active.inc();
+        Metered {
+            inner,
+            bytes: self.bytes.with_label_values(&[kind, direction]),
+            active,
+        }
+    }
+}
+
+// Define a reader counting the bytes read through it
+pub struct Metered<R> {
+    inner: R,
+    bytes: IntCounter,
+    active: IntGauge,
+}
+
+impl<R> Drop for Metered<R> {
+    fn drop(&mut self) {
+        self.active.dec();
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Metered<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.bytes.inc_by((buf.filled().len() - before) as u64);
+        polled
+    }
+}
+
+// Seeking passes through, so sized bodies such as files can still be measured by seeking
+impl<R: AsyncSeek + Unpin> AsyncSeek for Metered<R> {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+// Load the transfer settings and manage the transfer state, publishing its counters in
+// `/metrics`. Attached after the metrics stage and before the proxy and download routes.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Transfers", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<TransferConfig>("transfers")
+        {
+            Ok(config) => config,
+            Err(err) if err.missing() => TransferConfig::default(),
+            Err(err) => {
+                warn!(
+                    "using the default chunk size, invalid `transfers` configuration: {}",
+                    err
+                );
+                TransferConfig::default()
+            }
+        };
+
+        let bytes = IntCounterVec::new(
+            Opts::new(
+                "transfer_bytes_total",
+                "Bytes of streamed bodies, by kind and whether received from or sent to clients",
+            ),
+            &["kind", "direction"],
+        )
+        .expect("valid metric");
+        let active = IntGaugeVec::new(
+            Opts::new("transfers_active", "Streamed bodies under way, by kind"),
+            &["kind"],
+        )
+        .expect("valid metric");
+        if let Some(metrics) = rocket.state::<Metrics>() {
+            for collector in [Box::new(bytes.clone()) as _, Box::new(active.clone()) as _] {
+                if let Err(err) = metrics.register(collector) {
+                    warn!("failed to register transfer metrics: {}", err);
+                }
+            }
+        }
+
+        // A chunk size of 0 would stall every transfer
+        let chunk_size = (config.chunk_size.as_u64() as usize).max(1);
+        rocket.manage(Transfers {
+            chunk_size,
+            bytes,
+            active,
+        })
+    })
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::{self, json, Json};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationErrors};
+
+use crate::compression;
+use crate::errors::ApiError;
+
+// Define the reasons a request body can be rejected with 422
+#[derive(Debug, Clone)]
+pub enum ValidationError {
+    // The body was not valid JSON or did not match the expected shape
+    Malformed(String),
+    // The body parsed but some fields broke their validation rules
+    Fields(BTreeMap<String, Vec<String>>),
+}
+
+// Remember the failure of the current request so the 422 catcher can report it
+struct ValidationFailure(Option<ValidationError>);
+
+// Define a data guard that deserializes a body and runs its `validator` rules
+#[derive(Debug)]
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<Json<T>> {
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Validate> FromData<'r> for Validated<Json<T>> {
+    type Error = ValidationError;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        // Bodies may be sent gzip-compressed; oversized or unreadable ones keep their own status
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match compression::read_body(req, data, limit).await {
+            Ok(body) => body,
+            Err(err) => {
+                return data::Outcome::Error((
+                    err.status(),
+                    ValidationError::Malformed(err.to_string()),
+                ))
+            }
+        };
+        let err = match json::from_slice::<T>(&body) {
+            Ok(body) => match body.validate() {
+                Ok(()) => return data::Outcome::Success(Validated(Json(body))),
+                Err(errors) => ValidationError::Fields(field_messages(&errors)),
+            },
+            Err(err) => ValidationError::Malformed(err.to_string()),
+        };
+
+        req.local_cache(|| ValidationFailure(Some(err.clone())));
+        data::Outcome::Error((Status::UnprocessableEntity, err))
+    }
+}
+
+// Flatten validator's nested error tree into "field" => ["message", ...]
+pub fn field_messages(errors: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|error| match &error.message {
+                    Some(message) => message.to_string(),
+                    None => format!("failed the `{}` rule", error.code),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
+}
+
+// Return the body validation failure recorded for this request, as an API error
+pub fn failure(req: &Request) -> Option<ApiError> {
+    let error = match req.local_cache(|| ValidationFailure(None)).0.as_ref()? {
+        ValidationError::Fields(fields) => ApiError::new(
+            Status::UnprocessableEntity,
+            "the request body failed validation",
+        )
+        .with_details(json!({ "fields": fields })),
+        ValidationError::Malformed(reason) => ApiError::new(Status::UnprocessableEntity, reason),
+    };
+    Some(error)
+}
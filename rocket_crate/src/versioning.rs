@@ -0,0 +1,230 @@
+use std::marker::PhantomData;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use serde_json::json;
+
+use crate::errors::ApiError;
+
+// Header naming the API version wanted on paths without a `/v<N>` prefix, such as `2` or `v2`
+pub const ACCEPT_VERSION: &str = "Accept-Version";
+
+// Define the versions of the REST API, each mounted under its own prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl Version {
+    pub const ALL: [Version; 2] = [Version::V1, Version::V2];
+
+    // Version of requests to unprefixed paths that name none, as clients from before versioning
+    // send no Accept-Version
+    pub const DEFAULT: Version = Version::V1;
+
+    pub fn number(self) -> u8 {
+        match self {
+            Version::V1 => 1,
+            Version::V2 => 2,
+        }
+    }
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Version::V1 => "/v1",
+            Version::V2 => "/v2",
+        }
+    }
+
+    // Return the version replacing this one, whose responses are then marked deprecated
+    fn successor(self) -> Option<Version> {
+        match self {
+            Version::V1 => Some(Version::V2),
+            Version::V2 => None,
+        }
+    }
+
+    // Parse an Accept-Version value such as `2`, `v2` or `2.0`
+    fn parse(value: &str) -> Option<Version> {
+        let value = value.trim();
+        let value = value.strip_prefix(['v', 'V']).unwrap_or(value);
+        let major = value.split('.').next()?;
+        Version::ALL
+            .into_iter()
+            .find(|version| major == version.number().to_string())
+    }
+}
+
+// Strip the version prefix from a path, so `/v2/todos?page=2` becomes `/todos?page=2`
+pub fn unprefixed(path: &str) -> &str {
+    Version::ALL
+        .into_iter()
+        .filter_map(|version| path.strip_prefix(version.prefix()))
+        .find(|rest| rest.is_empty() || rest.starts_with(['/', '?']))
+        .unwrap_or(path)
+}
+
+// Define the version a request of the versioned API is answered in, and whether its path named
+// it, as paths of unprefixed routes still must not
+#[derive(Debug, Clone, Copy)]
+pub struct ApiVersion {
+    pub version: Version,
+    prefixed: bool,
+}
+
+impl ApiVersion {
+    // Return the prefix of the routes answering this request, to build links that stay on them
+    pub fn base(&self) -> &'static str {
+        match self.prefixed {
+            true => self.version.prefix(),
+            false => "",
+        }
+    }
+}
+
+// Remember the version negotiated for this request, or why none could be, for the response
+// fairing and the 406 catcher
+struct Resolved(Option<Result<ApiVersion, String>>);
+
+// Return the version of a request: that of the prefix its route is mounted under, or else the one
+// named by Accept-Version, or else the default
+fn resolve(req: &Request<'_>) -> Result<ApiVersion, String> {
+    let base = req.route().map(|route| route.uri.base());
+    if let Some(version) = Version::ALL
+        .into_iter()
+        .find(|version| base == Some(version.prefix()))
+    {
+        return Ok(ApiVersion {
+            version,
+            prefixed: true,
+        });
+    }
+    let version = match req.headers().get_one(ACCEPT_VERSION) {
+        None => Version::DEFAULT,
+        Some(value) => Version::parse(value).ok_or_else(|| {
+            format!(
+                "API version `{}` is not supported; send {} with one of {}",
+                value,
+                ACCEPT_VERSION,
+                supported().join(", ")
+            )
+        })?,
+    };
+    Ok(ApiVersion {
+        version,
+        prefixed: false,
+    })
+}
+
+fn supported() -> Vec<String> {
+    Version::ALL
+        .iter()
+        .map(|version| version.number().to_string())
+        .collect()
+}
+
+// Return the version this request was answered in, if a route of the versioned API took it
+pub fn of(req: &Request<'_>) -> Option<Version> {
+    match &req.local_cache(|| Resolved(None)).0 {
+        Some(Ok(api)) => Some(api.version),
+        _ => None,
+    }
+}
+
+// Return the 406 refusing the version this request asked for, if it asked for an unsupported one
+pub fn failure(req: &Request) -> Option<ApiError> {
+    let Some(Err(reason)) = &req.local_cache(|| Resolved(None)).0 else {
+        return None;
+    };
+    Some(
+        ApiError::new(Status::NotAcceptable, reason.clone())
+            .with_details(json!({ "supported": supported() })),
+    )
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiVersion {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        // Cached per request, so the routes tried in turn all agree
+        let resolved = req.local_cache(|| Resolved(Some(resolve(req))));
+        match resolved.0.clone().unwrap_or_else(|| resolve(req)) {
+            Ok(api) => request::Outcome::Success(api),
+            Err(reason) => request::Outcome::Error((Status::NotAcceptable, reason)),
+        }
+    }
+}
+
+// Define a version that a route can be reserved to
+pub trait VersionName {
+    const VERSION: Version;
+}
+
+pub struct V1;
+pub struct V2;
+
+impl VersionName for V1 {
+    const VERSION: Version = Version::V1;
+}
+
+impl VersionName for V2 {
+    const VERSION: Version = Version::V2;
+}
+
+// Define a guard for routes that differ between versions, which only succeeds for requests of
+// version `V`. Requests of another version are forwarded to that version's route.
+pub struct Accepts<V: VersionName> {
+    pub api: ApiVersion,
+    version: PhantomData<V>,
+}
+
+#[rocket::async_trait]
+impl<'r, V: VersionName> FromRequest<'r> for Accepts<V> {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match ApiVersion::from_request(req).await {
+            request::Outcome::Success(api) if api.version == V::VERSION => {
+                request::Outcome::Success(Accepts {
+                    api,
+                    version: PhantomData,
+                })
+            }
+            request::Outcome::Success(_) => request::Outcome::Forward(Status::NotFound),
+            request::Outcome::Error(failure) => request::Outcome::Error(failure),
+            request::Outcome::Forward(status) => request::Outcome::Forward(status),
+        }
+    }
+}
+
+// Say which version every response of the versioned API was answered in. Responses of
+// unprefixed paths vary with Accept-Version, and those of a deprecated version point to the
+// same path in its successor.
+pub fn stage() -> AdHoc {
+    AdHoc::on_response("API Versions", |req, res| {
+        Box::pin(async move {
+            let Some(Ok(api)) = req.local_cache(|| Resolved(None)).0 else {
+                return;
+            };
+            res.set_raw_header("API-Version", api.version.number().to_string());
+            if !api.prefixed {
+                res.adjoin_raw_header("Vary", ACCEPT_VERSION);
+            }
+            if let Some(successor) = api.version.successor() {
+                let path = req.uri().path();
+                res.set_raw_header("Deprecation", "true");
+                res.adjoin_raw_header(
+                    "Link",
+                    format!(
+                        "<{}{}>; rel=\"successor-version\"",
+                        successor.prefix(),
+                        unprefixed(path.as_str())
+                    ),
+                );
+            }
+        })
+    })
+}
@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use rocket::serde::json::{json, Value};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::sleep;
+use rocket::Shutdown;
+use rocket_db_pools::sqlx::{self, AnyPool};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use super::store::{self, Delivery, DeliveryStatus};
+use crate::breaker::CircuitBreakers;
+use crate::events::AppEvent;
+use crate::mail::{Email, Mailer};
+use crate::signature::{self, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+// Headers naming the event and delivery, so receivers can drop deliveries they already handled
+const EVENT_HEADER: &str = "X-Webhook-Event";
+const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+// Deliveries attempted per poll
+const BATCH_SIZE: i64 = 20;
+
+// Define the `webhooks` table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    // Attempts made before a delivery is given up on and kept as dead
+    pub max_attempts: i32,
+    // Seconds before the first retry; each further retry waits twice as long, up to `max_delay`
+    pub base_delay: u64,
+    pub max_delay: u64,
+    // Seconds a receiver has to answer
+    pub timeout: u64,
+    // Seconds between checks for due deliveries
+    pub poll_interval: u64,
+    // Address emailed whenever a delivery is given up on
+    pub notify: Option<String>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 8,
+            base_delay: 10,
+            max_delay: 3600,
+            timeout: 10,
+            poll_interval: 1,
+            notify: None,
+        }
+    }
+}
+
+impl WebhookConfig {
+    // Seconds to wait after the `attempts`-th failed attempt
+    fn backoff(&self, attempts: i32) -> i64 {
+        let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+        let delay = self.base_delay.saturating_mul(1 << exponent);
+        delay.min(self.max_delay) as i64
+    }
+}
+
+// Queue every published event for the subscriptions of its tenant that want it
+pub async fn enqueue_events(
+    pool: AnyPool,
+    mut receiver: rocket::tokio::sync::broadcast::Receiver<AppEvent>,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        let event = select! {
+            received = receiver.recv() => match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("{} events were published too fast to be queued for webhooks", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut shutdown => break,
+        };
+        if let Err(err) = enqueue(&pool, &event).await {
+            warn!("failed to queue `{}` for webhooks: {}", event.name, err);
+        }
+    }
+}
+
+async fn enqueue(pool: &AnyPool, event: &AppEvent) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let subscriptions = store::subscriptions(&mut conn, &event.tenant_id).await?;
+    for subscription in subscriptions.iter().filter(|s| s.wants(event.name)) {
+        store::enqueue(
+            &mut conn,
+            &event.tenant_id,
+            subscription.id,
+            event.name,
+            &event.data,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Attempt due deliveries until the server shuts down
+pub async fn deliver(
+    pool: AnyPool,
+    config: WebhookConfig,
+    mailer: Option<Mailer>,
+    breakers: CircuitBreakers,
+    mut shutdown: Shutdown,
+) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout))
+        .user_agent(concat!("rocket_crate-webhooks/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("HTTP client");
+    loop {
+        select! {
+            _ = sleep(Duration::from_secs(config.poll_interval)) => {}
+            _ = &mut shutdown => break,
+        }
+        if let Err(err) = deliver_due(&pool, &config, &client, mailer.as_ref(), &breakers).await {
+            warn!("failed to deliver webhooks: {}", err);
+        }
+    }
+    info!("webhook delivery stopped");
+}
+
+async fn deliver_due(
+    pool: &AnyPool,
+    config: &WebhookConfig,
+    client: &reqwest::Client,
+    mailer: Option<&Mailer>,
+    breakers: &CircuitBreakers,
+) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    // A lease longer than the receiver timeout, so a delivery is never sent twice at once
+    let lease = config.timeout as i64 + 5;
+    for delivery in store::due(&mut conn, BATCH_SIZE).await? {
+        let Some(subscription) =
+            store::subscription(&mut conn, &delivery.tenant_id, delivery.subscription_id).await?
+        else {
+            continue;
+        };
+        // While the receiver's circuit is open the delivery waits for it without using up an
+        // attempt, as the claim postpones it without counting one
+        let host = CircuitBreakers::host_of(&subscription.url);
+        if let Err(open) = breakers.admit(&host) {
+            debug!(
+                "webhook {} to {} postponed, its circuit is open",
+                delivery.id, open.host
+            );
+            store::claim(&mut conn, &delivery, open.retry_in.as_secs() as i64 + 1).await?;
+            continue;
+        }
+        if !store::claim(&mut conn, &delivery, lease).await? {
+            continue;
+        }
+        let sent = send(client, &subscription.url, &subscription.secret, &delivery).await;
+        // Receivers refusing a delivery are up; only those failing to answer trip the breaker
+        match &sent {
+            Ok(status) if !status.is_server_error() => breakers.succeeded(&host),
+            _ => breakers.failed(&host),
+        }
+        let sent = sent.and_then(|status| match status.is_success() {
+            true => Ok(()),
+            false => Err(format!("receiver answered {}", status)),
+        });
+        let attempts = delivery.attempts + 1;
+        let (status, next_attempt_at, error) = match sent {
+            Ok(()) => {
+                debug!("delivered webhook {} to {}", delivery.id, subscription.url);
+                (DeliveryStatus::Delivered, store::now(), None)
+            }
+            Err(err) if attempts >= config.max_attempts => {
+                warn!(
+                    "giving up on webhook {} to {} after {} attempts: {}",
+                    delivery.id, subscription.url, attempts, err
+                );
+                (DeliveryStatus::Dead, store::now(), Some(err))
+            }
+            Err(err) => {
+                let retry_in = config.backoff(attempts);
+                debug!(
+                    "webhook {} to {} failed, retrying in {}s: {}",
+                    delivery.id, subscription.url, retry_in, err
+                );
+                (DeliveryStatus::Pending, store::now() + retry_in, Some(err))
+            }
+        };
+        store::record_attempt(
+            &mut conn,
+            delivery.id,
+            status,
+            next_attempt_at,
+            error.as_deref(),
+        )
+        .await?;
+        if let (DeliveryStatus::Dead, Some(to), Some(mailer)) = (status, &config.notify, mailer) {
+            let email = dead_letter_email(to, &subscription.url, &delivery, attempts, &error);
+            if let Err(err) = mailer.send(email).await {
+                warn!(
+                    "failed to email about dead webhook {}: {}",
+                    delivery.id, err
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dead_letter_email(
+    to: &str,
+    url: &str,
+    delivery: &Delivery,
+    attempts: i32,
+    error: &Option<String>,
+) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: format!("Webhook delivery {} failed", delivery.id),
+        body: format!(
+            "The `{}` event could not be delivered to {} after {} attempts.\n\n\
+             Last error: {}\n\n\
+             It is kept at GET /webhooks/deliveries?status=dead; once the receiver is fixed, \
+             POST /webhooks/deliveries/{}/retry to send it again.",
+            delivery.event,
+            url,
+            attempts,
+            error.as_deref().unwrap_or("unknown"),
+            delivery.id
+        ),
+    }
+}
+
+// POST a delivery to its receiver, signed like the requests `/webhooks/inbound` accepts, and
+// return the status it answered. Any 2xx answer counts as delivered.
+async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    delivery: &Delivery,
+) -> Result<reqwest::StatusCode, String> {
+    let url = Url::parse(url).map_err(|err| format!("invalid URL: {}", err))?;
+    let body = payload(delivery).to_string();
+    let timestamp = store::now().to_string();
+    let uri = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let signature = signature::sign(secret, "POST", &uri, &timestamp, body.as_bytes());
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(response.status())
+}
+
+fn payload(delivery: &Delivery) -> Value {
+    json!({
+        "id": delivery.id,
+        "event": delivery.event,
+        "created_at": delivery.created_at,
+        "data": delivery.payload,
+    })
+}
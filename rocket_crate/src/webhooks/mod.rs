@@ -0,0 +1,85 @@
+mod delivery;
+mod routes;
+pub mod store;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+use rocket_db_pools::Database;
+use tracing::{error, info, warn};
+use utoipa::OpenApi;
+
+use self::delivery::WebhookConfig;
+use crate::breaker::CircuitBreakers;
+use crate::db::Db;
+use crate::events::Events;
+use crate::mail::Mailer;
+
+// Define the part of the OpenAPI document describing webhook subscriptions and deliveries
+#[derive(OpenApi)]
+#[openapi(paths(
+    routes::list,
+    routes::get,
+    routes::create,
+    routes::delete,
+    routes::deliveries,
+    routes::retry
+))]
+pub struct ApiDoc;
+
+// Mount the webhook subscription routes and, once the server is up, start the workers that
+// queue published events for subscribers and deliver them
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Webhooks", |rocket| async {
+        let config = match rocket.figment().extract_inner::<WebhookConfig>("webhooks") {
+            Ok(config) => config,
+            Err(err) if err.missing() => WebhookConfig::default(),
+            Err(err) => {
+                error!("invalid `webhooks` configuration: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .mount(
+                "/",
+                routes![
+                    routes::list,
+                    routes::get,
+                    routes::create,
+                    routes::delete,
+                    routes::deliveries,
+                    routes::retry
+                ],
+            )
+            .attach(AdHoc::on_liftoff("Webhook Delivery", |rocket| {
+                Box::pin(async move {
+                    let (Some(db), Some(events), Some(breakers)) = (
+                        Db::fetch(rocket),
+                        rocket.state::<Events>(),
+                        rocket.state::<CircuitBreakers>(),
+                    ) else {
+                        warn!("webhooks are not delivered, the application state is missing");
+                        return;
+                    };
+                    let pool = (**db).clone();
+                    tokio::spawn(delivery::enqueue_events(
+                        pool.clone(),
+                        events.subscribe(),
+                        rocket.shutdown(),
+                    ));
+                    let mailer = rocket.state::<Mailer>().cloned();
+                    if config.notify.is_some() && mailer.is_none() {
+                        warn!("dead webhooks cannot be notified by email, the mailer is missing");
+                    }
+                    tokio::spawn(delivery::deliver(
+                        pool,
+                        config,
+                        mailer,
+                        breakers.clone(),
+                        rocket.shutdown(),
+                    ));
+                    info!("delivering webhooks");
+                })
+            })))
+    })
+}
@@ -0,0 +1,195 @@
+use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
+use rocket_db_pools::Connection;
+use tracing::info;
+
+use super::store::{self, DeliveryStatus, NewSubscription, Subscription};
+use crate::auth::AdminUser;
+use crate::db::{internal_error, Db};
+use crate::errors::ApiError;
+use crate::tenant::Tenant;
+use crate::validation::Validated;
+
+type ApiResult<T> = Result<T, ApiError>;
+
+// Deliveries listed at most by `GET /webhooks/deliveries`
+const DELIVERY_LIST_LIMIT: i64 = 100;
+
+fn not_found(id: i64) -> ApiError {
+    ApiError::not_found(format!("webhook subscription {} does not exist", id))
+}
+
+// Define a route handler that lists the webhook subscriptions of the tenant
+#[utoipa::path(
+    tag = "webhooks",
+    operation_id = "list_webhooks",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every subscription", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/webhooks")]
+pub async fn list(
+    _admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+) -> ApiResult<Json<Value>> {
+    let subscriptions = store::subscriptions(&mut db, &tenant.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(json!({ "subscriptions": subscriptions })))
+}
+
+// Define a route handler that fetches one webhook subscription of the tenant
+#[utoipa::path(
+    tag = "webhooks",
+    operation_id = "get_webhook",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The subscription", body = Subscription),
+        (status = 404, description = "No such subscription", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/webhooks/<id>")]
+pub async fn get(
+    _admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+    id: i64,
+) -> ApiResult<Json<Subscription>> {
+    store::subscription(&mut db, &tenant.id, id)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or_else(|| not_found(id))
+}
+
+// Define a route handler that registers a URL to receive the named events of the tenant
+#[utoipa::path(
+    tag = "webhooks",
+    operation_id = "create_webhook",
+    security(("bearer" = [])),
+    request_body = NewSubscription,
+    responses(
+        (status = 201, description = "The new subscription", body = Subscription, headers(("Location" = String))),
+        (status = 422, description = "Invalid subscription", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/webhooks", format = "json", data = "<subscription>")]
+pub async fn create(
+    admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+    subscription: Validated<Json<NewSubscription>>,
+) -> ApiResult<status::Created<Json<Subscription>>> {
+    let subscription = subscription.into_inner();
+    if !subscription.url.starts_with("http://") && !subscription.url.starts_with("https://") {
+        return Err(ApiError::bad_request("webhook URLs must be http or https"));
+    }
+    let subscription = store::subscribe(&mut db, &tenant.id, &subscription)
+        .await
+        .map_err(internal_error)?;
+    info!(
+        events = %subscription.events.join(" "),
+        "webhook {} to {} registered by {}", subscription.id, subscription.url, admin.identity.subject
+    );
+    let location = uri!(get(subscription.id)).to_string();
+    Ok(status::Created::new(location).body(Json(subscription)))
+}
+
+// Define a route handler that deletes a subscription of the tenant and its pending deliveries
+#[utoipa::path(
+    tag = "webhooks",
+    operation_id = "delete_webhook",
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "The subscription and its deliveries were deleted"),
+        (status = 404, description = "No such subscription", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[delete("/webhooks/<id>")]
+pub async fn delete(
+    admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+    id: i64,
+) -> ApiResult<status::NoContent> {
+    match store::unsubscribe(&mut db, &tenant.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        true => {
+            info!("webhook {} removed by {}", id, admin.identity.subject);
+            Ok(status::NoContent)
+        }
+        false => Err(not_found(id)),
+    }
+}
+
+// Define a route handler that lists the latest deliveries of the tenant; `?status=dead` lists
+// the dead letters, deliveries given up on after their last attempt failed
+#[utoipa::path(
+    tag = "webhooks",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The latest deliveries, newest first", body = Value),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[get("/webhooks/deliveries?<status>")]
+pub async fn deliveries(
+    _admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+    status: Option<DeliveryStatus>,
+) -> ApiResult<Json<Value>> {
+    let deliveries = store::deliveries(&mut db, &tenant.id, status, DELIVERY_LIST_LIMIT)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(json!({ "deliveries": deliveries })))
+}
+
+// Define a route handler that queues a dead delivery of the tenant again
+#[utoipa::path(
+    tag = "webhooks",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The delivery, queued again", body = Value),
+        (status = 404, description = "No such dead delivery", body = ApiError),
+        (status = 401, description = "Missing or invalid credentials", body = ApiError),
+        (status = 403, description = "Not an administrator signed in with a one-time code from the admin network", body = ApiError),
+    )
+)]
+#[post("/webhooks/deliveries/<id>/retry")]
+pub async fn retry(
+    admin: AdminUser,
+    tenant: Tenant,
+    mut db: Connection<Db>,
+    id: i64,
+) -> ApiResult<Json<Value>> {
+    match store::redeliver(&mut db, &tenant.id, id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(delivery) => {
+            info!(
+                "webhook delivery {} requeued by {}",
+                id, admin.identity.subject
+            );
+            Ok(Json(json!(delivery)))
+        }
+        None => Err(ApiError::not_found(format!(
+            "webhook delivery {} does not exist or is not dead",
+            id
+        ))),
+    }
+}
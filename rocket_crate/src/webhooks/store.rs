@@ -0,0 +1,331 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::serde::json::Value;
+use rocket_db_pools::sqlx::{self, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::{DbConn, DbRow};
+
+// Define a webhook subscription as stored in the `webhook_subscriptions` table; the secret
+// deliveries are signed with is never returned
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Subscription {
+    pub id: i64,
+    pub url: String,
+    // Event names delivered to `url`, such as `todo.created`, or `*` for every event
+    pub events: Vec<String>,
+    #[serde(skip)]
+    #[schema(ignore)]
+    pub secret: String,
+    pub created_at: i64,
+}
+
+impl Subscription {
+    fn from_row(row: &DbRow) -> Result<Subscription, sqlx::Error> {
+        // Event names are stored space-separated
+        let events: String = row.try_get("events")?;
+        Ok(Subscription {
+            id: row.try_get("id")?,
+            url: row.try_get("url")?,
+            events: events.split_whitespace().map(str::to_string).collect(),
+            secret: row.try_get("secret")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    pub fn wants(&self, event: &str) -> bool {
+        self.events.iter().any(|name| name == "*" || name == event)
+    }
+}
+
+// Define the JSON body accepted when registering a subscription
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct NewSubscription {
+    #[validate(url(message = "must be an absolute URL"))]
+    pub url: String,
+    #[validate(length(min = 1, message = "must name at least one event"))]
+    pub events: Vec<String>,
+    #[validate(length(min = 16, max = 256, message = "must be between 16 and 256 characters"))]
+    #[schema(min_length = 16, max_length = 256)]
+    pub secret: String,
+}
+
+// Define the states of a delivery: waiting for its next attempt, accepted by the receiver, or
+// given up on after the last attempt failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, FromFormField, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl DeliveryStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+
+    fn parse(value: &str) -> DeliveryStatus {
+        match value {
+            "delivered" => DeliveryStatus::Delivered,
+            "dead" => DeliveryStatus::Dead,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
+
+// Define an event queued for one subscription, as stored in the `webhook_deliveries` table
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Delivery {
+    pub id: i64,
+    // Tenant of the subscription, which the deliveries are listed in
+    #[serde(skip)]
+    #[schema(ignore)]
+    pub tenant_id: String,
+    pub subscription_id: i64,
+    pub event: String,
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+const SUBSCRIPTION_COLUMNS: &str = "id, url, events, secret, created_at";
+// The Any driver cannot decode NULL text, so deliveries without an error read it as ''
+const DELIVERY_COLUMNS: &str = "id, tenant_id, subscription_id, event, payload, status, attempts, \
+     next_attempt_at, COALESCE(last_error, '') AS last_error, created_at, updated_at";
+
+impl Delivery {
+    fn from_row(row: &DbRow) -> Result<Delivery, sqlx::Error> {
+        let payload: String = row.try_get("payload")?;
+        let status: String = row.try_get("status")?;
+        Ok(Delivery {
+            id: row.try_get("id")?,
+            tenant_id: row.try_get("tenant_id")?,
+            subscription_id: row.try_get("subscription_id")?,
+            event: row.try_get("event")?,
+            payload: serde_json::from_str(&payload).unwrap_or(Value::Null),
+            status: DeliveryStatus::parse(&status),
+            attempts: row.try_get("attempts")?,
+            next_attempt_at: row.try_get("next_attempt_at")?,
+            last_error: Some(row.try_get::<String, _>("last_error")?).filter(|e| !e.is_empty()),
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+// List the subscriptions of `tenant`
+pub async fn subscriptions(
+    conn: &mut DbConn,
+    tenant: &str,
+) -> Result<Vec<Subscription>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {SUBSCRIPTION_COLUMNS} FROM webhook_subscriptions WHERE tenant_id = $1 ORDER BY id"
+    ))
+    .bind(tenant)
+    .fetch_all(conn)
+    .await?;
+    rows.iter().map(Subscription::from_row).collect()
+}
+
+pub async fn subscription(
+    conn: &mut DbConn,
+    tenant: &str,
+    id: i64,
+) -> Result<Option<Subscription>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "SELECT {SUBSCRIPTION_COLUMNS} FROM webhook_subscriptions WHERE tenant_id = $1 AND id = $2"
+    ))
+    .bind(tenant)
+    .bind(id)
+    .fetch_optional(conn)
+    .await?;
+    row.as_ref().map(Subscription::from_row).transpose()
+}
+
+pub async fn subscribe(
+    conn: &mut DbConn,
+    tenant: &str,
+    subscription: &NewSubscription,
+) -> Result<Subscription, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        "INSERT INTO webhook_subscriptions (tenant_id, url, events, secret, created_at)
+         VALUES ($1, $2, $3, $4, $5) RETURNING {SUBSCRIPTION_COLUMNS}"
+    ))
+    .bind(tenant)
+    .bind(&subscription.url)
+    .bind(subscription.events.join(" "))
+    .bind(&subscription.secret)
+    .bind(now())
+    .fetch_one(conn)
+    .await?;
+    Subscription::from_row(&row)
+}
+
+// Delete a subscription of `tenant` along with its deliveries, returning whether it existed
+pub async fn unsubscribe(conn: &mut DbConn, tenant: &str, id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query("DELETE FROM webhook_deliveries WHERE tenant_id = $1 AND subscription_id = $2")
+        .bind(tenant)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    let result = sqlx::query("DELETE FROM webhook_subscriptions WHERE tenant_id = $1 AND id = $2")
+        .bind(tenant)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Queue `payload` for delivery to a subscription of `tenant` as soon as possible
+pub async fn enqueue(
+    conn: &mut DbConn,
+    tenant: &str,
+    subscription_id: i64,
+    event: &str,
+    payload: &Value,
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    sqlx::query(
+        "INSERT INTO webhook_deliveries
+         (tenant_id, subscription_id, event, payload, status, attempts, next_attempt_at,
+          created_at, updated_at)
+         VALUES ($1, $2, $3, $4, 'pending', 0, $5, $5, $5)",
+    )
+    .bind(tenant)
+    .bind(subscription_id)
+    .bind(event)
+    .bind(payload.to_string())
+    .bind(now)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Return up to `limit` pending deliveries whose next attempt is due
+pub async fn due(conn: &mut DbConn, limit: i64) -> Result<Vec<Delivery>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+         WHERE status = 'pending' AND next_attempt_at <= $1 ORDER BY next_attempt_at, id LIMIT $2"
+    ))
+    .bind(now())
+    .bind(limit)
+    .fetch_all(conn)
+    .await?;
+    rows.iter().map(Delivery::from_row).collect()
+}
+
+// Take a due delivery for `lease` seconds by pushing its next attempt back, so other instances
+// polling the same database skip it. Returns false when another instance took it first.
+pub async fn claim(
+    conn: &mut DbConn,
+    delivery: &Delivery,
+    lease: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE webhook_deliveries SET next_attempt_at = $1
+         WHERE id = $2 AND status = 'pending' AND next_attempt_at = $3",
+    )
+    .bind(now() + lease)
+    .bind(delivery.id)
+    .bind(delivery.next_attempt_at)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Record the outcome of an attempt: `status` is Pending with the time of the next attempt after
+// a failure that will be retried
+pub async fn record_attempt(
+    conn: &mut DbConn,
+    id: i64,
+    status: DeliveryStatus,
+    next_attempt_at: i64,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE webhook_deliveries
+         SET status = $1, attempts = attempts + 1, next_attempt_at = $2, last_error = $3,
+             updated_at = $4
+         WHERE id = $5",
+    )
+    .bind(status.as_str())
+    .bind(next_attempt_at)
+    .bind(error)
+    .bind(now())
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// List the most recent deliveries of `tenant`, optionally only those in `status`
+pub async fn deliveries(
+    conn: &mut DbConn,
+    tenant: &str,
+    status: Option<DeliveryStatus>,
+    limit: i64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    let rows = match status {
+        Some(status) => {
+            sqlx::query(&format!(
+                "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+                 WHERE tenant_id = $1 AND status = $2 ORDER BY id DESC LIMIT $3"
+            ))
+            .bind(tenant)
+            .bind(status.as_str())
+            .bind(limit)
+            .fetch_all(conn)
+            .await?
+        }
+        None => {
+            sqlx::query(&format!(
+                "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+                 WHERE tenant_id = $1 ORDER BY id DESC LIMIT $2"
+            ))
+            .bind(tenant)
+            .bind(limit)
+            .fetch_all(conn)
+            .await?
+        }
+    };
+    rows.iter().map(Delivery::from_row).collect()
+}
+
+// Queue a dead delivery of `tenant` again with a fresh set of attempts, returning it if it was
+// dead
+pub async fn redeliver(
+    conn: &mut DbConn,
+    tenant: &str,
+    id: i64,
+) -> Result<Option<Delivery>, sqlx::Error> {
+    let now = now();
+    let row = sqlx::query(&format!(
+        "UPDATE webhook_deliveries
+         SET status = 'pending', attempts = 0, next_attempt_at = $1, updated_at = $1
+         WHERE tenant_id = $2 AND id = $3 AND status = 'dead' RETURNING {DELIVERY_COLUMNS}"
+    ))
+    .bind(now)
+    .bind(tenant)
+    .bind(id)
+    .fetch_optional(conn)
+    .await?;
+    row.as_ref().map(Delivery::from_row).transpose()
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}